@@ -0,0 +1,317 @@
+//! Coordination between a server that keeps its cache in a dump file and the
+//! `cache` commands. While it's running, the server holds a lock file next to
+//! the dump, and picks up requests to flush entries from a second file.
+
+use dns::record::{self, RecordType};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{error, fs, io, process};
+
+/// How often a server checks for flush requests.
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long `request_flush` waits for the server to pick up its request.
+const FLUSH_WAIT: Duration = Duration::from_secs(10);
+
+/// Held by a server for as long as it's using a cache dump, so nothing else
+/// changes the dump under it. The lock file holds the server's process ID,
+/// and is removed when this is dropped.
+#[derive(Debug)]
+pub struct CacheLock {
+    path: PathBuf,
+}
+
+impl CacheLock {
+    /// Take the lock on a dump. A lock left behind by a server that is no
+    /// longer running is replaced.
+    pub fn acquire(dump: &Path) -> Result<CacheLock, Box<dyn error::Error + Send + Sync>> {
+        let path = lock_path(dump);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        match options.open(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if let Some(pid) = owner(dump) {
+                    return Err(in_use(dump, pid));
+                }
+                remove_if_exists(&path)?;
+            }
+            Err(err) => return Err(err.into()),
+            Ok(file) => return CacheLock::fill(path, file),
+        }
+        // Another server could have replaced the stale lock in the meantime
+        match options.open(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => match owner(dump) {
+                Some(pid) => Err(in_use(dump, pid)),
+                None => Err(format!("could not replace the stale lock {}", path.display()).into()),
+            },
+            Err(err) => Err(err.into()),
+            Ok(file) => CacheLock::fill(path, file),
+        }
+    }
+
+    fn fill(
+        path: PathBuf,
+        mut file: fs::File,
+    ) -> Result<CacheLock, Box<dyn error::Error + Send + Sync>> {
+        let lock = CacheLock { path };
+        write!(file, "{}", process::id())?;
+        Ok(lock)
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The process ID of the running server holding the lock on a dump, if any.
+pub fn owner(dump: &Path) -> Option<u32> {
+    let pid = fs::read_to_string(lock_path(dump))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    is_running(pid).then_some(pid)
+}
+
+/// Ask the server holding the lock on a dump to remove the entries matching
+/// a name and type from its cache, and wait for it to do so. The server
+/// saves the dump afterwards.
+pub fn request_flush(
+    dump: &Path,
+    name: Option<&str>,
+    record_type: Option<RecordType>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let path = flush_path(dump);
+    let mut file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+    {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("another flush of {} is still waiting", dump.display()).into())
+        }
+        result => result?,
+    };
+    file.write_all(format_flush(name, record_type).as_bytes())?;
+    drop(file);
+
+    let started = std::time::Instant::now();
+    while path.exists() {
+        if started.elapsed() > FLUSH_WAIT {
+            remove_if_exists(&path)?;
+            return Err(format!(
+                "the server using {} didn't pick up the flush",
+                dump.display()
+            )
+            .into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Carry out flush requests for a dump as they come in, saving the dump
+/// after each one. Bad requests are reported and dropped, rather than
+/// stopping the server.
+pub async fn serve_flush_requests(
+    dump: &Path,
+    cache: &Mutex<resolver::Cache>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let path = flush_path(dump);
+    let mut interval = tokio::time::interval(FLUSH_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        match flush(&text, dump, cache) {
+            Ok(count) => println!("Flushed {} cache entries", count),
+            Err(err) => eprintln!("Ignoring flush request {}: {}", path.display(), err),
+        }
+        remove_if_exists(&path)?;
+    }
+}
+
+/// Carry out a single flush request, returning how many entries it removed.
+fn flush(
+    text: &str,
+    dump: &Path,
+    cache: &Mutex<resolver::Cache>,
+) -> Result<usize, Box<dyn error::Error + Send + Sync>> {
+    let (name, record_type) = parse_flush(text)?;
+    let mut cache = cache.lock().unwrap();
+    let count = cache.flush(Some(&name), record_type);
+    cache.save(dump)?;
+    Ok(count)
+}
+
+/// A flush request is the name to flush, `.` for every name, optionally
+/// followed by a record type.
+fn format_flush(name: Option<&str>, record_type: Option<RecordType>) -> String {
+    match record_type {
+        Some(typ) => format!(
+            "{} {}\n",
+            name.unwrap_or("."),
+            record::record_type_name(&typ)
+        ),
+        None => format!("{}\n", name.unwrap_or(".")),
+    }
+}
+
+fn parse_flush(
+    text: &str,
+) -> Result<(String, Option<RecordType>), Box<dyn error::Error + Send + Sync>> {
+    let mut fields = text.split_whitespace();
+    let name = fields.next().ok_or("the request is empty")?;
+    let record_type = match fields.next() {
+        Some(typ) => Some(
+            record::parse_record_type_name(typ)
+                .ok_or_else(|| format!("unknown record type {}", typ))?,
+        ),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return Err("the request has too many fields".into());
+    }
+    Ok((name.to_string(), record_type))
+}
+
+/// The file a server keeps next to its cache dump while it's running.
+fn lock_path(dump: &Path) -> PathBuf {
+    with_suffix(dump, ".lock")
+}
+
+/// The file `cache flush` leaves for a running server to pick up.
+fn flush_path(dump: &Path) -> PathBuf {
+    with_suffix(dump, ".flush")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn in_use(dump: &Path, pid: u32) -> Box<dyn error::Error + Send + Sync> {
+    format!(
+        "{} is in use by another server (process {})",
+        dump.display(),
+        pid
+    )
+    .into()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Returns true if a process with this ID exists. Where that can't be
+/// checked, it's assumed to.
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+    let status = process::Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(process::Stdio::null())
+        .status();
+    match status {
+        Ok(status) => status.success(),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-dns-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn test_lock_is_exclusive_and_removed_on_drop() {
+        let dump = dump("lock");
+        let lock = CacheLock::acquire(&dump).unwrap();
+        assert_eq!(owner(&dump), Some(process::id()));
+        let err = CacheLock::acquire(&dump).unwrap_err();
+        assert!(err.to_string().contains("in use"));
+        drop(lock);
+        assert!(!lock_path(&dump).exists());
+        assert_eq!(owner(&dump), None);
+    }
+
+    #[test]
+    fn test_lock_replaces_stale_lock() {
+        let dump = dump("stale-lock");
+        // Process IDs are capped well below this on every system
+        fs::write(lock_path(&dump), "4000000000").unwrap();
+        assert_eq!(owner(&dump), None);
+        let lock = CacheLock::acquire(&dump).unwrap();
+        assert_eq!(owner(&dump), Some(process::id()));
+        drop(lock);
+    }
+
+    #[test]
+    fn test_flush_requests_round_trip() {
+        let text = format_flush(Some("example.com"), Some(RecordType::A));
+        assert_eq!(text, "example.com A\n");
+        assert_eq!(
+            parse_flush(&text).unwrap(),
+            ("example.com".to_string(), Some(RecordType::A))
+        );
+        assert_eq!(
+            parse_flush(&format_flush(None, None)).unwrap(),
+            (".".to_string(), None)
+        );
+        assert!(parse_flush("example.com BOGUS").is_err());
+        assert!(parse_flush("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_server_carries_out_flush_requests() {
+        let dump = dump("flush");
+        let mut cache = resolver::Cache::new(resolver::CacheConfig::default());
+        let records = dns::zone::parse_master_file(
+            "example.com. 300 A 192.0.2.1\nexample.org. 300 A 192.0.2.2",
+            None,
+        )
+        .unwrap();
+        cache.insert(&records);
+        let cache = Mutex::new(cache);
+
+        let requesting = tokio::task::spawn_blocking({
+            let dump = dump.clone();
+            move || request_flush(&dump, Some("example.com"), None)
+        });
+        let serving = serve_flush_requests(&dump, &cache);
+        tokio::select! {
+            result = requesting => result.unwrap().unwrap(),
+            result = serving => panic!("stopped serving: {:?}", result),
+        }
+        assert!(cache
+            .lock()
+            .unwrap()
+            .lookup("example.com", RecordType::A)
+            .is_none());
+        assert!(cache
+            .lock()
+            .unwrap()
+            .lookup("example.org", RecordType::A)
+            .is_some());
+        let saved = resolver::dump::parse_dump(&fs::read_to_string(&dump).unwrap()).unwrap();
+        fs::remove_file(&dump).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert!(!flush_path(&dump).exists());
+    }
+}
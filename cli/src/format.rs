@@ -0,0 +1,157 @@
+//! Formatting of responses in the style of `dig`.
+
+use dns::edns;
+use dns::header::{self, Header, Opcode};
+use dns::packet::DnsPacket;
+use dns::record::{self, Record, RecordType};
+use dns::zone;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub fn format_response(response: &DnsPacket, server: SocketAddr, elapsed: Duration) -> String {
+    let mut out = format_message(response);
+    let _ = write!(
+        out,
+        "\n;; Query time: {} msec\n;; SERVER: {}#{}({})\n",
+        elapsed.as_millis(),
+        server.ip(),
+        server.port(),
+        server.ip()
+    );
+    out
+}
+
+/// Format a message's header and sections, without the details of where
+/// it came from. UPDATE messages use their own names for the sections
+/// (RFC 2136 §2).
+pub fn format_message(response: &DnsPacket) -> String {
+    let mut out = String::new();
+    let header = &response.header;
+    let (counts, titles) = match header.opcode {
+        Opcode::Update => (
+            ["ZONE", "PREREQ", "UPDATE"],
+            ["ZONE", "PREREQUISITE", "UPDATE"],
+        ),
+        _ => (
+            ["QUERY", "ANSWER", "AUTHORITY"],
+            ["QUESTION", "ANSWER", "AUTHORITY"],
+        ),
+    };
+    let _ = writeln!(
+        out,
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        header::opcode_name(&header.opcode),
+        header::response_code_name(&header.rcode),
+        header.id
+    );
+    let _ = writeln!(
+        out,
+        ";; flags:{}; {}: {}, {}: {}, {}: {}, ADDITIONAL: {}",
+        format_flags(header),
+        counts[0],
+        response.questions.len(),
+        counts[1],
+        response.answers.len(),
+        counts[2],
+        response.authoritative_entries.len(),
+        response.resource_entries.len()
+    );
+
+    if let Some(edns) = edns::parse_edns(response) {
+        let flags = if edns.dnssec_ok { " do" } else { "" };
+        let _ = write!(
+            out,
+            "\n;; OPT PSEUDOSECTION:\n; EDNS: version: {}, flags:{}; udp: {}\n",
+            edns.version, flags, edns.udp_payload_size
+        );
+    }
+
+    let _ = write!(out, "\n;; {} SECTION:\n", titles[0]);
+    for question in &response.questions {
+        let _ = writeln!(
+            out,
+            ";{}\t\t{}\t{}",
+            zone::format_name(&question.name),
+            record::class_name(&record::parse_class(question.class)),
+            record::record_type_name(&record::parse_record_type(question.typ))
+        );
+    }
+    format_section(&mut out, titles[1], &response.answers);
+    format_section(&mut out, titles[2], &response.authoritative_entries);
+    let additional: Vec<Record> = response
+        .resource_entries
+        .iter()
+        .filter(|record| record.record_type != RecordType::Opt)
+        .cloned()
+        .collect();
+    format_section(&mut out, "ADDITIONAL", &additional);
+    out
+}
+
+fn format_flags(header: &Header) -> String {
+    let flags = [
+        (!header.query, "qr"),
+        (header.authoritative_answer, "aa"),
+        (header.truncation, "tc"),
+        (header.recursion_desired, "rd"),
+        (header.recursion_available, "ra"),
+        (header.reserved & header::AUTHENTIC_DATA != 0, "ad"),
+        (header.reserved & header::CHECKING_DISABLED != 0, "cd"),
+    ];
+    flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| format!(" {}", name))
+        .collect()
+}
+
+fn format_section(out: &mut String, title: &str, records: &[Record]) {
+    if records.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "\n;; {} SECTION:", title);
+    for record in records {
+        out.push_str(&zone::format_record(record));
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_response_looks_like_dig() {
+        let mut response = dns::packet::build_query(1234, "example.com", &RecordType::Aaaa);
+        response.header.query = false;
+        response.header.authoritative_answer = true;
+        response.header.recursion_desired = true;
+        response.answers =
+            zone::parse_master_file("example.com. 3600 IN AAAA 2001:db8::1", None).unwrap();
+        edns::set_edns(&mut response, &edns::Edns::default());
+
+        let output = format_response(
+            &response,
+            "127.0.0.1:3000".parse().unwrap(),
+            Duration::from_millis(3),
+        );
+        assert_eq!(
+            output,
+            ";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 1234\n\
+             ;; flags: qr aa rd; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1\n\
+             \n\
+             ;; OPT PSEUDOSECTION:\n\
+             ; EDNS: version: 0, flags:; udp: 1232\n\
+             \n\
+             ;; QUESTION SECTION:\n\
+             ;example.com.\t\tIN\tAAAA\n\
+             \n\
+             ;; ANSWER SECTION:\n\
+             example.com.\t3600\tIN\tAAAA\t2001:db8::1\n\
+             \n\
+             ;; Query time: 3 msec\n\
+             ;; SERVER: 127.0.0.1#3000(127.0.0.1)\n"
+        );
+    }
+}
@@ -1,6 +1,14 @@
 use clap::{Parser, Subcommand};
-use std::{error, fs};
-use tokio::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use std::{error, fs, io};
+use tokio::net::{TcpListener, UdpSocket};
+
+mod cache;
+mod format;
+mod nsupdate;
 
 #[derive(Parser)]
 #[command(bin_name = "rust-dns", author = "Harrison Turton", version)]
@@ -16,6 +24,10 @@ enum Command {
     Parse {
         /// Path to the file
         filepath: String,
+        /// Parse the file as a capture of a TCP stream, where each packet is
+        /// prefixed with its length
+        #[arg(long)]
+        tcp: bool,
     },
     /// Write a DNS packet to a file
     #[command(name = "write")]
@@ -23,118 +35,1315 @@ enum Command {
         /// Path to the file
         filepath: String,
     },
-    /// Launch a stub DNS server
+    /// Launch a DNS server
     #[command(name = "serve")]
     Serve {
         /// Address to listen on, e.g. 127.0.0.1:3000
         addr: String,
+        /// Path to a zone file to serve. May be given more than once
+        #[arg(long = "zone")]
+        zones: Vec<String>,
+        /// Relay queries for names outside our zones to this server, e.g.
+        /// 10.0.0.53 or 10.0.0.53:5353. May be given more than once, in which
+        /// case later servers are used when earlier ones fail
+        #[arg(long = "forward", conflicts_with = "recursive")]
+        upstreams: Vec<String>,
+        /// Resolve queries for names outside our zones, starting from the
+        /// root servers
+        #[arg(long)]
+        recursive: bool,
+        /// Load the cache from this file on startup, and save it there every
+        /// minute and on shutdown. Needs --forward or --recursive
+        #[arg(long)]
+        cache_file: Option<String>,
+        /// Validate answers with DNSSEC, using the root zone's trust anchors.
+        /// Needs --recursive
+        #[arg(long, requires = "recursive")]
+        dnssec: bool,
+        /// Validate answers against the DS or DNSKEY records in this file
+        /// instead of the root zone's trust anchors
+        #[arg(long, requires = "dnssec")]
+        trust_anchor: Option<String>,
+        #[command(flatten)]
+        signing: SigningArgs,
+        #[command(flatten)]
+        tsig: TsigArgs,
+        /// Let these clients transfer zones with AXFR or IXFR: an address, a network
+        /// like 10.0.0.0/8, `key:<name>` for requests signed with that TSIG
+        /// key, or `any`. May be given more than once. Nobody can by default
+        #[arg(long)]
+        allow_transfer: Vec<String>,
+        /// Let requests signed with a TSIG key change records with UPDATE,
+        /// given as `<key> <scope> [types]`. The scope is `zone`, `self` for
+        /// the key's own name, `name:<name>` or `subdomain:<name>`, and the
+        /// types are separated by commas, e.g. `dhcp subdomain:dhcp.example.com
+        /// A,AAAA`. May be given more than once. Nobody can by default
+        #[arg(long)]
+        update_policy: Vec<String>,
+        #[command(flatten)]
+        replication: Box<ReplicationArgs>,
+        #[command(flatten)]
+        tls: Box<TlsServeArgs>,
+    },
+    /// Sign a zone file with DNSSEC, and print the DS records to give to the
+    /// parent zone
+    #[command(name = "sign")]
+    Sign {
+        /// Path to the zone file
+        zonefile: String,
+        /// Where to write the signed zone. Defaults to the zone file's path
+        /// with .signed appended
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        signing: SigningArgs,
+    },
+    /// Send a query to a DNS server and print the response, like dig
+    #[command(name = "query")]
+    Query {
+        /// `[@server] name [type]`. Without a server, the first nameserver in
+        /// /etc/resolv.conf is used. The type defaults to A
+        #[arg(required = true, num_args = 1..=3)]
+        args: Vec<String>,
+        /// Port to send the query to. Defaults to 53, 853 over TLS or 443 over
+        /// HTTPS
+        #[arg(short, long)]
+        port: Option<u16>,
+        /// Send the query over TCP instead of UDP
+        #[arg(long)]
+        tcp: bool,
+        /// Don't ask the server to resolve the query recursively
+        #[arg(long)]
+        norecurse: bool,
+        /// Seconds to wait for each attempt
+        #[arg(long, default_value_t = 2)]
+        timeout: u64,
+        /// How many times to send the query before giving up
+        #[arg(long, default_value_t = 3)]
+        tries: usize,
+        /// Set the DO bit, asking for DNSSEC records in the response
+        #[arg(long)]
+        dnssec: bool,
+        #[command(flatten)]
+        tsig: TsigArgs,
+        #[command(flatten)]
+        tls: TlsQueryArgs,
+    },
+    /// Transfer a whole zone from a server with AXFR and print it as a zone
+    /// file
+    #[command(name = "axfr")]
+    Axfr {
+        /// `[@server] zone`. Without a server, the first nameserver in
+        /// /etc/resolv.conf is used
+        #[arg(required = true, num_args = 1..=2)]
+        args: Vec<String>,
+        /// Port to send the request to
+        #[arg(short, long, default_value_t = 53)]
+        port: u16,
+        /// Write the zone to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Seconds to wait for each message of the transfer
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+        #[command(flatten)]
+        tsig: TsigArgs,
+    },
+    /// Bring a zone file up to date with the changes a server has made since
+    /// its serial, using IXFR
+    #[command(name = "ixfr")]
+    Ixfr {
+        /// `[@server] zonefile`. Without a server, the first nameserver in
+        /// /etc/resolv.conf is used
+        #[arg(required = true, num_args = 1..=2)]
+        args: Vec<String>,
+        /// Port to send the request to
+        #[arg(short, long, default_value_t = 53)]
+        port: u16,
+        /// Write the updated zone to this file instead of back to the zone
+        /// file
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Seconds to wait for each message of the transfer
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+        #[command(flatten)]
+        tsig: TsigArgs,
+    },
+    /// Send dynamic updates described by a script of commands, like
+    /// nsupdate: `server`, `zone`, `key`, `ttl`, `prereq`, `update add`,
+    /// `update delete`, `show` and `send`
+    #[command(name = "nsupdate")]
+    Nsupdate {
+        /// Path to the script. Read from stdin if not given
+        script: Option<String>,
+        /// Seconds to wait for each response
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+        #[command(flatten)]
+        tsig: TsigArgs,
+    },
+    /// Generate a DNSSEC key in BIND's key file format, and print the DS
+    /// records for key signing keys
+    #[command(name = "keygen")]
+    Keygen {
+        /// The zone the key is for
+        zone: String,
+        /// 13 (ECDSAP256SHA256) or 15 (ED25519)
+        #[arg(short, long, default_value_t = dns::dnssec::ECDSAP256SHA256)]
+        algorithm: u8,
+        /// Make a key signing key, with the SEP flag set
+        #[arg(long)]
+        ksk: bool,
+        /// Directory to write the key files to
+        #[arg(short = 'K', long, default_value = ".")]
+        directory: String,
+        /// When to add the key to the zone's DNSKEY records. Times are either
+        /// YYYYMMDDHHmmSS in UTC, `now`, or an offset from now like +30d.
+        /// Defaults to the activation time
+        #[arg(long)]
+        publish: Option<String>,
+        /// When the key starts signing. Defaults to the publication time, or
+        /// now
+        #[arg(long)]
+        activate: Option<String>,
+        /// When the key stops signing
+        #[arg(long)]
+        inactive: Option<String>,
+        /// When the key is removed from the zone
+        #[arg(long)]
+        delete: Option<String>,
+    },
+    /// Inspect or flush a cache dump saved by `serve --cache-file`
+    #[command(name = "cache", subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct SigningArgs {
+    /// Sign zones with this key, given as the path to its .key or .private
+    /// file. Keys with the SEP flag sign the DNSKEY records and the others
+    /// sign everything else. May be given more than once
+    #[arg(long = "key")]
+    keys: Vec<String>,
+    /// Prove that names don't exist with hashed NSEC3 records instead of
+    /// NSEC records
+    #[arg(long)]
+    nsec3: bool,
+    /// Salt for NSEC3 hashes, in hex, or - for none
+    #[arg(long, requires = "nsec3")]
+    salt: Option<String>,
+    /// Extra NSEC3 hash iterations
+    #[arg(long, requires = "nsec3", default_value_t = 0)]
+    iterations: u16,
+    /// Leave unsigned delegations out of the NSEC3 chain
+    #[arg(long, requires = "nsec3")]
+    opt_out: bool,
+    /// How long signatures are valid for, e.g. 30d
+    #[arg(long, default_value = "30d")]
+    validity: String,
+    /// Sign zones again when their signatures have this long left
+    #[arg(long, default_value = "7d")]
+    refresh: String,
+}
+
+impl SigningArgs {
+    /// Load the keys and group them into a signer for each zone.
+    fn signers(&self) -> Result<Vec<server::signer::Signer>, Box<dyn error::Error + Send + Sync>> {
+        let denial = match self.nsec3 {
+            true => server::signer::Denial::Nsec3 {
+                salt: dns::zone::parse_salt(self.salt.as_deref().unwrap_or("-"))?,
+                iterations: self.iterations,
+                opt_out: self.opt_out,
+            },
+            false => server::signer::Denial::Nsec,
+        };
+        let validity = Duration::from_secs(dns::zone::parse_ttl(&self.validity)? as u64);
+        let refresh = Duration::from_secs(dns::zone::parse_ttl(&self.refresh)? as u64);
+        if refresh >= validity {
+            return Err("--refresh must be shorter than --validity".into());
+        }
+        let config = server::signer::SignerConfig {
+            denial,
+            validity,
+            refresh,
+            ..server::signer::SignerConfig::default()
+        };
+
+        let mut zones: Vec<Vec<dns::key::SigningKey>> = vec![];
+        for path in &self.keys {
+            let key = dns::key::SigningKey::load(Path::new(path))
+                .map_err(|err| format!("could not load key {}: {}", path, err))?;
+            let zone = zones
+                .iter_mut()
+                .find(|keys| dns::name::eq(&keys[0].owner, &key.owner));
+            match zone {
+                Some(keys) => keys.push(key),
+                None => zones.push(vec![key]),
+            }
+        }
+        zones
+            .into_iter()
+            .map(|keys| server::signer::Signer::new(keys, config.clone()))
+            .collect()
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct TsigArgs {
+    /// A TSIG key to sign messages with, given as `[algorithm:]name:secret`
+    /// with the secret in base64. The algorithm defaults to hmac-sha256
+    #[arg(long = "tsig-key")]
+    tsig_keys: Vec<String>,
+    /// Path to a file of TSIG keys in BIND's `key "name" { ... };` format
+    #[arg(long = "tsig-keyfile")]
+    tsig_keyfiles: Vec<String>,
+}
+
+impl TsigArgs {
+    fn keys(&self) -> Result<Vec<dns::tsig::TsigKey>, Box<dyn error::Error + Send + Sync>> {
+        let mut keys = vec![];
+        for value in &self.tsig_keys {
+            keys.push(dns::tsig::TsigKey::parse(value)?);
+        }
+        for path in &self.tsig_keyfiles {
+            let text = fs::read_to_string(path)?;
+            keys.extend(
+                dns::tsig::TsigKey::parse_key_file(&text)
+                    .map_err(|err| format!("could not load keys from {}: {}", path, err))?,
+            );
+        }
+        Ok(keys)
+    }
+
+    /// The one key a client should sign its requests with, if any.
+    fn key(&self) -> Result<Option<dns::tsig::TsigKey>, Box<dyn error::Error + Send + Sync>> {
+        let mut keys = self.keys()?;
+        if keys.len() > 1 {
+            return Err("only one TSIG key can be used to sign a request".into());
+        }
+        Ok(keys.pop())
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct ReplicationArgs {
+    /// Send NOTIFY to this secondary, e.g. 10.0.0.2 or 10.0.0.2:5353,
+    /// whenever one of our zones gets a new serial. May be given more
+    /// than once
+    #[arg(long = "notify")]
+    notify: Vec<String>,
+    /// Keep a copy of a zone from its primary, given as
+    /// `<zone>@<primary>`. The zone is checked on its SOA refresh timer,
+    /// and whenever the primary sends a NOTIFY. Give a zone more than once
+    /// to fall back to other primaries
+    #[arg(long = "secondary")]
+    secondaries: Vec<String>,
+    /// Sign NOTIFY messages and requests to primaries with the TSIG key
+    /// of this name, which must be one of the --tsig-key keys
+    #[arg(long)]
+    transfer_key: Option<String>,
+}
+
+impl ReplicationArgs {
+    fn notify_addrs(&self) -> Result<Vec<SocketAddr>, Box<dyn error::Error + Send + Sync>> {
+        self.notify
+            .iter()
+            .map(|addr| parse_server_addr(addr))
+            .collect()
+    }
+
+    /// The zones to serve as a secondary, with every primary given for each.
+    fn secondary_zones(
+        &self,
+    ) -> Result<Vec<server::secondary::SecondaryZone>, Box<dyn error::Error + Send + Sync>> {
+        let mut zones: Vec<server::secondary::SecondaryZone> = vec![];
+        for value in &self.secondaries {
+            let zone = server::secondary::SecondaryZone::parse(value)?;
+            match zones
+                .iter_mut()
+                .find(|existing| existing.origin == zone.origin)
+            {
+                Some(existing) => existing.primaries.extend(zone.primaries),
+                None => zones.push(zone),
+            }
+        }
+        Ok(zones)
+    }
+
+    /// The key named by --transfer-key, out of the server's keys.
+    fn transfer_key(
+        &self,
+        keys: &[dns::tsig::TsigKey],
+    ) -> Result<Option<dns::tsig::TsigKey>, Box<dyn error::Error + Send + Sync>> {
+        let name = match &self.transfer_key {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match keys.iter().find(|key| dns::name::eq(&key.name, name)) {
+            Some(key) => Ok(Some(key.clone())),
+            None => Err(format!("no TSIG key named {}", name).into()),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct TlsServeArgs {
+    /// Also answer queries over TLS (RFC 7858), with the certificate chain
+    /// in this PEM file
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+    /// The PEM file holding the private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+    /// Port to answer queries over TLS on, at the same address as UDP and
+    /// TCP
+    #[arg(long, default_value_t = client::tls::DEFAULT_PORT)]
+    tls_port: u16,
+    /// Also answer queries over HTTPS (RFC 8484) at /dns-query on this
+    /// port, with the --tls-cert certificate
+    #[arg(long, requires = "tls_cert")]
+    https_port: Option<u16>,
+}
+
+impl TlsServeArgs {
+    /// The address to listen for TLS on, and the certificate to present,
+    /// if TLS is enabled.
+    fn listener(
+        &self,
+        addr: &str,
+    ) -> Result<Option<TlsListener>, Box<dyn error::Error + Send + Sync>> {
+        let (cert_path, key_path) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            _ => return Ok(None),
+        };
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address {}", addr))?;
+        let certs = client::tls::load_certificates(Path::new(cert_path))?;
+        let pin = client::tls::spki_pin(&certs[0])?;
+        let key = server::tls::load_private_key(Path::new(key_path))?;
+        Ok(Some(TlsListener {
+            addr: SocketAddr::new(addr.ip(), self.tls_port),
+            https_addr: self.https_port.map(|port| SocketAddr::new(addr.ip(), port)),
+            config: server::tls::server_config(certs, key)?,
+            pin,
+        }))
+    }
+}
+
+/// Where to answer queries over TLS and HTTPS, and how.
+struct TlsListener {
+    addr: SocketAddr,
+    https_addr: Option<SocketAddr>,
+    config: Arc<tokio_rustls::rustls::ServerConfig>,
+    /// The SPKI pin of our certificate, for clients to check it against.
+    pin: [u8; 32],
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct TlsQueryArgs {
+    /// Send the query over TLS (RFC 7858). The server's certificate must be
+    /// issued by one of the Mozilla roots, unless --tls-ca or --tls-pin is
+    /// given
+    #[arg(long, group = "encrypted", conflicts_with = "tcp")]
+    tls: bool,
+    /// Send the query over HTTPS (RFC 8484), checking the server's
+    /// certificate in the same way as --tls
+    #[arg(long, group = "encrypted", conflicts_with = "tcp")]
+    https: bool,
+    /// The path to send queries over HTTPS to. Defaults to /dns-query
+    #[arg(long, requires = "https")]
+    https_path: Option<String>,
+    /// Send queries over HTTPS in the URL of a GET request, rather than in
+    /// the body of a POST
+    #[arg(long, requires = "https")]
+    https_get: bool,
+    /// The name the server's certificate must be valid for. Defaults to the
+    /// server's address
+    #[arg(long, requires = "encrypted")]
+    tls_name: Option<String>,
+    /// Trust certificates issued by those in this PEM file, instead of the
+    /// Mozilla roots
+    #[arg(long, requires = "encrypted")]
+    tls_ca: Option<String>,
+    /// Trust the server if its public key has this SPKI pin, given as the
+    /// base64 SHA-256 digest, whoever issued its certificate. May be given
+    /// more than once
+    #[arg(long, requires = "encrypted", conflicts_with_all = ["tls_name", "tls_ca"])]
+    tls_pin: Vec<String>,
+}
+
+impl TlsQueryArgs {
+    /// The settings for queries over TLS, if they're to be sent that way.
+    fn config(
+        &self,
+    ) -> Result<Option<client::tls::TlsConfig>, Box<dyn error::Error + Send + Sync>> {
+        match self.tls {
+            true => self.tls_config().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// The settings for queries over HTTPS, if they're to be sent that way.
+    fn https_config(
+        &self,
+    ) -> Result<Option<client::https::HttpsConfig>, Box<dyn error::Error + Send + Sync>> {
+        if !self.https {
+            return Ok(None);
+        }
+        let path = self
+            .https_path
+            .as_deref()
+            .unwrap_or(client::https::DEFAULT_PATH);
+        let method = match self.https_get {
+            true => client::https::Method::Get,
+            false => client::https::Method::Post,
+        };
+        let tls = self.tls_config()?;
+        Ok(Some(client::https::HttpsConfig::new(tls, path, method)))
+    }
+
+    fn tls_config(&self) -> Result<client::tls::TlsConfig, Box<dyn error::Error + Send + Sync>> {
+        let authentication = if !self.tls_pin.is_empty() {
+            let pins = self
+                .tls_pin
+                .iter()
+                .map(|pin| client::tls::parse_pin(pin))
+                .collect::<Result<_, _>>()?;
+            client::tls::Authentication::Pins(pins)
+        } else {
+            let roots = match &self.tls_ca {
+                Some(path) => client::tls::load_certificates(Path::new(path))?,
+                None => vec![],
+            };
+            client::tls::Authentication::Certificate {
+                name: self.tls_name.clone(),
+                roots,
+            }
+        };
+        client::tls::TlsConfig::new(&authentication)
+    }
+
+    /// The port to send queries to if none is given.
+    fn default_port(&self) -> u16 {
+        match (self.tls, self.https) {
+            (true, _) => client::tls::DEFAULT_PORT,
+            (_, true) => client::https::DEFAULT_PORT,
+            _ => 53,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum CacheCommand {
+    /// Print the entries in a cache dump, with the time they have left
+    #[command(name = "show")]
+    Show {
+        /// Path to the cache dump
+        filepath: String,
+        /// Only show entries for this name and the names underneath it
+        name: Option<String>,
+    },
+    /// Remove entries from a cache dump, so they aren't loaded again. If a
+    /// server is using the dump, they are removed from its cache instead, and
+    /// it saves the dump again
+    #[command(name = "flush")]
+    Flush {
+        /// Path to the cache dump
+        filepath: String,
+        /// Remove entries for this name and the names underneath it
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+        /// Only remove entries of this type
+        #[arg(long = "type")]
+        record_type: Option<String>,
+        /// Remove every entry
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
     },
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn error::Error>> {
+async fn main() -> Result<(), Box<dyn error::Error + Send + Sync>> {
     let args = Args::parse();
     match &args.command {
-        Command::Parse { filepath } => run_parse(filepath).await?,
+        Command::Parse { filepath, tcp } => run_parse(filepath, *tcp).await?,
         Command::Write { filepath } => run_write(filepath).await?,
-        Command::Serve { addr } => run_serve(addr).await?,
+        Command::Serve {
+            addr,
+            zones,
+            upstreams,
+            recursive,
+            cache_file,
+            dnssec,
+            trust_anchor,
+            signing,
+            tsig,
+            allow_transfer,
+            update_policy,
+            replication,
+            tls,
+        } => {
+            let trust_anchors = match (dnssec, trust_anchor) {
+                (_, Some(path)) => dns::zone::parse_master_file(&fs::read_to_string(path)?, None)?,
+                (true, None) => resolver::hints::root_trust_anchors(),
+                (false, None) => vec![],
+            };
+            let tsig_keys = tsig.keys()?;
+            let transfer_key = replication.transfer_key(&tsig_keys)?;
+            let options = ServeOptions {
+                zones: zones.clone(),
+                upstreams: upstreams.clone(),
+                recursive: *recursive,
+                cache_file: cache_file.clone(),
+                trust_anchors,
+                signers: signing.signers()?,
+                notify: server::notify::NotifyConfig {
+                    secondaries: replication.notify_addrs()?,
+                    key: transfer_key.clone(),
+                },
+                secondaries: replication.secondary_zones()?,
+                transfer_key,
+                tls: tls.listener(addr)?,
+                config: server::runtime::ServerConfig {
+                    tsig_keys,
+                    allow_transfer: server::acl::Acl::parse(allow_transfer)?,
+                    update_policy: server::acl::UpdatePolicy::parse(update_policy)?,
+                    ..server::runtime::ServerConfig::default()
+                },
+            };
+            run_serve(addr, options).await?
+        }
+        Command::Sign {
+            zonefile,
+            output,
+            signing,
+        } => run_sign(zonefile, output.as_deref(), signing.signers()?)?,
+        Command::Query {
+            args,
+            port,
+            tcp,
+            norecurse,
+            timeout,
+            tries,
+            dnssec,
+            tsig,
+            tls,
+        } => {
+            let config = client::ClientConfig {
+                timeout: Duration::from_secs(*timeout),
+                attempts: *tries,
+                recursion_desired: !norecurse,
+                tcp: *tcp,
+                tls: tls.config()?,
+                https: tls.https_config()?,
+                dnssec_ok: *dnssec,
+                tsig: tsig.key()?,
+                ..client::ClientConfig::default()
+            };
+            let port = port.unwrap_or_else(|| tls.default_port());
+            run_query(args, port, config).await?
+        }
+        Command::Axfr {
+            args,
+            port,
+            output,
+            timeout,
+            tsig,
+        } => {
+            let timeout = Duration::from_secs(*timeout);
+            run_axfr(args, *port, output.as_deref(), timeout, tsig.key()?).await?
+        }
+        Command::Ixfr {
+            args,
+            port,
+            output,
+            timeout,
+            tsig,
+        } => {
+            let timeout = Duration::from_secs(*timeout);
+            run_ixfr(args, *port, output.as_deref(), timeout, tsig.key()?).await?
+        }
+        Command::Nsupdate {
+            script,
+            timeout,
+            tsig,
+        } => {
+            let timeout = Duration::from_secs(*timeout);
+            run_nsupdate(script.as_deref(), timeout, tsig.key()?).await?
+        }
+        Command::Keygen {
+            zone,
+            algorithm,
+            ksk,
+            directory,
+            publish,
+            activate,
+            inactive,
+            delete,
+        } => {
+            let now = dns::dnssec::signature_time(SystemTime::now());
+            let time = |value: &Option<String>| {
+                value
+                    .as_deref()
+                    .map(|value| parse_key_time(value, now))
+                    .transpose()
+            };
+            let (publish, activate) = (time(publish)?, time(activate)?);
+            let timing = dns::key::KeyTiming {
+                created: Some(now),
+                publish: publish.or(activate).or(Some(now)),
+                activate: activate.or(publish).or(Some(now)),
+                inactive: time(inactive)?,
+                delete: time(delete)?,
+            };
+            run_keygen(zone, *algorithm, *ksk, Path::new(directory), &timing)?
+        }
+        Command::Cache(CacheCommand::Show { filepath, name }) => {
+            run_cache_show(filepath, name.as_deref())?
+        }
+        Command::Cache(CacheCommand::Flush {
+            filepath,
+            name,
+            record_type,
+            all: _,
+        }) => run_cache_flush(filepath, name.as_deref(), record_type.as_deref())?,
     };
     Ok(())
 }
 
-/// Parse a packet stored in a file. This assumes the packet is a UDP packet,
-/// not a TCP. This means it cannot handle DNS packets that are too long.
-async fn run_parse(filepath: &str) -> Result<(), Box<dyn error::Error>> {
+/// Parse a packet stored in a file. By default this assumes the file holds a
+/// single UDP datagram. With `tcp`, the file may hold several packets, each
+/// prefixed with its length as they are when sent over TCP.
+async fn run_parse(filepath: &str, tcp: bool) -> Result<(), Box<dyn error::Error + Send + Sync>> {
     let file = fs::read(filepath)?;
-    let packet = core::parse_dns_packet(&file)?;
-    println!("Got packet: {:#?}", packet);
+    if !tcp {
+        let packet = dns::parse_dns_packet(&file)?;
+        println!("Got packet: {:#?}", packet);
+        return Ok(());
+    }
+    for message in dns::tcp::split_framed_messages(&file)? {
+        let packet = dns::parse_dns_packet(message)?;
+        println!("Got packet: {:#?}", packet);
+    }
     Ok(())
 }
 
-async fn run_write(filepath: &str) -> Result<(), Box<dyn error::Error>> {
-    let packet = core::packet::DnsPacket {
-        header: core::header::Header {
+async fn run_write(filepath: &str) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let packet = dns::packet::DnsPacket {
+        header: dns::header::Header {
             id: 9398,
             query: false,
-            opcode: core::header::Opcode::Query,
+            opcode: dns::header::Opcode::Query,
             authoritative_answer: true,
             truncation: true,
             recursion_desired: false,
             recursion_available: false,
             reserved: 0,
-            rcode: core::header::ResponseCode::Success,
+            rcode: dns::header::ResponseCode::Success,
             questions: 1,
             answers: 1,
             authoritative_entries: 0,
             resource_entries: 0,
         },
-        questions: vec![core::question::Question {
+        questions: vec![dns::question::Question {
             name: "example.com".to_string(),
             typ: 1,
             class: 1,
         }],
-        answers: vec![core::record::Record {
+        answers: vec![dns::record::Record {
             name: "example.com".to_string(),
-            record_type: core::record::RecordType::A,
-            class: core::record::Class::In,
+            record_type: dns::record::RecordType::A,
+            class: dns::record::Class::In,
             ttl: 215,
-            len: 4,
-            data: core::record::Data::Addr([127, 0, 0, 1]),
+            data: dns::record::Data::Addr([127, 0, 0, 1]),
         }],
         authoritative_entries: vec![],
         resource_entries: vec![],
     };
-    let bytes = core::packet::serialize_dns_packet(&packet)?;
+    let bytes = dns::packet::serialize_dns_packet(&packet)?;
     fs::write(filepath, bytes)?;
     Ok(())
 }
 
-/// Run a simple DNS server that prints parsed DNS UDP packets.
-async fn run_serve(addr: &str) -> Result<(), Box<dyn error::Error>> {
+/// Everything `serve` needs besides the address to listen on.
+struct ServeOptions {
+    zones: Vec<String>,
+    upstreams: Vec<String>,
+    recursive: bool,
+    cache_file: Option<String>,
+    trust_anchors: Vec<dns::record::Record>,
+    signers: Vec<server::signer::Signer>,
+    notify: server::notify::NotifyConfig,
+    secondaries: Vec<server::secondary::SecondaryZone>,
+    transfer_key: Option<dns::tsig::TsigKey>,
+    tls: Option<TlsListener>,
+    config: server::runtime::ServerConfig,
+}
+
+/// Run a DNS server that answers authoritatively from zone files. Queries
+/// for other names are refused, unless they can be forwarded upstream or
+/// resolved recursively.
+async fn run_serve(
+    addr: &str,
+    options: ServeOptions,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let ServeOptions {
+        zones,
+        upstreams,
+        recursive,
+        cache_file,
+        trust_anchors,
+        signers,
+        notify,
+        secondaries,
+        transfer_key,
+        tls,
+        config,
+    } = options;
+    let mut catalog = server::authority::Catalog::new();
+    for path in &zones {
+        let text = fs::read_to_string(path)?;
+        let mut zone = dns::zone::parse_zone(&text, None)
+            .map_err(|err| format!("could not load zone {}: {}", path, err))?;
+        println!("Loaded zone {} from {}", zone.origin, path);
+        let signer = signers
+            .iter()
+            .find(|signer| dns::name::eq(signer.origin(), &zone.origin));
+        if let Some(signer) = signer {
+            zone = signer.sign(&zone, SystemTime::now())?;
+            println!(
+                "Signed zone {} with {} keys",
+                zone.origin,
+                signer.keys().len()
+            );
+        }
+        catalog.insert(zone);
+    }
+    for signer in &signers {
+        let served = catalog
+            .zones()
+            .iter()
+            .any(|zone| dns::name::eq(&zone.origin, signer.origin()));
+        if !served {
+            return Err(format!("got keys for {}, which isn't served", signer.origin()).into());
+        }
+    }
+    let catalog = Arc::new(RwLock::new(catalog));
+    let signers = Arc::new(signers);
+
+    let cache;
+    let handler: Box<dyn server::handler::RequestHandler> = if !upstreams.is_empty() {
+        let upstreams = upstreams
+            .iter()
+            .map(|upstream| parse_server_addr(upstream))
+            .collect::<Result<Vec<_>, _>>()?;
+        println!("Forwarding other queries to {:?}", upstreams);
+        let forwarder = resolver::Forwarder::new(resolver::ForwarderConfig {
+            upstreams,
+            ..resolver::ForwarderConfig::default()
+        });
+        cache = Some(forwarder.cache().clone());
+        Box::new(server::handler::LocalZones::new(catalog.clone(), forwarder))
+    } else if recursive {
+        println!("Resolving other queries recursively");
+        if !trust_anchors.is_empty() {
+            println!(
+                "Validating answers against {} trust anchors",
+                trust_anchors.len()
+            );
+        }
+        let resolver = resolver::Resolver::new(resolver::ResolverConfig {
+            trust_anchors,
+            ..resolver::ResolverConfig::default()
+        });
+        cache = Some(resolver.cache().clone());
+        Box::new(server::handler::LocalZones::new(catalog.clone(), resolver))
+    } else {
+        cache = None;
+        Box::new(catalog.clone())
+    };
+    let secondary = Arc::new(server::secondary::Secondary::new(
+        catalog.clone(),
+        secondaries,
+        transfer_key,
+        Arc::new(server::clock::SystemClock),
+    ));
+    for zone in secondary.zones() {
+        println!(
+            "Serving {} as a secondary of {:?}",
+            zone.origin, zone.primaries
+        );
+    }
+    let handler = server::update::AcceptUpdates::new(catalog.clone(), signers.clone(), handler);
+    let handler = server::secondary::AcceptNotify::new(secondary.clone(), handler);
+    let persisted = match (cache_file.as_deref(), cache) {
+        (Some(path), Some(cache)) => Some((Path::new(path), cache)),
+        (Some(_), None) => return Err("--cache-file needs --forward or --recursive".into()),
+        (None, _) => None,
+    };
+    if let Some((path, cache)) = &persisted {
+        if path.exists() {
+            let count = cache.lock().unwrap().load(path)?;
+            println!("Loaded {} cache entries from {}", count, path.display());
+        }
+    }
+
     let sock = UdpSocket::bind(addr).await?;
-    println!("Listening on {}", addr);
+    let listener = TcpListener::bind(addr).await?;
+    println!("Listening on {} (UDP and TCP)", addr);
+    let (tls, https) = match tls {
+        Some(tls) => {
+            let listener = TcpListener::bind(tls.addr).await?;
+            println!(
+                "Listening on {} (TLS), with SPKI pin {}",
+                tls.addr,
+                dns::dnssec::encode_base64(&tls.pin)
+            );
+            let https = match tls.https_addr {
+                Some(addr) => {
+                    let listener = TcpListener::bind(addr).await?;
+                    println!("Listening on https://{}/dns-query", addr);
+                    Some((listener, tls.config.clone()))
+                }
+                None => None,
+            };
+            (Some((listener, tls.config)), https)
+        }
+        None => (None, None),
+    };
+    // Only once the server is sure to start, so failing doesn't leave the
+    // dump locked
+    let _lock = match &persisted {
+        Some((path, _)) => Some(cache::CacheLock::acquire(path)?),
+        None => None,
+    };
+    if !config.tsig_keys.is_empty() {
+        println!(
+            "Accepting requests signed with {} TSIG keys",
+            config.tsig_keys.len()
+        );
+    }
+    if !config.update_policy.grants().is_empty() {
+        println!(
+            "Accepting updates under {} grants",
+            config.update_policy.grants().len()
+        );
+    }
+    let server = server::runtime::Server::new(handler, config);
+    let serving = async {
+        tokio::try_join!(
+            server.serve_udp(sock),
+            server.serve_tcp(listener),
+            serve_tls(&server, tls),
+            serve_https(&server, https),
+            server::signer::keep_signed(catalog.clone(), &signers),
+            secondary.run(),
+            keep_notifying(catalog, notify),
+        )
+        .map(|_| ())
+    };
+    let (path, cache) = match persisted {
+        Some(persisted) => persisted,
+        None => return serving.await,
+    };
+    let result = tokio::select! {
+        result = serving => result,
+        result = save_cache_periodically(path, &cache) => result,
+        result = cache::serve_flush_requests(path, &cache) => result,
+        result = tokio::signal::ctrl_c() => result.map_err(Into::into),
+    };
+    result?;
+    cache.lock().unwrap().save(path)?;
+    println!("Saved cache to {}", path.display());
+    Ok(())
+}
+
+/// Answer queries over TLS, if there is a listener for them.
+async fn serve_tls<H: server::handler::RequestHandler + 'static>(
+    server: &server::runtime::Server<H>,
+    tls: Option<(TcpListener, Arc<tokio_rustls::rustls::ServerConfig>)>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    match tls {
+        Some((listener, config)) => server.serve_tls(listener, config).await,
+        None => Ok(()),
+    }
+}
+
+/// Answer queries over HTTPS, if there is a listener for them.
+async fn serve_https<H: server::handler::RequestHandler + 'static>(
+    server: &server::runtime::Server<H>,
+    https: Option<(TcpListener, Arc<tokio_rustls::rustls::ServerConfig>)>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    match https {
+        Some((listener, config)) => server.serve_https(listener, config).await,
+        None => Ok(()),
+    }
+}
 
-    let mut buf = [0; 512];
+/// Send NOTIFY to secondaries as zones change, if there are any.
+async fn keep_notifying(
+    catalog: Arc<RwLock<server::authority::Catalog>>,
+    config: server::notify::NotifyConfig,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    if config.secondaries.is_empty() {
+        return Ok(());
+    }
+    println!("Notifying {:?} of zone changes", config.secondaries);
+    server::notify::keep_notifying(catalog, config).await
+}
+
+/// Sign a zone file once, writing the signed zone next to it, and print the
+/// DS records for the parent zone.
+fn run_sign(
+    zonefile: &str,
+    output: Option<&str>,
+    signers: Vec<server::signer::Signer>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let zone = dns::zone::parse_zone(&fs::read_to_string(zonefile)?, None)
+        .map_err(|err| format!("could not load zone {}: {}", zonefile, err))?;
+    let signer = match signers.as_slice() {
+        [] => return Err("no keys to sign with; pass them with --key".into()),
+        [signer] => signer,
+        _ => return Err("all keys must belong to the same zone".into()),
+    };
+    let now = SystemTime::now();
+    for key in signer.keys() {
+        let kind = if key.is_key_signing_key() {
+            "KSK"
+        } else {
+            "ZSK"
+        };
+        let state = format!("{:?}", key.state(now)).to_lowercase();
+        println!("{} {} is {}", kind, key.key_tag(), state);
+    }
+    let signed = signer.sign(&zone, now)?;
+    let output = match output {
+        Some(output) => output.to_string(),
+        None => format!("{}.signed", zonefile),
+    };
+    fs::write(&output, dns::zone::format_zone(&signed))?;
+    println!("Wrote signed zone {} to {}", signed.origin, output);
+    for digest_type in [dns::dnssec::SHA256, dns::dnssec::SHA384] {
+        for ds in signer.ds_records(digest_type)? {
+            println!("{}", dns::zone::format_record(&ds));
+        }
+    }
+    Ok(())
+}
+
+/// Generate a key and write its key files.
+fn run_keygen(
+    zone: &str,
+    algorithm: u8,
+    ksk: bool,
+    directory: &Path,
+    timing: &dns::key::KeyTiming,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut flags = dns::dnssec::ZONE_KEY;
+    if ksk {
+        flags |= dns::dnssec::SECURE_ENTRY_POINT;
+    }
+    let files = dns::key::generate_key(zone, algorithm, flags, timing)?;
+    let key = files.load()?;
+    let base = files.save(directory)?;
+    println!("{}", base.display());
+    if ksk {
+        for digest_type in [dns::dnssec::SHA256, dns::dnssec::SHA384] {
+            println!("{}", dns::zone::format_record(&key.ds_record(digest_type)?));
+        }
+    }
+    Ok(())
+}
+
+/// Parse a time for a key's timing metadata: `now`, an offset from now like
+/// `+30d`, or an absolute time in the same format as RRSIG times.
+fn parse_key_time(value: &str, now: u32) -> Result<u32, Box<dyn error::Error + Send + Sync>> {
+    match value.strip_prefix('+') {
+        _ if value == "now" => Ok(now),
+        Some(offset) => Ok(now.wrapping_add(dns::zone::parse_ttl(offset)? as u32)),
+        None => dns::dnssec::parse_time(value),
+    }
+}
+
+/// Save the cache every minute, so not much is lost if we crash.
+async fn save_cache_periodically(
+    path: &Path,
+    cache: &Mutex<resolver::Cache>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    interval.tick().await;
     loop {
-        let (_, addr) = sock.recv_from(&mut buf).await?;
-        let packet = core::parse_dns_packet(&buf)?;
-        println!("--------------------------------------------------");
-        println!("RECEIVED PACKET FROM {}", addr);
-        println!("--------------------------------------------------");
-        println!("{:#?}", packet);
-        let response_packet = core::packet::DnsPacket {
-            header: core::header::Header {
-                id: packet.header.id,
-                query: false,
-                opcode: core::header::Opcode::Query,
-                authoritative_answer: true,
-                truncation: false,
-                recursion_desired: false,
-                recursion_available: false,
-                reserved: packet.header.reserved,
-                rcode: core::header::ResponseCode::Success,
-                questions: 1,
-                answers: 1,
-                authoritative_entries: 0,
-                resource_entries: 0,
-            },
-            questions: packet.questions,
-            answers: vec![core::record::Record {
-                name: "example.com".to_string(),
-                record_type: core::record::RecordType::A,
-                class: core::record::Class::In,
-                ttl: 100,
-                len: 4,
-                data: core::record::Data::Addr([127, 0, 0, 1]),
-            }],
-            authoritative_entries: vec![],
-            resource_entries: vec![],
+        interval.tick().await;
+        cache.lock().unwrap().save(path)?;
+    }
+}
+
+/// Print the entries of a cache dump, grouped by name.
+fn run_cache_show(
+    filepath: &str,
+    name: Option<&str>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut entries = resolver::dump::parse_dump(&fs::read_to_string(filepath)?)?;
+    entries.retain(|entry| name.is_none_or(|name| dns::name::is_subdomain(&entry.key.name, name)));
+    entries.sort_by_key(|entry| {
+        (
+            dns::name::canonical_key(&entry.key.name),
+            dns::record::record_type_code(&entry.key.record_type),
+        )
+    });
+    let now = resolver::dump::unix_time(SystemTime::now());
+    for entry in &entries {
+        let remaining = entry.remaining(now);
+        let state = if remaining > 0 {
+            format!("{}s left", remaining)
+        } else {
+            format!("stale for {}s", -remaining)
         };
-        println!("--------------------------------------------------");
-        println!("RESPONDING WITH PACKET:");
-        println!("--------------------------------------------------");
-        println!("{:#?}", response_packet);
-        let response_packet = core::serialize_dns_packet(&response_packet)?;
-        let _ = sock.send_to(&response_packet, addr).await?;
+        println!(
+            "; {} {} {}, {}",
+            dns::zone::format_name(&entry.key.name),
+            dns::record::record_type_name(&entry.key.record_type),
+            resolver::dump::kind_name(entry.kind),
+            state
+        );
+        for record in &entry.records {
+            let record = dns::record::Record {
+                ttl: remaining.clamp(0, i32::MAX as i64) as i32,
+                ..record.clone()
+            };
+            println!("{}", dns::zone::format_record(&record));
+        }
+    }
+    println!(";; {} entries", entries.len());
+    Ok(())
+}
+
+/// Remove the entries of a cache dump that match a name and type. Either may
+/// be left out to match everything. A server using the dump is asked to
+/// remove them from its cache, as it would write them back otherwise.
+fn run_cache_flush(
+    filepath: &str,
+    name: Option<&str>,
+    record_type: Option<&str>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let record_type = match record_type {
+        Some(typ) => Some(
+            dns::record::parse_record_type_name(typ)
+                .ok_or_else(|| format!("unknown record type {}", typ))?,
+        ),
+        None => None,
+    };
+    if let Some(pid) = cache::owner(Path::new(filepath)) {
+        cache::request_flush(Path::new(filepath), name, record_type)?;
+        println!("Flushed entries from the cache of process {}", pid);
+        return Ok(());
+    }
+    let mut entries = resolver::dump::parse_dump(&fs::read_to_string(filepath)?)?;
+    let before = entries.len();
+    entries.retain(|entry| !entry.key.matches(name, record_type));
+    fs::write(filepath, resolver::dump::format_dump(&entries))?;
+    println!("Flushed {} of {} entries", before - entries.len(), before);
+    Ok(())
+}
+
+/// Parse the address of a DNS server, which defaults to port 53.
+fn parse_server_addr(value: &str) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    value
+        .parse()
+        .map_err(|_| format!("invalid server address {}", value).into())
+}
+
+/// Send a single query and print the response in the same format as dig.
+async fn run_query(
+    args: &[String],
+    port: u16,
+    config: client::ClientConfig,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let rest = positional_args(args);
+    let (name, record_type) = match rest[..] {
+        [name] => (name, dns::record::RecordType::A),
+        [name, typ] => {
+            let record_type = dns::record::parse_record_type_name(typ)
+                .ok_or_else(|| format!("unknown record type {}", typ))?;
+            (name, record_type)
+        }
+        _ => return Err("expected a name and an optional type".into()),
+    };
+    let server = server_arg(args, port)?;
+
+    let client = client::Client::new(config);
+    let start = Instant::now();
+    let response = client.query(server, name, record_type).await?;
+    print!(
+        "{}",
+        format::format_response(&response, server, start.elapsed())
+    );
+    Ok(())
+}
+
+/// Transfer a zone and write it out as a zone file.
+async fn run_axfr(
+    args: &[String],
+    port: u16,
+    output: Option<&str>,
+    timeout: Duration,
+    key: Option<dns::tsig::TsigKey>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let rest = positional_args(args);
+    let zone = match rest[..] {
+        [zone] => zone,
+        _ => return Err("expected the name of a zone".into()),
+    };
+    let server = server_arg(args, port)?;
+
+    let start = Instant::now();
+    let zone = client::transfer::axfr(server, zone, key.as_ref(), timeout).await?;
+    let text = dns::zone::format_zone(&zone);
+    match output {
+        Some(path) => {
+            fs::write(path, text)?;
+            println!(
+                "Transferred {} records of {} from {} in {} msec",
+                zone.records().count(),
+                zone.origin,
+                server,
+                start.elapsed().as_millis()
+            );
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Bring a zone file up to date with IXFR, writing it back in place unless
+/// told otherwise.
+async fn run_ixfr(
+    args: &[String],
+    port: u16,
+    output: Option<&str>,
+    timeout: Duration,
+    key: Option<dns::tsig::TsigKey>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let rest = positional_args(args);
+    let zonefile = match rest[..] {
+        [zonefile] => zonefile,
+        _ => return Err("expected the path to a zone file".into()),
+    };
+    let server = server_arg(args, port)?;
+
+    let zone = dns::zone::parse_zone(&fs::read_to_string(zonefile)?, None)
+        .map_err(|err| format!("could not load zone {}: {}", zonefile, err))?;
+    let updated = client::transfer::ixfr(server, &zone, key.as_ref(), timeout).await?;
+    let (old, new) = (zone.serial().unwrap_or(0), updated.serial().unwrap_or(0));
+    if updated == zone {
+        println!("{} is up to date at serial {}", zone.origin, old);
+        return Ok(());
+    }
+    let path = output.unwrap_or(zonefile);
+    fs::write(path, dns::zone::format_zone(&updated))?;
+    println!(
+        "Updated {} from serial {} to {}, written to {}",
+        zone.origin, old, new, path
+    );
+    Ok(())
+}
+
+/// Read an update script, sending each update as it is finished. Without
+/// a `server` command, updates go to the system's nameserver.
+async fn run_nsupdate(
+    path: Option<&str>,
+    timeout: Duration,
+    key: Option<dns::tsig::TsigKey>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let text = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => io::read_to_string(io::stdin())?,
+    };
+    let mut script = nsupdate::Script::new(key);
+    for (index, line) in text.lines().enumerate() {
+        let action = script
+            .read_line(line)
+            .map_err(|err| format!("line {}: {}", index + 1, err))?;
+        match action {
+            nsupdate::Action::Continue => {}
+            nsupdate::Action::Show => {
+                let request = script.update()?.to_packet(0);
+                print!(
+                    "Outgoing update query:\n{}\n",
+                    format::format_message(&request)
+                );
+            }
+            nsupdate::Action::Send => send_update(&mut script, timeout).await?,
+            nsupdate::Action::Quit => return Ok(()),
+        }
+    }
+    if !script.is_empty() {
+        send_update(&mut script, timeout).await?;
+    }
+    Ok(())
+}
+
+/// Send the update a script has built up so far, and start a new one.
+async fn send_update(
+    script: &mut nsupdate::Script,
+    timeout: Duration,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let update = script.update()?;
+    let server = match script.server {
+        Some(server) => server,
+        None => SocketAddr::new(system_nameserver()?, 53),
+    };
+    let rcode = client::update::send(server, &update, script.key.as_ref(), timeout).await?;
+    script.clear();
+    if rcode != dns::header::ResponseCode::Success {
+        let name = dns::header::response_code_name(&rcode);
+        return Err(format!("update to {} failed: {}", update.zone, name).into());
+    }
+    println!(
+        "Updated {} at {} with {} changes",
+        update.zone,
+        server,
+        update.changes.len()
+    );
+    Ok(())
+}
+
+/// The server named by an `@server` argument, or the system's nameserver if
+/// there isn't one.
+fn server_arg(
+    args: &[String],
+    port: u16,
+) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+    let ip = match args.iter().rev().find_map(|arg| arg.strip_prefix('@')) {
+        Some(addr) => addr.parse::<IpAddr>()?,
+        None => system_nameserver()?,
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// The arguments other than `@server`.
+fn positional_args(args: &[String]) -> Vec<&str> {
+    args.iter()
+        .filter(|arg| !arg.starts_with('@'))
+        .map(|arg| arg.as_str())
+        .collect()
+}
+
+/// The first nameserver listed in /etc/resolv.conf.
+fn system_nameserver() -> Result<IpAddr, Box<dyn error::Error + Send + Sync>> {
+    let text = fs::read_to_string("/etc/resolv.conf")?;
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next() == Some("nameserver") {
+            if let Some(Ok(addr)) = fields.next().map(|addr| addr.parse()) {
+                return Ok(addr);
+            }
+        }
     }
+    Err("no nameserver found in /etc/resolv.conf, use @server".into())
 }
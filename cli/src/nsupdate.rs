@@ -0,0 +1,329 @@
+//! Scripts of changes to send as UPDATE messages, in the style of BIND's
+//! `nsupdate`.
+
+use dns::name;
+use dns::record::{self, Record, RecordType};
+use dns::tsig::TsigKey;
+use dns::update::Update;
+use dns::zone;
+use std::error;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+
+/// What to do once a line of a script has been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Keep reading.
+    Continue,
+    /// Print the update that would be sent.
+    Show,
+    /// Send the update, and start a new one.
+    Send,
+    /// Stop without sending anything else.
+    Quit,
+}
+
+/// The state built up by reading a script: where to send the next update,
+/// how to sign it, and what is in it. Names are always fully qualified,
+/// whether or not they end in a dot.
+pub struct Script {
+    pub server: Option<SocketAddr>,
+    pub key: Option<TsigKey>,
+    zone: Option<String>,
+    ttl: Option<i32>,
+    update: Update,
+}
+
+impl Script {
+    pub fn new(key: Option<TsigKey>) -> Script {
+        Script {
+            server: None,
+            key,
+            zone: None,
+            ttl: None,
+            update: Update::new("."),
+        }
+    }
+
+    /// The update to send, for the zone given by the `zone` command.
+    pub fn update(&self) -> Result<Update, Box<dyn error::Error + Send + Sync>> {
+        let zone = self
+            .zone
+            .as_ref()
+            .ok_or("no zone given, use `zone <name>`")?;
+        Ok(Update {
+            zone: zone.clone(),
+            ..self.update.clone()
+        })
+    }
+
+    /// Whether there is nothing to send yet.
+    pub fn is_empty(&self) -> bool {
+        self.update.prerequisites.is_empty() && self.update.changes.is_empty()
+    }
+
+    /// Start a new update once the last one has been sent.
+    pub fn clear(&mut self) {
+        self.update = Update::new(".");
+    }
+
+    /// Read one line of a script. A blank line sends the update, like
+    /// `send`, and lines starting with `;` are comments.
+    pub fn read_line(&mut self, line: &str) -> Result<Action, Box<dyn error::Error + Send + Sync>> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(match self.is_empty() {
+                true => Action::Continue,
+                false => Action::Send,
+            });
+        }
+        if line.starts_with(';') {
+            return Ok(Action::Continue);
+        }
+        let (command, rest) = split_word(line);
+        match command.to_ascii_lowercase().as_str() {
+            "server" => {
+                let (addr, port) = split_word(rest);
+                let ip = addr
+                    .parse::<IpAddr>()
+                    .map_err(|_| format!("invalid server address {}", addr))?;
+                let port = match port {
+                    "" => 53,
+                    port => port.parse()?,
+                };
+                self.server = Some(SocketAddr::new(ip, port));
+            }
+            "zone" => match split_word(rest) {
+                ("", _) => return Err("zone requires a name".into()),
+                (zone, "") => self.zone = Some(name::normalize(zone)),
+                _ => return Err("zone takes a single name".into()),
+            },
+            "key" => match split_word(rest) {
+                (name, secret) if !name.is_empty() && !secret.is_empty() => {
+                    self.key = Some(TsigKey::parse(&format!("{}:{}", name, secret))?)
+                }
+                _ => return Err("key requires [algorithm:]name and a secret".into()),
+            },
+            "ttl" => self.ttl = Some(zone::parse_ttl(rest)?),
+            "prereq" => self.prerequisite(rest)?,
+            "update" => self.change(rest)?,
+            "add" | "del" | "delete" => self.change(line)?,
+            "show" => return Ok(Action::Show),
+            "send" => return Ok(Action::Send),
+            "quit" => return Ok(Action::Quit),
+            other => return Err(format!("unknown command {}", other).into()),
+        }
+        Ok(Action::Continue)
+    }
+
+    /// Read `nxdomain|yxdomain <name>`, `nxrrset <name> [class] <type>` or
+    /// `yxrrset <name> [class] <type> [data]`.
+    fn prerequisite(&mut self, text: &str) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (kind, rest) = split_word(text);
+        let (name, rest) = owner(rest)?;
+        let (record_type, data) = record_type(rest)?;
+        match (kind.to_ascii_lowercase().as_str(), record_type, data) {
+            ("nxdomain", None, _) => self.build(|update| update.name_not_in_use(name)),
+            ("yxdomain", None, _) => self.build(|update| update.name_in_use(name)),
+            ("nxrrset", Some(record_type), "") => {
+                self.build(|update| update.rrset_does_not_exist(name, record_type))
+            }
+            ("yxrrset", Some(record_type), "") => {
+                self.build(|update| update.rrset_exists(name, record_type))
+            }
+            ("yxrrset", Some(record_type), data) => {
+                let record = parse_record(name, Some(0), record_type, data)?;
+                self.build(|update| update.rrset_equals(record))
+            }
+            ("nxdomain" | "yxdomain", _, _) => {
+                return Err(format!("prereq {} only takes a name", kind).into())
+            }
+            ("nxrrset" | "yxrrset", None, _) => {
+                return Err(format!("prereq {} requires a type", kind).into())
+            }
+            ("nxrrset", _, _) => return Err("prereq nxrrset doesn't take data".into()),
+            _ => return Err(format!("unknown prerequisite {}", kind).into()),
+        }
+        Ok(())
+    }
+
+    /// Read `add <name> [ttl] [class] <type> <data>` or
+    /// `delete <name> [class] [<type> [data]]`.
+    fn change(&mut self, text: &str) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (operation, rest) = split_word(text);
+        match operation.to_ascii_lowercase().as_str() {
+            "add" => {
+                let (name, rest) = owner(rest)?;
+                let (ttl, rest) = match split_word(rest) {
+                    (ttl, rest) if ttl.starts_with(|c: char| c.is_ascii_digit()) => {
+                        (Some(zone::parse_ttl(ttl)?), rest)
+                    }
+                    _ => (self.ttl, rest),
+                };
+                let (record_type, data) = record_type(rest)?;
+                let record_type = record_type.ok_or("add requires a type")?;
+                let record = parse_record(name, ttl, record_type, data)?;
+                self.build(|update| update.add_record(record));
+            }
+            "del" | "delete" => {
+                let (name, rest) = owner(rest)?;
+                match record_type(rest)? {
+                    (None, _) => self.build(|update| update.delete_name(name)),
+                    (Some(record_type), "") => {
+                        self.build(|update| update.delete_rrset(name, record_type))
+                    }
+                    (Some(record_type), data) => {
+                        let record = parse_record(name, Some(0), record_type, data)?;
+                        self.build(|update| update.delete_record(record));
+                    }
+                }
+            }
+            other => return Err(format!("unknown update {}, expected add or delete", other).into()),
+        }
+        Ok(())
+    }
+
+    fn build(&mut self, f: impl FnOnce(Update) -> Update) {
+        let update = mem::replace(&mut self.update, Update::new("."));
+        self.update = f(update);
+    }
+}
+
+/// Split the first word off some text.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Split off the name a line is about.
+fn owner(text: &str) -> Result<(&str, &str), Box<dyn error::Error + Send + Sync>> {
+    match split_word(text) {
+        ("", _) => Err("expected a name".into()),
+        (name, rest) => Ok((name, rest)),
+    }
+}
+
+/// Split off an optional class and type, leaving the record's data. Only
+/// class IN is supported.
+fn record_type(
+    text: &str,
+) -> Result<(Option<RecordType>, &str), Box<dyn error::Error + Send + Sync>> {
+    let (word, rest) = match split_word(text) {
+        (class, rest) if class.eq_ignore_ascii_case("IN") => split_word(rest),
+        (word, rest) => (word, rest),
+    };
+    if word.is_empty() {
+        return Ok((None, rest));
+    }
+    let record_type = record::parse_record_type_name(word)
+        .ok_or_else(|| format!("unknown record type {}", word))?;
+    Ok((Some(record_type), rest))
+}
+
+fn parse_record(
+    name: &str,
+    ttl: Option<i32>,
+    record_type: RecordType,
+    data: &str,
+) -> Result<Record, Box<dyn error::Error + Send + Sync>> {
+    let ttl = ttl.ok_or("no TTL given, add one or use `ttl <seconds>`")?;
+    let line = format!(
+        "{} {} IN {} {}",
+        name,
+        ttl,
+        record::record_type_name(&record_type),
+        data
+    );
+    let mut records = zone::parse_master_file(&line, Some("."))?;
+    match records.len() {
+        1 => Ok(records.remove(0)),
+        _ => Err(format!("expected a single record, got {}", line).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::record::Data;
+    use dns::update::{Change, Prerequisite};
+
+    fn read(text: &str) -> Result<(Script, Vec<Action>), Box<dyn error::Error + Send + Sync>> {
+        let mut script = Script::new(None);
+        let mut actions = vec![];
+        for line in text.lines() {
+            actions.push(script.read_line(line)?);
+        }
+        Ok((script, actions))
+    }
+
+    #[test]
+    fn test_script_builds_update() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (script, actions) = read(
+            "; move the host to a new address
+server 127.0.0.1 3000
+zone example.com
+key dhcp c2VjcmV0
+ttl 300
+prereq yxrrset pc1.example.com IN A 192.0.2.10
+prereq nxdomain pc2.example.com
+update delete pc1.example.com A
+update add pc1.example.com IN A 192.0.2.11
+add pc1.example.com. 60 TXT \"two  spaces\"
+del old.example.com
+show",
+        )?;
+        assert_eq!(actions.last(), Some(&Action::Show));
+        assert_eq!(script.server, Some("127.0.0.1:3000".parse()?));
+        assert_eq!(
+            script.key.as_ref().map(|key| key.name.as_str()),
+            Some("dhcp")
+        );
+
+        let update = script.update()?;
+        assert_eq!(update.zone, "example.com");
+        assert_eq!(
+            update.prerequisites[1],
+            Prerequisite::NameNotInUse("pc2.example.com".to_string())
+        );
+        match &update.changes[..] {
+            [Change::DeleteRrset { name, .. }, Change::Add(a), Change::Add(txt), Change::DeleteName(old)] =>
+            {
+                assert_eq!(name, "pc1.example.com");
+                assert_eq!(a.ttl, 300);
+                assert_eq!(a.data, Data::Addr([192, 0, 2, 11]));
+                assert_eq!(txt.ttl, 60);
+                assert_eq!(txt.data, Data::Txt(vec![b"two  spaces".to_vec()]));
+                assert_eq!(old, "old.example.com");
+            }
+            changes => panic!("unexpected changes {:?}", changes),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_sends_on_blank_lines() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (_, actions) = read("\nzone example.com\nadd www.example.com 60 A 192.0.2.1\n\nsend")?;
+        assert_eq!(
+            actions,
+            vec![
+                Action::Continue,
+                Action::Continue,
+                Action::Continue,
+                Action::Send,
+                Action::Send
+            ]
+        );
+        assert!(read("add www.example.com A 192.0.2.1").is_err());
+        assert!(read("prereq nxrrset www.example.com").is_err());
+        assert!(read("prereq maybe www.example.com").is_err());
+        assert!(read("update www.example.com").is_err());
+        assert!(read("add www.example.com 60 A 192.0.2.1")?
+            .0
+            .update()
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use super::{tcp, udp};
+use dns::packet::DnsPacket;
+use dns::tsig::TsigKey;
+use std::error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Send a request over UDP, retrying over TCP if the response comes back
+/// truncated. Each attempt gets the full timeout.
+pub async fn exchange(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    exchange_signed(addr, request, None, timeout).await
+}
+
+/// Like `exchange`, but signs each request with a TSIG key if one is given.
+pub async fn exchange_signed(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    let response = udp::query_signed(addr, request, key, timeout).await?;
+    if !response.header.truncation {
+        return Ok(response);
+    }
+    tcp::query_signed(addr, request, key, timeout).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+
+    /// Start a server on the same port over UDP and TCP. UDP responses have
+    /// the TC bit set if `truncate` is true, while TCP responses carry an
+    /// extra answer so the two can be told apart.
+    async fn start(truncate: bool) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let sock = UdpSocket::bind(addr).await?;
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, peer) = sock.recv_from(&mut buf).await.unwrap();
+            let mut response = dns::parse_dns_packet(&buf[..len]).unwrap();
+            response.header.query = false;
+            response.header.truncation = truncate;
+            let bytes = dns::serialize_dns_packet(&response).unwrap();
+            sock.send_to(&bytes, peer).await.unwrap();
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap();
+            let mut request = vec![0; len as usize];
+            stream.read_exact(&mut request).await.unwrap();
+            let mut response = dns::parse_dns_packet(&request).unwrap();
+            response.header.query = false;
+            response.answers =
+                dns::zone::parse_master_file("google.com. 60 A 1.2.3.4", None).unwrap();
+            let bytes = dns::serialize_dns_packet(&response).unwrap();
+            let bytes = dns::tcp::frame_message(&bytes).unwrap();
+            stream.write_all(&bytes).await.unwrap();
+        });
+        Ok(addr)
+    }
+
+    fn request() -> DnsPacket {
+        dns::parse_dns_packet(include_bytes!("../../examples/query_packet")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exchange_returns_complete_udp_response(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let addr = start(false).await?;
+        let response = exchange(addr, &request(), Duration::from_secs(1)).await?;
+        assert!(!response.header.truncation);
+        assert!(response.answers.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exchange_retries_truncated_responses_over_tcp(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let addr = start(true).await?;
+        let response = exchange(addr, &request(), Duration::from_secs(1)).await?;
+        assert!(!response.header.truncation);
+        assert_eq!(response.answers.len(), 1);
+        Ok(())
+    }
+}
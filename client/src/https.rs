@@ -0,0 +1,139 @@
+//! DNS over HTTPS (RFC 8484), which sends each message in its own HTTP
+//! request, over HTTP/2 if the server supports it.
+
+use super::tls::TlsConfig;
+use super::validate;
+use anyhow::anyhow;
+use data_encoding::BASE64URL_NOPAD;
+use dns::packet::DnsPacket;
+use dns::tsig::TsigKey;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{ACCEPT, CONTENT_TYPE, HOST};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The port DNS over HTTPS servers listen on.
+pub const DEFAULT_PORT: u16 = 443;
+
+/// The path servers usually answer queries at.
+pub const DEFAULT_PATH: &str = "/dns-query";
+
+/// The media type of DNS messages in requests and responses.
+pub const MEDIA_TYPE: &str = "application/dns-message";
+
+/// How a message is put into an HTTP request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// In the `dns` parameter of the URL, encoded with base64url. Responses
+    /// to these are easier for HTTP caches to reuse.
+    Get,
+    /// As the body of the request.
+    Post,
+}
+
+/// The settings for sending queries to a server over HTTPS.
+#[derive(Debug, Clone)]
+pub struct HttpsConfig {
+    tls: TlsConfig,
+    path: String,
+    method: Method,
+}
+
+impl HttpsConfig {
+    /// Send queries to `path`, in TLS sessions with the given settings.
+    pub fn new(tls: TlsConfig, path: &str, method: Method) -> HttpsConfig {
+        HttpsConfig {
+            tls: tls.with_alpn(&[b"h2", b"http/1.1"]),
+            path: path.to_string(),
+            method,
+        }
+    }
+}
+
+/// Send a request over a new HTTPS connection and wait for the response,
+/// signing the request with a TSIG key if one is given. As RFC 8484 §4.1
+/// suggests, the request is sent with an ID of 0 so that the responses can
+/// be cached, and the response is given the original ID.
+pub async fn query_signed(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+    config: &HttpsConfig,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    let mut message = request.clone();
+    message.header.id = 0;
+    let exchange = async {
+        let (bytes, mut session) = validate::sign_request(&message, key)?;
+        let bytes = post_or_get(addr, &bytes, config).await?;
+        validate::check_signature(&mut session, &bytes)?;
+        dns::parse_dns_packet(&bytes)
+    };
+    let mut response = match tokio::time::timeout(timeout, exchange).await {
+        Ok(result) => result?,
+        Err(_) => return Err(anyhow!("timed out waiting for a response from {}", addr).into()),
+    };
+    if !validate::is_response_to(&message, &response) {
+        return Err(anyhow!("{} answered with a response to another request", addr).into());
+    }
+    response.header.id = request.header.id;
+    Ok(response)
+}
+
+/// Send a message in an HTTP request, and return the message in the
+/// response.
+async fn post_or_get(
+    addr: SocketAddr,
+    message: &[u8],
+    config: &HttpsConfig,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let stream = config.tls.handshake(addr).await?;
+    let http2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+    let host = match addr.port() {
+        DEFAULT_PORT => config.tls.host(addr),
+        port => format!("{}:{}", config.tls.host(addr), port),
+    };
+    let path = match config.method {
+        Method::Get => format!("{}?dns={}", config.path, BASE64URL_NOPAD.encode(message)),
+        Method::Post => config.path.clone(),
+    };
+    // HTTP/2 takes the host from the URL, and HTTP/1.1 from a header
+    let request = match http2 {
+        true => Request::builder().uri(format!("https://{}{}", host, path)),
+        false => Request::builder().uri(path).header(HOST, host),
+    }
+    .header(ACCEPT, MEDIA_TYPE);
+    let request = match config.method {
+        Method::Get => request.method("GET").body(Full::default())?,
+        Method::Post => request
+            .method("POST")
+            .header(CONTENT_TYPE, MEDIA_TYPE)
+            .body(Full::new(Bytes::copy_from_slice(message)))?,
+    };
+
+    let io = TokioIo::new(stream);
+    let response: Response<Incoming> = if http2 {
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), io).await?;
+        tokio::spawn(connection);
+        sender.send_request(request).await?
+    } else {
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(io).await?;
+        tokio::spawn(connection);
+        sender.send_request(request).await?
+    };
+    if response.status() != StatusCode::OK {
+        return Err(anyhow!("{} answered with HTTP {}", addr, response.status()).into());
+    }
+    let content_type = response.headers().get(CONTENT_TYPE);
+    if content_type.map(|value| value.as_bytes()) != Some(MEDIA_TYPE.as_bytes()) {
+        return Err(anyhow!("{} answered with content type {:?}", addr, content_type).into());
+    }
+    // DNS messages can't be any larger, so don't let the server send more
+    let body = Limited::new(response.into_body(), u16::MAX as usize).collect();
+    Ok(body.await?.to_bytes().to_vec())
+}
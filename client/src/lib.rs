@@ -0,0 +1,14 @@
+//! This package provides methods to send DNS queries to a server.
+
+mod exchange;
+pub mod https;
+mod stub;
+pub mod tcp;
+pub mod tls;
+pub mod transfer;
+pub mod udp;
+pub mod update;
+pub mod validate;
+
+pub use exchange::{exchange, exchange_signed};
+pub use stub::{Client, ClientConfig};
@@ -0,0 +1,211 @@
+use super::https::{self, HttpsConfig};
+use super::tls::{self, TlsConfig};
+use super::{exchange_signed, tcp};
+use anyhow::anyhow;
+use dns::edns::{self, Edns};
+use dns::packet::DnsPacket;
+use dns::record::RecordType;
+use dns::tsig::TsigKey;
+use std::error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// How long to wait for a response to each attempt.
+    pub timeout: Duration,
+    /// How many times to send a query before giving up.
+    pub attempts: usize,
+    /// Whether to ask the server to resolve the query recursively.
+    pub recursion_desired: bool,
+    /// The EDNS payload size to advertise, or `None` to not use EDNS.
+    pub udp_payload_size: Option<u16>,
+    /// Always use TCP, rather than trying UDP first.
+    pub tcp: bool,
+    /// Send queries over TLS with these settings, instead of UDP or TCP.
+    pub tls: Option<TlsConfig>,
+    /// Send queries over HTTPS with these settings. Takes precedence over
+    /// `tls`.
+    pub https: Option<HttpsConfig>,
+    /// Whether to set the DO bit, asking for DNSSEC records in responses.
+    /// Only has an effect when EDNS is used.
+    pub dnssec_ok: bool,
+    /// A key to sign requests with. Responses must be signed with it too.
+    pub tsig: Option<TsigKey>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            timeout: Duration::from_secs(2),
+            attempts: 3,
+            recursion_desired: true,
+            udp_payload_size: Some(edns::DEFAULT_UDP_PAYLOAD_SIZE),
+            tcp: false,
+            tls: None,
+            https: None,
+            dnssec_ok: false,
+            tsig: None,
+        }
+    }
+}
+
+/// A stub resolver, which sends queries to a server and leaves the work of
+/// resolving them to it.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    config: ClientConfig,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Client {
+        Client { config }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Ask a server for the records of a given name and type.
+    pub async fn query(
+        &self,
+        server: SocketAddr,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        let request = self.build_query(name, &record_type);
+        self.send(server, &request).await
+    }
+
+    /// Create a query with a random ID, using this client's settings.
+    pub fn build_query(&self, name: &str, record_type: &RecordType) -> DnsPacket {
+        let mut request = dns::packet::build_query(rand::random(), name, record_type);
+        request.header.recursion_desired = self.config.recursion_desired;
+        if let Some(udp_payload_size) = self.config.udp_payload_size {
+            let edns = Edns {
+                udp_payload_size,
+                dnssec_ok: self.config.dnssec_ok,
+                ..Edns::default()
+            };
+            edns::set_edns(&mut request, &edns);
+        }
+        request
+    }
+
+    /// Send a request to a server, retrying if it doesn't answer in time.
+    pub async fn send(
+        &self,
+        server: SocketAddr,
+        request: &DnsPacket,
+    ) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        let mut last_err = anyhow!("no attempts were made to query {}", server).into();
+        for _ in 0..self.config.attempts {
+            let key = self.config.tsig.as_ref();
+            let timeout = self.config.timeout;
+            let result = match (&self.config.https, &self.config.tls) {
+                (Some(https), _) => https::query_signed(server, request, key, https, timeout).await,
+                (None, Some(tls)) => tls::query_signed(server, request, key, tls, timeout).await,
+                _ if self.config.tcp => tcp::query_signed(server, request, key, timeout).await,
+                _ => exchange_signed(server, request, key, timeout).await,
+            };
+            match result {
+                Ok(response) => return Ok(response),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+
+    /// Start a UDP server that ignores the first `drop` requests it receives
+    /// and answers the rest with a single A record. Returns the server's
+    /// address and a count of requests received.
+    async fn start(
+        drop: usize,
+    ) -> Result<(SocketAddr, Arc<AtomicUsize>), Box<dyn error::Error + Send + Sync>> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, peer) = sock.recv_from(&mut buf).await.unwrap();
+                if counter.fetch_add(1, Ordering::SeqCst) < drop {
+                    continue;
+                }
+                let mut response = dns::parse_dns_packet(&buf[..len]).unwrap();
+                response.header.query = false;
+                response.answers =
+                    dns::zone::parse_master_file("example.com. 60 A 1.2.3.4", None).unwrap();
+                let bytes = dns::serialize_dns_packet(&response).unwrap();
+                sock.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        Ok((addr, received))
+    }
+
+    fn client(attempts: usize) -> Client {
+        Client::new(ClientConfig {
+            timeout: Duration::from_millis(100),
+            attempts,
+            ..ClientConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_build_query_uses_random_ids_and_edns() {
+        let client = Client::default();
+        let ids: Vec<u16> = (0..8)
+            .map(|_| client.build_query("example.com", &RecordType::A).header.id)
+            .collect();
+        assert!(ids.iter().any(|id| *id != ids[0]));
+
+        let request = client.build_query("example.com", &RecordType::Aaaa);
+        assert!(request.header.recursion_desired);
+        assert_eq!(request.questions[0].typ, 28);
+        assert_eq!(edns::max_udp_payload_size(&request), 1232);
+        assert!(!edns::parse_edns(&request).unwrap().dnssec_ok);
+
+        let client = Client::new(ClientConfig {
+            dnssec_ok: true,
+            ..ClientConfig::default()
+        });
+        let request = client.build_query("example.com", &RecordType::A);
+        assert!(edns::parse_edns(&request).unwrap().dnssec_ok);
+    }
+
+    #[tokio::test]
+    async fn test_query_returns_answers() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, _) = start(0).await?;
+        let response = client(1).query(addr, "example.com", RecordType::A).await?;
+        assert_eq!(response.answers.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_retries_after_timeout() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, received) = start(2).await?;
+        let response = client(3).query(addr, "example.com", RecordType::A).await?;
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_gives_up_after_all_attempts(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, received) = start(usize::MAX).await?;
+        let result = client(2).query(addr, "example.com", RecordType::A).await;
+        assert!(result.is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use super::validate;
+use anyhow::anyhow;
+use dns::packet::DnsPacket;
+use dns::tsig::TsigKey;
+use std::error;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A TCP connection to a DNS server. Several requests can be sent before
+/// reading any responses, but the server may answer them in any order. The
+/// same framing is used inside other streams, such as TLS sessions.
+#[derive(Debug)]
+pub struct TcpConnection<S = TcpStream> {
+    stream: S,
+}
+
+impl TcpConnection {
+    pub async fn connect(
+        addr: SocketAddr,
+    ) -> Result<TcpConnection, Box<dyn error::Error + Send + Sync>> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(TcpConnection { stream })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TcpConnection<S> {
+    /// Send and receive framed messages over an established stream.
+    pub fn new(stream: S) -> TcpConnection<S> {
+        TcpConnection { stream }
+    }
+
+    pub async fn send(
+        &mut self,
+        request: &DnsPacket,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = dns::serialize_dns_packet(request)?;
+        self.send_bytes(&bytes).await
+    }
+
+    pub async fn send_bytes(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = dns::tcp::frame_message(bytes)?;
+        self.stream.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    pub async fn receive(&mut self) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        let bytes = self.receive_bytes().await?;
+        dns::parse_dns_packet(&bytes)
+    }
+
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        let len = self.stream.read_u16().await?;
+        let mut bytes = vec![0; len as usize];
+        self.stream.read_exact(&mut bytes).await?;
+        Ok(bytes)
+    }
+}
+
+/// Send a request over a new TCP connection and wait for a response that
+/// matches it.
+pub async fn query(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    query_signed(addr, request, None, timeout).await
+}
+
+/// Like `query`, but signs the request with a TSIG key if one is given. The
+/// response must then be signed with the same key.
+pub async fn query_signed(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    let exchange = async {
+        let mut connection = TcpConnection::connect(addr).await?;
+        exchange_over(&mut connection, request, key).await
+    };
+    match tokio::time::timeout(timeout, exchange).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("timed out waiting for a response from {}", addr).into()),
+    }
+}
+
+/// Send a request over a connection, signed with a TSIG key if one is
+/// given, and wait for the response that matches it.
+pub(crate) async fn exchange_over<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut TcpConnection<S>,
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    let (bytes, mut session) = validate::sign_request(request, key)?;
+    connection.send_bytes(&bytes).await?;
+    loop {
+        let bytes = connection.receive_bytes().await?;
+        let response = dns::parse_dns_packet(&bytes)?;
+        if validate::is_response_to(request, &response) {
+            validate::check_signature(&mut session, &bytes)?;
+            return Ok(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Start a server that answers each request on a connection by echoing it
+    /// back as a response. Requests are answered in reverse order once
+    /// `batch` of them have arrived.
+    async fn start(batch: usize) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut requests = vec![];
+            while requests.len() < batch {
+                let len = stream.read_u16().await.unwrap();
+                let mut request = vec![0; len as usize];
+                stream.read_exact(&mut request).await.unwrap();
+                requests.push(request);
+            }
+            for mut response in requests.into_iter().rev() {
+                response[2] |= 0b1000_0000;
+                let response = dns::tcp::frame_message(&response).unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        Ok(addr)
+    }
+
+    fn request(id: u16) -> DnsPacket {
+        let mut request =
+            dns::parse_dns_packet(include_bytes!("../../examples/query_packet")).unwrap();
+        request.header.id = id;
+        request
+    }
+
+    #[tokio::test]
+    async fn test_query_returns_response() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let addr = start(1).await?;
+        let response = query(addr, &request(42), Duration::from_secs(1)).await?;
+        assert_eq!(response.header.id, 42);
+        assert!(!response.header.query);
+        assert_eq!(response.questions[0].name, "google.com");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_times_out() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // Never answers, as it waits for a second request
+        let addr = start(2).await?;
+        let result = query(addr, &request(42), Duration::from_millis(50)).await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connection_pipelines_requests() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let addr = start(2).await?;
+        let mut connection = TcpConnection::connect(addr).await?;
+        connection.send(&request(1)).await?;
+        connection.send(&request(2)).await?;
+        assert_eq!(connection.receive().await?.header.id, 2);
+        assert_eq!(connection.receive().await?.header.id, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+//! DNS over TLS (RFC 7858), which sends the same length-prefixed messages as
+//! TCP inside a TLS session.
+
+use super::tcp::{self, TcpConnection};
+use anyhow::anyhow;
+use dns::packet::DnsPacket;
+use dns::tsig::TsigKey;
+use ring::digest;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{error, fs, io};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{self, WebPkiSupportedAlgorithms};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::ParsedCertificate;
+use tokio_rustls::rustls::{self, CertificateError, DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::TlsConnector;
+
+/// The port DNS over TLS servers listen on.
+pub const DEFAULT_PORT: u16 = 853;
+
+/// A connection to a DNS over TLS server.
+pub type TlsConnection = TcpConnection<TlsStream<TcpStream>>;
+
+/// How to decide whether to trust a server's certificate.
+#[derive(Debug, Clone)]
+pub enum Authentication {
+    /// The certificate must be valid for `name`, or for the server's address
+    /// if no name is given, and issued by one of `roots`, or by one of the
+    /// Mozilla roots if none are given.
+    Certificate {
+        name: Option<String>,
+        roots: Vec<CertificateDer<'static>>,
+    },
+    /// The certificate's public key must match one of these SPKI pins
+    /// (RFC 7858 §4.2), whoever issued it.
+    Pins(Vec<[u8; 32]>),
+}
+
+/// The settings for TLS sessions with a server.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    config: Arc<rustls::ClientConfig>,
+    /// The name to ask for, or `None` to use the server's address.
+    name: Option<ServerName<'static>>,
+}
+
+impl TlsConfig {
+    pub fn new(
+        authentication: &Authentication,
+    ) -> Result<TlsConfig, Box<dyn error::Error + Send + Sync>> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let (config, name) = match authentication {
+            Authentication::Certificate { name, roots } => {
+                let mut store = rustls::RootCertStore::empty();
+                if roots.is_empty() {
+                    store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                }
+                for root in roots {
+                    store.add(root.clone())?;
+                }
+                let config = builder.with_root_certificates(store).with_no_client_auth();
+                let name = name.clone().map(ServerName::try_from).transpose()?;
+                (config, name)
+            }
+            Authentication::Pins(pins) => {
+                let verifier = PinVerifier {
+                    pins: pins.clone(),
+                    algorithms: provider.signature_verification_algorithms,
+                };
+                let config = builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(verifier))
+                    .with_no_client_auth();
+                (config, None)
+            }
+        };
+        Ok(TlsConfig {
+            config: Arc::new(config),
+            name,
+        })
+    }
+
+    /// Offer these application protocols during the handshake (ALPN), most
+    /// preferred first.
+    pub fn with_alpn(mut self, protocols: &[&[u8]]) -> TlsConfig {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = protocols.iter().map(|protocol| protocol.to_vec()).collect();
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Open a TLS session with a server, checking its certificate.
+    pub async fn connect(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<TlsConnection, Box<dyn error::Error + Send + Sync>> {
+        Ok(TcpConnection::new(self.handshake(addr).await?))
+    }
+
+    /// Like `connect`, but leaves the session unframed for other protocols.
+    pub(crate) async fn handshake(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<TlsStream<TcpStream>, Box<dyn error::Error + Send + Sync>> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let name = self
+            .name
+            .clone()
+            .unwrap_or_else(|| ServerName::IpAddress(addr.ip().into()));
+        let connector = TlsConnector::from(self.config.clone());
+        Ok(connector.connect(name, stream).await?)
+    }
+
+    /// The name a server goes by in URLs: the one its certificate is checked
+    /// against, or its address.
+    pub(crate) fn host(&self, addr: SocketAddr) -> String {
+        let ip = match &self.name {
+            Some(ServerName::DnsName(name)) => return name.as_ref().to_string(),
+            Some(ServerName::IpAddress(ip)) => IpAddr::from(*ip),
+            _ => addr.ip(),
+        };
+        match ip {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{}]", ip),
+        }
+    }
+}
+
+/// Send a request over a new TLS session and wait for a response that
+/// matches it, signing the request with a TSIG key if one is given.
+pub async fn query_signed(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+    config: &TlsConfig,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    let exchange = async {
+        let mut connection = config.connect(addr).await?;
+        tcp::exchange_over(&mut connection, request, key).await
+    };
+    match tokio::time::timeout(timeout, exchange).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("timed out waiting for a response from {}", addr).into()),
+    }
+}
+
+/// The SPKI pin of a certificate: the SHA-256 digest of its public key.
+pub fn spki_pin(cert: &CertificateDer) -> Result<[u8; 32], rustls::Error> {
+    let spki = ParsedCertificate::try_from(cert)?.subject_public_key_info();
+    let mut pin = [0; 32];
+    pin.copy_from_slice(digest::digest(&digest::SHA256, &spki).as_ref());
+    Ok(pin)
+}
+
+/// Parse an SPKI pin given in base64, as in `pin-sha256` values.
+pub fn parse_pin(value: &str) -> Result<[u8; 32], Box<dyn error::Error + Send + Sync>> {
+    let bytes = dns::dnssec::decode_base64(value)?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("SPKI pin {} isn't a SHA-256 digest", value).into())
+}
+
+/// Load every certificate in a PEM file.
+pub fn load_certificates(
+    path: &Path,
+) -> Result<Vec<CertificateDer<'static>>, Box<dyn error::Error + Send + Sync>> {
+    let mut reader = io::BufReader::new(fs::File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()).into());
+    }
+    Ok(certs)
+}
+
+/// Trusts certificates by their public keys alone. The handshake signatures
+/// are still checked, so the server must hold the private key.
+#[derive(Debug)]
+struct PinVerifier {
+    pins: Vec<[u8; 32]>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pins.contains(&spki_pin(end_entity)?) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(CertificateError::ApplicationVerificationFailure.into())
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::TlsAcceptor;
+
+    /// Start a TLS server with a self-signed certificate for localhost, which
+    /// echoes each request back as a response. Returns its address and
+    /// certificate.
+    async fn start(
+    ) -> Result<(SocketAddr, CertificateDer<'static>), Box<dyn error::Error + Send + Sync>> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let cert = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let config =
+            rustls::ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_single_cert(vec![cert.clone()], PrivateKeyDer::Pkcs8(key))?;
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                // Clients that reject the certificate abort the handshake
+                let mut stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let len = stream.read_u16().await.unwrap();
+                let mut response = vec![0; len as usize];
+                stream.read_exact(&mut response).await.unwrap();
+                response[2] |= 0b1000_0000;
+                let response = dns::tcp::frame_message(&response).unwrap();
+                stream.write_all(&response).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        Ok((addr, cert))
+    }
+
+    fn request() -> DnsPacket {
+        dns::parse_dns_packet(include_bytes!("../../examples/query_packet")).unwrap()
+    }
+
+    async fn query(
+        addr: SocketAddr,
+        authentication: Authentication,
+    ) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        let config = TlsConfig::new(&authentication)?;
+        query_signed(addr, &request(), None, &config, Duration::from_secs(5)).await
+    }
+
+    #[tokio::test]
+    async fn test_query_checks_certificate() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, cert) = start().await?;
+        let trusted = Authentication::Certificate {
+            name: Some("localhost".to_string()),
+            roots: vec![cert.clone()],
+        };
+        let response = query(addr, trusted).await?;
+        assert_eq!(response.header.id, request().header.id);
+        assert!(!response.header.query);
+
+        // The certificate isn't for this name, or from a root we trust
+        let wrong_name = Authentication::Certificate {
+            name: Some("example.com".to_string()),
+            roots: vec![cert],
+        };
+        assert!(query(addr, wrong_name).await.is_err());
+        let untrusted = Authentication::Certificate {
+            name: Some("localhost".to_string()),
+            roots: vec![],
+        };
+        assert!(query(addr, untrusted).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_checks_spki_pins() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, cert) = start().await?;
+        let pin = spki_pin(&cert)?;
+        let encoded = dns::dnssec::encode_base64(&pin);
+        assert_eq!(parse_pin(&encoded)?, pin);
+        assert!(parse_pin("c2VjcmV0").is_err());
+
+        let response = query(addr, Authentication::Pins(vec![[0; 32], pin])).await?;
+        assert_eq!(response.header.id, request().header.id);
+        assert!(query(addr, Authentication::Pins(vec![[0; 32]]))
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use super::tcp::TcpConnection;
+use super::validate;
+use anyhow::anyhow;
+use dns::header::{self, ResponseCode};
+use dns::journal::{self, Transfer};
+use dns::packet::DnsPacket;
+use dns::record::RecordType;
+use dns::tsig::TsigKey;
+use dns::zone::Zone;
+use std::error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+/// Fetch a whole zone from a server with AXFR (RFC 5936). The records may be
+/// spread over several messages, and the transfer is complete once the
+/// zone's SOA record comes round again. `timeout` applies to each message
+/// rather than the whole transfer, so large zones don't need a long one.
+pub async fn axfr(
+    addr: SocketAddr,
+    origin: &str,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<Zone, Box<dyn error::Error + Send + Sync>> {
+    let request = dns::packet::build_query(rand::random(), origin, &RecordType::Axfr);
+    match transfer(addr, request, None, key, timeout).await? {
+        Transfer::Full(records) => full_zone(origin, records),
+        _ => Err(anyhow!("{} didn't send the whole of {}", addr, origin).into()),
+    }
+}
+
+/// Bring a local copy of a zone up to date with IXFR (RFC 1995). The server
+/// sends the changes since our version if it still has them, and otherwise
+/// the whole zone.
+pub async fn ixfr(
+    addr: SocketAddr,
+    zone: &Zone,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<Zone, Box<dyn error::Error + Send + Sync>> {
+    let soa = zone
+        .soa()
+        .ok_or_else(|| anyhow!("zone {} has no SOA record", zone.origin))?;
+    let mut request = dns::packet::build_query(rand::random(), &zone.origin, &RecordType::Ixfr);
+    request.authoritative_entries.push(soa.clone());
+    match transfer(addr, request, zone.serial(), key, timeout).await? {
+        Transfer::UpToDate => Ok(zone.clone()),
+        Transfer::Full(records) => full_zone(&zone.origin, records),
+        Transfer::Incremental(diffs) => {
+            let mut zone = zone.clone();
+            for diff in diffs {
+                diff.apply(&mut zone)?;
+            }
+            Ok(zone)
+        }
+    }
+}
+
+/// Send a transfer request over a new TCP connection, and collect records
+/// from the responses until the transfer is complete.
+async fn transfer(
+    addr: SocketAddr,
+    mut request: DnsPacket,
+    serial: Option<u32>,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<Transfer, Box<dyn error::Error + Send + Sync>> {
+    request.header.recursion_desired = false;
+    let origin = request.questions[0].name.clone();
+    let (bytes, mut session) = validate::sign_request(&request, key)?;
+    let mut connection = within(timeout, addr, TcpConnection::connect(addr)).await?;
+    connection.send_bytes(&bytes).await?;
+
+    let mut records = vec![];
+    loop {
+        let bytes = within(timeout, addr, connection.receive_bytes()).await?;
+        let response = dns::parse_dns_packet(&bytes)?;
+        if response.header.query || response.header.id != request.header.id {
+            return Err(anyhow!("{} sent a message that isn't part of the transfer", addr).into());
+        }
+        let signed = match &mut session {
+            Some(session) => session
+                .verify(&bytes, SystemTime::now())
+                .map_err(|err| anyhow!("bad TSIG on transfer: {}", err))?,
+            None => true,
+        };
+        if response.header.rcode != ResponseCode::Success {
+            return Err(anyhow!(
+                "transfer of {} from {} failed: {}",
+                origin,
+                addr,
+                header::response_code_name(&response.header.rcode)
+            )
+            .into());
+        }
+        records.extend(response.answers);
+        if let Some(transfer) = journal::parse_transfer(&origin, &records, serial)? {
+            // Only the last message has to be signed, but it must be
+            if !signed {
+                return Err(anyhow!("last message of the transfer wasn't signed").into());
+            }
+            return Ok(transfer);
+        }
+    }
+}
+
+fn full_zone(
+    origin: &str,
+    records: Vec<dns::record::Record>,
+) -> Result<Zone, Box<dyn error::Error + Send + Sync>> {
+    let mut zone = Zone::new(origin);
+    for record in records {
+        zone.insert(record)?;
+    }
+    Ok(zone)
+}
+
+/// Wait for a step of the transfer, giving up after `timeout`.
+async fn within<T>(
+    timeout: Duration,
+    addr: SocketAddr,
+    future: impl Future<Output = Result<T, Box<dyn error::Error + Send + Sync>>>,
+) -> Result<T, Box<dyn error::Error + Send + Sync>> {
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("timed out waiting for a transfer from {}", addr).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::packet::DnsPacket;
+    use dns::record::Record;
+    use dns::tsig::{self, Algorithm};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Start a server that answers one AXFR request with each group of
+    /// records in its own message, signed if a key is given.
+    async fn start(
+        messages: Vec<Vec<Record>>,
+        key: Option<TsigKey>,
+    ) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap();
+            let mut request = vec![0; len as usize];
+            stream.read_exact(&mut request).await.unwrap();
+            let keys: Vec<TsigKey> = key.into_iter().collect();
+            let mut session = tsig::verify_request(&request, &keys, SystemTime::now()).unwrap();
+            let request = dns::parse_dns_packet(&request).unwrap();
+            for answers in messages {
+                let response = DnsPacket {
+                    answers,
+                    ..server_response(&request)
+                };
+                let mut bytes = dns::serialize_dns_packet(&response).unwrap();
+                if let Some(session) = &mut session {
+                    bytes = session.sign(&bytes, SystemTime::now()).unwrap();
+                }
+                let bytes = dns::tcp::frame_message(&bytes).unwrap();
+                stream.write_all(&bytes).await.unwrap();
+            }
+        });
+        Ok(addr)
+    }
+
+    fn server_response(request: &DnsPacket) -> DnsPacket {
+        let mut response = request.clone();
+        response.header.query = false;
+        response.authoritative_entries.clear();
+        response.resource_entries.clear();
+        response
+    }
+
+    fn records(text: &str) -> Vec<Record> {
+        dns::zone::parse_master_file(text, Some("example.com")).unwrap()
+    }
+
+    const SOA: &str = "@ 60 SOA ns hostmaster 1 2 3 4 5";
+
+    #[tokio::test]
+    async fn test_axfr_collects_records_across_messages(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let key = TsigKey::new("transfer", Algorithm::HmacSha512, b"secret".to_vec());
+        let messages = vec![
+            records(&format!("{}\n@ 60 NS ns", SOA)),
+            records("ns 60 A 192.0.2.1\nwww 60 A 192.0.2.2"),
+            records(SOA),
+        ];
+        let addr = start(messages, Some(key.clone())).await?;
+        let zone = axfr(addr, "example.com", Some(&key), Duration::from_secs(1)).await?;
+        assert_eq!(zone.origin, "example.com");
+        assert_eq!(zone.records().count(), 4);
+        assert_eq!(zone.rrset("www.example.com", RecordType::A).len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_axfr_rejects_incomplete_transfers(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let timeout = Duration::from_millis(100);
+        let addr = start(vec![records(&format!("{}\n@ 60 NS ns", SOA))], None).await?;
+        assert!(axfr(addr, "example.com", None, timeout).await.is_err());
+
+        let addr = start(vec![records("@ 60 NS ns")], None).await?;
+        assert!(axfr(addr, "example.com", None, timeout).await.is_err());
+
+        let changed = SOA.replace(" 1 2", " 2 2");
+        let addr = start(vec![records(&format!("{}\n{}", SOA, changed))], None).await?;
+        assert!(axfr(addr, "example.com", None, timeout).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ixfr_applies_changes_to_local_zone(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let old =
+            dns::zone::parse_zone(&format!("{}\nwww 60 A 192.0.2.1", SOA), Some("example.com"))?;
+        let new_soa = SOA.replace(" 1 2", " 2 2");
+        let text = format!("{}\nwww 60 A 192.0.2.2\nftp 60 A 192.0.2.3", new_soa);
+        let new = dns::zone::parse_zone(&text, Some("example.com"))?;
+        let diff = journal::Diff::between(&old, &new).unwrap();
+        let mut sequence = vec![new.soa().unwrap().clone()];
+        sequence.extend(diff.records().cloned());
+        let (first, rest) = sequence.split_at(3);
+        let mut rest = rest.to_vec();
+        rest.push(new.soa().unwrap().clone());
+
+        let timeout = Duration::from_secs(1);
+        let addr = start(vec![first.to_vec(), rest], None).await?;
+        assert_eq!(ixfr(addr, &old, None, timeout).await?, new);
+
+        // An up to date zone comes back unchanged
+        let addr = start(vec![records(&new_soa)], None).await?;
+        assert_eq!(ixfr(addr, &new, None, timeout).await?, new);
+
+        // As does the whole zone, when the server can't send changes
+        let mut whole: Vec<Record> = new.records().cloned().collect();
+        whole.push(new.soa().unwrap().clone());
+        let addr = start(vec![whole], None).await?;
+        assert_eq!(ixfr(addr, &old, None, timeout).await?, new);
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use super::validate;
+use anyhow::anyhow;
+use dns::packet::DnsPacket;
+use dns::tsig::TsigKey;
+use rand::Rng;
+use std::error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// The largest datagram we will accept. Servers shouldn't send more than the
+/// payload size we advertise, but there's no harm in reading it if they do.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// How many random ports to try before letting the OS pick one.
+const MAX_BIND_ATTEMPTS: usize = 16;
+
+/// Send a request from a new UDP socket on a random port, and wait for a
+/// response that matches it. Datagrams from other addresses, or that don't
+/// match the request, are ignored. The response may be truncated, in which
+/// case the TC bit is set.
+pub async fn query(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    query_signed(addr, request, None, timeout).await
+}
+
+/// Like `query`, but signs the request with a TSIG key if one is given. The
+/// response must then be signed with the same key. Responses with a bad
+/// signature are ignored like any other that doesn't match, so a spoofed one
+/// can't end the exchange early.
+pub async fn query_signed(
+    addr: SocketAddr,
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+    let mut rejected = None;
+    let exchange = async {
+        let (bytes, mut session) = validate::sign_request(request, key)?;
+        let sock = bind_random_port(addr).await?;
+        // Connecting means the socket only receives datagrams from `addr`.
+        sock.connect(addr).await?;
+        sock.send(&bytes).await?;
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let len = sock.recv(&mut buf).await?;
+            match dns::parse_dns_packet(&buf[..len]) {
+                Ok(response) if validate::is_response_to(request, &response) => {
+                    match validate::check_signature(&mut session, &buf[..len]) {
+                        Ok(()) => return Ok(response),
+                        Err(err) => rejected = Some(err),
+                    }
+                }
+                _ => continue,
+            }
+        }
+    };
+    let result = tokio::time::timeout(timeout, exchange).await;
+    match (result, rejected) {
+        (Ok(result), _) => result,
+        (Err(_), Some(err)) => Err(anyhow!(
+            "timed out waiting for a response from {}, after ignoring one: {}",
+            addr,
+            err
+        )
+        .into()),
+        (Err(_), None) => Err(anyhow!("timed out waiting for a response from {}", addr).into()),
+    }
+}
+
+/// Bind a socket to a random unprivileged port, to make responses harder to
+/// spoof (RFC 5452 §9.2).
+async fn bind_random_port(
+    remote: SocketAddr,
+) -> Result<UdpSocket, Box<dyn error::Error + Send + Sync>> {
+    let ip = match remote {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    for _ in 0..MAX_BIND_ATTEMPTS {
+        let port = rand::thread_rng().gen_range(1024..=u16::MAX);
+        if let Ok(sock) = UdpSocket::bind(SocketAddr::new(ip, port)).await {
+            return Ok(sock);
+        }
+    }
+    Ok(UdpSocket::bind(SocketAddr::new(ip, 0)).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u16) -> DnsPacket {
+        let mut request =
+            dns::parse_dns_packet(include_bytes!("../../examples/query_packet")).unwrap();
+        request.header.id = id;
+        request
+    }
+
+    #[tokio::test]
+    async fn test_query_ignores_responses_that_do_not_match(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+            let mut response = dns::parse_dns_packet(&buf[..len]).unwrap();
+            response.header.query = false;
+            response.header.id += 1;
+            let bytes = dns::serialize_dns_packet(&response).unwrap();
+            server.send_to(&bytes, peer).await.unwrap();
+            server.send_to(&[0xff], peer).await.unwrap();
+            response.header.id -= 1;
+            response.questions[0].name = "example.com".to_string();
+            let bytes = dns::serialize_dns_packet(&response).unwrap();
+            server.send_to(&bytes, peer).await.unwrap();
+            response.questions[0].name = "google.com".to_string();
+            let bytes = dns::serialize_dns_packet(&response).unwrap();
+            server.send_to(&bytes, peer).await.unwrap();
+        });
+        let response = query(addr, &request(42), Duration::from_secs(1)).await?;
+        assert_eq!(response.header.id, 42);
+        assert!(!response.header.query);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_times_out() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let result = query(
+            server.local_addr()?,
+            &request(42),
+            Duration::from_millis(50),
+        )
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bind_random_port_varies_port() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let remote = "127.0.0.1:53".parse()?;
+        let first = bind_random_port(remote).await?.local_addr()?;
+        let second = bind_random_port(remote).await?.local_addr()?;
+        assert!(first.port() >= 1024);
+        assert_ne!(first.port(), second.port());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_signed_checks_response_signature(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        use dns::tsig::{self, Algorithm};
+        use std::time::SystemTime;
+
+        let key = TsigKey::new("transfer", Algorithm::HmacSha256, b"secret".to_vec());
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?;
+        let keys = vec![key.clone()];
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            for sign in [true, false] {
+                let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+                let now = SystemTime::now();
+                let mut session = tsig::verify_request(&buf[..len], &keys, now)
+                    .unwrap()
+                    .unwrap();
+                let mut response = dns::parse_dns_packet(&buf[..len]).unwrap();
+                response.header.query = false;
+                tsig::strip(&mut response.resource_entries);
+                let mut bytes = dns::serialize_dns_packet(&response).unwrap();
+                if sign {
+                    bytes = session.sign(&bytes, now).unwrap();
+                }
+                server.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        let timeout = Duration::from_secs(1);
+        let response = query_signed(addr, &request(42), Some(&key), timeout).await?;
+        assert_eq!(response.header.id, 42);
+        let timeout = Duration::from_millis(200);
+        let err = query_signed(addr, &request(43), Some(&key), timeout)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("bad TSIG"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_signed_waits_past_spoofed_responses(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        use dns::tsig::{self, Algorithm};
+        use std::time::SystemTime;
+
+        let key = TsigKey::new("transfer", Algorithm::HmacSha256, b"secret".to_vec());
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?;
+        let keys = vec![key.clone()];
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+            let now = SystemTime::now();
+            let mut session = tsig::verify_request(&buf[..len], &keys, now)
+                .unwrap()
+                .unwrap();
+            let mut response = dns::parse_dns_packet(&buf[..len]).unwrap();
+            response.header.query = false;
+            tsig::strip(&mut response.resource_entries);
+            let bytes = dns::serialize_dns_packet(&response).unwrap();
+            // A spoofer who guessed the ID, but can't sign
+            server.send_to(&bytes, peer).await.unwrap();
+            let bytes = session.sign(&bytes, now).unwrap();
+            server.send_to(&bytes, peer).await.unwrap();
+        });
+        let timeout = Duration::from_secs(1);
+        let response = query_signed(addr, &request(42), Some(&key), timeout).await?;
+        assert_eq!(response.header.id, 42);
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use super::exchange;
+use dns::header::ResponseCode;
+use dns::tsig::TsigKey;
+use dns::update::Update;
+use std::error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Send an update to a zone's primary server (RFC 2136), signed with a TSIG
+/// key if one is given, and return the response code it was answered with.
+/// The update is sent again over TCP if the response comes back truncated.
+pub async fn send(
+    addr: SocketAddr,
+    update: &Update,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<ResponseCode, Box<dyn error::Error + Send + Sync>> {
+    let request = update.to_packet(rand::random());
+    let response = exchange::exchange_signed(addr, &request, key, timeout).await?;
+    Ok(response.header.rcode)
+}
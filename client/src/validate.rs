@@ -0,0 +1,113 @@
+use anyhow::anyhow;
+use dns::header::ResponseCode;
+use dns::packet::DnsPacket;
+use dns::tsig::{TsigKey, TsigSession};
+use std::error;
+use std::time::SystemTime;
+
+/// Returns true if a packet is a plausible response to a request: it must be
+/// a response, carry the same ID, and repeat the request's questions, unless
+/// it's a FORMERR. Names are compared case-insensitively, as some servers
+/// don't preserve case.
+pub fn is_response_to(request: &DnsPacket, response: &DnsPacket) -> bool {
+    if response.header.query || response.header.id != request.header.id {
+        return false;
+    }
+    // Servers may leave out the question when they couldn't parse it
+    if response.questions.is_empty() && response.header.rcode == ResponseCode::FormatError {
+        return true;
+    }
+    response.questions.len() == request.questions.len()
+        && request
+            .questions
+            .iter()
+            .zip(&response.questions)
+            .all(|(asked, answered)| {
+                dns::name::eq(&asked.name, &answered.name)
+                    && asked.typ == answered.typ
+                    && asked.class == answered.class
+            })
+}
+
+/// Serialize a request, signing it with TSIG if there's a key. The returned
+/// session checks the signature on the response.
+pub(crate) fn sign_request(
+    request: &DnsPacket,
+    key: Option<&TsigKey>,
+) -> Result<(Vec<u8>, Option<TsigSession>), Box<dyn error::Error + Send + Sync>> {
+    let bytes = dns::serialize_dns_packet(request)?;
+    match key {
+        Some(key) => {
+            let mut session = TsigSession::new(key.clone());
+            let bytes = session.sign(&bytes, SystemTime::now())?;
+            Ok((bytes, Some(session)))
+        }
+        None => Ok((bytes, None)),
+    }
+}
+
+/// Check that a response to a signed request is signed with the same key.
+pub(crate) fn check_signature(
+    session: &mut Option<TsigSession>,
+    response: &[u8],
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    if let Some(session) = session {
+        session
+            .verify(response, SystemTime::now())
+            .map_err(|err| anyhow!("bad TSIG on response: {}", err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::record::RecordType;
+
+    fn request() -> DnsPacket {
+        dns::packet::build_query(42, "example.com", &RecordType::A)
+    }
+
+    fn response() -> DnsPacket {
+        let mut response = request();
+        response.header.query = false;
+        response.questions[0].name = "ExAmPlE.cOm".to_string();
+        response
+    }
+
+    #[test]
+    fn test_is_response_to_accepts_matching_response() {
+        assert!(is_response_to(&request(), &response()));
+    }
+
+    #[test]
+    fn test_is_response_to_rejects_mismatches() {
+        assert!(!is_response_to(&request(), &request()));
+
+        let mut wrong_id = response();
+        wrong_id.header.id = 43;
+        assert!(!is_response_to(&request(), &wrong_id));
+
+        let mut wrong_name = response();
+        wrong_name.questions[0].name = "example.net".to_string();
+        assert!(!is_response_to(&request(), &wrong_name));
+
+        let mut wrong_type = response();
+        wrong_type.questions[0].typ = 28;
+        assert!(!is_response_to(&request(), &wrong_type));
+
+        let mut no_question = response();
+        no_question.questions.clear();
+        assert!(!is_response_to(&request(), &no_question));
+    }
+
+    #[test]
+    fn test_is_response_to_accepts_errors_without_question() {
+        let mut response = response();
+        response.questions.clear();
+        response.header.rcode = ResponseCode::FormatError;
+        assert!(is_response_to(&request(), &response));
+        response.header.rcode = ResponseCode::Refused;
+        assert!(!is_response_to(&request(), &response));
+    }
+}
@@ -17,67 +17,69 @@ impl<'a> ByteBuffer<'a> {
     }
 
     pub fn get(&self, pos: usize) -> Option<u8> {
-        self.check_bounds(pos, 1).ok()?;
+        self.check_bounds(pos, pos + 1).ok()?;
         Some(self.buf[pos])
     }
 
     pub fn peek(&mut self) -> Option<u8> {
-        self.check_bounds(self.pos, 1).ok()?;
+        self.check_bounds(self.pos, self.pos + 1).ok()?;
         Some(self.buf[self.pos])
     }
 
     pub fn read(&mut self) -> Option<u8> {
-        self.check_bounds(self.pos, 1).ok()?;
+        self.check_bounds(self.pos, self.pos + 1).ok()?;
         let value = self.peek()?;
         self.pos += 1;
         Some(value)
     }
 
-    pub fn jump(&mut self, pos: usize) -> Result<(), Box<dyn error::Error>> {
-        self.check_bounds(pos, 1)?;
+    pub fn jump(&mut self, pos: usize) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        self.check_bounds(pos, pos)?;
         self.pos = pos;
         Ok(())
     }
 
-    pub fn read_range(&mut self, len: usize) -> Result<&'a [u8], Box<dyn error::Error>> {
+    pub fn read_range(
+        &mut self,
+        len: usize,
+    ) -> Result<&'a [u8], Box<dyn error::Error + Send + Sync>> {
         self.check_bounds(self.pos, self.pos + len)?;
         let slice = &self.buf[self.pos..self.pos + len];
         self.pos += len;
         Ok(slice)
     }
 
-    pub fn read_u16(&mut self) -> Result<u16, Box<dyn error::Error>> {
+    pub fn read_u16(&mut self) -> Result<u16, Box<dyn error::Error + Send + Sync>> {
         let bytes = self.read_range(2)?;
         let bytes: [u8; 2] = bytes.try_into()?;
         let value = u16::from_be_bytes(bytes);
         Ok(value)
     }
 
-    pub fn read_i32(&mut self) -> Result<i32, Box<dyn error::Error>> {
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Box<dyn error::Error + Send + Sync>> {
+        let bytes = self.read_range(4)?;
+        let bytes: [u8; 4] = bytes.try_into()?;
+        let value = u32::from_be_bytes(bytes);
+        Ok(value)
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, Box<dyn error::Error + Send + Sync>> {
         let bytes = self.read_range(4)?;
         let bytes: [u8; 4] = bytes.try_into()?;
         let value = i32::from_be_bytes(bytes);
         Ok(value)
     }
 
-    fn check_bounds(&self, from: usize, to: usize) -> Result<(), Box<dyn error::Error>> {
-        if self.buf.is_empty() && from + to > 0 {
-            Err(anyhow!(
-                "from:{} to:{} len:{} out of bounds",
-                from,
-                to,
-                self.buf.len()
-            )
-            .into())
-        } else if from > self.buf.len() - 1 {
-            Err(anyhow!(
-                "from:{} to:{} len:{} out of bounds",
-                from,
-                to,
-                self.buf.len()
-            )
-            .into())
-        } else if to > self.buf.len() {
+    fn check_bounds(
+        &self,
+        from: usize,
+        to: usize,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if from > to || to > self.buf.len() {
             Err(anyhow!(
                 "from:{} to:{} len:{} out of bounds",
                 from,
@@ -102,7 +104,7 @@ mod tests {
     #[test]
     fn test_pos_returns_expected_position() {
         let buf = ByteBuffer {
-            buf: &vec![1, 2, 3, 4, 5],
+            buf: &[1, 2, 3, 4, 5],
             pos: 3,
         };
         assert_eq!(buf.pos(), 3);
@@ -239,7 +241,8 @@ mod tests {
     }
 
     #[test]
-    fn test_read_range_returns_expected_value_on_buf_len_1() -> Result<(), Box<dyn error::Error>> {
+    fn test_read_range_returns_expected_value_on_buf_len_1(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
         let bytes = vec![1];
         let mut buf = ByteBuffer::from(&bytes);
         assert_eq!(buf.read_range(1)?, &bytes[0..1]);
@@ -247,14 +250,52 @@ mod tests {
     }
 
     #[test]
-    fn test_read_range_returns_expected_value_on_buf_len_3() -> Result<(), Box<dyn error::Error>> {
+    fn test_read_range_returns_expected_value_on_buf_len_3(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
         let bytes = vec![1, 2, 3];
         let mut buf = ByteBuffer::from(&bytes);
         assert_eq!(buf.read_range(3)?, &bytes[0..3]);
         Ok(())
     }
 
+    #[test]
+    fn test_read_range_returns_empty_slice_at_end_of_buffer(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = vec![1, 2];
+        let mut buf = ByteBuffer::from(&bytes);
+        buf.read_range(2)?;
+        assert_eq!(buf.read_range(0)?, &[] as &[u8]);
+        Ok(())
+    }
+
     // --------------------------------------------------
     // read_u16()
     // --------------------------------------------------
+
+    #[test]
+    fn test_read_u16_returns_big_endian_value() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = vec![0x12, 0x34];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert_eq!(buf.read_u16()?, 0x1234);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_u16_returns_error_on_short_buffer() {
+        let bytes = vec![0x12];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert!(buf.read_u16().is_err());
+    }
+
+    // --------------------------------------------------
+    // jump()
+    // --------------------------------------------------
+
+    #[test]
+    fn test_jump_allows_end_of_buffer() {
+        let bytes = vec![1, 2, 3];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert!(buf.jump(3).is_ok());
+        assert!(buf.jump(4).is_err());
+    }
 }
@@ -0,0 +1,820 @@
+//! Helpers for the record types added by DNSSEC (RFC 4034, RFC 5155).
+
+use super::name;
+use super::record::{self, Data, Record, RecordType};
+use anyhow::{anyhow, Context};
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER_PERMISSIVE};
+use ring::{digest, signature};
+use std::error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Algorithm numbers for DNSKEY, RRSIG and DS records.
+pub const RSASHA256: u8 = 8;
+pub const ECDSAP256SHA256: u8 = 13;
+pub const ECDSAP384SHA384: u8 = 14;
+pub const ED25519: u8 = 15;
+
+/// Digest types for DS records.
+pub const SHA1: u8 = 1;
+pub const SHA256: u8 = 2;
+pub const SHA384: u8 = 4;
+
+/// DNSKEY flags (RFC 4034 §2.1.1, RFC 5011 §7).
+pub const ZONE_KEY: u16 = 0x0100;
+pub const REVOKE: u16 = 0x0080;
+pub const SECURE_ENTRY_POINT: u16 = 0x0001;
+
+/// The only hash algorithm defined for NSEC3.
+pub const NSEC3_SHA1: u8 = 1;
+
+/// The NSEC3 flag for spans that may contain unsigned delegations.
+pub const OPT_OUT: u8 = 0x01;
+
+// --------------------------------------------------
+// Type bitmaps
+// --------------------------------------------------
+
+/// Parse the type bitmap of an NSEC or NSEC3 record (RFC 4034 §4.1.2).
+pub fn parse_type_bitmap(
+    bytes: &[u8],
+) -> Result<Vec<RecordType>, Box<dyn error::Error + Send + Sync>> {
+    let mut types = vec![];
+    let mut pos = 0;
+    let mut last_window = None;
+    while pos < bytes.len() {
+        let window = bytes[pos];
+        let len = *bytes.get(pos + 1).context("type bitmap ends early")? as usize;
+        if last_window.is_some_and(|last| window <= last) {
+            return Err(anyhow!("type bitmap windows are out of order").into());
+        }
+        if len == 0 || len > 32 {
+            return Err(anyhow!("invalid type bitmap length {}", len).into());
+        }
+        let bitmap = bytes
+            .get(pos + 2..pos + 2 + len)
+            .context("type bitmap ends early")?;
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let code = (window as u16) << 8 | (i * 8 + bit) as u16;
+                    types.push(record::parse_record_type(code));
+                }
+            }
+        }
+        last_window = Some(window);
+        pos += 2 + len;
+    }
+    Ok(types)
+}
+
+pub fn serialize_type_bitmap(types: &[RecordType]) -> Vec<u8> {
+    let mut codes: Vec<u16> = types.iter().map(record::record_type_code).collect();
+    codes.sort_unstable();
+    codes.dedup();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < codes.len() {
+        let window = (codes[i] >> 8) as u8;
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+        while i < codes.len() && (codes[i] >> 8) as u8 == window {
+            let low = (codes[i] & 0xff) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            len = low / 8 + 1;
+            i += 1;
+        }
+        bytes.push(window);
+        bytes.push(len as u8);
+        bytes.extend(&bitmap[..len]);
+    }
+    bytes
+}
+
+// --------------------------------------------------
+// Key tags
+// --------------------------------------------------
+
+/// Calculate the key tag of a DNSKEY or CDNSKEY record (RFC 4034 App. B).
+/// Returns `None` for other records.
+pub fn key_tag(data: &Data) -> Option<u16> {
+    let (algorithm, public_key) = match data {
+        Data::Dnskey {
+            algorithm,
+            public_key,
+            ..
+        } => (*algorithm, public_key),
+        _ => return None,
+    };
+    // RSA/MD5 keys use a different, long deprecated method
+    if algorithm == 1 {
+        let len = public_key.len();
+        if len < 3 {
+            return Some(0);
+        }
+        return Some(u16::from_be_bytes([
+            public_key[len - 3],
+            public_key[len - 2],
+        ]));
+    }
+    let rdata = record::serialize_data(data).ok()?;
+    let mut sum: u32 = 0;
+    for (i, byte) in rdata.iter().enumerate() {
+        if i % 2 == 0 {
+            sum += (*byte as u32) << 8;
+        } else {
+            sum += *byte as u32;
+        }
+    }
+    sum += (sum >> 16) & 0xffff;
+    Some((sum & 0xffff) as u16)
+}
+
+// --------------------------------------------------
+// Signatures
+// --------------------------------------------------
+
+/// Returns true if we can verify signatures made with an algorithm.
+pub fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(
+        algorithm,
+        RSASHA256 | ECDSAP256SHA256 | ECDSAP384SHA384 | ED25519
+    )
+}
+
+/// A name in canonical wire format: uncompressed and lowercase (RFC 4034
+/// §6.2).
+pub fn canonical_name(value: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    record::serialize_name(&value.to_ascii_lowercase())
+}
+
+/// The number of labels in a name, not counting the root or a leading
+/// wildcard label, as it appears in the labels field of an RRSIG.
+pub fn signature_labels(value: &str) -> u8 {
+    let labels = name::labels(value);
+    let count = match labels.first() {
+        Some(&"*") => labels.len() - 1,
+        _ => labels.len(),
+    };
+    count as u8
+}
+
+/// Record data in canonical form, with the names inside it lowercased for
+/// the types listed in RFC 4034 §6.2 (as amended by RFC 6840 §5.1).
+fn canonical_data(data: &Data) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let lower = |value: &String| value.to_ascii_lowercase();
+    let data = match data {
+        Data::Ns(target) => Data::Ns(lower(target)),
+        Data::Cname(target) => Data::Cname(lower(target)),
+        Data::Ptr(target) => Data::Ptr(lower(target)),
+        Data::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => Data::Soa {
+            mname: lower(mname),
+            rname: lower(rname),
+            serial: *serial,
+            refresh: *refresh,
+            retry: *retry,
+            expire: *expire,
+            minimum: *minimum,
+        },
+        Data::Mx {
+            preference,
+            exchange,
+        } => Data::Mx {
+            preference: *preference,
+            exchange: lower(exchange),
+        },
+        Data::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => Data::Srv {
+            priority: *priority,
+            weight: *weight,
+            port: *port,
+            target: lower(target),
+        },
+        data => {
+            let mut data = data.clone();
+            if let Data::Rrsig { signer_name, .. } = &mut data {
+                *signer_name = signer_name.to_ascii_lowercase();
+            }
+            data
+        }
+    };
+    record::serialize_data(&data)
+}
+
+/// The data covered by an RRSIG: its own data without the signature,
+/// followed by the RRset in canonical form and order, with the TTLs set to
+/// the original TTL from the RRSIG (RFC 4034 §3.1.8.1).
+pub fn signed_data(
+    rrsig: &Data,
+    records: &[Record],
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let (labels, original_ttl) = match rrsig {
+        Data::Rrsig {
+            labels,
+            original_ttl,
+            ..
+        } => (*labels, *original_ttl),
+        _ => return Err(anyhow!("expected RRSIG data").into()),
+    };
+    let mut bytes = canonical_data(rrsig)?;
+    let unsigned_len = bytes.len() - signature_len(rrsig);
+    bytes.truncate(unsigned_len);
+
+    let mut rdatas = records
+        .iter()
+        .map(|record| canonical_data(&record.data))
+        .collect::<Result<Vec<_>, _>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+    let first = records.first().context("cannot sign an empty RRset")?;
+    // Records synthesized from a wildcard are signed with the wildcard as
+    // their owner name
+    let owner_labels = name::labels(&first.name);
+    let owner = if (labels as usize) < owner_labels.len() {
+        let suffix = owner_labels[owner_labels.len() - labels as usize..].join(".");
+        name::child("*", if suffix.is_empty() { "." } else { &suffix })
+    } else {
+        first.name.clone()
+    };
+    let owner = canonical_name(&owner)?;
+    for rdata in rdatas {
+        bytes.extend(&owner);
+        bytes.extend(record::serialize_record_type(&first.record_type));
+        bytes.extend(record::serialize_class(&first.class));
+        bytes.extend(original_ttl.to_be_bytes());
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+    }
+    Ok(bytes)
+}
+
+fn signature_len(rrsig: &Data) -> usize {
+    match rrsig {
+        Data::Rrsig { signature, .. } => signature.len(),
+        _ => 0,
+    }
+}
+
+/// Check that an RRSIG over an RRset was made by the private half of a
+/// DNSKEY. This only checks the signature itself, not whether the RRSIG is
+/// within its validity period or matches the key's tag.
+pub fn verify_signature(
+    dnskey: &Data,
+    rrsig: &Data,
+    records: &[Record],
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let (key_algorithm, public_key) = match dnskey {
+        Data::Dnskey {
+            algorithm,
+            public_key,
+            ..
+        } => (*algorithm, public_key),
+        _ => return Err(anyhow!("expected DNSKEY data").into()),
+    };
+    let (algorithm, sig) = match rrsig {
+        Data::Rrsig {
+            algorithm,
+            signature,
+            ..
+        } => (*algorithm, signature),
+        _ => return Err(anyhow!("expected RRSIG data").into()),
+    };
+    if algorithm != key_algorithm {
+        return Err(anyhow!("signature and key use different algorithms").into());
+    }
+    let message = signed_data(rrsig, records)?;
+    let result = match algorithm {
+        RSASHA256 => {
+            let (exponent, modulus) = split_rsa_key(public_key).context("invalid RSA key")?;
+            signature::RsaPublicKeyComponents {
+                n: modulus,
+                e: exponent,
+            }
+            .verify(
+                &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                &message,
+                sig,
+            )
+        }
+        ECDSAP256SHA256 | ECDSAP384SHA384 => {
+            let verifier = if algorithm == ECDSAP256SHA256 {
+                &signature::ECDSA_P256_SHA256_FIXED
+            } else {
+                &signature::ECDSA_P384_SHA384_FIXED
+            };
+            // DNSKEYs hold the bare point, without the uncompressed prefix
+            let mut point = vec![0x04];
+            point.extend(public_key);
+            signature::UnparsedPublicKey::new(verifier, point).verify(&message, sig)
+        }
+        ED25519 => {
+            signature::UnparsedPublicKey::new(&signature::ED25519, public_key).verify(&message, sig)
+        }
+        _ => return Err(anyhow!("unsupported algorithm {}", algorithm).into()),
+    };
+    result.map_err(|_| anyhow!("signature does not match").into())
+}
+
+/// Split an RSA public key in DNSKEY format into its exponent and modulus
+/// (RFC 3110 §2).
+fn split_rsa_key(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = match key {
+        [0, high, low, rest @ ..] => (u16::from_be_bytes([*high, *low]) as usize, rest),
+        [len, rest @ ..] => (*len as usize, rest),
+        [] => return None,
+    };
+    if len == 0 || rest.len() <= len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+/// The digest of a DNSKEY, as published in a DS record by the parent zone
+/// (RFC 4034 §5.1.4).
+pub fn ds_digest(
+    owner: &str,
+    dnskey: &Data,
+    digest_type: u8,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let algorithm = match digest_type {
+        SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        SHA256 => &digest::SHA256,
+        SHA384 => &digest::SHA384,
+        _ => return Err(anyhow!("unsupported digest type {}", digest_type).into()),
+    };
+    let mut data = canonical_name(owner)?;
+    data.extend(record::serialize_data(dnskey)?);
+    Ok(digest::digest(algorithm, &data).as_ref().to_vec())
+}
+
+/// Create the DS record data for a DNSKEY.
+pub fn ds_data(
+    owner: &str,
+    dnskey: &Data,
+    digest_type: u8,
+) -> Result<Data, Box<dyn error::Error + Send + Sync>> {
+    let algorithm = match dnskey {
+        Data::Dnskey { algorithm, .. } => *algorithm,
+        _ => return Err(anyhow!("expected DNSKEY data").into()),
+    };
+    Ok(Data::Ds {
+        key_tag: key_tag(dnskey).unwrap_or(0),
+        algorithm,
+        digest_type,
+        digest: ds_digest(owner, dnskey, digest_type)?,
+    })
+}
+
+// --------------------------------------------------
+// Denial of existence
+// --------------------------------------------------
+
+/// Returns true if a name falls strictly between the owner and next name of
+/// an NSEC record in canonical order. The last NSEC in a zone points back to
+/// the apex, so it covers every name after its owner.
+pub fn nsec_covers(owner: &str, next_name: &str, value: &str) -> bool {
+    let owner = name::canonical_key(owner);
+    let next = name::canonical_key(next_name);
+    let value = name::canonical_key(value);
+    if owner < next {
+        owner < value && value < next
+    } else {
+        owner < value || value < next
+    }
+}
+
+/// Returns true if a hash falls strictly between the owner hash and next
+/// hash of an NSEC3 record, wrapping around at the end of the chain.
+pub fn nsec3_covers(owner_hash: &[u8], next_hash: &[u8], hash: &[u8]) -> bool {
+    if owner_hash < next_hash {
+        owner_hash < hash && hash < next_hash
+    } else {
+        owner_hash < hash || hash < next_hash
+    }
+}
+
+/// Hash a name for NSEC3 (RFC 5155 §5). Each iteration hashes the previous
+/// digest with the salt appended.
+pub fn nsec3_hash(
+    value: &str,
+    salt: &[u8],
+    iterations: u16,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let mut data = canonical_name(value)?;
+    data.extend(salt);
+    let mut hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    for _ in 0..iterations {
+        let mut data = hash.as_ref().to_vec();
+        data.extend(salt);
+        hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    }
+    Ok(hash.as_ref().to_vec())
+}
+
+/// The owner name of the NSEC3 record that matches a name in a zone.
+pub fn nsec3_owner(
+    value: &str,
+    zone: &str,
+    salt: &[u8],
+    iterations: u16,
+) -> Result<String, Box<dyn error::Error + Send + Sync>> {
+    let hash = nsec3_hash(value, salt, iterations)?;
+    Ok(name::child(&encode_base32hex(&hash), zone))
+}
+
+/// The hash in the first label of an NSEC3 record's owner name.
+pub fn nsec3_owner_hash(owner: &str) -> Option<Vec<u8>> {
+    let labels = name::labels(owner);
+    decode_base32hex(labels.first()?).ok()
+}
+
+// --------------------------------------------------
+// Encodings
+// --------------------------------------------------
+
+/// Encode bytes in base32 with the extended hex alphabet, as used for NSEC3
+/// hashes (RFC 4648 §7). The result is lowercase, as it is when used in
+/// owner names.
+pub fn encode_base32hex(bytes: &[u8]) -> String {
+    BASE32HEX_NOPAD.encode(bytes).to_ascii_lowercase()
+}
+
+pub fn decode_base32hex(value: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    BASE32HEX_NOPAD
+        .decode(value.to_ascii_uppercase().as_bytes())
+        .with_context(|| format!("invalid base32hex {}", value))
+        .map_err(|err| err.into())
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    BASE64.encode(bytes)
+}
+
+pub fn decode_base64(value: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    BASE64
+        .decode(value.as_bytes())
+        .with_context(|| format!("invalid base64 {}", value))
+        .map_err(|err| err.into())
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+pub fn decode_hex(value: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    HEXUPPER_PERMISSIVE
+        .decode(value.as_bytes())
+        .with_context(|| format!("invalid hex {}", value))
+        .map_err(|err| err.into())
+}
+
+// --------------------------------------------------
+// Signature times
+// --------------------------------------------------
+
+/// Format an RRSIG inception or expiration time as `YYYYMMDDHHmmSS` in UTC
+/// (RFC 4034 §3.2).
+pub fn format_time(time: u32) -> String {
+    let days = (time / 86400) as i64;
+    let seconds = time % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parse an RRSIG inception or expiration time, which is either in the
+/// `YYYYMMDDHHmmSS` format or a plain number of seconds since the epoch.
+pub fn parse_time(value: &str) -> Result<u32, Box<dyn error::Error + Send + Sync>> {
+    let invalid = || anyhow!("invalid signature time {}", value);
+    if value.len() != 14 || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return value.parse().map_err(|_| invalid().into());
+    }
+    let field = |range: std::ops::Range<usize>| value[range].parse::<i64>().unwrap_or(0);
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hour, minute, second) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid().into());
+    }
+    let time = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    // Times wrap around, and are compared with serial number arithmetic
+    Ok(time.rem_euclid(1 << 32) as u32)
+}
+
+/// A point in time as an RRSIG inception or expiration time, which counts
+/// seconds since the epoch modulo 2^32.
+pub fn signature_time(time: SystemTime) -> u32 {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    seconds as u32
+}
+
+/// Returns true if `a` is before `b`, treating both as serial numbers that
+/// wrap around (RFC 1982), as RRSIG times are.
+pub fn time_before(a: u32, b: u32) -> bool {
+    a != b && b.wrapping_sub(a) < 1 << 31
+}
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_bitmap_round_trip() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // The example from RFC 4034 §4.3
+        let types = vec![
+            RecordType::A,
+            RecordType::Mx,
+            RecordType::Rrsig,
+            RecordType::Nsec,
+            RecordType::Unknown(1234),
+        ];
+        let bytes = serialize_type_bitmap(&types);
+        let mut expected = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1b];
+        expected.extend([0; 26]);
+        expected.push(0x20);
+        assert_eq!(bytes, expected);
+        assert_eq!(parse_type_bitmap(&bytes)?, types);
+        assert!(parse_type_bitmap(&[0x00, 0x00]).is_err());
+        assert!(parse_type_bitmap(&[0x01, 0x01, 0x40, 0x00, 0x01, 0x40]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_tag() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // The root zone's 2017 key signing key, which has key tag 20326
+        let public_key = decode_base64(
+            "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+        )?;
+        let dnskey = Data::Dnskey {
+            flags: ZONE_KEY | SECURE_ENTRY_POINT,
+            protocol: 3,
+            algorithm: RSASHA256,
+            public_key,
+        };
+        assert_eq!(key_tag(&dnskey), Some(20326));
+        assert_eq!(key_tag(&Data::Ns("example.com".to_string())), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_base32hex_round_trip() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let hash = decode_hex("1F3A6B2C9D8E7F6051423344556677889900AABB")?;
+        let encoded = encode_base32hex(&hash);
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(decode_base32hex(&encoded.to_ascii_uppercase())?, hash);
+        assert_eq!(encode_base32hex(b"foobar"), "cpnmuoj1e8");
+        assert!(decode_base32hex("xyz").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_times() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        assert_eq!(format_time(0), "19700101000000");
+        assert_eq!(parse_time("20240229123456")?, 1709210096);
+        assert_eq!(format_time(1709210096), "20240229123456");
+        assert_eq!(parse_time("1709210096")?, 1709210096);
+        assert!(parse_time("20241301000000").is_err());
+        assert!(time_before(u32::MAX - 10, 10));
+        assert!(!time_before(10, u32::MAX - 10));
+        Ok(())
+    }
+
+    fn rrset(text: &str) -> Vec<Record> {
+        crate::zone::parse_master_file(text, None).unwrap()
+    }
+
+    fn unsigned_rrsig(algorithm: u8, records: &[Record], signer_name: &str) -> Data {
+        Data::Rrsig {
+            type_covered: records[0].record_type,
+            algorithm,
+            labels: signature_labels(&records[0].name),
+            original_ttl: 3600,
+            expiration: 1_800_000_000,
+            inception: 1_700_000_000,
+            key_tag: 12345,
+            signer_name: signer_name.to_string(),
+            signature: vec![],
+        }
+    }
+
+    fn with_signature(rrsig: Data, sig: &[u8]) -> Data {
+        match rrsig {
+            Data::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                ..
+            } => Data::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature: sig.to_vec(),
+            },
+            data => data,
+        }
+    }
+
+    fn dnskey(algorithm: u8, public_key: Vec<u8>) -> Data {
+        Data::Dnskey {
+            flags: ZONE_KEY,
+            protocol: 3,
+            algorithm,
+            public_key,
+        }
+    }
+
+    #[test]
+    fn test_verify_signature() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let records = rrset("www.example.com. 300 A 192.0.2.1\nwww.example.com. 300 A 192.0.2.2");
+        let rng = ring::rand::SystemRandom::new();
+        let mut keys = vec![];
+
+        for (algorithm, signing) in [
+            (ECDSAP256SHA256, &signature::ECDSA_P256_SHA256_FIXED_SIGNING),
+            (ECDSAP384SHA384, &signature::ECDSA_P384_SHA384_FIXED_SIGNING),
+        ] {
+            let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(signing, &rng).unwrap();
+            let pair = signature::EcdsaKeyPair::from_pkcs8(signing, pkcs8.as_ref(), &rng).unwrap();
+            let rrsig = unsigned_rrsig(algorithm, &records, "example.com");
+            let sig = pair.sign(&rng, &signed_data(&rrsig, &records)?).unwrap();
+            let public_key = signature::KeyPair::public_key(&pair).as_ref()[1..].to_vec();
+            keys.push((
+                dnskey(algorithm, public_key),
+                with_signature(rrsig, sig.as_ref()),
+            ));
+        }
+
+        let pkcs8 = signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pair = signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let rrsig = unsigned_rrsig(ED25519, &records, "example.com");
+        let sig = pair.sign(&signed_data(&rrsig, &records)?);
+        let public_key = signature::KeyPair::public_key(&pair).as_ref().to_vec();
+        keys.push((
+            dnskey(ED25519, public_key),
+            with_signature(rrsig, sig.as_ref()),
+        ));
+
+        let pair =
+            signature::RsaKeyPair::from_pkcs8(include_bytes!("../testdata/rsa2048.pk8")).unwrap();
+        let rrsig = unsigned_rrsig(RSASHA256, &records, "example.com");
+        let mut sig = vec![0; pair.public().modulus_len()];
+        pair.sign(
+            &signature::RSA_PKCS1_SHA256,
+            &rng,
+            &signed_data(&rrsig, &records)?,
+            &mut sig,
+        )
+        .unwrap();
+        let components = signature::RsaPublicKeyComponents::<Vec<u8>>::from(pair.public());
+        let mut public_key = vec![components.e.len() as u8];
+        public_key.extend(components.e);
+        public_key.extend(components.n);
+        keys.push((dnskey(RSASHA256, public_key), with_signature(rrsig, &sig)));
+
+        // Signatures don't depend on the order or case of the records
+        let reordered = rrset("WWW.Example.com. 60 A 192.0.2.2\nwww.example.com. 60 A 192.0.2.1");
+        let tampered = rrset("www.example.com. 300 A 192.0.2.1\nwww.example.com. 300 A 192.0.2.3");
+        for (key, rrsig) in &keys {
+            verify_signature(key, rrsig, &records)?;
+            verify_signature(key, rrsig, &reordered)?;
+            assert!(verify_signature(key, rrsig, &tampered).is_err());
+        }
+        assert!(verify_signature(&keys[0].0, &keys[1].1, &records).is_err());
+        assert!(verify_signature(&keys[0].0, &keys[2].1, &records).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_data_expands_wildcards() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let wildcard = rrset("*.example.com. 300 TXT \"hello\"");
+        let synthesized = rrset("a.b.example.com. 300 TXT \"hello\"");
+        let rrsig = unsigned_rrsig(ED25519, &wildcard, "example.com");
+        assert_eq!(signature_labels("*.example.com"), 2);
+        assert_eq!(signature_labels("."), 0);
+        assert_eq!(
+            signed_data(&rrsig, &synthesized)?,
+            signed_data(&rrsig, &wildcard)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ds_digest() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // The examples from RFC 4034 §5.4 and RFC 4509 §2.2
+        let records = rrset(
+            "dskey.example.com. 86400 IN DNSKEY 256 3 5 ( AQOeiiR0GOMYkDshWoSKz9Xz
+                fwJr1AYtsmx3TGkJaNXVbfi/ 2pHm822aJ5iI9BMzNXxeYCmZ
+                DRD99WYwYqUSdjMmmAphXdvx egXd/M5+X7OrzKBaMbCVdFLU
+                Uh6DhweJBjEVv5f2wwjM9Xzc nOf+EPbtG9DMBmADjFDc2w/r
+                ljwvFw== )",
+        );
+        let key = &records[0].data;
+        assert_eq!(key_tag(key), Some(60485));
+        assert_eq!(
+            encode_hex(&ds_digest("dskey.example.com", key, SHA1)?),
+            "2BB183AF5F22588179A53B0A98631FAD1A292118"
+        );
+        assert_eq!(
+            ds_data("DSKEY.example.com", key, SHA256)?,
+            Data::Ds {
+                key_tag: 60485,
+                algorithm: 5,
+                digest_type: SHA256,
+                digest: decode_hex(
+                    "D4B7D520E7BB5F0F67674A0CCEB1E3E0614B93C4F9E99B8383F6A1E4469DA50A"
+                )?,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nsec_covers() {
+        assert!(nsec_covers("a.example", "d.example", "b.example"));
+        assert!(nsec_covers("a.example", "d.example", "x.b.example"));
+        assert!(!nsec_covers("a.example", "d.example", "a.example"));
+        assert!(!nsec_covers("a.example", "d.example", "e.example"));
+        // The last NSEC in the chain wraps around to the apex
+        assert!(nsec_covers("z.example", "example", "zz.example"));
+        assert!(!nsec_covers("z.example", "example", "b.example"));
+    }
+
+    #[test]
+    fn test_nsec3_hash() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // The examples from RFC 5155 Appendix A
+        let salt = decode_hex("AABBCCDD")?;
+        assert_eq!(
+            nsec3_owner("example", "example", &salt, 12)?,
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example"
+        );
+        let hash = nsec3_hash("A.EXAMPLE.", &salt, 12)?;
+        assert_eq!(encode_base32hex(&hash), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+        assert_eq!(
+            nsec3_owner_hash("35MTHGPGCU1QG68FAB165KLNSNK3DPVL.example"),
+            Some(hash.clone())
+        );
+        assert!(nsec3_covers(&[1], &[3], &[2]));
+        assert!(nsec3_covers(&[3], &[1], &[4]));
+        assert!(!nsec3_covers(&[3], &[1], &[2]));
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+//! Extension mechanisms for DNS (RFC 6891). These are carried in an OPT
+//! pseudo-record in the additional section, which reuses the class and TTL
+//! fields for its own purposes.
+
+use super::packet::DnsPacket;
+use super::record::{self, Data, EdnsOption, Record, RecordType};
+
+/// Without EDNS, UDP messages cannot be larger than this (RFC 1035 §4.2.1).
+pub const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
+/// A payload size that avoids IP fragmentation on almost all networks
+/// (DNS flag day 2020).
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// The DO bit in the OPT record's TTL field.
+const DNSSEC_OK: u32 = 1 << 15;
+
+/// The option code for Extended DNS Errors (RFC 8914).
+pub const EXTENDED_ERROR: u16 = 15;
+
+/// The Extended DNS Error info code for answers served from stale cache
+/// entries (RFC 8914 §4.4).
+pub const STALE_ANSWER: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Default for Edns {
+    fn default() -> Edns {
+        Edns {
+            udp_payload_size: DEFAULT_UDP_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![],
+        }
+    }
+}
+
+/// Find the EDNS parameters of a packet, if it has an OPT record.
+pub fn parse_edns(packet: &DnsPacket) -> Option<Edns> {
+    let opt = packet
+        .resource_entries
+        .iter()
+        .find(|record| record.record_type == RecordType::Opt)?;
+    let flags = opt.ttl as u32;
+    let options = match &opt.data {
+        Data::Opt(options) => options.clone(),
+        _ => vec![],
+    };
+    Some(Edns {
+        udp_payload_size: record::class_code(&opt.class),
+        extended_rcode: (flags >> 24) as u8,
+        version: (flags >> 16) as u8,
+        dnssec_ok: flags & DNSSEC_OK != 0,
+        options,
+    })
+}
+
+pub fn edns_record(edns: &Edns) -> Record {
+    let mut flags = (edns.extended_rcode as u32) << 24 | (edns.version as u32) << 16;
+    if edns.dnssec_ok {
+        flags |= DNSSEC_OK;
+    }
+    Record {
+        name: ".".to_string(),
+        record_type: RecordType::Opt,
+        class: record::parse_class(edns.udp_payload_size),
+        ttl: flags as i32,
+        data: Data::Opt(edns.options.clone()),
+    }
+}
+
+/// Add an OPT record to a packet, replacing any it already has.
+pub fn set_edns(packet: &mut DnsPacket, edns: &Edns) {
+    packet
+        .resource_entries
+        .retain(|record| record.record_type != RecordType::Opt);
+    packet.resource_entries.push(edns_record(edns));
+}
+
+/// Create an Extended DNS Error option, with optional explanatory text.
+pub fn extended_error(info_code: u16, text: &str) -> EdnsOption {
+    let mut data = info_code.to_be_bytes().to_vec();
+    data.extend(text.as_bytes());
+    EdnsOption {
+        code: EXTENDED_ERROR,
+        data,
+    }
+}
+
+/// The largest UDP response the sender of a request is willing to receive.
+pub fn max_udp_payload_size(request: &DnsPacket) -> u16 {
+    parse_edns(request)
+        .map(|edns| edns.udp_payload_size.max(MIN_UDP_PAYLOAD_SIZE))
+        .unwrap_or(MIN_UDP_PAYLOAD_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error;
+
+    fn packet() -> DnsPacket {
+        dns_packet(include_bytes!("../../examples/query_packet"))
+    }
+
+    fn dns_packet(bytes: &[u8]) -> DnsPacket {
+        crate::parse_dns_packet(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_parse_edns_returns_none_without_opt_record() {
+        assert_eq!(parse_edns(&packet()), None);
+        assert_eq!(max_udp_payload_size(&packet()), 512);
+    }
+
+    #[test]
+    fn test_set_edns_round_trips_through_wire_format(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let edns = Edns {
+            udp_payload_size: 4096,
+            extended_rcode: 1,
+            version: 0,
+            dnssec_ok: true,
+            options: vec![EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }],
+        };
+        let mut packet = packet();
+        set_edns(&mut packet, &edns);
+        set_edns(&mut packet, &edns);
+        assert_eq!(packet.resource_entries.len(), 1);
+
+        let packet = dns_packet(&crate::serialize_dns_packet(&packet)?);
+        assert_eq!(parse_edns(&packet), Some(edns));
+        assert_eq!(max_udp_payload_size(&packet), 4096);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_udp_payload_size_is_at_least_512() {
+        let mut packet = packet();
+        set_edns(
+            &mut packet,
+            &Edns {
+                udp_payload_size: 100,
+                ..Edns::default()
+            },
+        );
+        assert_eq!(max_udp_payload_size(&packet), 512);
+    }
+}
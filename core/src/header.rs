@@ -7,7 +7,15 @@ use std::error;
 // Header
 // --------------------------------------------------
 
-#[derive(Debug)]
+/// The AD bit in the reserved flags, set by validating resolvers when every
+/// record in the answer was verified (RFC 4035 §3.2.3).
+pub const AUTHENTIC_DATA: u8 = 0b010;
+
+/// The CD bit in the reserved flags, set by clients that will do their own
+/// validation and want data even if it fails (RFC 4035 §3.2.2).
+pub const CHECKING_DISABLED: u8 = 0b001;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     pub id: u16,
     pub query: bool,
@@ -30,51 +38,42 @@ pub fn serialize_header(header: &Header) -> Vec<u8> {
     res[0] = id_bytes[0];
     res[1] = id_bytes[1];
     res[2] = (!header.query).as_u8() << 7;
-    res[2] = res[2] | serialize_opcode(&header.opcode) << 4;
-    res[2] = res[2] | header.authoritative_answer.as_u8() << 2;
-    res[2] = res[2] | header.truncation.as_u8() << 1;
-    res[2] = res[2] | header.recursion_desired.as_u8();
+    res[2] |= (serialize_opcode(&header.opcode) & 0b1111) << 3;
+    res[2] |= header.authoritative_answer.as_u8() << 2;
+    res[2] |= header.truncation.as_u8() << 1;
+    res[2] |= header.recursion_desired.as_u8();
     res[3] = header.recursion_available.as_u8() << 7;
-    res[3] = res[3] | header.reserved.as_u8() << 4;
-    res[3] = res[3] | serialize_response_code(&header.rcode);
-    res[4] = (header.questions << 8) as u8;
-    res[5] = header.questions as u8;
-    res[6] = (header.answers << 8) as u8;
-    res[7] = header.answers as u8;
-    res[8] = (header.authoritative_entries << 8) as u8;
-    res[9] = header.authoritative_entries as u8;
-    res[10] = (header.resource_entries << 8) as u8;
-    res[11] = header.resource_entries as u8;
+    res[3] |= (header.reserved & 0b111) << 4;
+    res[3] |= serialize_response_code(&header.rcode) & 0b1111;
+    res[4..6].copy_from_slice(&header.questions.to_be_bytes());
+    res[6..8].copy_from_slice(&header.answers.to_be_bytes());
+    res[8..10].copy_from_slice(&header.authoritative_entries.to_be_bytes());
+    res[10..12].copy_from_slice(&header.resource_entries.to_be_bytes());
     res
 }
 
-pub fn parse_header(packet: &mut ByteBuffer) -> Result<Header, Box<dyn error::Error>> {
+pub fn parse_header(
+    packet: &mut ByteBuffer,
+) -> Result<Header, Box<dyn error::Error + Send + Sync>> {
     let bytes = packet.read_range(12)?;
     let mut header = ByteBuffer::from(bytes);
     let id = header.read_u16()?;
     let flags = header.read_range(2)?;
     let flags = flags.as_bits::<Msb0>().to_bitvec();
-    let query = flags.get(0).context("query flag not found")?.as_bool();
+    let query = !*flags.get(0).context("query flag not found")?;
     let opcode = flags.get(1..5).context("opcode not found")?.load::<u8>();
     let opcode = parse_opcode(opcode);
-    let authoritative_answer = flags
+    let authoritative_answer = *flags
         .get(5)
-        .context("authoritative_answer flag not found")?
-        .as_bool();
-    let truncation = flags.get(6).context("truncation flag not found")?.as_bool();
-    let recursion_desired = flags
-        .get(7)
-        .context("recursion_desired flag not found")?
-        .as_bool();
-    let recursion_available = flags
-        .get(8)
-        .context("recursion_available flag not found")?
-        .as_bool();
+        .context("authoritative_answer flag not found")?;
+    let truncation = *flags.get(6).context("truncation flag not found")?;
+    let recursion_desired = *flags.get(7).context("recursion_desired flag not found")?;
+    let recursion_available = *flags.get(8).context("recursion_available flag not found")?;
     let reserved = flags
-        .get(9..13)
+        .get(9..12)
         .context("reserved flags not found")?
         .load::<u8>();
-    let rcode = flags.get(13..15).context("rcode not found")?.load::<u8>();
+    let rcode = flags.get(12..16).context("rcode not found")?.load::<u8>();
     let rcode = parse_response_code(rcode);
     let questions = header.read_u16()?;
     let answers = header.read_u16()?;
@@ -101,11 +100,15 @@ pub fn parse_header(packet: &mut ByteBuffer) -> Result<Header, Box<dyn error::Er
 // Opcode
 // --------------------------------------------------
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     Query,
     InverseQuery,
     Status,
+    /// A primary telling its secondaries that a zone changed (RFC 1996).
+    Notify,
+    /// Changes to a zone's records (RFC 2136).
+    Update,
     Unknown(u8),
 }
 
@@ -114,24 +117,40 @@ pub fn parse_opcode(value: u8) -> Opcode {
         0 => Opcode::Query,
         1 => Opcode::InverseQuery,
         2 => Opcode::Status,
+        4 => Opcode::Notify,
+        5 => Opcode::Update,
         _ => Opcode::Unknown(value),
     }
 }
 
 pub fn serialize_opcode(opcode: &Opcode) -> u8 {
     match opcode {
-        Opcode::Query => 1,
-        Opcode::InverseQuery => 2,
-        Opcode::Status => 3,
+        Opcode::Query => 0,
+        Opcode::InverseQuery => 1,
+        Opcode::Status => 2,
+        Opcode::Notify => 4,
+        Opcode::Update => 5,
         Opcode::Unknown(value) => *value,
     }
 }
 
+/// The mnemonic for an opcode, as used by tools like dig.
+pub fn opcode_name(opcode: &Opcode) -> String {
+    match opcode {
+        Opcode::Query => "QUERY".to_string(),
+        Opcode::InverseQuery => "IQUERY".to_string(),
+        Opcode::Status => "STATUS".to_string(),
+        Opcode::Notify => "NOTIFY".to_string(),
+        Opcode::Update => "UPDATE".to_string(),
+        Opcode::Unknown(value) => format!("OPCODE{}", value),
+    }
+}
+
 // --------------------------------------------------
 // Response Code
 // --------------------------------------------------
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResponseCode {
     Success,
     FormatError,
@@ -139,6 +158,17 @@ pub enum ResponseCode {
     NameError,
     NotImplemented,
     Refused,
+    /// A name that an update requires not to exist does (RFC 2136 §2.2).
+    YxDomain,
+    /// An RRset that an update requires not to exist does.
+    YxRrset,
+    /// An RRset that an update requires to exist doesn't.
+    NxRrset,
+    /// The server isn't authoritative for the zone, or the request's TSIG
+    /// signature didn't verify (RFC 8945 §5.2).
+    NotAuth,
+    /// A name in an update is outside the zone being updated.
+    NotZone,
     Unknown(u8),
 }
 
@@ -150,22 +180,50 @@ pub fn parse_response_code(value: u8) -> ResponseCode {
         3 => ResponseCode::NameError,
         4 => ResponseCode::NotImplemented,
         5 => ResponseCode::Refused,
+        6 => ResponseCode::YxDomain,
+        7 => ResponseCode::YxRrset,
+        8 => ResponseCode::NxRrset,
+        9 => ResponseCode::NotAuth,
+        10 => ResponseCode::NotZone,
         _ => ResponseCode::Unknown(value),
     }
 }
 
 pub fn serialize_response_code(response_code: &ResponseCode) -> u8 {
     match response_code {
-        ResponseCode::Success => 1,
-        ResponseCode::FormatError => 2,
-        ResponseCode::ServerFailure => 3,
-        ResponseCode::NameError => 4,
-        ResponseCode::NotImplemented => 5,
-        ResponseCode::Refused => 6,
+        ResponseCode::Success => 0,
+        ResponseCode::FormatError => 1,
+        ResponseCode::ServerFailure => 2,
+        ResponseCode::NameError => 3,
+        ResponseCode::NotImplemented => 4,
+        ResponseCode::Refused => 5,
+        ResponseCode::YxDomain => 6,
+        ResponseCode::YxRrset => 7,
+        ResponseCode::NxRrset => 8,
+        ResponseCode::NotAuth => 9,
+        ResponseCode::NotZone => 10,
         ResponseCode::Unknown(value) => *value,
     }
 }
 
+/// The mnemonic for a response code, as used by tools like dig.
+pub fn response_code_name(response_code: &ResponseCode) -> String {
+    match response_code {
+        ResponseCode::Success => "NOERROR".to_string(),
+        ResponseCode::FormatError => "FORMERR".to_string(),
+        ResponseCode::ServerFailure => "SERVFAIL".to_string(),
+        ResponseCode::NameError => "NXDOMAIN".to_string(),
+        ResponseCode::NotImplemented => "NOTIMP".to_string(),
+        ResponseCode::Refused => "REFUSED".to_string(),
+        ResponseCode::YxDomain => "YXDOMAIN".to_string(),
+        ResponseCode::YxRrset => "YXRRSET".to_string(),
+        ResponseCode::NxRrset => "NXRRSET".to_string(),
+        ResponseCode::NotAuth => "NOTAUTH".to_string(),
+        ResponseCode::NotZone => "NOTZONE".to_string(),
+        ResponseCode::Unknown(value) => format!("RCODE{}", value),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -173,13 +231,13 @@ mod tests {
     // From examples/query_packet
     const QUERY_ID: u16 = 9398;
     const QUERY_QUERY: bool = true;
-    const QUERY_OPCODE: u8 = 0;
-    const QUERY_AUTHORITATIVE_ANSWER: bool = true;
-    const QUERY_TRUNCATION: bool = true;
-    const QUERY_RECURSION_DESIRED: bool = false;
-    const QUERY_RECURSION_AVAILABLE: bool = true;
-    const QUERY_RESERVED: u8 = 0b100;
-    const QUERY_RCODE: u8 = 0;
+    const QUERY_OPCODE: Opcode = Opcode::Query;
+    const QUERY_AUTHORITATIVE_ANSWER: bool = false;
+    const QUERY_TRUNCATION: bool = false;
+    const QUERY_RECURSION_DESIRED: bool = true;
+    const QUERY_RECURSION_AVAILABLE: bool = false;
+    const QUERY_RESERVED: u8 = 0b010;
+    const QUERY_RCODE: ResponseCode = ResponseCode::Success;
     const QUERY_QUESTIONS: u16 = 1;
     const QUERY_ANSWERS: u16 = 0;
     const QUERY_AUTHORITATIVE_ENTRIES: u16 = 0;
@@ -190,36 +248,50 @@ mod tests {
         let header = Header {
             id: 100,
             query: false,
-            opcode: 0b111,
+            opcode: Opcode::Unknown(0b111),
             authoritative_answer: true,
             truncation: false,
             recursion_desired: true,
             recursion_available: false,
             reserved: 0b000,
-            rcode: 0b1111,
+            rcode: ResponseCode::Unknown(0b1111),
             questions: 3,
             answers: 4,
             authoritative_entries: 5,
             resource_entries: 6,
         };
         let mut expected = vec![0; 12];
-        expected[0] = (100 << 8) as u8;
-        expected[1] = 100 as u8;
-        expected[2] = 0b01110101;
-        expected[3] = 0b0001111;
-        expected[4] = (3 << 8) as u8;
-        expected[5] = 3 as u8;
-        expected[6] = (4 << 8) as u8;
-        expected[7] = 4 as u8;
-        expected[8] = (5 << 8) as u8;
-        expected[9] = 5 as u8;
-        expected[10] = (6 << 8) as u8;
-        expected[11] = 6 as u8;
+        expected[0] = 0;
+        expected[1] = 100;
+        expected[2] = 0b10111101;
+        expected[3] = 0b00001111;
+        expected[4] = 0;
+        expected[5] = 3;
+        expected[6] = 0;
+        expected[7] = 4;
+        expected[8] = 0;
+        expected[9] = 5;
+        expected[10] = 0;
+        expected[11] = 6;
         assert_eq!(serialize_header(&header), expected);
     }
 
     #[test]
-    fn test_parse_header_returns_expected_id() -> Result<(), Box<dyn error::Error>> {
+    fn test_serialize_header_round_trips_response_packet(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let packet = include_bytes!("../../examples/response_packet");
+        let bytes = &packet[0..12];
+
+        let mut buf = ByteBuffer::from(bytes);
+        let header = parse_header(&mut buf)?;
+        assert!(!header.query);
+        assert_eq!(header.rcode, ResponseCode::Success);
+        assert_eq!(serialize_header(&header), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_header_returns_expected_id() -> Result<(), Box<dyn error::Error + Send + Sync>> {
         let packet = include_bytes!("../../examples/query_packet");
         let bytes = &packet[0..12];
 
@@ -0,0 +1,347 @@
+//! Changes between versions of a zone, as carried by incremental zone
+//! transfers (RFC 1995).
+
+use super::dnssec;
+use super::name;
+use super::record::{Data, Record, RecordType};
+use super::zone::Zone;
+use anyhow::anyhow;
+use std::collections::VecDeque;
+use std::error;
+
+/// How many changes a journal keeps before forgetting the oldest.
+pub const DEFAULT_JOURNAL_LEN: usize = 100;
+
+/// The records removed and added to go from one version of a zone to the
+/// next. The SOA records aren't part of either list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// The SOA record of the version this applies to.
+    pub from: Record,
+    /// The SOA record of the version it produces.
+    pub to: Record,
+    pub removed: Vec<Record>,
+    pub added: Vec<Record>,
+}
+
+impl Diff {
+    /// Work out what changed between two versions of a zone. Returns `None`
+    /// if either has no SOA record.
+    pub fn between(old: &Zone, new: &Zone) -> Option<Diff> {
+        let changed = |from: &Zone, to: &Zone| -> Vec<Record> {
+            from.records()
+                .filter(|record| record.record_type != RecordType::Soa)
+                .filter(|record| !to.lookup(&record.name).contains(record))
+                .cloned()
+                .collect()
+        };
+        Some(Diff {
+            from: old.soa()?.clone(),
+            to: new.soa()?.clone(),
+            removed: changed(old, new),
+            added: changed(new, old),
+        })
+    }
+
+    /// Apply the changes to a zone, which must be at the version they start
+    /// from.
+    pub fn apply(&self, zone: &mut Zone) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if zone.soa() != Some(&self.from) {
+            return Err(anyhow!(
+                "changes to {} start from serial {:?}, but the zone is at {:?}",
+                zone.origin,
+                serial(&self.from),
+                zone.serial()
+            )
+            .into());
+        }
+        zone.remove(&self.from);
+        for record in &self.removed {
+            if !zone.remove(record) {
+                return Err(anyhow!("{} has no record {:?} to remove", zone.origin, record).into());
+            }
+        }
+        zone.insert(self.to.clone())?;
+        for record in &self.added {
+            zone.insert(record.clone())?;
+        }
+        Ok(())
+    }
+
+    /// The records of the difference sequence for this change, as sent in
+    /// an IXFR response.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        std::iter::once(&self.from)
+            .chain(&self.removed)
+            .chain(std::iter::once(&self.to))
+            .chain(&self.added)
+    }
+}
+
+/// The most recent changes to a zone, so that secondaries which are only a
+/// few versions behind can catch up without transferring the whole zone.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    diffs: VecDeque<Diff>,
+    max_len: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Journal {
+        Journal::new(DEFAULT_JOURNAL_LEN)
+    }
+}
+
+impl Journal {
+    pub fn new(max_len: usize) -> Journal {
+        Journal {
+            diffs: VecDeque::new(),
+            max_len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.diffs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diffs.is_empty()
+    }
+
+    /// Record the next change to the zone. If it doesn't follow on from the
+    /// last one, the history is broken and the older changes are forgotten.
+    pub fn push(&mut self, diff: Diff) {
+        if self.diffs.back().is_some_and(|last| last.to != diff.from) {
+            self.diffs.clear();
+        }
+        self.diffs.push_back(diff);
+        while self.diffs.len() > self.max_len {
+            self.diffs.pop_front();
+        }
+    }
+
+    /// Forget every change, such as when a zone is replaced by one that
+    /// isn't a newer version of it.
+    pub fn clear(&mut self) {
+        self.diffs.clear();
+    }
+
+    /// The changes needed to bring a zone at `serial` up to date, or `None`
+    /// if they go back further than the journal does.
+    pub fn since(&self, serial: u32) -> Option<Vec<&Diff>> {
+        let start = self
+            .diffs
+            .iter()
+            .position(|diff| self::serial(&diff.from) == Some(serial))?;
+        Some(self.diffs.range(start..).collect())
+    }
+}
+
+/// What a server sent in answer to a zone transfer request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transfer {
+    /// The client already has the latest version.
+    UpToDate,
+    /// The whole zone, starting with its SOA record.
+    Full(Vec<Record>),
+    /// The changes to make to the client's version, in order.
+    Incremental(Vec<Diff>),
+}
+
+/// Interpret the answer records of an AXFR or IXFR response, which may span
+/// several messages. Returns `None` until enough records have arrived to
+/// complete the transfer. `serial` is the version the client has, if any.
+pub fn parse_transfer(
+    origin: &str,
+    records: &[Record],
+    serial: Option<u32>,
+) -> Result<Option<Transfer>, Box<dyn error::Error + Send + Sync>> {
+    let is_soa =
+        |record: &Record| record.record_type == RecordType::Soa && name::eq(&record.name, origin);
+    let first = match records.first() {
+        Some(first) if is_soa(first) => first,
+        Some(_) => return Err(anyhow!("transfer of {} didn't start with SOA", origin).into()),
+        None => return Ok(None),
+    };
+    let latest = self::serial(first);
+    if records.len() == 1 {
+        let up_to_date = match (serial, latest) {
+            (Some(serial), Some(latest)) => !dnssec::time_before(serial, latest),
+            _ => false,
+        };
+        return Ok(up_to_date.then_some(Transfer::UpToDate));
+    }
+
+    // A difference sequence starts with the SOA of an older version, while
+    // a full zone goes straight on to its other records. A zone with no
+    // other records looks like an empty difference sequence, but is sent as
+    // the same SOA record twice.
+    if !is_soa(&records[1]) || (records.len() == 2 && records[1] == *first) {
+        let end = match records[1..].iter().position(is_soa) {
+            Some(end) => end + 1,
+            None => return Ok(None),
+        };
+        if records[end] != *first {
+            return Err(anyhow!("serial of {} changed during transfer", origin).into());
+        }
+        if end != records.len() - 1 {
+            return Err(anyhow!("transfer of {} has records after its last SOA", origin).into());
+        }
+        return Ok(Some(Transfer::Full(records[..end].to_vec())));
+    }
+
+    let mut diffs = vec![];
+    let mut rest = &records[1..];
+    loop {
+        let (from, tail) = match rest.split_first() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+        if from == first {
+            if !tail.is_empty() {
+                return Err(
+                    anyhow!("transfer of {} has records after its last SOA", origin).into(),
+                );
+            }
+            return Ok(Some(Transfer::Incremental(diffs)));
+        }
+        let to = match tail.iter().position(is_soa) {
+            Some(to) => to,
+            None => return Ok(None),
+        };
+        let added = &tail[to + 1..];
+        let end = match added.iter().position(is_soa) {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        diffs.push(Diff {
+            from: from.clone(),
+            to: tail[to].clone(),
+            removed: tail[..to].to_vec(),
+            added: added[..end].to_vec(),
+        });
+        rest = &added[end..];
+    }
+}
+
+/// The serial number in an SOA record.
+pub fn serial(soa: &Record) -> Option<u32> {
+    match soa.data {
+        Data::Soa { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone;
+
+    fn zone(serial: u32, records: &str) -> Zone {
+        let text = format!("@ 60 SOA ns hostmaster {} 2 3 4 5\n{}", serial, records);
+        zone::parse_zone(&text, Some("example.com")).unwrap()
+    }
+
+    #[test]
+    fn test_diff_between_versions_applies_to_old_version() {
+        let old = zone(1, "@ 60 NS ns\nns 60 A 192.0.2.1\nold 60 A 192.0.2.2");
+        let new = zone(2, "@ 60 NS ns\nns 60 A 192.0.2.9\nnew 60 A 192.0.2.3");
+        let diff = Diff::between(&old, &new).unwrap();
+        assert_eq!(diff.removed.len(), 2);
+        assert_eq!(diff.added.len(), 2);
+        assert_eq!(diff.records().count(), 6);
+
+        let mut zone = old.clone();
+        diff.apply(&mut zone).unwrap();
+        assert_eq!(zone, new);
+        // It can't be applied twice
+        assert!(diff.apply(&mut zone).is_err());
+    }
+
+    #[test]
+    fn test_journal_finds_changes_since_serial() {
+        let versions: Vec<Zone> = (1..=4)
+            .map(|serial| zone(serial, &format!("www 60 A 192.0.2.{}", serial)))
+            .collect();
+        let mut journal = Journal::new(2);
+        for pair in versions.windows(2) {
+            journal.push(Diff::between(&pair[0], &pair[1]).unwrap());
+        }
+        assert_eq!(journal.len(), 2);
+        assert!(journal.since(1).is_none());
+        let diffs = journal.since(2).unwrap();
+        assert_eq!(diffs.len(), 2);
+        let mut zone = versions[1].clone();
+        for diff in diffs {
+            diff.apply(&mut zone).unwrap();
+        }
+        assert_eq!(zone, versions[3]);
+        assert!(journal.since(4).is_none());
+
+        // A change that doesn't follow on starts the history again
+        journal.push(Diff::between(&versions[0], &versions[1]).unwrap());
+        assert_eq!(journal.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_transfer_handles_each_kind_of_response() {
+        let old = zone(1, "www 60 A 192.0.2.1");
+        let middle = zone(2, "www 60 A 192.0.2.2");
+        let new = zone(3, "www 60 A 192.0.2.2\nftp 60 A 192.0.2.3");
+        let soa = new.soa().unwrap().clone();
+
+        // Difference sequences, which arrive a few records at a time
+        let diffs = vec![
+            Diff::between(&old, &middle).unwrap(),
+            Diff::between(&middle, &new).unwrap(),
+        ];
+        let mut records = vec![soa.clone()];
+        records.extend(diffs.iter().flat_map(|diff| diff.records()).cloned());
+        records.push(soa.clone());
+        for len in 1..records.len() {
+            assert_eq!(
+                parse_transfer("example.com", &records[..len], Some(1)).unwrap(),
+                None
+            );
+        }
+        assert_eq!(
+            parse_transfer("example.com", &records, Some(1)).unwrap(),
+            Some(Transfer::Incremental(diffs))
+        );
+
+        // A whole zone
+        let mut records: Vec<Record> = new.records().cloned().collect();
+        records.push(soa.clone());
+        assert_eq!(
+            parse_transfer("example.com", &records[..2], None).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_transfer("example.com", &records, Some(1)).unwrap(),
+            Some(Transfer::Full(new.records().cloned().collect()))
+        );
+        let only_soa = vec![soa.clone(), soa.clone()];
+        assert_eq!(
+            parse_transfer("example.com", &only_soa, None).unwrap(),
+            Some(Transfer::Full(vec![soa.clone()]))
+        );
+
+        // Already up to date
+        assert_eq!(
+            parse_transfer("example.com", std::slice::from_ref(&soa), Some(3)).unwrap(),
+            Some(Transfer::UpToDate)
+        );
+        assert_eq!(
+            parse_transfer("example.com", std::slice::from_ref(&soa), Some(1)).unwrap(),
+            None
+        );
+
+        // Malformed responses
+        let www = old.lookup("www.example.com")[0].clone();
+        assert!(parse_transfer("example.com", std::slice::from_ref(&www), None).is_err());
+        let changed = vec![soa.clone(), www.clone(), old.soa().unwrap().clone()];
+        assert!(parse_transfer("example.com", &changed, None).is_err());
+        let trailing = vec![soa.clone(), www.clone(), soa.clone(), www];
+        assert!(parse_transfer("example.com", &trailing, None).is_err());
+    }
+}
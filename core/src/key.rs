@@ -0,0 +1,711 @@
+//! Private keys for signing zones, kept in the key files that BIND's
+//! dnssec-keygen writes: `K<zone>+<alg>+<tag>.key` holds the DNSKEY record,
+//! and `K<zone>+<alg>+<tag>.private` the private half of the key, along with
+//! the times the key moves through a rollover.
+
+use super::dnssec;
+use super::name;
+use super::record::{Class, Data, Record, RecordType};
+use super::zone;
+use anyhow::{anyhow, Context};
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+use ring::rsa;
+use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{error, fmt, fs};
+
+/// The TTL given to DNSKEY records in key files that don't have one.
+const DEFAULT_KEY_TTL: i32 = 3600;
+
+/// A DNSKEY record of a zone, and the private key it was made from.
+pub struct SigningKey {
+    /// The zone the key belongs to.
+    pub owner: String,
+    pub ttl: i32,
+    pub dnskey: Data,
+    pub timing: KeyTiming,
+    private: PrivateKey,
+}
+
+enum PrivateKey {
+    Rsa(RsaKeyPair),
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+/// Where a key is in a rollover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    /// Not in the zone yet.
+    Unpublished,
+    /// In the zone's DNSKEY RRset but not signing yet, so that caches learn
+    /// about the key before it's needed.
+    Published,
+    /// Published and signing.
+    Active,
+    /// No longer signing, but still published until signatures made with it
+    /// have expired from caches.
+    Retired,
+    /// Removed from the zone.
+    Deleted,
+}
+
+/// When a key changes state, as RRSIG-style times in seconds since the
+/// epoch. Keys without a publish or activate time have always been
+/// published or active, and those without an inactive or delete time never
+/// stop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyTiming {
+    pub created: Option<u32>,
+    pub publish: Option<u32>,
+    pub activate: Option<u32>,
+    pub inactive: Option<u32>,
+    pub delete: Option<u32>,
+}
+
+/// The timing fields of a `.private` file, in the order they are written.
+const TIMING_FIELDS: [&str; 5] = ["Created", "Publish", "Activate", "Inactive", "Delete"];
+
+impl KeyTiming {
+    pub fn state(&self, now: u32) -> KeyState {
+        let reached = |time: Option<u32>| time.map(|time| !dnssec::time_before(now, time));
+        if reached(self.delete) == Some(true) {
+            KeyState::Deleted
+        } else if reached(self.inactive) == Some(true) {
+            KeyState::Retired
+        } else if reached(self.activate) != Some(false) {
+            KeyState::Active
+        } else if reached(self.publish) != Some(false) {
+            KeyState::Published
+        } else {
+            KeyState::Unpublished
+        }
+    }
+
+    /// The next time after `now` that the key changes state.
+    pub fn next_change(&self, now: u32) -> Option<u32> {
+        [self.publish, self.activate, self.inactive, self.delete]
+            .into_iter()
+            .flatten()
+            .filter(|time| dnssec::time_before(now, *time))
+            .min_by_key(|time| time.wrapping_sub(now))
+    }
+
+    /// Check that the times come in the order a rollover goes through them.
+    pub fn validate(&self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let times = [self.publish, self.activate, self.inactive, self.delete];
+        let names = &TIMING_FIELDS[1..];
+        for (i, earlier) in times.iter().enumerate() {
+            for (j, later) in times.iter().enumerate().skip(i + 1) {
+                if let (Some(earlier), Some(later)) = (earlier, later) {
+                    if dnssec::time_before(*later, *earlier) {
+                        return Err(anyhow!(
+                            "{} time is after {} time",
+                            names[i].to_lowercase(),
+                            names[j].to_lowercase()
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, field: &str) -> Option<u32> {
+        match field {
+            "Created" => self.created,
+            "Publish" => self.publish,
+            "Activate" => self.activate,
+            "Inactive" => self.inactive,
+            "Delete" => self.delete,
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, field: &str, time: u32) {
+        match field {
+            "Created" => self.created = Some(time),
+            "Publish" => self.publish = Some(time),
+            "Activate" => self.activate = Some(time),
+            "Inactive" => self.inactive = Some(time),
+            "Delete" => self.delete = Some(time),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Leave the private key out, so it doesn't end up in logs
+        f.debug_struct("SigningKey")
+            .field("owner", &self.owner)
+            .field("flags", &self.flags())
+            .field("algorithm", &self.algorithm())
+            .field("key_tag", &self.key_tag())
+            .field("timing", &self.timing)
+            .finish()
+    }
+}
+
+impl SigningKey {
+    /// Load a key from its pair of key files. The path may name either file,
+    /// or leave the extension off.
+    pub fn load(path: &Path) -> Result<SigningKey, Box<dyn error::Error + Send + Sync>> {
+        let base = key_file_base(path);
+        let public_path = format!("{}.key", base);
+        let private_path = format!("{}.private", base);
+        let public = fs::read_to_string(&public_path)
+            .with_context(|| format!("could not read {}", public_path))?;
+        let private = fs::read_to_string(&private_path)
+            .with_context(|| format!("could not read {}", private_path))?;
+        SigningKey::parse(&public, &private)
+            .map_err(|err| anyhow!("invalid key {}: {}", base, err).into())
+    }
+
+    /// Parse a key from the contents of its `.key` and `.private` files.
+    pub fn parse(
+        public: &str,
+        private: &str,
+    ) -> Result<SigningKey, Box<dyn error::Error + Send + Sync>> {
+        let text = format!("$TTL {}\n{}", DEFAULT_KEY_TTL, public);
+        let record = zone::parse_master_file(&text, None)?
+            .into_iter()
+            .find(|record| record.record_type == RecordType::Dnskey)
+            .context("no DNSKEY record in key file")?;
+        let (algorithm, public_key) = match &record.data {
+            Data::Dnskey {
+                algorithm,
+                public_key,
+                ..
+            } => (*algorithm, public_key),
+            _ => return Err(anyhow!("no DNSKEY record in key file").into()),
+        };
+        let fields = PrivateFields::parse(private)?;
+        let private_algorithm = fields.get("Algorithm")?;
+        let private_algorithm = private_algorithm
+            .split_whitespace()
+            .next()
+            .and_then(|value| value.parse::<u8>().ok())
+            .with_context(|| format!("invalid algorithm {}", private_algorithm))?;
+        if private_algorithm != algorithm {
+            return Err(anyhow!("key files are for different algorithms").into());
+        }
+        let rejected = |err: ring::error::KeyRejected| anyhow!("private key rejected: {}", err);
+        let private = match algorithm {
+            dnssec::RSASHA256 => {
+                let modulus = fields.decode("Modulus")?;
+                let exponent = fields.decode("PublicExponent")?;
+                if rsa_public_key(&exponent, &modulus) != *public_key {
+                    return Err(anyhow!("private key doesn't match the DNSKEY").into());
+                }
+                let components = rsa::KeyPairComponents {
+                    public_key: rsa::PublicKeyComponents {
+                        n: modulus,
+                        e: exponent,
+                    },
+                    d: fields.decode("PrivateExponent")?,
+                    p: fields.decode("Prime1")?,
+                    q: fields.decode("Prime2")?,
+                    dP: fields.decode("Exponent1")?,
+                    dQ: fields.decode("Exponent2")?,
+                    qInv: fields.decode("Coefficient")?,
+                };
+                PrivateKey::Rsa(RsaKeyPair::from_components(&components).map_err(rejected)?)
+            }
+            dnssec::ECDSAP256SHA256 | dnssec::ECDSAP384SHA384 => {
+                let signing = if algorithm == dnssec::ECDSAP256SHA256 {
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING
+                } else {
+                    &signature::ECDSA_P384_SHA384_FIXED_SIGNING
+                };
+                let mut point = vec![0x04];
+                point.extend(public_key);
+                let pair = EcdsaKeyPair::from_private_key_and_public_key(
+                    signing,
+                    &fields.decode("PrivateKey")?,
+                    &point,
+                    &SystemRandom::new(),
+                )
+                .map_err(rejected)?;
+                PrivateKey::Ecdsa(pair)
+            }
+            dnssec::ED25519 => {
+                let pair = Ed25519KeyPair::from_seed_and_public_key(
+                    &fields.decode("PrivateKey")?,
+                    public_key,
+                )
+                .map_err(rejected)?;
+                PrivateKey::Ed25519(pair)
+            }
+            _ => return Err(anyhow!("unsupported algorithm {}", algorithm).into()),
+        };
+        let mut timing = KeyTiming::default();
+        for field in TIMING_FIELDS {
+            if let Ok(value) = fields.get(field) {
+                timing.set(field, dnssec::parse_time(value)?);
+            }
+        }
+        Ok(SigningKey {
+            owner: record.name,
+            ttl: record.ttl,
+            dnskey: record.data,
+            timing,
+            private,
+        })
+    }
+
+    /// Where the key is in its rollover at a point in time.
+    pub fn state(&self, now: SystemTime) -> KeyState {
+        self.timing.state(dnssec::signature_time(now))
+    }
+
+    pub fn algorithm(&self) -> u8 {
+        match &self.dnskey {
+            Data::Dnskey { algorithm, .. } => *algorithm,
+            _ => 0,
+        }
+    }
+
+    pub fn flags(&self) -> u16 {
+        match &self.dnskey {
+            Data::Dnskey { flags, .. } => *flags,
+            _ => 0,
+        }
+    }
+
+    pub fn key_tag(&self) -> u16 {
+        dnssec::key_tag(&self.dnskey).unwrap_or(0)
+    }
+
+    /// Returns true for key signing keys, which have the SEP flag set. These
+    /// sign the zone's DNSKEY RRset, and are what the parent's DS records
+    /// point at.
+    pub fn is_key_signing_key(&self) -> bool {
+        self.flags() & dnssec::SECURE_ENTRY_POINT != 0
+    }
+
+    pub fn dnskey_record(&self) -> Record {
+        Record {
+            name: self.owner.clone(),
+            record_type: RecordType::Dnskey,
+            class: Class::In,
+            ttl: self.ttl,
+            data: self.dnskey.clone(),
+        }
+    }
+
+    /// The DS record that points at this key from the parent zone.
+    pub fn ds_record(
+        &self,
+        digest_type: u8,
+    ) -> Result<Record, Box<dyn error::Error + Send + Sync>> {
+        Ok(Record {
+            name: self.owner.clone(),
+            record_type: RecordType::Ds,
+            class: Class::In,
+            ttl: self.ttl,
+            data: dnssec::ds_data(&self.owner, &self.dnskey, digest_type)?,
+        })
+    }
+
+    /// Sign an RRset on behalf of the key's zone. The signature is valid from
+    /// `inception` until `expiration`.
+    pub fn sign(
+        &self,
+        rrset: &[Record],
+        inception: u32,
+        expiration: u32,
+    ) -> Result<Record, Box<dyn error::Error + Send + Sync>> {
+        let first = rrset.first().context("cannot sign an empty RRset")?;
+        if !name::is_subdomain(&first.name, &self.owner) {
+            return Err(anyhow!("{} is outside of zone {}", first.name, self.owner).into());
+        }
+        let rrsig = |signature| Data::Rrsig {
+            type_covered: first.record_type,
+            algorithm: self.algorithm(),
+            labels: dnssec::signature_labels(&first.name),
+            original_ttl: first.ttl.max(0) as u32,
+            expiration,
+            inception,
+            key_tag: self.key_tag(),
+            signer_name: self.owner.clone(),
+            signature,
+        };
+        let message = dnssec::signed_data(&rrsig(vec![]), rrset)?;
+        let rng = SystemRandom::new();
+        let failed = |_| anyhow!("could not sign {}", first.name);
+        let signature = match &self.private {
+            PrivateKey::Rsa(pair) => {
+                let mut signature = vec![0; pair.public().modulus_len()];
+                pair.sign(&signature::RSA_PKCS1_SHA256, &rng, &message, &mut signature)
+                    .map_err(failed)?;
+                signature
+            }
+            PrivateKey::Ecdsa(pair) => pair.sign(&rng, &message).map_err(failed)?.as_ref().to_vec(),
+            PrivateKey::Ed25519(pair) => pair.sign(&message).as_ref().to_vec(),
+        };
+        Ok(Record {
+            name: first.name.clone(),
+            record_type: RecordType::Rrsig,
+            class: first.class,
+            ttl: first.ttl,
+            data: rrsig(signature),
+        })
+    }
+}
+
+/// A newly generated key, as the contents of its pair of key files.
+#[derive(Clone)]
+pub struct KeyFiles {
+    /// The files' name without an extension, `K<zone>+<alg>+<tag>`.
+    pub name: String,
+    pub public: String,
+    pub private: String,
+}
+
+impl fmt::Debug for KeyFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyFiles")
+            .field("name", &self.name)
+            .field("public", &self.public)
+            .finish()
+    }
+}
+
+impl KeyFiles {
+    pub fn load(&self) -> Result<SigningKey, Box<dyn error::Error + Send + Sync>> {
+        SigningKey::parse(&self.public, &self.private)
+    }
+
+    /// Write the key files to a directory, refusing to overwrite existing
+    /// keys. Returns the path of the files without their extension. Like
+    /// BIND, only the owner may read the private key.
+    pub fn save(&self, directory: &Path) -> Result<PathBuf, Box<dyn error::Error + Send + Sync>> {
+        for (extension, contents) in [("key", &self.public), ("private", &self.private)] {
+            let path = directory.join(format!("{}.{}", self.name, extension));
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            if extension == "private" {
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            }
+            let mut file = options
+                .open(&path)
+                .with_context(|| format!("could not create {}", path.display()))?;
+            file.write_all(contents.as_bytes())?;
+        }
+        Ok(directory.join(&self.name))
+    }
+}
+
+/// Generate a key for a zone with one of the elliptic curve algorithms,
+/// ECDSAP256SHA256 or ED25519. `flags` should include the zone key flag, and
+/// the SEP flag for key signing keys.
+pub fn generate_key(
+    owner: &str,
+    algorithm: u8,
+    flags: u16,
+    timing: &KeyTiming,
+) -> Result<KeyFiles, Box<dyn error::Error + Send + Sync>> {
+    timing.validate()?;
+    let rng = SystemRandom::new();
+    let failed = |_| anyhow!("could not generate a key");
+    let (mnemonic, public_key, private_key) = match algorithm {
+        dnssec::ECDSAP256SHA256 => {
+            let signing = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(signing, &rng).map_err(failed)?;
+            let pair = EcdsaKeyPair::from_pkcs8(signing, pkcs8.as_ref(), &rng)
+                .map_err(|err| anyhow!("generated key rejected: {}", err))?;
+            let private_key = ec_private_key(pkcs8.as_ref()).context("invalid generated key")?;
+            // DNSKEYs leave off the uncompressed point marker (RFC 6605 §4)
+            let public_key = signature::KeyPair::public_key(&pair).as_ref()[1..].to_vec();
+            ("ECDSAP256SHA256", public_key, private_key)
+        }
+        dnssec::ED25519 => {
+            let mut seed = [0; 32];
+            rng.fill(&mut seed).map_err(failed)?;
+            let pair = Ed25519KeyPair::from_seed_unchecked(&seed)
+                .map_err(|err| anyhow!("generated key rejected: {}", err))?;
+            let public_key = signature::KeyPair::public_key(&pair).as_ref().to_vec();
+            ("ED25519", public_key, seed.to_vec())
+        }
+        _ => return Err(anyhow!("cannot generate keys for algorithm {}", algorithm).into()),
+    };
+    let record = Record {
+        name: name::normalize(owner),
+        record_type: RecordType::Dnskey,
+        class: Class::In,
+        ttl: DEFAULT_KEY_TTL,
+        data: Data::Dnskey {
+            flags,
+            protocol: 3,
+            algorithm,
+            public_key,
+        },
+    };
+    let key_tag = dnssec::key_tag(&record.data).context("invalid generated key")?;
+    let fqdn = match record.name.as_str() {
+        "." => ".".to_string(),
+        owner => format!("{}.", owner),
+    };
+    let kind = match flags & dnssec::SECURE_ENTRY_POINT {
+        0 => "zone-signing",
+        _ => "key-signing",
+    };
+    let mut public = format!(
+        "; This is a {} key, keyid {}, for {}\n",
+        kind, key_tag, fqdn
+    );
+    let mut private = format!(
+        "Private-key-format: v1.3\nAlgorithm: {} ({})\nPrivateKey: {}\n",
+        algorithm,
+        mnemonic,
+        dnssec::encode_base64(&private_key)
+    );
+    for field in TIMING_FIELDS {
+        if let Some(time) = timing.get(field) {
+            public += &format!("; {}: {}\n", field, dnssec::format_time(time));
+            private += &format!("{}: {}\n", field, dnssec::format_time(time));
+        }
+    }
+    public += &zone::format_record(&record);
+    public += "\n";
+    Ok(KeyFiles {
+        name: format!("K{}+{:03}+{:05}", fqdn, algorithm, key_tag),
+        public,
+        private,
+    })
+}
+
+/// The private scalar of a P-256 key in PKCS #8, which is the octet string
+/// following the version in the ECPrivateKey structure (RFC 5915 §3).
+fn ec_private_key(pkcs8: &[u8]) -> Option<Vec<u8>> {
+    const PREFIX: [u8; 5] = [0x02, 0x01, 0x01, 0x04, 0x20];
+    let start = pkcs8
+        .windows(PREFIX.len())
+        .position(|window| window == PREFIX)?
+        + PREFIX.len();
+    pkcs8.get(start..start + 32).map(|key| key.to_vec())
+}
+
+/// The name of a pair of key files without its extension. Zone names are
+/// full of dots, so only the two extensions we know of are removed.
+fn key_file_base(path: &Path) -> String {
+    let path = path.display().to_string();
+    for extension in [".key", ".private"] {
+        if let Some(base) = path.strip_suffix(extension) {
+            return base.to_string();
+        }
+    }
+    path
+}
+
+/// An RSA public key in DNSKEY format (RFC 3110 §2).
+fn rsa_public_key(exponent: &[u8], modulus: &[u8]) -> Vec<u8> {
+    let mut key = match exponent.len() {
+        len @ 0..=255 => vec![len as u8],
+        len => vec![0, (len >> 8) as u8, len as u8],
+    };
+    key.extend(exponent);
+    key.extend(modulus);
+    key
+}
+
+/// The `Field: value` lines of a `.private` file.
+struct PrivateFields(Vec<(String, String)>);
+
+impl PrivateFields {
+    fn parse(text: &str) -> Result<PrivateFields, Box<dyn error::Error + Send + Sync>> {
+        let mut fields = vec![];
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let (field, value) = line
+                .split_once(':')
+                .with_context(|| format!("invalid line in private key file: {}", line))?;
+            fields.push((field.trim().to_string(), value.trim().to_string()));
+        }
+        Ok(PrivateFields(fields))
+    }
+
+    fn get(&self, field: &str) -> Result<&str, Box<dyn error::Error + Send + Sync>> {
+        self.0
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field))
+            .map(|(_, value)| value.as_str())
+            .with_context(|| format!("private key file has no {} field", field))
+            .map_err(|err| err.into())
+    }
+
+    fn decode(&self, field: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        dnssec::decode_base64(self.get(field)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Ed25519 example from RFC 8080 §6.1.
+    const ED25519_KEY: &str =
+        "example.com. 3600 IN DNSKEY 257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=";
+    const ED25519_PRIVATE: &str = "Private-key-format: v1.2
+Algorithm: 15 (ED25519)
+PrivateKey: ODIyNjAzODQ2MjgwODAxMjI2NDUxOTAyMDQxNDIyNjI=
+";
+
+    /// The P-256 example from RFC 6605 §6.1.
+    const ECDSA_KEY: &str = "example.net. 3600 IN DNSKEY 257 3 13 (
+        GojIhhXUN/u4v54ZQqGSnyhWJwaubCvTmeexv7bR6edb
+        krSqQpF64cYbcB7wNcP+e+MAnLr+Wi9xMWyQLc8NAA== )";
+    const ECDSA_PRIVATE: &str = "Private-key-format: v1.2
+Algorithm: 13 (ECDSAP256SHA256)
+PrivateKey: GU6SnQ/Ou+xC5RumuIUIuJZteXT2z0O/ok1s38Et6mQ=
+";
+
+    #[test]
+    fn test_sign_matches_rfc_8080_example() {
+        let key = SigningKey::parse(ED25519_KEY, ED25519_PRIVATE).unwrap();
+        assert_eq!(key.key_tag(), 3613);
+        assert!(key.is_key_signing_key());
+        let rrset =
+            zone::parse_master_file("example.com. 3600 IN MX 10 mail.example.com.", None).unwrap();
+        let rrsig = key.sign(&rrset, 1438207200, 1440021600).unwrap();
+        let signature = match &rrsig.data {
+            Data::Rrsig { signature, .. } => signature.clone(),
+            _ => panic!("expected an RRSIG"),
+        };
+        assert_eq!(
+            dnssec::encode_base64(&signature),
+            "oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9VrbpMngwcrqNAg=="
+        );
+    }
+
+    #[test]
+    fn test_signatures_verify() {
+        let rsa_public = include_str!("../testdata/Kexample.org.+008+60127.key");
+        let rsa_private = include_str!("../testdata/Kexample.org.+008+60127.private");
+        for (public, private) in [
+            (ECDSA_KEY, ECDSA_PRIVATE),
+            (ED25519_KEY, ED25519_PRIVATE),
+            (rsa_public, rsa_private),
+        ] {
+            let key = SigningKey::parse(public, private).unwrap();
+            let text = format!("www.{}. 300 IN A 192.0.2.1", key.owner);
+            let rrset = zone::parse_master_file(&text, None).unwrap();
+            let rrsig = key.sign(&rrset, 0, 100).unwrap();
+            assert_eq!(rrsig.ttl, 300);
+            dnssec::verify_signature(&key.dnskey, &rrsig.data, &rrset).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_mismatched_keys() {
+        let private = ED25519_PRIVATE.replace("Algorithm: 15", "Algorithm: 13");
+        assert!(SigningKey::parse(ED25519_KEY, &private).is_err());
+        assert!(SigningKey::parse(ECDSA_KEY, &ECDSA_PRIVATE.replace("GU6S", "AU6S")).is_err());
+        assert!(SigningKey::parse(ED25519_KEY, "Algorithm: 15 (ED25519)").is_err());
+    }
+
+    #[test]
+    fn test_key_file_base() {
+        let base = "keys/Kexample.com.+013+12345";
+        for path in [base, &format!("{}.key", base), &format!("{}.private", base)] {
+            assert_eq!(key_file_base(Path::new(path)), base);
+        }
+    }
+
+    #[test]
+    fn test_generate_keys() {
+        let timing = KeyTiming {
+            created: Some(1_700_000_000),
+            activate: Some(1_700_000_000),
+            ..KeyTiming::default()
+        };
+        for algorithm in [dnssec::ECDSAP256SHA256, dnssec::ED25519] {
+            let flags = dnssec::ZONE_KEY | dnssec::SECURE_ENTRY_POINT;
+            let files = generate_key("example.com", algorithm, flags, &timing).unwrap();
+            let key = files.load().unwrap();
+            assert_eq!(key.owner, "example.com");
+            assert_eq!(key.algorithm(), algorithm);
+            assert!(key.is_key_signing_key());
+            assert_eq!(key.timing, timing);
+            assert_eq!(
+                files.name,
+                format!("Kexample.com.+{:03}+{:05}", algorithm, key.key_tag())
+            );
+            assert!(files.private.contains("Activate: 20231114221320\n"));
+
+            let rrset =
+                zone::parse_master_file("www.example.com. 300 IN A 192.0.2.1", None).unwrap();
+            let rrsig = key.sign(&rrset, 0, 100).unwrap();
+            dnssec::verify_signature(&key.dnskey, &rrsig.data, &rrset).unwrap();
+        }
+        assert!(generate_key("example.com", dnssec::RSASHA256, 256, &timing).is_err());
+    }
+
+    #[test]
+    fn test_save_keeps_private_key_private() {
+        let directory = std::env::temp_dir().join(format!("rust-dns-keys-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let files = generate_key(
+            "example.com",
+            dnssec::ED25519,
+            dnssec::ZONE_KEY,
+            &KeyTiming::default(),
+        )
+        .unwrap();
+        assert_eq!(files.save(&directory).unwrap(), directory.join(&files.name));
+        let private = directory.join(format!("{}.private", files.name));
+        assert_eq!(fs::read_to_string(&private).unwrap(), files.private);
+        // Existing keys are never overwritten
+        assert!(files.save(&directory).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&private).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_key_states() {
+        let timing = KeyTiming {
+            created: Some(50),
+            publish: Some(100),
+            activate: Some(200),
+            inactive: Some(300),
+            delete: Some(400),
+        };
+        let states = [0, 100, 250, 399, 400].map(|now| timing.state(now));
+        assert_eq!(
+            states,
+            [
+                KeyState::Unpublished,
+                KeyState::Published,
+                KeyState::Active,
+                KeyState::Retired,
+                KeyState::Deleted
+            ]
+        );
+        assert_eq!(timing.next_change(0), Some(100));
+        assert_eq!(timing.next_change(200), Some(300));
+        assert_eq!(timing.next_change(400), None);
+        timing.validate().unwrap();
+
+        // Keys without timing metadata are always active
+        assert_eq!(KeyTiming::default().state(0), KeyState::Active);
+        let unordered = KeyTiming {
+            activate: Some(300),
+            inactive: Some(200),
+            ..KeyTiming::default()
+        };
+        assert!(unordered.validate().is_err());
+    }
+}
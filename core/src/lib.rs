@@ -1,10 +1,19 @@
-//! This package provides methods to serialize and deserialize UDP DNS packets.
+//! This package provides methods to serialize and deserialize DNS packets.
 
 mod buffer;
 
+pub mod dnssec;
+pub mod edns;
 pub mod header;
+pub mod journal;
+pub mod key;
+pub mod name;
 pub mod packet;
 pub mod question;
 pub mod record;
+pub mod tcp;
+pub mod tsig;
+pub mod update;
+pub mod zone;
 
 pub use packet::{parse_dns_packet, serialize_dns_packet};
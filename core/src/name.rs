@@ -0,0 +1,133 @@
+//! Helpers for working with domain names in their dotted text form.
+//!
+//! Names are stored as plain strings throughout this crate, e.g.
+//! `www.example.com`, with the root written as `.`. These functions treat
+//! names case-insensitively and ignore a trailing dot.
+
+/// Lowercase a name and strip any trailing dot. The root is returned as `.`.
+pub fn normalize(name: &str) -> String {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        ".".to_string()
+    } else {
+        name.to_ascii_lowercase()
+    }
+}
+
+/// Compare two names, ignoring case and trailing dots.
+pub fn eq(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// Split a name into its labels, from left to right. The root has no labels.
+pub fn labels(name: &str) -> Vec<&str> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        vec![]
+    } else {
+        name.split('.').collect()
+    }
+}
+
+/// Returns true if `name` is equal to, or underneath, `zone`.
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = normalize(name);
+    let zone = normalize(zone);
+    if zone == "." || name == zone {
+        return true;
+    }
+    name.ends_with(&format!(".{}", zone))
+}
+
+/// Strip the leftmost label from a name. Returns `None` for the root.
+pub fn parent(name: &str) -> Option<String> {
+    let name = normalize(name);
+    if name == "." {
+        return None;
+    }
+    match name.split_once('.') {
+        Some((_, rest)) => Some(rest.to_string()),
+        None => Some(".".to_string()),
+    }
+}
+
+/// Prepend a label to a name.
+pub fn child(label: &str, name: &str) -> String {
+    if normalize(name) == "." {
+        label.to_string()
+    } else {
+        format!("{}.{}", label, name.strip_suffix('.').unwrap_or(name))
+    }
+}
+
+/// Returns a key that sorts names in DNSSEC canonical order (RFC 4034 §6.1)
+/// when compared bytewise.
+pub fn canonical_key(name: &str) -> Vec<u8> {
+    let normalized = normalize(name);
+    let mut key = vec![];
+    for label in labels(&normalized).iter().rev() {
+        key.extend(label.as_bytes());
+        key.push(0);
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_lowercases_and_strips_trailing_dot() {
+        assert_eq!(normalize("WWW.Example.COM."), "www.example.com");
+        assert_eq!(normalize("."), ".");
+        assert_eq!(normalize(""), ".");
+    }
+
+    #[test]
+    fn test_is_subdomain_matches_on_label_boundaries() {
+        assert!(is_subdomain("www.example.com", "example.com"));
+        assert!(is_subdomain("example.com", "EXAMPLE.com."));
+        assert!(is_subdomain("example.com", "."));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+        assert!(!is_subdomain("com", "example.com"));
+    }
+
+    #[test]
+    fn test_parent_walks_towards_root() {
+        assert_eq!(parent("www.example.com"), Some("example.com".to_string()));
+        assert_eq!(parent("com"), Some(".".to_string()));
+        assert_eq!(parent("."), None);
+    }
+
+    #[test]
+    fn test_child_prepends_label() {
+        assert_eq!(child("*", "example.com."), "*.example.com");
+        assert_eq!(child("com", "."), "com");
+    }
+
+    #[test]
+    fn test_canonical_key_sorts_in_canonical_order() {
+        let mut names = vec![
+            "z.example",
+            "example",
+            "*.z.example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+        ];
+        names.sort_by_key(|name| canonical_key(name));
+        assert_eq!(
+            names,
+            vec![
+                "example",
+                "a.example",
+                "yljkjljk.a.example",
+                "Z.a.example",
+                "zABC.a.EXAMPLE",
+                "z.example",
+                "*.z.example",
+            ]
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use super::buffer::ByteBuffer;
-use super::header::{self, Header};
+use super::header::{self, Header, Opcode, ResponseCode};
 use super::question::{self, Question};
-use super::record::{self, Record};
+use super::record::{self, Class, Record, RecordType};
+use anyhow::Context;
 use std::error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsPacket {
     pub header: Header,
     pub questions: Vec<Question>,
@@ -13,9 +14,20 @@ pub struct DnsPacket {
     pub resource_entries: Vec<Record>,
 }
 
-pub fn serialize_dns_packet(packet: &DnsPacket) -> Result<Vec<u8>, Box<dyn error::Error>> {
+/// Serialize a packet. The section counts in the header are taken from the
+/// number of entries in each section, rather than the header fields.
+pub fn serialize_dns_packet(
+    packet: &DnsPacket,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
     let mut bytes = vec![];
-    let mut header = header::serialize_header(&packet.header);
+    let header = Header {
+        questions: section_count(packet.questions.len())?,
+        answers: section_count(packet.answers.len())?,
+        authoritative_entries: section_count(packet.authoritative_entries.len())?,
+        resource_entries: section_count(packet.resource_entries.len())?,
+        ..packet.header.clone()
+    };
+    let mut header = header::serialize_header(&header);
     bytes.append(&mut header);
     let mut questions = question::serialize_questions(&packet.questions)?;
     bytes.append(&mut questions);
@@ -28,7 +40,7 @@ pub fn serialize_dns_packet(packet: &DnsPacket) -> Result<Vec<u8>, Box<dyn error
     Ok(bytes)
 }
 
-pub fn parse_dns_packet(packet: &[u8]) -> Result<DnsPacket, Box<dyn error::Error>> {
+pub fn parse_dns_packet(packet: &[u8]) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
     let mut packet = ByteBuffer::from(packet);
     let header = header::parse_header(&mut packet)?;
     let questions = question::parse_questions(&mut packet, header.questions as usize)?;
@@ -44,3 +56,91 @@ pub fn parse_dns_packet(packet: &[u8]) -> Result<DnsPacket, Box<dyn error::Error
         resource_entries,
     })
 }
+
+/// Create a standard query for a single question, with no flags set.
+pub fn build_query(id: u16, name: &str, record_type: &RecordType) -> DnsPacket {
+    DnsPacket {
+        header: Header {
+            id,
+            query: true,
+            opcode: Opcode::Query,
+            authoritative_answer: false,
+            truncation: false,
+            recursion_desired: false,
+            recursion_available: false,
+            reserved: 0,
+            rcode: ResponseCode::Success,
+            questions: 1,
+            answers: 0,
+            authoritative_entries: 0,
+            resource_entries: 0,
+        },
+        questions: vec![Question {
+            name: name.to_string(),
+            typ: record::record_type_code(record_type),
+            class: record::class_code(&Class::In),
+        }],
+        answers: vec![],
+        authoritative_entries: vec![],
+        resource_entries: vec![],
+    }
+}
+
+fn section_count(len: usize) -> Result<u16, Box<dyn error::Error + Send + Sync>> {
+    let count = len
+        .try_into()
+        .context("a section cannot have more than 65535 entries")?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dns_packet_parses_query_packet() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let bytes = include_bytes!("../../examples/query_packet");
+        let packet = parse_dns_packet(bytes)?;
+        assert_eq!(packet.questions.len(), 1);
+        assert_eq!(packet.questions[0].name, "google.com");
+        assert_eq!(packet.questions[0].typ, 1);
+        assert_eq!(packet.questions[0].class, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dns_packet_parses_compressed_response(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = include_bytes!("../../examples/response_packet");
+        let packet = parse_dns_packet(bytes)?;
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(packet.answers[0].name, "google.com");
+        assert_eq!(packet.answers[0].ttl, 215);
+        assert_eq!(
+            packet.answers[0].data,
+            record::Data::Addr([142, 250, 76, 110])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_dns_packet_round_trips() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = include_bytes!("../../examples/response_packet");
+        let packet = parse_dns_packet(bytes)?;
+        let serialized = serialize_dns_packet(&packet)?;
+        assert_eq!(parse_dns_packet(&serialized)?, packet);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_dns_packet_uses_section_lengths_for_counts(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = include_bytes!("../../examples/query_packet");
+        let mut packet = parse_dns_packet(bytes)?;
+        packet.header.questions = 7;
+        let serialized = serialize_dns_packet(&packet)?;
+        assert_eq!(&serialized[4..6], &[0, 1]);
+        Ok(())
+    }
+}
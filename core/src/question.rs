@@ -1,7 +1,7 @@
 use super::buffer::ByteBuffer;
 use std::error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Question {
     pub name: String,
     pub typ: u16,
@@ -11,7 +11,7 @@ pub struct Question {
 pub fn parse_questions(
     packet: &mut ByteBuffer,
     count: usize,
-) -> Result<Vec<Question>, Box<dyn error::Error>> {
+) -> Result<Vec<Question>, Box<dyn error::Error + Send + Sync>> {
     let mut records = vec![];
     for _ in 0..count {
         let record = parse_single_question(packet)?;
@@ -20,27 +20,33 @@ pub fn parse_questions(
     Ok(records)
 }
 
-pub fn serialize_questions(questions: &[Question]) -> Result<Vec<u8>, Box<dyn error::Error>> {
+pub fn serialize_questions(
+    questions: &[Question],
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
     let mut bytes = vec![];
     for question in questions {
-        let question_bytes = serialize_single_question(&question)?;
+        let question_bytes = serialize_single_question(question)?;
         bytes.extend(question_bytes);
     }
     Ok(bytes)
 }
 
-pub fn serialize_single_question(question: &Question) -> Result<Vec<u8>, Box<dyn error::Error>> {
+pub fn serialize_single_question(
+    question: &Question,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
     let mut bytes = vec![];
     let name = crate::record::serialize_name(&question.name)?;
     bytes.extend(name);
     let typ = question.typ.to_be_bytes();
     bytes.extend(typ);
-    let class = question.typ.to_be_bytes();
+    let class = question.class.to_be_bytes();
     bytes.extend(class);
     Ok(bytes)
 }
 
-pub fn parse_single_question(packet: &mut ByteBuffer) -> Result<Question, Box<dyn error::Error>> {
+pub fn parse_single_question(
+    packet: &mut ByteBuffer,
+) -> Result<Question, Box<dyn error::Error + Send + Sync>> {
     let name = crate::record::parse_name(packet)?;
     let typ = packet.read_u16()?;
     let class = packet.read_u16()?;
@@ -1,4 +1,5 @@
 use super::buffer::ByteBuffer;
+use super::dnssec;
 use anyhow::{anyhow, Context};
 use std::error;
 
@@ -9,7 +10,7 @@ use std::error;
 pub fn parse_records(
     packet: &mut ByteBuffer,
     count: usize,
-) -> Result<Vec<Record>, Box<dyn error::Error>> {
+) -> Result<Vec<Record>, Box<dyn error::Error + Send + Sync>> {
     let mut records = vec![];
     for _ in 0..count {
         let record = parse_single_record(packet)?;
@@ -18,17 +19,18 @@ pub fn parse_records(
     Ok(records)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Record {
     pub name: String,
     pub record_type: RecordType,
     pub class: Class,
     pub ttl: i32,
-    pub len: u16,
     pub data: Data,
 }
 
-pub fn parse_single_record(packet: &mut ByteBuffer) -> Result<Record, Box<dyn error::Error>> {
+pub fn parse_single_record(
+    packet: &mut ByteBuffer,
+) -> Result<Record, Box<dyn error::Error + Send + Sync>> {
     let name = parse_name(packet)?;
     let record_type = packet.read_u16()?;
     let record_type = parse_record_type(record_type);
@@ -36,107 +38,367 @@ pub fn parse_single_record(packet: &mut ByteBuffer) -> Result<Record, Box<dyn er
     let class = parse_class(class);
     let ttl = packet.read_i32()?;
     let len = packet.read_u16()?;
-    let data = packet.read_range(len as usize)?.clone().to_vec();
-    let data = parse_data(&record_type, &data)?;
+    // Updates and their prerequisites leave the data out of records that
+    // stand for a whole RRset (RFC 2136 §2.4)
+    let data = if len == 0 && matches!(class, Class::None | Class::Any) {
+        Data::Unknown(vec![])
+    } else {
+        parse_data(&record_type, packet, len as usize)?
+    };
     Ok(Record {
         name,
         record_type,
         class,
         ttl,
-        len,
         data,
     })
 }
 
-pub fn serialize_records(records: &[Record]) -> Result<Vec<u8>, Box<dyn error::Error>> {
+pub fn serialize_records(
+    records: &[Record],
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
     let mut bytes = vec![];
     for record in records {
-        let record_bytes = serialize_single_record(&record)?;
+        let record_bytes = serialize_single_record(record)?;
         bytes.extend(record_bytes);
     }
     Ok(bytes)
 }
 
-pub fn serialize_single_record(record: &Record) -> Result<Vec<u8>, Box<dyn error::Error>> {
+pub fn serialize_single_record(
+    record: &Record,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
     let mut bytes = vec![];
     let name = serialize_name(&record.name)?;
     bytes.extend(name);
     let record_type = serialize_record_type(&record.record_type);
     bytes.extend(record_type);
-    let class = serialize_record_type(&record.record_type);
+    let class = serialize_class(&record.class);
     bytes.extend(class);
     let ttl = record.ttl.to_be_bytes();
     bytes.extend(ttl);
-    let len = record.len.to_be_bytes();
-    bytes.extend(len);
-    let data = serialize_data(&record.data);
+    let data = serialize_data(&record.data)?;
+    let len: u16 = data
+        .len()
+        .try_into()
+        .context("record data cannot be larger than 65535 bytes")?;
+    bytes.extend(len.to_be_bytes());
     bytes.extend(data);
     Ok(bytes)
 }
 
-pub fn serialize_name(name: &str) -> Result<Vec<u8>, Box<dyn error::Error>> {
-    let parts = name.split(".");
+// --------------------------------------------------
+// Name
+// --------------------------------------------------
+
+/// Names may not be longer than this many bytes on the wire (RFC 1035 §2.3.4)
+const MAX_NAME_LEN: usize = 255;
+
+pub fn serialize_name(name: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
     let mut bytes = vec![];
-    for part in parts {
-        let len = part.len() as u8;
-        if len > 63 {
+    for part in crate::name::labels(name) {
+        if part.is_empty() {
+            return Err(anyhow!("name {:?} contains an empty label", name).into());
+        }
+        if part.len() > 63 {
             return Err(anyhow!("label cannot be larger than 63 characters").into());
         }
-        bytes.push(len);
+        bytes.push(part.len() as u8);
         bytes.extend(part.as_bytes());
     }
     bytes.push(0x0);
+    if bytes.len() > MAX_NAME_LEN {
+        return Err(anyhow!("name cannot be larger than {} bytes", MAX_NAME_LEN).into());
+    }
     Ok(bytes)
 }
 
+pub fn parse_name(packet: &mut ByteBuffer) -> Result<String, Box<dyn error::Error + Send + Sync>> {
+    let mut name_parts = vec![];
+    let mut name_len = 1;
+    let mut original_position = None;
+    let mut jumps = 0;
+    loop {
+        let label_len = packet.read().context("could not read label length")?;
+        if label_len == 0 {
+            // Reached end of the label sequence
+            break;
+        }
+
+        // A label is preceded by a u8 indicating the number of characters in
+        // the label. When the two most significant bits of this number are set,
+        // the label is actually a pointer to another section in the packet.
+        let label_flags = label_len >> 6;
+        match label_flags {
+            0b00 => {
+                let label_bytes = packet.read_range(label_len as usize)?;
+                let label = String::from_utf8_lossy(label_bytes);
+                name_len += label_bytes.len() + 1;
+                name_parts.push(label);
+            }
+            0b11 => {
+                // Pointers must always point backwards, otherwise a malicious
+                // packet could send us around in circles.
+                let low = packet.read().context("could not read pointer position")?;
+                let pointer_pos = (((label_len & 0b0011_1111) as usize) << 8) | low as usize;
+                if pointer_pos >= packet.pos() - 2 || jumps > MAX_NAME_LEN / 2 {
+                    return Err(anyhow!("invalid name compression pointer").into());
+                }
+                if original_position.is_none() {
+                    original_position = Some(packet.pos());
+                }
+                jumps += 1;
+                packet.jump(pointer_pos)?;
+            }
+            _ => return Err(anyhow!("unknown label flags: {:#04b}", label_flags).into()),
+        };
+        if name_len > MAX_NAME_LEN {
+            return Err(anyhow!("name cannot be larger than {} bytes", MAX_NAME_LEN).into());
+        }
+    }
+    if let Some(pos) = original_position {
+        packet.jump(pos)?;
+    }
+    let name = if name_parts.is_empty() {
+        ".".to_string()
+    } else {
+        name_parts.join(".")
+    };
+    Ok(name)
+}
+
 // --------------------------------------------------
 // Record type
 // --------------------------------------------------
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RecordType {
     A,
     Ns,
+    Cname,
+    Soa,
+    Ptr,
+    Mx,
+    Txt,
+    Aaaa,
+    Srv,
+    Opt,
+    Ds,
+    Rrsig,
+    Nsec,
+    Dnskey,
+    Nsec3,
+    Nsec3param,
+    Cds,
+    Cdnskey,
+    Tsig,
+    Ixfr,
+    Axfr,
+    Any,
     Unknown(u16),
 }
 
+const KNOWN_RECORD_TYPES: &[RecordType] = &[
+    RecordType::A,
+    RecordType::Ns,
+    RecordType::Cname,
+    RecordType::Soa,
+    RecordType::Ptr,
+    RecordType::Mx,
+    RecordType::Txt,
+    RecordType::Aaaa,
+    RecordType::Srv,
+    RecordType::Opt,
+    RecordType::Ds,
+    RecordType::Rrsig,
+    RecordType::Nsec,
+    RecordType::Dnskey,
+    RecordType::Nsec3,
+    RecordType::Nsec3param,
+    RecordType::Cds,
+    RecordType::Cdnskey,
+    RecordType::Tsig,
+    RecordType::Ixfr,
+    RecordType::Axfr,
+    RecordType::Any,
+];
+
 pub fn parse_record_type(record_type: u16) -> RecordType {
     match record_type {
         1 => RecordType::A,
         2 => RecordType::Ns,
+        5 => RecordType::Cname,
+        6 => RecordType::Soa,
+        12 => RecordType::Ptr,
+        15 => RecordType::Mx,
+        16 => RecordType::Txt,
+        28 => RecordType::Aaaa,
+        33 => RecordType::Srv,
+        41 => RecordType::Opt,
+        43 => RecordType::Ds,
+        46 => RecordType::Rrsig,
+        47 => RecordType::Nsec,
+        48 => RecordType::Dnskey,
+        50 => RecordType::Nsec3,
+        51 => RecordType::Nsec3param,
+        59 => RecordType::Cds,
+        60 => RecordType::Cdnskey,
+        250 => RecordType::Tsig,
+        251 => RecordType::Ixfr,
+        252 => RecordType::Axfr,
+        255 => RecordType::Any,
         _ => RecordType::Unknown(record_type),
     }
 }
 
+pub fn record_type_code(record_type: &RecordType) -> u16 {
+    match record_type {
+        RecordType::A => 1,
+        RecordType::Ns => 2,
+        RecordType::Cname => 5,
+        RecordType::Soa => 6,
+        RecordType::Ptr => 12,
+        RecordType::Mx => 15,
+        RecordType::Txt => 16,
+        RecordType::Aaaa => 28,
+        RecordType::Srv => 33,
+        RecordType::Opt => 41,
+        RecordType::Ds => 43,
+        RecordType::Rrsig => 46,
+        RecordType::Nsec => 47,
+        RecordType::Dnskey => 48,
+        RecordType::Nsec3 => 50,
+        RecordType::Nsec3param => 51,
+        RecordType::Cds => 59,
+        RecordType::Cdnskey => 60,
+        RecordType::Tsig => 250,
+        RecordType::Ixfr => 251,
+        RecordType::Axfr => 252,
+        RecordType::Any => 255,
+        RecordType::Unknown(value) => *value,
+    }
+}
+
 pub fn serialize_record_type(record_type: &RecordType) -> Vec<u8> {
+    record_type_code(record_type).to_be_bytes().to_vec()
+}
+
+/// Returns true for the types that only carry DNSSEC signatures and
+/// proofs, which are left out of responses unless the client sets DO.
+pub fn is_dnssec_type(record_type: &RecordType) -> bool {
+    matches!(
+        record_type,
+        RecordType::Rrsig | RecordType::Nsec | RecordType::Nsec3
+    )
+}
+
+/// Returns the mnemonic used for a record type in zone files, e.g. `AAAA`.
+/// Types without a mnemonic use the generic `TYPE<n>` form from RFC 3597.
+pub fn record_type_name(record_type: &RecordType) -> String {
     match record_type {
-        RecordType::A => u16::to_be_bytes(1).to_vec(),
-        RecordType::Ns => u16::to_be_bytes(2).to_vec(),
-        RecordType::Unknown(value) => value.to_be_bytes().to_vec(),
+        RecordType::A => "A".to_string(),
+        RecordType::Ns => "NS".to_string(),
+        RecordType::Cname => "CNAME".to_string(),
+        RecordType::Soa => "SOA".to_string(),
+        RecordType::Ptr => "PTR".to_string(),
+        RecordType::Mx => "MX".to_string(),
+        RecordType::Txt => "TXT".to_string(),
+        RecordType::Aaaa => "AAAA".to_string(),
+        RecordType::Srv => "SRV".to_string(),
+        RecordType::Opt => "OPT".to_string(),
+        RecordType::Ds => "DS".to_string(),
+        RecordType::Rrsig => "RRSIG".to_string(),
+        RecordType::Nsec => "NSEC".to_string(),
+        RecordType::Dnskey => "DNSKEY".to_string(),
+        RecordType::Nsec3 => "NSEC3".to_string(),
+        RecordType::Nsec3param => "NSEC3PARAM".to_string(),
+        RecordType::Cds => "CDS".to_string(),
+        RecordType::Cdnskey => "CDNSKEY".to_string(),
+        RecordType::Tsig => "TSIG".to_string(),
+        RecordType::Ixfr => "IXFR".to_string(),
+        RecordType::Axfr => "AXFR".to_string(),
+        RecordType::Any => "ANY".to_string(),
+        RecordType::Unknown(value) => format!("TYPE{}", value),
     }
 }
 
+pub fn parse_record_type_name(name: &str) -> Option<RecordType> {
+    let name = name.to_ascii_uppercase();
+    if let Some(value) = name.strip_prefix("TYPE") {
+        return value.parse::<u16>().ok().map(parse_record_type);
+    }
+    KNOWN_RECORD_TYPES
+        .iter()
+        .find(|typ| record_type_name(typ) == name)
+        .copied()
+}
+
 // --------------------------------------------------
 // Class
 // --------------------------------------------------
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Class {
     In,
+    Ch,
+    Hs,
+    /// Used by updates to delete records, and in prerequisites that
+    /// something doesn't exist (RFC 2136 §2.4).
+    None,
+    /// Matches any class in questions, and is the class of TSIG records.
+    Any,
     Unknown(u16),
 }
 
 pub fn parse_class(class: u16) -> Class {
     match class {
         1 => Class::In,
+        3 => Class::Ch,
+        4 => Class::Hs,
+        254 => Class::None,
+        255 => Class::Any,
         _ => Class::Unknown(class),
     }
 }
 
+pub fn class_code(class: &Class) -> u16 {
+    match class {
+        Class::In => 1,
+        Class::Ch => 3,
+        Class::Hs => 4,
+        Class::None => 254,
+        Class::Any => 255,
+        Class::Unknown(value) => *value,
+    }
+}
+
 pub fn serialize_class(class: &Class) -> Vec<u8> {
+    class_code(class).to_be_bytes().to_vec()
+}
+
+pub fn class_name(class: &Class) -> String {
     match class {
-        Class::In => u16::to_be_bytes(1).to_vec(),
-        Class::Unknown(value) => value.to_be_bytes().to_vec(),
+        Class::In => "IN".to_string(),
+        Class::Ch => "CH".to_string(),
+        Class::Hs => "HS".to_string(),
+        Class::None => "NONE".to_string(),
+        Class::Any => "ANY".to_string(),
+        Class::Unknown(value) => format!("CLASS{}", value),
+    }
+}
+
+pub fn parse_class_name(name: &str) -> Option<Class> {
+    let name = name.to_ascii_uppercase();
+    match name.as_str() {
+        "IN" => Some(Class::In),
+        "CH" => Some(Class::Ch),
+        "HS" => Some(Class::Hs),
+        "NONE" => Some(Class::None),
+        "ANY" => Some(Class::Any),
+        _ => name
+            .strip_prefix("CLASS")
+            .and_then(|value| value.parse::<u16>().ok())
+            .map(parse_class),
     }
 }
 
@@ -144,26 +406,442 @@ pub fn serialize_class(class: &Class) -> Vec<u8> {
 // Record data
 // --------------------------------------------------
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Data {
     Addr([u8; 4]),
+    Addr6([u8; 16]),
+    Ns(String),
+    Cname(String),
+    Ptr(String),
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Txt(Vec<Vec<u8>>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Opt(Vec<EdnsOption>),
+    /// The data of DNSKEY and CDNSKEY records.
+    Dnskey {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    /// The data of DS and CDS records.
+    Ds {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    Rrsig {
+        type_covered: RecordType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        signature: Vec<u8>,
+    },
+    Nsec {
+        next_name: String,
+        types: Vec<RecordType>,
+    },
+    Nsec3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>,
+        types: Vec<RecordType>,
+    },
+    Nsec3param {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+    },
+    /// A transaction signature (RFC 8945 §4.2). The time is 48 bits.
+    Tsig {
+        algorithm: String,
+        time_signed: u64,
+        fudge: u16,
+        mac: Vec<u8>,
+        original_id: u16,
+        error: u16,
+        other: Vec<u8>,
+    },
     Unknown(Vec<u8>),
 }
 
-pub fn parse_data(record_type: &RecordType, data: &[u8]) -> Result<Data, Box<dyn error::Error>> {
-    match record_type {
+/// An option carried in the data of an OPT record (RFC 6891 §6.1.2).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+/// Parse `len` bytes of record data starting at the current position. Names
+/// inside the data may be compressed, so this needs the whole packet.
+pub fn parse_data(
+    record_type: &RecordType,
+    packet: &mut ByteBuffer,
+    len: usize,
+) -> Result<Data, Box<dyn error::Error + Send + Sync>> {
+    let start = packet.pos();
+    let data = match record_type {
         RecordType::A => {
-            let addr: [u8; 4] = data.try_into()?;
-            Ok(Data::Addr(addr))
+            let addr: [u8; 4] = packet.read_range(len)?.try_into()?;
+            Data::Addr(addr)
+        }
+        RecordType::Aaaa => {
+            let addr: [u8; 16] = packet.read_range(len)?.try_into()?;
+            Data::Addr6(addr)
         }
-        _ => Ok(Data::Unknown(data.to_vec())),
+        RecordType::Ns => Data::Ns(parse_name(packet)?),
+        RecordType::Cname => Data::Cname(parse_name(packet)?),
+        RecordType::Ptr => Data::Ptr(parse_name(packet)?),
+        RecordType::Soa => Data::Soa {
+            mname: parse_name(packet)?,
+            rname: parse_name(packet)?,
+            serial: packet.read_u32()?,
+            refresh: packet.read_u32()?,
+            retry: packet.read_u32()?,
+            expire: packet.read_u32()?,
+            minimum: packet.read_u32()?,
+        },
+        RecordType::Mx => Data::Mx {
+            preference: packet.read_u16()?,
+            exchange: parse_name(packet)?,
+        },
+        RecordType::Txt => {
+            let mut strings = vec![];
+            while packet.pos() < start + len {
+                let string_len = packet.read().context("could not read string length")?;
+                strings.push(packet.read_range(string_len as usize)?.to_vec());
+            }
+            Data::Txt(strings)
+        }
+        RecordType::Srv => Data::Srv {
+            priority: packet.read_u16()?,
+            weight: packet.read_u16()?,
+            port: packet.read_u16()?,
+            target: parse_name(packet)?,
+        },
+        RecordType::Opt => {
+            let mut options = vec![];
+            while packet.pos() < start + len {
+                let code = packet.read_u16()?;
+                let option_len = packet.read_u16()?;
+                let data = packet.read_range(option_len as usize)?.to_vec();
+                options.push(EdnsOption { code, data });
+            }
+            Data::Opt(options)
+        }
+        RecordType::Dnskey | RecordType::Cdnskey => Data::Dnskey {
+            flags: packet.read_u16()?,
+            protocol: packet.read().context("could not read DNSKEY protocol")?,
+            algorithm: packet.read().context("could not read DNSKEY algorithm")?,
+            public_key: read_rest(packet, start + len)?,
+        },
+        RecordType::Ds | RecordType::Cds => Data::Ds {
+            key_tag: packet.read_u16()?,
+            algorithm: packet.read().context("could not read DS algorithm")?,
+            digest_type: packet.read().context("could not read DS digest type")?,
+            digest: read_rest(packet, start + len)?,
+        },
+        RecordType::Rrsig => Data::Rrsig {
+            type_covered: parse_record_type(packet.read_u16()?),
+            algorithm: packet.read().context("could not read RRSIG algorithm")?,
+            labels: packet.read().context("could not read RRSIG labels")?,
+            original_ttl: packet.read_u32()?,
+            expiration: packet.read_u32()?,
+            inception: packet.read_u32()?,
+            key_tag: packet.read_u16()?,
+            signer_name: parse_name(packet)?,
+            signature: read_rest(packet, start + len)?,
+        },
+        RecordType::Nsec => Data::Nsec {
+            next_name: parse_name(packet)?,
+            types: dnssec::parse_type_bitmap(&read_rest(packet, start + len)?)?,
+        },
+        RecordType::Nsec3 => {
+            let hash_algorithm = packet.read().context("could not read NSEC3 algorithm")?;
+            let flags = packet.read().context("could not read NSEC3 flags")?;
+            let iterations = packet.read_u16()?;
+            let salt_len = packet.read().context("could not read NSEC3 salt length")?;
+            let salt = packet.read_range(salt_len as usize)?.to_vec();
+            let hash_len = packet.read().context("could not read NSEC3 hash length")?;
+            let next_hashed = packet.read_range(hash_len as usize)?.to_vec();
+            let types = dnssec::parse_type_bitmap(&read_rest(packet, start + len)?)?;
+            Data::Nsec3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed,
+                types,
+            }
+        }
+        RecordType::Nsec3param => {
+            let hash_algorithm = packet.read().context("could not read NSEC3 algorithm")?;
+            let flags = packet.read().context("could not read NSEC3 flags")?;
+            let iterations = packet.read_u16()?;
+            let salt_len = packet.read().context("could not read NSEC3 salt length")?;
+            let salt = packet.read_range(salt_len as usize)?.to_vec();
+            Data::Nsec3param {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            }
+        }
+        RecordType::Tsig => {
+            let algorithm = parse_name(packet)?;
+            let time_high = packet.read_u16()? as u64;
+            let time_signed = time_high << 32 | packet.read_u32()? as u64;
+            let fudge = packet.read_u16()?;
+            let mac_len = packet.read_u16()?;
+            let mac = packet.read_range(mac_len as usize)?.to_vec();
+            let original_id = packet.read_u16()?;
+            let error = packet.read_u16()?;
+            let other_len = packet.read_u16()?;
+            let other = packet.read_range(other_len as usize)?.to_vec();
+            Data::Tsig {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+            }
+        }
+        _ => Data::Unknown(packet.read_range(len)?.to_vec()),
+    };
+    if packet.pos() != start + len {
+        return Err(anyhow!(
+            "record data was {} bytes but declared length was {}",
+            packet.pos() - start,
+            len
+        )
+        .into());
     }
+    Ok(data)
+}
+
+/// Read the rest of the record data, which ends at `end`.
+fn read_rest(
+    packet: &mut ByteBuffer,
+    end: usize,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let len = end
+        .checked_sub(packet.pos())
+        .context("record data is longer than its declared length")?;
+    Ok(packet.read_range(len)?.to_vec())
 }
 
-pub fn serialize_data(data: &Data) -> Vec<u8> {
+pub fn serialize_data(data: &Data) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let mut bytes = vec![];
     match data {
-        Data::Addr(addr) => addr.to_vec(),
-        Data::Unknown(data) => data.to_vec(),
+        Data::Addr(addr) => bytes.extend(addr),
+        Data::Addr6(addr) => bytes.extend(addr),
+        Data::Ns(name) | Data::Cname(name) | Data::Ptr(name) => bytes.extend(serialize_name(name)?),
+        Data::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => {
+            bytes.extend(serialize_name(mname)?);
+            bytes.extend(serialize_name(rname)?);
+            for value in [serial, refresh, retry, expire, minimum] {
+                bytes.extend(value.to_be_bytes());
+            }
+        }
+        Data::Mx {
+            preference,
+            exchange,
+        } => {
+            bytes.extend(preference.to_be_bytes());
+            bytes.extend(serialize_name(exchange)?);
+        }
+        Data::Txt(strings) => {
+            for string in strings {
+                let len: u8 = string
+                    .len()
+                    .try_into()
+                    .context("character strings cannot be larger than 255 bytes")?;
+                bytes.push(len);
+                bytes.extend(string);
+            }
+        }
+        Data::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => {
+            bytes.extend(priority.to_be_bytes());
+            bytes.extend(weight.to_be_bytes());
+            bytes.extend(port.to_be_bytes());
+            bytes.extend(serialize_name(target)?);
+        }
+        Data::Opt(options) => {
+            for option in options {
+                let len: u16 = option
+                    .data
+                    .len()
+                    .try_into()
+                    .context("EDNS options cannot be larger than 65535 bytes")?;
+                bytes.extend(option.code.to_be_bytes());
+                bytes.extend(len.to_be_bytes());
+                bytes.extend(&option.data);
+            }
+        }
+        Data::Dnskey {
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+        } => {
+            bytes.extend(flags.to_be_bytes());
+            bytes.push(*protocol);
+            bytes.push(*algorithm);
+            bytes.extend(public_key);
+        }
+        Data::Ds {
+            key_tag,
+            algorithm,
+            digest_type,
+            digest,
+        } => {
+            bytes.extend(key_tag.to_be_bytes());
+            bytes.push(*algorithm);
+            bytes.push(*digest_type);
+            bytes.extend(digest);
+        }
+        Data::Rrsig {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer_name,
+            signature,
+        } => {
+            bytes.extend(serialize_record_type(type_covered));
+            bytes.push(*algorithm);
+            bytes.push(*labels);
+            for value in [original_ttl, expiration, inception] {
+                bytes.extend(value.to_be_bytes());
+            }
+            bytes.extend(key_tag.to_be_bytes());
+            bytes.extend(serialize_name(signer_name)?);
+            bytes.extend(signature);
+        }
+        Data::Nsec { next_name, types } => {
+            bytes.extend(serialize_name(next_name)?);
+            bytes.extend(dnssec::serialize_type_bitmap(types));
+        }
+        Data::Nsec3 {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+            next_hashed,
+            types,
+        } => {
+            bytes.push(*hash_algorithm);
+            bytes.push(*flags);
+            bytes.extend(iterations.to_be_bytes());
+            bytes.push(short_len(salt, "NSEC3 salts")?);
+            bytes.extend(salt);
+            bytes.push(short_len(next_hashed, "NSEC3 hashes")?);
+            bytes.extend(next_hashed);
+            bytes.extend(dnssec::serialize_type_bitmap(types));
+        }
+        Data::Nsec3param {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+        } => {
+            bytes.push(*hash_algorithm);
+            bytes.push(*flags);
+            bytes.extend(iterations.to_be_bytes());
+            bytes.push(short_len(salt, "NSEC3 salts")?);
+            bytes.extend(salt);
+        }
+        Data::Tsig {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        } => {
+            bytes.extend(serialize_name(algorithm)?);
+            bytes.extend(&time_signed.to_be_bytes()[2..]);
+            bytes.extend(fudge.to_be_bytes());
+            bytes.extend(long_len(mac, "TSIG MACs")?.to_be_bytes());
+            bytes.extend(mac);
+            bytes.extend(original_id.to_be_bytes());
+            bytes.extend(error.to_be_bytes());
+            bytes.extend(long_len(other, "TSIG other data")?.to_be_bytes());
+            bytes.extend(other);
+        }
+        Data::Unknown(data) => bytes.extend(data),
+    };
+    Ok(bytes)
+}
+
+/// The length of a field that is prefixed with a single length byte.
+fn short_len(value: &[u8], what: &str) -> Result<u8, Box<dyn error::Error + Send + Sync>> {
+    value
+        .len()
+        .try_into()
+        .map_err(|_| anyhow!("{} cannot be larger than 255 bytes", what).into())
+}
+
+/// The length of a field that is prefixed with two length bytes.
+fn long_len(value: &[u8], what: &str) -> Result<u16, Box<dyn error::Error + Send + Sync>> {
+    value
+        .len()
+        .try_into()
+        .map_err(|_| anyhow!("{} cannot be larger than 65535 bytes", what).into())
+}
+
+/// Returns the name a record points at, if any. These are the names that
+/// need address records in the additional section.
+pub fn target_name(data: &Data) -> Option<&str> {
+    match data {
+        Data::Ns(name) | Data::Cname(name) | Data::Ptr(name) => Some(name),
+        Data::Mx { exchange, .. } => Some(exchange),
+        Data::Srv { target, .. } => Some(target),
+        _ => None,
     }
 }
 
@@ -175,63 +853,193 @@ mod tests {
     fn test_serialize_name_returns_expected_bytes() {
         let name = "example.com";
         let expected_bytes: Vec<u8> = vec![
-            0b00000000 & 7,
-            'e' as u8,
-            'x' as u8,
-            'a' as u8,
-            'm' as u8,
-            'p' as u8,
-            'l' as u8,
-            'e' as u8,
-            3,
-            'c' as u8,
-            'o' as u8,
-            'm' as u8,
-            0,
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
         ];
-        assert_eq!(serialize_name(&name).unwrap(), expected_bytes);
+        assert_eq!(serialize_name(name).unwrap(), expected_bytes);
     }
-}
 
-pub fn parse_name(packet: &mut ByteBuffer) -> Result<String, Box<dyn error::Error>> {
-    let mut name_parts = vec![];
-    let mut original_position = None;
-    loop {
-        let label_len = packet.read().context("could not read label length")?;
-        if label_len == 0 {
-            // Reached end of the label sequence
-            break;
-        }
+    #[test]
+    fn test_serialize_name_handles_root_and_trailing_dot() {
+        assert_eq!(serialize_name(".").unwrap(), vec![0]);
+        assert_eq!(
+            serialize_name("com.").unwrap(),
+            vec![3, b'c', b'o', b'm', 0]
+        );
+    }
 
-        // A label is preceded by a u8 indicating the number of characters in
-        // the label. When the two most significant bits of this number are set,
-        // the label is actually a pointer to another section in the packet.
-        let label_flags = label_len >> 6;
-        match label_flags {
-            0b00 => {
-                let label_bytes = packet.read_range(label_len as usize)?;
-                let label = String::from_utf8_lossy(label_bytes);
-                name_parts.push(label);
-            }
-            0b11 => {
-                let pointer_pos = packet.read().context("could not read pointer position")?;
-                original_position = Some(packet.pos());
-                packet.jump(pointer_pos as usize)?;
-                let label_len = packet.read().context("could not get label length")?;
-                let label_bytes = packet.read_range(label_len as usize)?;
-                let label = String::from_utf8_lossy(label_bytes);
-                name_parts.push(label);
-            }
-            _ => return Err(anyhow!("unknown label flags: {:#04b}", label_flags).into()),
+    #[test]
+    fn test_serialize_name_rejects_empty_labels() {
+        assert!(serialize_name("example..com").is_err());
+    }
+
+    #[test]
+    fn test_parse_name_follows_compression_pointers(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // "com" at offset 0, then "www.example" + pointer to offset 0
+        let bytes = vec![
+            3, b'c', b'o', b'm', 0, 3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l',
+            b'e', 0xc0, 0x00, 0xff,
+        ];
+        let mut buf = ByteBuffer::from(&bytes);
+        buf.jump(5)?;
+        assert_eq!(parse_name(&mut buf)?, "www.example.com");
+        assert_eq!(buf.pos(), 19);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_name_rejects_pointer_loops() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = vec![1, b'a', 0xc0, 0x00];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert!(parse_name(&mut buf).is_err());
+
+        let bytes = vec![0xc0, 0x00];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert!(parse_name(&mut buf).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_round_trips_through_wire_format(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let records = vec![
+            Record {
+                name: "example.com".to_string(),
+                record_type: RecordType::Soa,
+                class: Class::In,
+                ttl: 3600,
+                data: Data::Soa {
+                    mname: "ns1.example.com".to_string(),
+                    rname: "hostmaster.example.com".to_string(),
+                    serial: 2023010101,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1209600,
+                    minimum: 300,
+                },
+            },
+            Record {
+                name: "example.com".to_string(),
+                record_type: RecordType::Mx,
+                class: Class::In,
+                ttl: 3600,
+                data: Data::Mx {
+                    preference: 10,
+                    exchange: "mail.example.com".to_string(),
+                },
+            },
+            Record {
+                name: "example.com".to_string(),
+                record_type: RecordType::Txt,
+                class: Class::In,
+                ttl: 60,
+                data: Data::Txt(vec![b"hello".to_vec(), b"".to_vec()]),
+            },
+        ];
+        let bytes = serialize_records(&records)?;
+        let mut buf = ByteBuffer::from(&bytes);
+        assert_eq!(parse_records(&mut buf, records.len())?, records);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dnssec_records_round_trip_through_wire_format(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let record = |record_type, data| Record {
+            name: "example.com".to_string(),
+            record_type,
+            class: Class::In,
+            ttl: 3600,
+            data,
         };
+        let records = vec![
+            record(
+                RecordType::Dnskey,
+                Data::Dnskey {
+                    flags: 257,
+                    protocol: 3,
+                    algorithm: 13,
+                    public_key: vec![1; 64],
+                },
+            ),
+            record(
+                RecordType::Cds,
+                Data::Ds {
+                    key_tag: 12345,
+                    algorithm: 13,
+                    digest_type: 2,
+                    digest: vec![2; 32],
+                },
+            ),
+            record(
+                RecordType::Rrsig,
+                Data::Rrsig {
+                    type_covered: RecordType::Dnskey,
+                    algorithm: 13,
+                    labels: 2,
+                    original_ttl: 3600,
+                    expiration: 1_700_086_400,
+                    inception: 1_700_000_000,
+                    key_tag: 12345,
+                    signer_name: "example.com".to_string(),
+                    signature: vec![3; 64],
+                },
+            ),
+            record(
+                RecordType::Nsec,
+                Data::Nsec {
+                    next_name: "www.example.com".to_string(),
+                    types: vec![RecordType::A, RecordType::Rrsig, RecordType::Nsec],
+                },
+            ),
+            record(
+                RecordType::Nsec3,
+                Data::Nsec3 {
+                    hash_algorithm: 1,
+                    flags: 1,
+                    iterations: 0,
+                    salt: vec![0xab, 0xcd],
+                    next_hashed: vec![4; 20],
+                    types: vec![RecordType::Ns, RecordType::Ds, RecordType::Rrsig],
+                },
+            ),
+            record(
+                RecordType::Nsec3param,
+                Data::Nsec3param {
+                    hash_algorithm: 1,
+                    flags: 0,
+                    iterations: 0,
+                    salt: vec![],
+                },
+            ),
+        ];
+        let bytes = serialize_records(&records)?;
+        let mut buf = ByteBuffer::from(&bytes);
+        assert_eq!(parse_records(&mut buf, records.len())?, records);
+        Ok(())
     }
-    if let Some(pos) = original_position {
-        packet.jump(pos)?;
+
+    #[test]
+    fn test_parse_data_rejects_length_mismatch() {
+        // An A record must be exactly 4 bytes
+        let bytes = vec![127, 0, 0, 1, 0];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert!(parse_data(&RecordType::A, &mut buf, 5).is_err());
+
+        // The name inside an NS record ends before the declared length
+        let bytes = vec![0, 0];
+        let mut buf = ByteBuffer::from(&bytes);
+        assert!(parse_data(&RecordType::Ns, &mut buf, 2).is_err());
+    }
+
+    #[test]
+    fn test_parse_record_type_name_accepts_mnemonics_and_generic_form() {
+        assert_eq!(parse_record_type_name("aaaa"), Some(RecordType::Aaaa));
+        assert_eq!(parse_record_type_name("TYPE28"), Some(RecordType::Aaaa));
+        assert_eq!(
+            parse_record_type_name("TYPE999"),
+            Some(RecordType::Unknown(999))
+        );
+        assert_eq!(parse_record_type_name("BOGUS"), None);
     }
-    let name = if name_parts.is_empty() {
-        ".".to_string()
-    } else {
-        name_parts.join(".")
-    };
-    Ok(name)
 }
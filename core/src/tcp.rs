@@ -0,0 +1,69 @@
+//! Messages sent over TCP are prefixed with their length as a two byte
+//! integer, so they can be told apart in the stream (RFC 1035 §4.2.2).
+
+use anyhow::{anyhow, Context};
+use std::error;
+
+/// Prefix a serialized message with its length.
+pub fn frame_message(message: &[u8]) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let len: u16 = message
+        .len()
+        .try_into()
+        .context("messages sent over TCP cannot be larger than 65535 bytes")?;
+    let mut bytes = Vec::with_capacity(message.len() + 2);
+    bytes.extend(len.to_be_bytes());
+    bytes.extend(message);
+    Ok(bytes)
+}
+
+/// Split a stream of length-prefixed messages, such as a capture of a TCP
+/// connection, into the individual messages.
+pub fn split_framed_messages(
+    mut bytes: &[u8],
+) -> Result<Vec<&[u8]>, Box<dyn error::Error + Send + Sync>> {
+    let mut messages = vec![];
+    while !bytes.is_empty() {
+        if bytes.len() < 2 {
+            return Err(anyhow!("stream ends partway through a length prefix").into());
+        }
+        let len = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+        let message = bytes.get(2..2 + len).with_context(|| {
+            format!(
+                "stream ends partway through a message of {} bytes ({} remaining)",
+                len,
+                bytes.len() - 2
+            )
+        })?;
+        messages.push(message);
+        bytes = &bytes[2 + len..];
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_message_prefixes_length() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        assert_eq!(frame_message(&[7; 3])?, vec![0, 3, 7, 7, 7]);
+        assert_eq!(frame_message(&[])?, vec![0, 0]);
+        assert!(frame_message(&vec![0; 65536]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_framed_messages_returns_each_message(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let bytes = vec![0, 2, 1, 2, 0, 0, 0, 1, 3];
+        let messages = split_framed_messages(&bytes)?;
+        assert_eq!(messages, vec![&[1, 2][..], &[][..], &[3][..]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_framed_messages_rejects_truncated_streams() {
+        assert!(split_framed_messages(&[0]).is_err());
+        assert!(split_framed_messages(&[0, 3, 1, 2]).is_err());
+    }
+}
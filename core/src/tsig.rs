@@ -0,0 +1,791 @@
+//! Transaction signatures (RFC 8945). A TSIG record at the end of a message
+//! carries an HMAC over the rest of it, keyed with a secret shared by the two
+//! ends, so each can tell that a message came from the other and wasn't
+//! changed on the way.
+
+use super::buffer::ByteBuffer;
+use super::dnssec;
+use super::header;
+use super::name;
+use super::question;
+use super::record::{self, Class, Data, Record, RecordType};
+use anyhow::{anyhow, Context};
+use ring::hmac;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{error, fmt};
+
+/// How far apart the clocks of the two ends may be, in seconds (RFC 8945
+/// §10).
+pub const DEFAULT_FUDGE: u16 = 300;
+
+/// Error codes carried in TSIG records (RFC 8945 §3).
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+
+/// Senders must sign at least every 100th message of a stream (RFC 8945
+/// §5.3.1), so we give up after this many unsigned messages in a row.
+const MAX_UNSIGNED_MESSAGES: usize = 99;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl Algorithm {
+    /// The algorithm's name, as it appears in TSIG records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::HmacSha256 => "hmac-sha256",
+            Algorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    pub fn from_name(value: &str) -> Option<Algorithm> {
+        [Algorithm::HmacSha256, Algorithm::HmacSha512]
+            .into_iter()
+            .find(|algorithm| name::eq(algorithm.name(), value))
+    }
+
+    fn hmac(&self) -> hmac::Algorithm {
+        match self {
+            Algorithm::HmacSha256 => hmac::HMAC_SHA256,
+            Algorithm::HmacSha512 => hmac::HMAC_SHA512,
+        }
+    }
+
+    fn mac_len(&self) -> usize {
+        self.hmac().digest_algorithm().output_len()
+    }
+}
+
+/// A secret shared with another server or client, and the name both ends
+/// know it by.
+#[derive(Clone)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: Algorithm,
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Leave the secret out, so it doesn't end up in logs
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl TsigKey {
+    pub fn new(name: &str, algorithm: Algorithm, secret: Vec<u8>) -> TsigKey {
+        TsigKey {
+            name: name::normalize(name),
+            algorithm,
+            secret,
+        }
+    }
+
+    /// Parse a key given as `[algorithm:]name:secret`, like dig's `-y`
+    /// option, with the secret in base64. The algorithm defaults to
+    /// hmac-sha256.
+    pub fn parse(value: &str) -> Result<TsigKey, Box<dyn error::Error + Send + Sync>> {
+        let parts: Vec<&str> = value.split(':').collect();
+        let (algorithm, name, secret) = match parts[..] {
+            [name, secret] => (Algorithm::HmacSha256, name, secret),
+            [algorithm, name, secret] => {
+                let algorithm = Algorithm::from_name(algorithm)
+                    .with_context(|| format!("unsupported TSIG algorithm {}", algorithm))?;
+                (algorithm, name, secret)
+            }
+            _ => return Err(anyhow!("TSIG keys look like [algorithm:]name:secret").into()),
+        };
+        Ok(TsigKey::new(
+            name,
+            algorithm,
+            dnssec::decode_base64(secret)?,
+        ))
+    }
+
+    /// Parse the `key` statements of a BIND configuration file, such as
+    /// those written by tsig-keygen:
+    ///
+    /// ```text
+    /// key "transfer" {
+    ///     algorithm hmac-sha256;
+    ///     secret "c2VjcmV0";
+    /// };
+    /// ```
+    pub fn parse_key_file(text: &str) -> Result<Vec<TsigKey>, Box<dyn error::Error + Send + Sync>> {
+        let mut tokens = key_file_tokens(text).into_iter();
+        let mut keys = vec![];
+        while let Some(token) = tokens.next() {
+            if token != "key" {
+                return Err(anyhow!("expected a key statement, found {}", token).into());
+            }
+            let name = tokens.next().context("key statement has no name")?;
+            if tokens.next().as_deref() != Some("{") {
+                return Err(anyhow!("expected {{ after key {}", name).into());
+            }
+            let (mut algorithm, mut secret) = (None, None);
+            loop {
+                let field = tokens.next().context("unterminated key statement")?;
+                if field == "}" {
+                    break;
+                }
+                let value = tokens.next().context("key field has no value")?;
+                match field.as_str() {
+                    "algorithm" => {
+                        let parsed = Algorithm::from_name(&value)
+                            .with_context(|| format!("unsupported TSIG algorithm {}", value))?;
+                        algorithm = Some(parsed);
+                    }
+                    "secret" => secret = Some(dnssec::decode_base64(&value)?),
+                    _ => return Err(anyhow!("unknown key field {}", field).into()),
+                }
+                if tokens.next().as_deref() != Some(";") {
+                    return Err(anyhow!("expected ; after {} {}", field, value).into());
+                }
+            }
+            if tokens.next().as_deref() != Some(";") {
+                return Err(anyhow!("expected ; after key {}", name).into());
+            }
+            keys.push(TsigKey::new(
+                &name,
+                algorithm.with_context(|| format!("key {} has no algorithm", name))?,
+                secret.with_context(|| format!("key {} has no secret", name))?,
+            ));
+        }
+        Ok(keys)
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let key = hmac::Key::new(self.algorithm.hmac(), &self.secret);
+        hmac::sign(&key, data).as_ref().to_vec()
+    }
+
+    fn verify(&self, data: &[u8], mac: &[u8]) -> bool {
+        let key = hmac::Key::new(self.algorithm.hmac(), &self.secret);
+        hmac::verify(&key, data, mac).is_ok()
+    }
+}
+
+/// Why a signed message was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TsigError {
+    /// The message should have been signed but wasn't.
+    Unsigned,
+    /// The TSIG record isn't where it should be, or can't be parsed.
+    Malformed(String),
+    /// The message was signed with a key we don't know.
+    BadKey,
+    /// The MAC doesn't match the message.
+    BadSig,
+    /// The message was signed too long ago, or too far in the future.
+    BadTime,
+    /// The other end rejected our signature with this error code.
+    Rejected(u16),
+}
+
+impl TsigError {
+    /// The code to report this error with in a TSIG record.
+    pub fn code(&self) -> u16 {
+        match self {
+            TsigError::BadKey => BADKEY,
+            TsigError::BadTime => BADTIME,
+            TsigError::Rejected(code) => *code,
+            _ => BADSIG,
+        }
+    }
+}
+
+impl fmt::Display for TsigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TsigError::Unsigned => write!(f, "message is not signed"),
+            TsigError::Malformed(reason) => write!(f, "malformed TSIG record: {}", reason),
+            TsigError::BadKey => write!(f, "message is signed with an unknown key"),
+            TsigError::BadSig => write!(f, "TSIG signature does not match"),
+            TsigError::BadTime => write!(f, "TSIG signature is outside the allowed time"),
+            TsigError::Rejected(code) => {
+                write!(f, "signature was rejected with {}", error_name(*code))
+            }
+        }
+    }
+}
+
+impl error::Error for TsigError {}
+
+/// The mnemonic for a TSIG error code, as used by tools like dig.
+pub fn error_name(code: u16) -> String {
+    match code {
+        0 => "NOERROR".to_string(),
+        BADSIG => "BADSIG".to_string(),
+        BADKEY => "BADKEY".to_string(),
+        BADTIME => "BADTIME".to_string(),
+        _ => format!("RCODE{}", code),
+    }
+}
+
+/// Signs or verifies the messages of one transaction: a request, its
+/// response, and any further responses on the same TCP connection, such as
+/// those of a zone transfer. Each signature covers the one before it, so
+/// messages can't be dropped or reordered without being noticed.
+#[derive(Debug, Clone)]
+pub struct TsigSession {
+    key: TsigKey,
+    fudge: u16,
+    /// The MAC of the last signed message.
+    mac: Option<Vec<u8>>,
+    /// How many messages have been signed or verified so far.
+    messages: usize,
+    /// Unsigned messages received since the last signed one, which the next
+    /// signature also covers.
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl TsigSession {
+    pub fn new(key: TsigKey) -> TsigSession {
+        TsigSession {
+            key,
+            fudge: DEFAULT_FUDGE,
+            mac: None,
+            messages: 0,
+            unsigned: vec![],
+            unsigned_count: 0,
+        }
+    }
+
+    pub fn key(&self) -> &TsigKey {
+        &self.key
+    }
+
+    /// Sign a serialized message, returning it with a TSIG record appended.
+    pub fn sign(
+        &mut self,
+        message: &[u8],
+        now: SystemTime,
+    ) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        self.sign_with_error(message, now, 0, vec![])
+    }
+
+    /// How many bytes signing a message adds to it.
+    pub fn signature_len(&self) -> usize {
+        let tsig = self.record(0, 0, vec![0; self.key.algorithm.mac_len()], 0, vec![]);
+        record::serialize_single_record(&tsig)
+            .map(|bytes| bytes.len())
+            .unwrap_or(0)
+    }
+
+    /// Verify the next message of the transaction. Returns whether it was
+    /// signed; only the messages after the first response may be unsigned,
+    /// and the signature on the next one covers them.
+    pub fn verify(&mut self, message: &[u8], now: SystemTime) -> Result<bool, TsigError> {
+        let (unsigned, tsig) = match split(message)? {
+            Some(split) => split,
+            None if self.messages < 2 || self.unsigned_count >= MAX_UNSIGNED_MESSAGES => {
+                return Err(TsigError::Unsigned)
+            }
+            None => {
+                self.unsigned.extend(message);
+                self.unsigned_count += 1;
+                self.messages += 1;
+                return Ok(false);
+            }
+        };
+        let (algorithm, time_signed, fudge, mac, error) = match &tsig.data {
+            Data::Tsig {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                error,
+                ..
+            } => (algorithm, *time_signed, *fudge, mac, *error),
+            _ => unreachable!(),
+        };
+        if !name::eq(&tsig.name, &self.key.name)
+            || Algorithm::from_name(algorithm) != Some(self.key.algorithm)
+        {
+            return Err(TsigError::BadKey);
+        }
+        if error != 0 && mac.is_empty() {
+            return Err(TsigError::Rejected(error));
+        }
+        if mac.len() != self.key.algorithm.mac_len() {
+            return Err(TsigError::BadSig);
+        }
+        let timers_only = self.messages >= 2;
+        let variables = variables(&tsig, timers_only).map_err(malformed)?;
+        let data = self.digest_input(&unsigned, &variables);
+        if !self.key.verify(&data, mac) {
+            return Err(TsigError::BadSig);
+        }
+        self.mac = Some(mac.clone());
+        self.messages += 1;
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+        if error != 0 {
+            return Err(TsigError::Rejected(error));
+        }
+        if !within_fudge(time_signed, fudge, now) {
+            return Err(TsigError::BadTime);
+        }
+        Ok(true)
+    }
+
+    /// Verify the request that starts a transaction.
+    fn verify_first(&mut self, message: &[u8], now: SystemTime) -> Result<(), TsigError> {
+        match self.verify(message, now) {
+            Ok(_) => Ok(()),
+            Err(TsigError::Rejected(_)) => Err(TsigError::BadSig),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn sign_with_error(
+        &mut self,
+        message: &[u8],
+        now: SystemTime,
+        error: u16,
+        other: Vec<u8>,
+    ) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        let id = message.get(0..2).context("message is too short to sign")?;
+        let original_id = u16::from_be_bytes([id[0], id[1]]);
+        let time = time_signed(now);
+        let unsigned = self.record(time, original_id, vec![], error, other.clone());
+        let timers_only = self.messages >= 2;
+        let mac = self
+            .key
+            .sign(&self.digest_input(message, &variables(&unsigned, timers_only)?));
+        let tsig = self.record(time, original_id, mac.clone(), error, other);
+        self.mac = Some(mac);
+        self.messages += 1;
+        append_record(message, &tsig)
+    }
+
+    /// The data a MAC is computed over: the previous MAC, any unsigned
+    /// messages since, the message itself and the TSIG variables.
+    fn digest_input(&self, message: &[u8], variables: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        if let Some(mac) = &self.mac {
+            data.extend((mac.len() as u16).to_be_bytes());
+            data.extend(mac);
+        }
+        data.extend(&self.unsigned);
+        data.extend(message);
+        data.extend(variables);
+        data
+    }
+
+    fn record(
+        &self,
+        time_signed: u64,
+        original_id: u16,
+        mac: Vec<u8>,
+        error: u16,
+        other: Vec<u8>,
+    ) -> Record {
+        Record {
+            name: self.key.name.clone(),
+            record_type: RecordType::Tsig,
+            class: Class::Any,
+            ttl: 0,
+            data: Data::Tsig {
+                algorithm: self.key.algorithm.name().to_string(),
+                time_signed,
+                fudge: self.fudge,
+                mac,
+                original_id,
+                error,
+                other,
+            },
+        }
+    }
+}
+
+/// A request whose signature didn't verify, with what's needed to tell the
+/// client why.
+#[derive(Debug)]
+pub struct TsigFailure {
+    pub error: TsigError,
+    /// The request's TSIG record, if it had one we could parse.
+    tsig: Option<Box<Record>>,
+    /// For requests signed at the wrong time, the session to sign the error
+    /// response with, as its MAC was valid.
+    session: Option<Box<TsigSession>>,
+}
+
+impl TsigFailure {
+    /// Add a TSIG record to an error response, telling the client why its
+    /// request was rejected (RFC 8945 §5.3.2). Only BADTIME errors are
+    /// signed, and they carry our time so the client can see how far off its
+    /// clock is.
+    pub fn sign_response(
+        self,
+        response: &[u8],
+        now: SystemTime,
+    ) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        if let Some(mut session) = self.session {
+            let other = time_signed(now).to_be_bytes()[2..].to_vec();
+            return session.sign_with_error(response, now, BADTIME, other);
+        }
+        let tsig = match self.tsig {
+            Some(tsig) => *tsig,
+            None => return Ok(response.to_vec()),
+        };
+        let data = match tsig.data {
+            Data::Tsig {
+                algorithm,
+                time_signed,
+                fudge,
+                original_id,
+                ..
+            } => Data::Tsig {
+                algorithm,
+                time_signed,
+                fudge,
+                mac: vec![],
+                original_id,
+                error: self.error.code(),
+                other: vec![],
+            },
+            data => data,
+        };
+        append_record(response, &Record { data, ..tsig })
+    }
+}
+
+impl fmt::Display for TsigFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+/// Verify a request signed with one of `keys`. Returns `None` if the request
+/// isn't signed, or the session to sign the response with if it is.
+pub fn verify_request(
+    message: &[u8],
+    keys: &[TsigKey],
+    now: SystemTime,
+) -> Result<Option<TsigSession>, TsigFailure> {
+    let failure = |error, tsig: Option<Record>, session: Option<TsigSession>| TsigFailure {
+        error,
+        tsig: tsig.map(Box::new),
+        session: session.map(Box::new),
+    };
+    let tsig = match split(message) {
+        Ok(Some((_, tsig))) => tsig,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err(failure(err, None, None)),
+    };
+    let algorithm = match &tsig.data {
+        Data::Tsig { algorithm, .. } => Algorithm::from_name(algorithm),
+        _ => None,
+    };
+    let key = keys
+        .iter()
+        .find(|key| name::eq(&key.name, &tsig.name) && Some(key.algorithm) == algorithm);
+    let mut session = match key {
+        Some(key) => TsigSession::new(key.clone()),
+        None => return Err(failure(TsigError::BadKey, Some(tsig), None)),
+    };
+    match session.verify_first(message, now) {
+        Ok(()) => Ok(Some(session)),
+        Err(TsigError::BadTime) => Err(failure(TsigError::BadTime, Some(tsig), Some(session))),
+        Err(err) => Err(failure(err, Some(tsig), None)),
+    }
+}
+
+/// Remove a request's TSIG record once it has been verified, so it isn't
+/// mistaken for one of the request's own records.
+pub fn strip(records: &mut Vec<Record>) {
+    records.retain(|record| record.record_type != RecordType::Tsig);
+}
+
+/// Split a message into the bytes its MAC covers and its TSIG record, which
+/// must be the last record. Returns `None` for unsigned messages.
+fn split(message: &[u8]) -> Result<Option<(Vec<u8>, Record)>, TsigError> {
+    let mut buffer = ByteBuffer::from(message);
+    let header = header::parse_header(&mut buffer).map_err(malformed)?;
+    if header.resource_entries == 0 {
+        return Ok(None);
+    }
+    question::parse_questions(&mut buffer, header.questions as usize).map_err(malformed)?;
+    let count = header.answers as usize
+        + header.authoritative_entries as usize
+        + header.resource_entries as usize;
+    let records = record::parse_records(&mut buffer, count - 1).map_err(malformed)?;
+    if records
+        .iter()
+        .any(|record| record.record_type == RecordType::Tsig)
+    {
+        return Err(TsigError::Malformed(
+            "TSIG must be the last record".to_string(),
+        ));
+    }
+    let start = buffer.pos();
+    let tsig = record::parse_single_record(&mut buffer).map_err(malformed)?;
+    let original_id = match &tsig.data {
+        Data::Tsig { original_id, .. } => *original_id,
+        _ => return Ok(None),
+    };
+    // The MAC covers the message as it was before the TSIG record was added
+    let mut unsigned = message[..start].to_vec();
+    unsigned[0..2].copy_from_slice(&original_id.to_be_bytes());
+    unsigned[10..12].copy_from_slice(&(header.resource_entries - 1).to_be_bytes());
+    Ok(Some((unsigned, tsig)))
+}
+
+/// The TSIG variables that the MAC covers along with the message (RFC 8945
+/// §4.3.3). Messages after the first response only cover the timers.
+fn variables(
+    tsig: &Record,
+    timers_only: bool,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let (algorithm, time_signed, fudge, error, other) = match &tsig.data {
+        Data::Tsig {
+            algorithm,
+            time_signed,
+            fudge,
+            error,
+            other,
+            ..
+        } => (algorithm, time_signed, fudge, error, other),
+        _ => return Err(anyhow!("not a TSIG record").into()),
+    };
+    let mut bytes = vec![];
+    if !timers_only {
+        bytes.extend(dnssec::canonical_name(&tsig.name)?);
+        bytes.extend(record::class_code(&Class::Any).to_be_bytes());
+        bytes.extend(0u32.to_be_bytes());
+        bytes.extend(dnssec::canonical_name(algorithm)?);
+    }
+    bytes.extend(&time_signed.to_be_bytes()[2..]);
+    bytes.extend(fudge.to_be_bytes());
+    if !timers_only {
+        bytes.extend(error.to_be_bytes());
+        bytes.extend((other.len() as u16).to_be_bytes());
+        bytes.extend(other);
+    }
+    Ok(bytes)
+}
+
+/// Append a record to the additional section of a serialized message.
+fn append_record(
+    message: &[u8],
+    record: &Record,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let count = message.get(10..12).context("message is too short")?;
+    let count = u16::from_be_bytes([count[0], count[1]])
+        .checked_add(1)
+        .context("too many additional records")?;
+    let mut bytes = message.to_vec();
+    bytes[10..12].copy_from_slice(&count.to_be_bytes());
+    bytes.extend(record::serialize_single_record(record)?);
+    Ok(bytes)
+}
+
+/// A point in time as a TSIG time, which counts seconds since the epoch in
+/// 48 bits.
+fn time_signed(now: SystemTime) -> u64 {
+    let seconds = now
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    seconds & 0xffff_ffff_ffff
+}
+
+fn within_fudge(time_signed: u64, fudge: u16, now: SystemTime) -> bool {
+    self::time_signed(now).abs_diff(time_signed) <= fudge as u64
+}
+
+fn malformed(err: Box<dyn error::Error + Send + Sync>) -> TsigError {
+    TsigError::Malformed(err.to_string())
+}
+
+/// Split a BIND configuration file into words, quoted strings and the
+/// punctuation `{`, `}` and `;`, dropping comments.
+fn key_file_tokens(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    for line in text.lines() {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '#' => break,
+                '/' if chars.peek() == Some(&'/') => break,
+                '{' | '}' | ';' => tokens.push(c.to_string()),
+                '"' => tokens.push(chars.by_ref().take_while(|c| *c != '"').collect()),
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut token = c.to_string();
+                    while let Some(c) =
+                        chars.next_if(|c| !c.is_whitespace() && !"{};\"".contains(*c))
+                    {
+                        token.push(c);
+                    }
+                    tokens.push(token);
+                }
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{self, DnsPacket};
+    use std::time::Duration;
+
+    fn key(algorithm: Algorithm) -> TsigKey {
+        TsigKey::new(
+            "transfer.example.com",
+            algorithm,
+            b"0123456789abcdef".to_vec(),
+        )
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+
+    fn message(id: u16, query: bool) -> Vec<u8> {
+        let mut packet = packet::build_query(id, "example.com", &RecordType::Soa);
+        packet.header.query = query;
+        crate::serialize_dns_packet(&packet).unwrap()
+    }
+
+    fn parse(message: &[u8]) -> DnsPacket {
+        crate::parse_dns_packet(message).unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify_exchange() {
+        for algorithm in [Algorithm::HmacSha256, Algorithm::HmacSha512] {
+            let mut client = TsigSession::new(key(algorithm));
+            let request = client.sign(&message(1, true), at(0)).unwrap();
+            let tsig = parse(&request).resource_entries.pop().unwrap();
+            assert_eq!(tsig.record_type, RecordType::Tsig);
+            assert_eq!(tsig.class, Class::Any);
+
+            let mut server = verify_request(&request, &[key(algorithm)], at(10))
+                .unwrap()
+                .unwrap();
+            let response = server.sign(&message(1, false), at(10)).unwrap();
+            assert_eq!(
+                response.len(),
+                message(1, false).len() + server.signature_len()
+            );
+            assert_eq!(client.verify(&response, at(20)), Ok(true));
+
+            // The response's MAC covers the request's, so it can't be
+            // replayed in another exchange
+            let mut other = TsigSession::new(key(algorithm));
+            other.sign(&message(1, true), at(1)).unwrap();
+            assert_eq!(other.verify(&response, at(20)), Err(TsigError::BadSig));
+        }
+        assert!(verify_request(&message(1, true), &[], at(0))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_verify_rejects_bad_requests() {
+        let mut client = TsigSession::new(key(Algorithm::HmacSha256));
+        let request = client.sign(&message(7, true), at(0)).unwrap();
+
+        let mut tampered = request.clone();
+        tampered[2] |= 0b1; // Set RD
+        let failure = verify_request(&tampered, &[key(Algorithm::HmacSha256)], at(0)).unwrap_err();
+        assert_eq!(failure.error, TsigError::BadSig);
+
+        // Errors for unknown keys and bad signatures come back unsigned
+        let failure = verify_request(&request, &[key(Algorithm::HmacSha512)], at(0)).unwrap_err();
+        assert_eq!(failure.error, TsigError::BadKey);
+        let response = failure.sign_response(&message(7, false), at(0)).unwrap();
+        let tsig = parse(&response).resource_entries.pop().unwrap();
+        assert!(matches!(tsig.data, Data::Tsig { error: BADKEY, ref mac, .. } if mac.is_empty()));
+        let mut session = client.clone();
+        assert_eq!(
+            session.verify(&response, at(0)),
+            Err(TsigError::Rejected(BADKEY))
+        );
+
+        // BADTIME responses are signed, and carry the server's time
+        let late = at(DEFAULT_FUDGE as u64 + 1);
+        let failure = verify_request(&request, &[key(Algorithm::HmacSha256)], late).unwrap_err();
+        assert_eq!(failure.error, TsigError::BadTime);
+        let response = failure.sign_response(&message(7, false), late).unwrap();
+        let tsig = parse(&response).resource_entries.pop().unwrap();
+        let other = time_signed(late).to_be_bytes()[2..].to_vec();
+        assert!(
+            matches!(tsig.data, Data::Tsig { error: BADTIME, other: ref o, .. } if *o == other)
+        );
+        assert_eq!(
+            client.verify(&response, late),
+            Err(TsigError::Rejected(BADTIME))
+        );
+    }
+
+    #[test]
+    fn test_verify_multi_message_stream() {
+        let mut client = TsigSession::new(key(Algorithm::HmacSha256));
+        let request = client.sign(&message(3, true), at(0)).unwrap();
+        let mut server = verify_request(&request, &[key(Algorithm::HmacSha256)], at(0))
+            .unwrap()
+            .unwrap();
+
+        // The server signs the first and last messages, and leaves the
+        // middle ones unsigned but covered by the last signature
+        let first = server.sign(&message(3, false), at(0)).unwrap();
+        let middle = message(3, false);
+        server.unsigned.extend(&middle);
+        let last = server.sign(&message(3, false), at(1)).unwrap();
+
+        let mut stream = client.clone();
+        assert_eq!(stream.verify(&first, at(1)), Ok(true));
+        assert_eq!(stream.verify(&middle, at(1)), Ok(false));
+        assert!(stream.unsigned_count > 0);
+        assert_eq!(stream.verify(&last, at(1)), Ok(true));
+        assert_eq!(stream.unsigned_count, 0);
+
+        // Dropping a message breaks the chain
+        let mut stream = client.clone();
+        assert_eq!(stream.verify(&first, at(1)), Ok(true));
+        assert_eq!(stream.verify(&last, at(1)), Err(TsigError::BadSig));
+
+        // The first response has to be signed
+        assert_eq!(client.verify(&middle, at(1)), Err(TsigError::Unsigned));
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let key = TsigKey::parse("hmac-sha512:transfer.example.com.:c2VjcmV0").unwrap();
+        assert_eq!(key.name, "transfer.example.com");
+        assert_eq!(key.algorithm, Algorithm::HmacSha512);
+        assert_eq!(key.secret, b"secret");
+        let key = TsigKey::parse("transfer:c2VjcmV0").unwrap();
+        assert_eq!(key.algorithm, Algorithm::HmacSha256);
+        assert!(TsigKey::parse("hmac-md5:transfer:c2VjcmV0").is_err());
+        assert!(TsigKey::parse("transfer").is_err());
+
+        let text = r#"
+            # Written by tsig-keygen
+            key "transfer" {
+                algorithm hmac-sha256;
+                secret "c2VjcmV0";
+            };
+            key update { algorithm HMAC-SHA512; secret "dXBkYXRl"; };
+        "#;
+        let keys = TsigKey::parse_key_file(text).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].name, "transfer");
+        assert_eq!(keys[1].algorithm, Algorithm::HmacSha512);
+        assert_eq!(keys[1].secret, b"update");
+        assert!(TsigKey::parse_key_file("key transfer { secret \"c2VjcmV0\"; };").is_err());
+    }
+}
@@ -0,0 +1,675 @@
+//! Dynamic updates to a zone's records (RFC 2136).
+
+use super::header::{Opcode, ResponseCode};
+use super::journal;
+use super::name;
+use super::packet::DnsPacket;
+use super::record::{self, Class, Data, Record, RecordType};
+use super::zone::Zone;
+use std::collections::HashSet;
+
+/// Something that must be true of the zone before an update is applied
+/// (RFC 2136 §2.4).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prerequisite {
+    /// The name owns at least one record.
+    NameInUse(String),
+    /// The name owns no records.
+    NameNotInUse(String),
+    /// The name owns records of this type, whatever their data.
+    RrsetExists {
+        name: String,
+        record_type: RecordType,
+    },
+    /// The name owns no records of this type.
+    RrsetDoesNotExist {
+        name: String,
+        record_type: RecordType,
+    },
+    /// The name's records of one type are exactly these, ignoring TTLs.
+    RrsetEquals(Vec<Record>),
+}
+
+/// A change to make to the zone (RFC 2136 §2.5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Add a record, or update its TTL if it is already there.
+    Add(Record),
+    /// Delete every record of a type owned by a name.
+    DeleteRrset {
+        name: String,
+        record_type: RecordType,
+    },
+    /// Delete every record owned by a name.
+    DeleteName(String),
+    /// Delete a single record, whatever its TTL.
+    DeleteRecord(Record),
+}
+
+impl Change {
+    /// The name whose records change.
+    pub fn name(&self) -> &str {
+        match self {
+            Change::Add(record) | Change::DeleteRecord(record) => &record.name,
+            Change::DeleteRrset { name, .. } | Change::DeleteName(name) => name,
+        }
+    }
+
+    /// The type of record that changes, or `RecordType::Any` for all of them.
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            Change::Add(record) | Change::DeleteRecord(record) => record.record_type,
+            Change::DeleteRrset { record_type, .. } => *record_type,
+            Change::DeleteName(_) => RecordType::Any,
+        }
+    }
+}
+
+/// The contents of an UPDATE message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// The origin of the zone to update.
+    pub zone: String,
+    pub prerequisites: Vec<Prerequisite>,
+    pub changes: Vec<Change>,
+}
+
+impl Update {
+    /// Start an empty update to a zone, to be filled in with the builder
+    /// methods below.
+    pub fn new(zone: &str) -> Update {
+        Update {
+            zone: name::normalize(zone),
+            prerequisites: vec![],
+            changes: vec![],
+        }
+    }
+
+    /// Require the name to own at least one record.
+    pub fn name_in_use(mut self, name: &str) -> Update {
+        let name = name::normalize(name);
+        self.prerequisites.push(Prerequisite::NameInUse(name));
+        self
+    }
+
+    /// Require the name to own no records.
+    pub fn name_not_in_use(mut self, name: &str) -> Update {
+        let name = name::normalize(name);
+        self.prerequisites.push(Prerequisite::NameNotInUse(name));
+        self
+    }
+
+    /// Require the name to own records of this type.
+    pub fn rrset_exists(mut self, name: &str, record_type: RecordType) -> Update {
+        let name = name::normalize(name);
+        let prerequisite = Prerequisite::RrsetExists { name, record_type };
+        self.prerequisites.push(prerequisite);
+        self
+    }
+
+    /// Require the name to own no records of this type.
+    pub fn rrset_does_not_exist(mut self, name: &str, record_type: RecordType) -> Update {
+        let name = name::normalize(name);
+        let prerequisite = Prerequisite::RrsetDoesNotExist { name, record_type };
+        self.prerequisites.push(prerequisite);
+        self
+    }
+
+    /// Require the record's RRset to hold exactly the records given for it.
+    /// Records with the same name and type are collected into one RRset.
+    pub fn rrset_equals(mut self, record: Record) -> Update {
+        let record = Record { ttl: 0, ..record };
+        let rrset = self
+            .prerequisites
+            .iter_mut()
+            .find_map(|prerequisite| match prerequisite {
+                Prerequisite::RrsetEquals(rrset)
+                    if name::eq(&rrset[0].name, &record.name)
+                        && rrset[0].record_type == record.record_type =>
+                {
+                    Some(rrset)
+                }
+                _ => None,
+            });
+        match rrset {
+            Some(rrset) => rrset.push(record),
+            None => self
+                .prerequisites
+                .push(Prerequisite::RrsetEquals(vec![record])),
+        }
+        self
+    }
+
+    /// Add a record to the zone.
+    pub fn add_record(mut self, record: Record) -> Update {
+        self.changes.push(Change::Add(record));
+        self
+    }
+
+    /// Delete every record of a type owned by a name.
+    pub fn delete_rrset(mut self, name: &str, record_type: RecordType) -> Update {
+        let name = name::normalize(name);
+        self.changes.push(Change::DeleteRrset { name, record_type });
+        self
+    }
+
+    /// Delete every record owned by a name.
+    pub fn delete_name(mut self, name: &str) -> Update {
+        self.changes.push(Change::DeleteName(name::normalize(name)));
+        self
+    }
+
+    /// Delete a single record, whatever its TTL.
+    pub fn delete_record(mut self, record: Record) -> Update {
+        self.changes
+            .push(Change::DeleteRecord(Record { ttl: 0, ..record }));
+        self
+    }
+
+    /// Read an update from a message's sections, which UPDATE reuses: the
+    /// question names the zone, the answers are prerequisites, and the
+    /// authority records are changes. Returns the response code to reject
+    /// a malformed update with.
+    pub fn parse(request: &DnsPacket) -> Result<Update, ResponseCode> {
+        let zone = match &request.questions[..] {
+            [question] if record::parse_record_type(question.typ) == RecordType::Soa => question,
+            _ => return Err(ResponseCode::FormatError),
+        };
+        let class = record::parse_class(zone.class);
+        let in_zone = |record: &Record| {
+            if name::is_subdomain(&record.name, &zone.name) {
+                Ok(())
+            } else {
+                Err(ResponseCode::NotZone)
+            }
+        };
+
+        let mut prerequisites = vec![];
+        let mut rrsets: Vec<Vec<Record>> = vec![];
+        for record in &request.answers {
+            in_zone(record)?;
+            if record.ttl != 0 {
+                return Err(ResponseCode::FormatError);
+            }
+            let name = record.name.clone();
+            let record_type = record.record_type;
+            let prerequisite = match record.class {
+                Class::Any | Class::None if !is_empty(&record.data) => {
+                    return Err(ResponseCode::FormatError)
+                }
+                Class::Any if record_type == RecordType::Any => Prerequisite::NameInUse(name),
+                Class::Any => Prerequisite::RrsetExists { name, record_type },
+                Class::None if record_type == RecordType::Any => Prerequisite::NameNotInUse(name),
+                Class::None => Prerequisite::RrsetDoesNotExist { name, record_type },
+                _ if record.class == class && !is_meta_type(record_type) => {
+                    let rrset = rrsets.iter_mut().find(|rrset| {
+                        name::eq(&rrset[0].name, &name) && rrset[0].record_type == record_type
+                    });
+                    match rrset {
+                        Some(rrset) => rrset.push(record.clone()),
+                        None => rrsets.push(vec![record.clone()]),
+                    }
+                    continue;
+                }
+                _ => return Err(ResponseCode::FormatError),
+            };
+            prerequisites.push(prerequisite);
+        }
+        prerequisites.extend(rrsets.into_iter().map(Prerequisite::RrsetEquals));
+
+        let mut changes = vec![];
+        for record in &request.authoritative_entries {
+            in_zone(record)?;
+            let name = record.name.clone();
+            let record_type = record.record_type;
+            let change = match record.class {
+                _ if record.class == class && !is_meta_type(record_type) => {
+                    Change::Add(record.clone())
+                }
+                Class::Any if record.ttl != 0 || !is_empty(&record.data) => {
+                    return Err(ResponseCode::FormatError)
+                }
+                Class::Any if record_type == RecordType::Any => Change::DeleteName(name),
+                Class::Any if !is_meta_type(record_type) => {
+                    Change::DeleteRrset { name, record_type }
+                }
+                Class::None if record.ttl == 0 && !is_meta_type(record_type) => {
+                    Change::DeleteRecord(Record {
+                        class,
+                        ..record.clone()
+                    })
+                }
+                _ => return Err(ResponseCode::FormatError),
+            };
+            changes.push(change);
+        }
+        Ok(Update {
+            zone: zone.name.clone(),
+            prerequisites,
+            changes,
+        })
+    }
+
+    /// Build the UPDATE message for this update, the reverse of `parse`.
+    /// The zone is assumed to be in class IN.
+    pub fn to_packet(&self, id: u16) -> DnsPacket {
+        let mut request = super::packet::build_query(id, &self.zone, &RecordType::Soa);
+        request.header.opcode = Opcode::Update;
+        let empty = |name: &str, record_type: RecordType, class: Class| Record {
+            name: name.to_string(),
+            record_type,
+            class,
+            ttl: 0,
+            data: Data::Unknown(vec![]),
+        };
+        for prerequisite in &self.prerequisites {
+            match prerequisite {
+                Prerequisite::NameInUse(name) => {
+                    request
+                        .answers
+                        .push(empty(name, RecordType::Any, Class::Any))
+                }
+                Prerequisite::NameNotInUse(name) => {
+                    request
+                        .answers
+                        .push(empty(name, RecordType::Any, Class::None))
+                }
+                Prerequisite::RrsetExists { name, record_type } => {
+                    request.answers.push(empty(name, *record_type, Class::Any))
+                }
+                Prerequisite::RrsetDoesNotExist { name, record_type } => {
+                    request.answers.push(empty(name, *record_type, Class::None))
+                }
+                Prerequisite::RrsetEquals(records) => {
+                    request.answers.extend(records.iter().map(|record| Record {
+                        ttl: 0,
+                        ..record.clone()
+                    }))
+                }
+            }
+        }
+        for change in &self.changes {
+            let record = match change {
+                Change::Add(record) => record.clone(),
+                Change::DeleteRrset { name, record_type } => empty(name, *record_type, Class::Any),
+                Change::DeleteName(name) => empty(name, RecordType::Any, Class::Any),
+                Change::DeleteRecord(record) => Record {
+                    class: Class::None,
+                    ttl: 0,
+                    ..record.clone()
+                },
+            };
+            request.authoritative_entries.push(record);
+        }
+        request
+    }
+
+    /// Check the prerequisites against a zone, returning the response code
+    /// for the first one that doesn't hold (RFC 2136 §3.2.5).
+    pub fn check(&self, zone: &Zone) -> Result<(), ResponseCode> {
+        for prerequisite in &self.prerequisites {
+            let holds = match prerequisite {
+                Prerequisite::NameInUse(name) => !zone.lookup(name).is_empty(),
+                Prerequisite::NameNotInUse(name) => zone.lookup(name).is_empty(),
+                Prerequisite::RrsetExists { name, record_type } => {
+                    !zone.rrset(name, *record_type).is_empty()
+                }
+                Prerequisite::RrsetDoesNotExist { name, record_type } => {
+                    zone.rrset(name, *record_type).is_empty()
+                }
+                Prerequisite::RrsetEquals(records) => {
+                    let expected: HashSet<&Data> =
+                        records.iter().map(|record| &record.data).collect();
+                    let actual: HashSet<&Data> = zone
+                        .rrset(&records[0].name, records[0].record_type)
+                        .into_iter()
+                        .map(|record| &record.data)
+                        .collect();
+                    expected == actual
+                }
+            };
+            if !holds {
+                return Err(match prerequisite {
+                    Prerequisite::NameInUse(_) => ResponseCode::NameError,
+                    Prerequisite::NameNotInUse(_) => ResponseCode::YxDomain,
+                    Prerequisite::RrsetDoesNotExist { .. } => ResponseCode::YxRrset,
+                    _ => ResponseCode::NxRrset,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the prerequisites and make the changes to a copy of the zone,
+    /// so that either all of them happen or none do. The serial is bumped
+    /// unless the update sets a newer one itself. Returns `None` if nothing
+    /// changed.
+    pub fn apply(&self, zone: &Zone) -> Result<Option<Zone>, ResponseCode> {
+        self.check(zone)?;
+        let mut updated = zone.clone();
+        for change in &self.changes {
+            apply_change(&mut updated, change)?;
+        }
+        if updated == *zone {
+            return Ok(None);
+        }
+        if updated.serial() == zone.serial() {
+            updated.increment_serial();
+        }
+        Ok(Some(updated))
+    }
+}
+
+/// Make one change to a zone, skipping changes that would leave it broken
+/// (RFC 2136 §3.4.2).
+fn apply_change(zone: &mut Zone, change: &Change) -> Result<(), ResponseCode> {
+    let name = change.name().to_string();
+    let at_apex = name::eq(&name, &zone.origin);
+    let existing: Vec<Record> = zone.lookup(&name).to_vec();
+    let remove = |zone: &mut Zone, keep: &dyn Fn(&Record) -> bool| {
+        for record in existing.iter().filter(|record| !keep(record)) {
+            zone.remove(record);
+        }
+    };
+    match change {
+        Change::Add(record) if record.record_type == RecordType::Soa => {
+            let newer = match (zone.serial(), journal::serial(record)) {
+                (Some(old), Some(new)) => crate::dnssec::time_before(old, new),
+                _ => false,
+            };
+            if at_apex && newer {
+                remove(zone, &|existing| existing.record_type != RecordType::Soa);
+                insert(zone, record)?;
+            }
+        }
+        Change::Add(record) => {
+            let is_cname = |record: &Record| record.record_type == RecordType::Cname;
+            let conflicts = if is_cname(record) {
+                existing.iter().any(|existing| {
+                    !is_cname(existing) && !record::is_dnssec_type(&existing.record_type)
+                })
+            } else {
+                existing.iter().any(is_cname)
+            };
+            let rrset: Vec<&Record> = existing
+                .iter()
+                .filter(|existing| existing.record_type == record.record_type)
+                .collect();
+            let present = rrset.iter().any(|existing| existing.data == record.data)
+                && rrset.iter().all(|existing| existing.ttl == record.ttl);
+            if conflicts || present {
+                return Ok(());
+            }
+            // A name has only one CNAME. Other records join the RRset, which
+            // all takes the new TTL, as an RRset can't have more than one
+            // (RFC 2181 §5.2)
+            remove(zone, &|existing| existing.record_type != record.record_type);
+            if !is_cname(record) {
+                for existing in rrset.iter().filter(|existing| existing.data != record.data) {
+                    let existing = Record {
+                        ttl: record.ttl,
+                        ..(*existing).clone()
+                    };
+                    insert(zone, &existing)?;
+                }
+            }
+            insert(zone, record)?;
+        }
+        Change::DeleteRrset { record_type, .. } => {
+            if at_apex && matches!(record_type, RecordType::Soa | RecordType::Ns) {
+                return Ok(());
+            }
+            remove(zone, &|existing| existing.record_type != *record_type);
+        }
+        Change::DeleteName(_) => remove(zone, &|existing| {
+            at_apex && matches!(existing.record_type, RecordType::Soa | RecordType::Ns)
+        }),
+        Change::DeleteRecord(record) => {
+            let last_ns = at_apex
+                && record.record_type == RecordType::Ns
+                && zone.rrset(&name, RecordType::Ns).len() == 1;
+            if record.record_type == RecordType::Soa || last_ns {
+                return Ok(());
+            }
+            remove(zone, &|existing| {
+                existing.record_type != record.record_type || existing.data != record.data
+            });
+        }
+    }
+    Ok(())
+}
+
+fn insert(zone: &mut Zone, record: &Record) -> Result<(), ResponseCode> {
+    zone.insert(record.clone())
+        .map_err(|_| ResponseCode::ServerFailure)
+}
+
+/// Types that can't be added to a zone, only asked about.
+fn is_meta_type(record_type: RecordType) -> bool {
+    matches!(
+        record_type,
+        RecordType::Any | RecordType::Axfr | RecordType::Ixfr | RecordType::Opt | RecordType::Tsig
+    )
+}
+
+/// Whether a record's data was left out, as it is for whole RRsets.
+fn is_empty(data: &Data) -> bool {
+    matches!(data, Data::Unknown(bytes) if bytes.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone;
+
+    const ZONE: &str = "@ 60 SOA ns hostmaster 1 2 3 4 5
+@ 60 NS ns
+ns 60 A 192.0.2.1
+www 60 A 192.0.2.2
+www 60 A 192.0.2.3
+alias 60 CNAME www";
+
+    fn zone() -> Zone {
+        zone::parse_zone(ZONE, Some("example.com")).unwrap()
+    }
+
+    /// Build an UPDATE message from master file lines for each section.
+    fn request(prerequisites: &str, changes: &str) -> DnsPacket {
+        let mut request = crate::packet::build_query(1, "example.com", &RecordType::Soa);
+        request.header.opcode = crate::header::Opcode::Update;
+        request.answers = records(prerequisites);
+        request.authoritative_entries = records(changes);
+        // Round trip through the wire format, which is where empty data
+        // comes from
+        let bytes = crate::serialize_dns_packet(&request).unwrap();
+        crate::parse_dns_packet(&bytes).unwrap()
+    }
+
+    /// Parse records, allowing a class of NONE or ANY with no data.
+    fn records(text: &str) -> Vec<Record> {
+        text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields[..] {
+                    [owner, ttl, class @ ("ANY" | "NONE"), record_type] => Record {
+                        name: name::child(owner, "example.com"),
+                        record_type: record::parse_record_type_name(record_type).unwrap(),
+                        class: record::parse_class_name(class).unwrap(),
+                        ttl: ttl.parse().unwrap(),
+                        data: Data::Unknown(vec![]),
+                    },
+                    _ => {
+                        let mut records =
+                            zone::parse_master_file(line, Some("example.com")).unwrap();
+                        records.remove(0)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn apply(prerequisites: &str, changes: &str) -> Result<Option<Zone>, ResponseCode> {
+        Update::parse(&request(prerequisites, changes))?.apply(&zone())
+    }
+
+    #[test]
+    fn test_parse_update_sections() {
+        let update = Update::parse(&request(
+            "www 0 ANY A\nnew 0 NONE ANY\nwww 0 IN A 192.0.2.2\nwww 0 IN A 192.0.2.3",
+            "new 300 IN A 192.0.2.9\nwww 0 ANY A\nalias 0 ANY ANY\nns 0 NONE A 192.0.2.1",
+        ))
+        .unwrap();
+        assert_eq!(update.zone, "example.com");
+        assert_eq!(update.prerequisites.len(), 3);
+        assert!(matches!(
+            &update.prerequisites[2],
+            Prerequisite::RrsetEquals(records) if records.len() == 2
+        ));
+        assert_eq!(
+            update.changes[1],
+            Change::DeleteRrset {
+                name: "www.example.com".to_string(),
+                record_type: RecordType::A
+            }
+        );
+        assert_eq!(update.changes[2].record_type(), RecordType::Any);
+        match &update.changes[3] {
+            Change::DeleteRecord(record) => assert_eq!(record.class, Class::In),
+            other => panic!("expected a record to delete, got {:?}", other),
+        }
+
+        let malformed = ["www 60 ANY A", "www 0 ANY A 192.0.2.1"];
+        for prerequisite in malformed {
+            assert_eq!(
+                Update::parse(&request(prerequisite, "")),
+                Err(ResponseCode::FormatError)
+            );
+        }
+        assert_eq!(
+            Update::parse(&request("", "www 60 ANY A")),
+            Err(ResponseCode::FormatError)
+        );
+        let outside = "www.example.net. 60 IN A 192.0.2.1";
+        assert_eq!(
+            Update::parse(&request("", outside)),
+            Err(ResponseCode::NotZone)
+        );
+    }
+
+    #[test]
+    fn test_prerequisites_are_checked() {
+        assert_eq!(apply("www 0 ANY ANY", ""), Ok(None));
+        assert_eq!(apply("nope 0 ANY ANY", ""), Err(ResponseCode::NameError));
+        assert_eq!(apply("www 0 NONE ANY", ""), Err(ResponseCode::YxDomain));
+        assert_eq!(apply("www 0 ANY AAAA", ""), Err(ResponseCode::NxRrset));
+        assert_eq!(apply("www 0 NONE A", ""), Err(ResponseCode::YxRrset));
+        let both = "www 0 IN A 192.0.2.3\nwww 0 IN A 192.0.2.2";
+        assert_eq!(apply(both, ""), Ok(None));
+        let one = "www 0 IN A 192.0.2.2";
+        assert_eq!(apply(one, ""), Err(ResponseCode::NxRrset));
+    }
+
+    #[test]
+    fn test_apply_changes_and_bump_serial() {
+        let updated = apply(
+            "new 0 NONE ANY",
+            "new 300 IN A 192.0.2.9\nwww 0 NONE A 192.0.2.2\nalias 0 ANY ANY",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(updated.serial(), Some(2));
+        assert_eq!(updated.rrset("new.example.com", RecordType::A).len(), 1);
+        assert_eq!(updated.rrset("www.example.com", RecordType::A).len(), 1);
+        assert!(updated.lookup("alias.example.com").is_empty());
+
+        // A failed prerequisite means none of the changes are made
+        assert_eq!(
+            apply("new 0 ANY ANY", "new 300 IN A 192.0.2.9"),
+            Err(ResponseCode::NameError)
+        );
+
+        // Adding a record that is already there only changes the TTL
+        let updated = apply("", "www 300 IN A 192.0.2.2").unwrap().unwrap();
+        let ttls: Vec<i32> = updated
+            .rrset("www.example.com", RecordType::A)
+            .iter()
+            .map(|record| record.ttl)
+            .collect();
+        assert_eq!(ttls, vec![300, 300]);
+        assert_eq!(apply("", "www 60 IN A 192.0.2.2"), Ok(None));
+    }
+
+    #[test]
+    fn test_apply_keeps_one_ttl_per_rrset() {
+        let updated = apply("", "www 300 IN A 192.0.2.4").unwrap().unwrap();
+        let rrset = updated.rrset("www.example.com", RecordType::A);
+        assert_eq!(rrset.len(), 3);
+        assert!(rrset.iter().all(|record| record.ttl == 300));
+        // Other types at the name keep their own TTL
+        let updated = apply(
+            "",
+            "www 300 IN AAAA 2001:db8::1
+ns 30 IN TXT hello",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(updated
+            .rrset("www.example.com", RecordType::A)
+            .iter()
+            .all(|record| record.ttl == 60));
+        assert_eq!(updated.rrset("ns.example.com", RecordType::Txt)[0].ttl, 30);
+    }
+
+    #[test]
+    fn test_apply_keeps_zone_consistent() {
+        // CNAMEs can't share a name with other records
+        assert_eq!(apply("", "alias 60 IN A 192.0.2.9"), Ok(None));
+        assert_eq!(apply("", "www 60 IN CNAME ns"), Ok(None));
+        let updated = apply("", "alias 60 IN CNAME ns").unwrap().unwrap();
+        let cnames = updated.rrset("alias.example.com", RecordType::Cname);
+        assert_eq!(cnames.len(), 1);
+        assert_eq!(cnames[0].data, Data::Cname("ns.example.com".to_string()));
+
+        // The SOA and the last NS at the apex can't be deleted
+        assert_eq!(apply("", "@ 0 ANY SOA\n@ 0 ANY NS"), Ok(None));
+        assert_eq!(apply("", "@ 0 NONE NS ns"), Ok(None));
+        assert_eq!(apply("", "@ 0 ANY ANY"), Ok(None));
+
+        // The SOA can only be replaced by one with a newer serial
+        assert_eq!(apply("", "@ 60 SOA ns hostmaster 0 2 3 4 5"), Ok(None));
+        let updated = apply("", "@ 60 SOA ns hostmaster 10 2 3 4 5")
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.serial(), Some(10));
+        assert_eq!(updated.rrset("example.com", RecordType::Soa).len(), 1);
+    }
+
+    #[test]
+    fn test_built_update_round_trips() {
+        let record = |line: &str| records(line).remove(0);
+        let update = Update::new("example.com")
+            .name_in_use("www.example.com")
+            .name_not_in_use("new.example.com")
+            .rrset_exists("www.example.com", RecordType::A)
+            .rrset_does_not_exist("www.example.com", RecordType::Aaaa)
+            .rrset_equals(record("www 60 IN A 192.0.2.2"))
+            .rrset_equals(record("www 60 IN A 192.0.2.3"))
+            .add_record(record("new 300 IN A 192.0.2.9"))
+            .delete_rrset("alias.example.com", RecordType::Cname)
+            .delete_name("old.example.com")
+            .delete_record(record("www 60 IN A 192.0.2.2"));
+        assert_eq!(update.prerequisites.len(), 5);
+
+        let bytes = crate::serialize_dns_packet(&update.to_packet(7)).unwrap();
+        let request = crate::parse_dns_packet(&bytes).unwrap();
+        assert_eq!(request.header.opcode, Opcode::Update);
+        assert_eq!(request.header.id, 7);
+        assert_eq!(Update::parse(&request), Ok(update.clone()));
+
+        let updated = update.apply(&zone()).unwrap().unwrap();
+        assert_eq!(updated.rrset("www.example.com", RecordType::A).len(), 1);
+        assert_eq!(updated.rrset("new.example.com", RecordType::A).len(), 1);
+        assert!(updated.lookup("alias.example.com").is_empty());
+    }
+}
@@ -0,0 +1,1153 @@
+//! Zone files in the master file format described in RFC 1035 §5.
+
+use super::buffer::ByteBuffer;
+use super::dnssec;
+use super::journal;
+use super::name;
+use super::record::{self, Class, Data, Record, RecordType};
+use super::tsig;
+use anyhow::{anyhow, Context};
+use std::collections::BTreeMap;
+use std::error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// --------------------------------------------------
+// Zone
+// --------------------------------------------------
+
+/// The records of a single zone, indexed by owner name. Names are kept in
+/// DNSSEC canonical order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    pub origin: String,
+    records: BTreeMap<Vec<u8>, Vec<Record>>,
+}
+
+impl Zone {
+    pub fn new(origin: &str) -> Zone {
+        Zone {
+            origin: name::normalize(origin),
+            records: BTreeMap::new(),
+        }
+    }
+
+    /// Add a record to the zone. Records that are already present are ignored.
+    /// The SOA record is kept ahead of the other records at the apex.
+    pub fn insert(&mut self, record: Record) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if !name::is_subdomain(&record.name, &self.origin) {
+            return Err(anyhow!("{} is outside of zone {}", record.name, self.origin).into());
+        }
+        let records = self
+            .records
+            .entry(name::canonical_key(&record.name))
+            .or_default();
+        if records.contains(&record) {
+            return Ok(());
+        }
+        if record.record_type == RecordType::Soa {
+            records.insert(0, record);
+        } else {
+            records.push(record);
+        }
+        Ok(())
+    }
+
+    /// The SOA record at the zone apex, if the zone has one.
+    pub fn soa(&self) -> Option<&Record> {
+        self.rrset(&self.origin, RecordType::Soa).into_iter().next()
+    }
+
+    /// Remove a record from the zone. Returns false if it wasn't there.
+    pub fn remove(&mut self, record: &Record) -> bool {
+        let key = name::canonical_key(&record.name);
+        let records = match self.records.get_mut(&key) {
+            Some(records) => records,
+            None => return false,
+        };
+        let len = records.len();
+        records.retain(|existing| existing != record);
+        let removed = records.len() != len;
+        if records.is_empty() {
+            self.records.remove(&key);
+        }
+        removed
+    }
+
+    /// The serial number in the zone's SOA record.
+    pub fn serial(&self) -> Option<u32> {
+        journal::serial(self.soa()?)
+    }
+
+    /// Add one to the serial number in the zone's SOA record, wrapping around
+    /// as serial numbers do (RFC 1982). Returns the new serial.
+    pub fn increment_serial(&mut self) -> Option<u32> {
+        let records = self.records.get_mut(&name::canonical_key(&self.origin))?;
+        records
+            .iter_mut()
+            .find_map(|record| match &mut record.data {
+                Data::Soa { serial, .. } => {
+                    *serial = serial.wrapping_add(1);
+                    Some(*serial)
+                }
+                _ => None,
+            })
+    }
+
+    /// All records owned by a name.
+    pub fn lookup(&self, name: &str) -> &[Record] {
+        self.records
+            .get(&name::canonical_key(name))
+            .map(|records| records.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// All records of a single type owned by a name.
+    pub fn rrset(&self, name: &str, record_type: RecordType) -> Vec<&Record> {
+        self.lookup(name)
+            .iter()
+            .filter(|record| record.record_type == record_type)
+            .collect()
+    }
+
+    /// Returns true if the name owns records, or is an empty non-terminal
+    /// (i.e. only has records underneath it).
+    pub fn contains_name(&self, name: &str) -> bool {
+        let key = name::canonical_key(name);
+        self.records
+            .range(key.clone()..)
+            .next()
+            .map(|(next, _)| next.starts_with(&key))
+            .unwrap_or(false)
+    }
+
+    /// All records in the zone, in canonical order of their owner names.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.values().flatten()
+    }
+
+    /// The records of every name in the zone, starting with the one just
+    /// before `name` in canonical order and working backwards, wrapping
+    /// around from the apex to the end of the zone. This is how the NSEC
+    /// record covering a name is found.
+    pub fn preceding(&self, name: &str) -> impl Iterator<Item = &[Record]> {
+        let key = name::canonical_key(name);
+        self.records
+            .range(..key.clone())
+            .rev()
+            .chain(self.records.range(key..).rev())
+            .map(|(_, records)| records.as_slice())
+    }
+}
+
+/// Parse a zone file. The zone's origin is taken from the owner of its SOA
+/// record. `origin` is used to resolve relative names until the file sets
+/// its own with `$ORIGIN`.
+pub fn parse_zone(
+    text: &str,
+    origin: Option<&str>,
+) -> Result<Zone, Box<dyn error::Error + Send + Sync>> {
+    let records = parse_master_file(text, origin)?;
+    let mut soa_records = records
+        .iter()
+        .filter(|record| record.record_type == RecordType::Soa);
+    let soa = soa_records.next().context("zone has no SOA record")?;
+    if soa_records.next().is_some() {
+        return Err(anyhow!("zone has more than one SOA record").into());
+    }
+    let mut zone = Zone::new(&soa.name);
+    for record in records {
+        zone.insert(record)?;
+    }
+    Ok(zone)
+}
+
+// --------------------------------------------------
+// Master file
+// --------------------------------------------------
+
+/// Parse every record in a master file, without requiring them to form a
+/// zone.
+pub fn parse_master_file(
+    text: &str,
+    origin: Option<&str>,
+) -> Result<Vec<Record>, Box<dyn error::Error + Send + Sync>> {
+    let mut origin = origin.map(name::normalize);
+    let mut default_ttl = None;
+    let mut last_owner: Option<String> = None;
+    let mut last_ttl = None;
+    let mut last_class = Class::In;
+    let mut records = vec![];
+    for entry in tokenize(text)? {
+        let line = entry.line;
+        let with_line =
+            |err: Box<dyn error::Error + Send + Sync>| -> Box<dyn error::Error + Send + Sync> {
+                anyhow!("line {}: {}", line, err).into()
+            };
+        let tokens: Vec<&str> = entry.tokens.iter().map(|token| token.as_str()).collect();
+        if !entry.inherits_owner {
+            match tokens[0] {
+                "$ORIGIN" => {
+                    let value = tokens.get(1).context("$ORIGIN requires a name")?;
+                    origin = Some(resolve_name(value, origin.as_deref()).map_err(with_line)?);
+                    continue;
+                }
+                "$TTL" => {
+                    let value = tokens.get(1).context("$TTL requires a value")?;
+                    default_ttl = Some(parse_ttl(value).map_err(with_line)?);
+                    continue;
+                }
+                directive if directive.starts_with('$') => {
+                    return Err(
+                        anyhow!("line {}: unsupported directive {}", line, directive).into(),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let mut pos = 0;
+        let owner = if entry.inherits_owner {
+            last_owner
+                .clone()
+                .with_context(|| format!("line {}: record has no owner name", line))?
+        } else {
+            pos += 1;
+            resolve_name(tokens[0], origin.as_deref()).map_err(with_line)?
+        };
+
+        // The TTL and class are both optional, and may appear in either order
+        let mut ttl = None;
+        let mut class = None;
+        for _ in 0..2 {
+            match tokens.get(pos) {
+                Some(value) if ttl.is_none() && value.starts_with(|c: char| c.is_ascii_digit()) => {
+                    ttl = Some(parse_ttl(value).map_err(with_line)?);
+                    pos += 1;
+                }
+                Some(value) if class.is_none() && record::parse_class_name(value).is_some() => {
+                    class = record::parse_class_name(value);
+                    pos += 1;
+                }
+                _ => break,
+            }
+        }
+        let typ = tokens
+            .get(pos)
+            .with_context(|| format!("line {}: missing record type", line))?;
+        let record_type = record::parse_record_type_name(typ)
+            .with_context(|| format!("line {}: unknown record type {}", line, typ))?;
+        let data =
+            parse_rdata(&record_type, &tokens[pos + 1..], origin.as_deref()).map_err(with_line)?;
+
+        let ttl = match ttl.or(default_ttl).or(last_ttl) {
+            Some(ttl) => ttl,
+            None => return Err(anyhow!("line {}: record has no TTL", line).into()),
+        };
+        let class = class.unwrap_or(last_class);
+        last_owner = Some(owner.clone());
+        last_ttl = Some(ttl);
+        last_class = class;
+        records.push(Record {
+            name: owner,
+            record_type,
+            class,
+            ttl,
+            data,
+        });
+    }
+    Ok(records)
+}
+
+fn parse_rdata(
+    record_type: &RecordType,
+    texts: &[&str],
+    origin: Option<&str>,
+) -> Result<Data, Box<dyn error::Error + Send + Sync>> {
+    if texts.first() == Some(&"\\#") {
+        return parse_generic_rdata(record_type, &texts[1..]);
+    }
+    let expect = |count: usize| -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if texts.len() == count {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "expected {} fields of record data but found {}",
+                count,
+                texts.len()
+            )
+            .into())
+        }
+    };
+    let at_least = |count: usize| -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if texts.len() >= count {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "expected at least {} fields of record data but found {}",
+                count,
+                texts.len()
+            )
+            .into())
+        }
+    };
+    let data = match record_type {
+        RecordType::A => {
+            expect(1)?;
+            Data::Addr(texts[0].parse::<Ipv4Addr>()?.octets())
+        }
+        RecordType::Aaaa => {
+            expect(1)?;
+            Data::Addr6(texts[0].parse::<Ipv6Addr>()?.octets())
+        }
+        RecordType::Ns => {
+            expect(1)?;
+            Data::Ns(resolve_name(texts[0], origin)?)
+        }
+        RecordType::Cname => {
+            expect(1)?;
+            Data::Cname(resolve_name(texts[0], origin)?)
+        }
+        RecordType::Ptr => {
+            expect(1)?;
+            Data::Ptr(resolve_name(texts[0], origin)?)
+        }
+        RecordType::Soa => {
+            expect(7)?;
+            Data::Soa {
+                mname: resolve_name(texts[0], origin)?,
+                rname: resolve_name(texts[1], origin)?,
+                serial: texts[2].parse()?,
+                refresh: parse_ttl(texts[3])? as u32,
+                retry: parse_ttl(texts[4])? as u32,
+                expire: parse_ttl(texts[5])? as u32,
+                minimum: parse_ttl(texts[6])? as u32,
+            }
+        }
+        RecordType::Mx => {
+            expect(2)?;
+            Data::Mx {
+                preference: texts[0].parse()?,
+                exchange: resolve_name(texts[1], origin)?,
+            }
+        }
+        RecordType::Txt => {
+            if texts.is_empty() {
+                return Err(anyhow!("TXT records need at least one string").into());
+            }
+            let strings = texts
+                .iter()
+                .map(|text| unescape(text))
+                .collect::<Result<Vec<_>, _>>()?;
+            Data::Txt(strings)
+        }
+        RecordType::Srv => {
+            expect(4)?;
+            Data::Srv {
+                priority: texts[0].parse()?,
+                weight: texts[1].parse()?,
+                port: texts[2].parse()?,
+                target: resolve_name(texts[3], origin)?,
+            }
+        }
+        RecordType::Dnskey | RecordType::Cdnskey => {
+            at_least(4)?;
+            Data::Dnskey {
+                flags: texts[0].parse()?,
+                protocol: texts[1].parse()?,
+                algorithm: texts[2].parse()?,
+                public_key: dnssec::decode_base64(&texts[3..].concat())?,
+            }
+        }
+        RecordType::Ds | RecordType::Cds => {
+            at_least(4)?;
+            Data::Ds {
+                key_tag: texts[0].parse()?,
+                algorithm: texts[1].parse()?,
+                digest_type: texts[2].parse()?,
+                digest: dnssec::decode_hex(&texts[3..].concat())?,
+            }
+        }
+        RecordType::Rrsig => {
+            at_least(9)?;
+            Data::Rrsig {
+                type_covered: parse_type(texts[0])?,
+                algorithm: texts[1].parse()?,
+                labels: texts[2].parse()?,
+                original_ttl: parse_ttl(texts[3])? as u32,
+                expiration: dnssec::parse_time(texts[4])?,
+                inception: dnssec::parse_time(texts[5])?,
+                key_tag: texts[6].parse()?,
+                signer_name: resolve_name(texts[7], origin)?,
+                signature: dnssec::decode_base64(&texts[8..].concat())?,
+            }
+        }
+        RecordType::Nsec => {
+            at_least(1)?;
+            Data::Nsec {
+                next_name: resolve_name(texts[0], origin)?,
+                types: parse_types(&texts[1..])?,
+            }
+        }
+        RecordType::Nsec3 => {
+            at_least(5)?;
+            Data::Nsec3 {
+                hash_algorithm: texts[0].parse()?,
+                flags: texts[1].parse()?,
+                iterations: texts[2].parse()?,
+                salt: parse_salt(texts[3])?,
+                next_hashed: dnssec::decode_base32hex(texts[4])?,
+                types: parse_types(&texts[5..])?,
+            }
+        }
+        RecordType::Nsec3param => {
+            expect(4)?;
+            Data::Nsec3param {
+                hash_algorithm: texts[0].parse()?,
+                flags: texts[1].parse()?,
+                iterations: texts[2].parse()?,
+                salt: parse_salt(texts[3])?,
+            }
+        }
+        _ => {
+            return Err(anyhow!(
+                "{} records must use the generic \\# format",
+                record::record_type_name(record_type)
+            )
+            .into())
+        }
+    };
+    Ok(data)
+}
+
+fn parse_type(value: &str) -> Result<RecordType, Box<dyn error::Error + Send + Sync>> {
+    record::parse_record_type_name(value)
+        .with_context(|| format!("unknown record type {}", value))
+        .map_err(|err| err.into())
+}
+
+fn parse_types(values: &[&str]) -> Result<Vec<RecordType>, Box<dyn error::Error + Send + Sync>> {
+    values.iter().map(|value| parse_type(value)).collect()
+}
+
+/// Parse an NSEC3 salt, which is written in hex, or as `-` when there isn't
+/// one.
+pub fn parse_salt(value: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    if value == "-" {
+        Ok(vec![])
+    } else {
+        dnssec::decode_hex(value)
+    }
+}
+
+/// Parse record data in the generic `\# <len> <hex>` format from RFC 3597.
+fn parse_generic_rdata(
+    record_type: &RecordType,
+    texts: &[&str],
+) -> Result<Data, Box<dyn error::Error + Send + Sync>> {
+    let len: usize = texts
+        .first()
+        .context("generic record data requires a length")?
+        .parse()?;
+    let hex: String = texts[1..].concat();
+    let bytes = decode_hex(&hex)?;
+    if bytes.len() != len {
+        return Err(anyhow!(
+            "expected {} bytes of record data but found {}",
+            len,
+            bytes.len()
+        )
+        .into());
+    }
+    let mut buf = ByteBuffer::from(&bytes);
+    record::parse_data(record_type, &mut buf, len)
+}
+
+/// Turn a name from a zone file into an absolute name without a trailing dot.
+fn resolve_name(
+    value: &str,
+    origin: Option<&str>,
+) -> Result<String, Box<dyn error::Error + Send + Sync>> {
+    if value == "@" {
+        return origin
+            .map(|origin| origin.to_string())
+            .context("@ used without an origin")
+            .map_err(|err| err.into());
+    }
+    if value == "." {
+        return Ok(".".to_string());
+    }
+    if let Some(absolute) = value.strip_suffix('.') {
+        return Ok(absolute.to_string());
+    }
+    let origin =
+        origin.with_context(|| format!("relative name {} used without an origin", value))?;
+    Ok(name::child(value, origin))
+}
+
+/// Parse a TTL, which is either a number of seconds or a sequence of
+/// durations with units, e.g. `1h30m`. Either way, TTLs above 2^31 - 1 are
+/// rejected (RFC 2181 §8).
+pub fn parse_ttl(value: &str) -> Result<i32, Box<dyn error::Error + Send + Sync>> {
+    let too_large = || anyhow!("TTL {} is too large", value);
+    if !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) {
+        return value
+            .bytes()
+            .try_fold(0i32, |seconds, c| {
+                seconds.checked_mul(10)?.checked_add((c - b'0') as i32)
+            })
+            .ok_or_else(|| too_large().into());
+    }
+    let mut total: u64 = 0;
+    let mut current: u64 = 0;
+    let mut has_digits = false;
+    for c in value.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            current = current
+                .checked_mul(10)
+                .and_then(|current| current.checked_add(digit as u64))
+                .ok_or_else(too_large)?;
+            has_digits = true;
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            'w' => 60 * 60 * 24 * 7,
+            _ => return Err(anyhow!("invalid TTL {}", value).into()),
+        };
+        if !has_digits {
+            return Err(anyhow!("invalid TTL {}", value).into());
+        }
+        total = current
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(too_large)?;
+        current = 0;
+        has_digits = false;
+        if total > i32::MAX as u64 {
+            return Err(too_large().into());
+        }
+    }
+    if has_digits {
+        return Err(anyhow!("invalid TTL {}", value).into());
+    }
+    Ok(total as i32)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(anyhow!("hex string has an odd number of digits").into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .with_context(|| format!("invalid hex string {}", hex))
+                .map_err(|err| err.into())
+        })
+        .collect()
+}
+
+/// Decode `\X` and `\DDD` escapes in a character string.
+fn unescape(value: &str) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let bytes = value.as_bytes();
+    let mut result = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            result.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let digits = bytes.get(i + 1..i + 4);
+        match digits {
+            Some(digits) if digits.iter().all(|b| b.is_ascii_digit()) => {
+                let value: u16 = std::str::from_utf8(digits)?.parse()?;
+                let value: u8 = value.try_into().context("escaped byte out of range")?;
+                result.push(value);
+                i += 4;
+            }
+            _ => {
+                let escaped = bytes.get(i + 1).context("string ends with a backslash")?;
+                result.push(*escaped);
+                i += 2;
+            }
+        }
+    }
+    Ok(result)
+}
+
+// --------------------------------------------------
+// Tokenizer
+// --------------------------------------------------
+
+/// A single logical entry in a master file, which may span several physical
+/// lines when wrapped in parentheses.
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    inherits_owner: bool,
+    tokens: Vec<String>,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Entry>, Box<dyn error::Error + Send + Sync>> {
+    let mut entries = vec![];
+    let mut tokens: Vec<String> = vec![];
+    let mut current: Option<String> = None;
+    let mut depth = 0;
+    let mut line = 1;
+    let mut entry_line = 1;
+    let mut inherits_owner = false;
+    let mut at_line_start = true;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if at_line_start && depth == 0 && tokens.is_empty() {
+            entry_line = line;
+            inherits_owner = c == ' ' || c == '\t';
+        }
+        at_line_start = false;
+        match c {
+            '"' => {
+                let mut quoted = current.take().unwrap_or_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            quoted.push('\\');
+                            if let Some(escaped) = chars.next() {
+                                quoted.push(escaped);
+                            }
+                        }
+                        Some('\n') | None => {
+                            return Err(anyhow!("line {}: unterminated string", line).into())
+                        }
+                        Some(c) => quoted.push(c),
+                    }
+                }
+                tokens.push(quoted);
+            }
+            ';' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '(' | ')' | ' ' | '\t' | '\r' | '\n' => {
+                if let Some(text) = current.take() {
+                    tokens.push(text);
+                }
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => {
+                        return Err(anyhow!("line {}: unbalanced parentheses", line).into())
+                    }
+                    ')' => depth -= 1,
+                    '\n' => {
+                        line += 1;
+                        at_line_start = true;
+                        if depth == 0 && !tokens.is_empty() {
+                            entries.push(Entry {
+                                line: entry_line,
+                                inherits_owner,
+                                tokens: std::mem::take(&mut tokens),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            '\\' => {
+                let text = current.get_or_insert_with(String::new);
+                text.push('\\');
+                if let Some(escaped) = chars.next() {
+                    text.push(escaped);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if depth != 0 {
+        return Err(anyhow!("line {}: unbalanced parentheses", line).into());
+    }
+    if let Some(text) = current.take() {
+        tokens.push(text);
+    }
+    if !tokens.is_empty() {
+        entries.push(Entry {
+            line: entry_line,
+            inherits_owner,
+            tokens,
+        });
+    }
+    Ok(entries)
+}
+
+// --------------------------------------------------
+// Presentation format
+// --------------------------------------------------
+
+/// Format a whole zone as a master file, one record per line.
+pub fn format_zone(zone: &Zone) -> String {
+    let mut text = format!("$ORIGIN {}\n", format_name(&zone.origin));
+    for record in zone.records() {
+        text.push_str(&format_record(record));
+        text.push('\n');
+    }
+    text
+}
+
+/// Format a record as a single master file line, with absolute names.
+pub fn format_record(record: &Record) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        format_name(&record.name),
+        record.ttl,
+        record::class_name(&record.class),
+        record::record_type_name(&record.record_type),
+        format_data(&record.data)
+    )
+}
+
+/// Format record data as it would appear in a master file. Types without a
+/// text format of their own use the generic `\# <len> <hex>` format.
+pub fn format_data(data: &Data) -> String {
+    match data {
+        Data::Addr(addr) => Ipv4Addr::from(*addr).to_string(),
+        Data::Addr6(addr) => Ipv6Addr::from(*addr).to_string(),
+        Data::Ns(name) | Data::Cname(name) | Data::Ptr(name) => format_name(name),
+        Data::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => format!(
+            "{} {} {} {} {} {} {}",
+            format_name(mname),
+            format_name(rname),
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum
+        ),
+        Data::Mx {
+            preference,
+            exchange,
+        } => format!("{} {}", preference, format_name(exchange)),
+        Data::Txt(strings) => strings
+            .iter()
+            .map(|string| escape(string))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Data::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => format!("{} {} {} {}", priority, weight, port, format_name(target)),
+        Data::Dnskey {
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+        } => format!(
+            "{} {} {} {}",
+            flags,
+            protocol,
+            algorithm,
+            dnssec::encode_base64(public_key)
+        ),
+        Data::Ds {
+            key_tag,
+            algorithm,
+            digest_type,
+            digest,
+        } => format!(
+            "{} {} {} {}",
+            key_tag,
+            algorithm,
+            digest_type,
+            dnssec::encode_hex(digest)
+        ),
+        Data::Rrsig {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer_name,
+            signature,
+        } => format!(
+            "{} {} {} {} {} {} {} {} {}",
+            record::record_type_name(type_covered),
+            algorithm,
+            labels,
+            original_ttl,
+            dnssec::format_time(*expiration),
+            dnssec::format_time(*inception),
+            key_tag,
+            format_name(signer_name),
+            dnssec::encode_base64(signature)
+        ),
+        Data::Nsec { next_name, types } => {
+            let mut fields = vec![format_name(next_name)];
+            fields.extend(types.iter().map(record::record_type_name));
+            fields.join(" ")
+        }
+        Data::Nsec3 {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+            next_hashed,
+            types,
+        } => {
+            let mut fields = vec![
+                hash_algorithm.to_string(),
+                flags.to_string(),
+                iterations.to_string(),
+                format_salt(salt),
+                dnssec::encode_base32hex(next_hashed).to_ascii_uppercase(),
+            ];
+            fields.extend(types.iter().map(record::record_type_name));
+            fields.join(" ")
+        }
+        Data::Nsec3param {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+        } => format!(
+            "{} {} {} {}",
+            hash_algorithm,
+            flags,
+            iterations,
+            format_salt(salt)
+        ),
+        Data::Tsig {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        } => format!(
+            "{} {} {} {} {} {} {} {} {}",
+            format_name(algorithm),
+            time_signed,
+            fudge,
+            mac.len(),
+            dnssec::encode_base64(mac),
+            original_id,
+            tsig::error_name(*error),
+            other.len(),
+            dnssec::encode_base64(other)
+        )
+        .trim_end()
+        .to_string(),
+        Data::Opt(_) | Data::Unknown(_) => {
+            let bytes = record::serialize_data(data).unwrap_or_default();
+            format_generic_rdata(&bytes)
+        }
+    }
+}
+
+/// Format a name as an absolute name, i.e. with a trailing dot.
+pub fn format_name(value: &str) -> String {
+    if value == "." || value.ends_with('.') {
+        value.to_string()
+    } else {
+        format!("{}.", value)
+    }
+}
+
+/// NSEC3 salts are written in hex, or as `-` when there isn't one.
+fn format_salt(salt: &[u8]) -> String {
+    if salt.is_empty() {
+        "-".to_string()
+    } else {
+        dnssec::encode_hex(salt)
+    }
+}
+
+fn format_generic_rdata(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "\\# 0".to_string();
+    }
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\\# {} {}", bytes.len(), hex)
+}
+
+/// Quote a character string, escaping anything that isn't printable ASCII.
+fn escape(value: &[u8]) -> String {
+    let mut result = "\"".to_string();
+    for byte in value {
+        match byte {
+            b'"' | b'\\' => {
+                result.push('\\');
+                result.push(*byte as char);
+            }
+            0x20..=0x7e => result.push(*byte as char),
+            _ => result.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@       IN  SOA ns1 hostmaster (
+                2023010101 ; serial
+                2h         ; refresh
+                15m        ; retry
+                2w         ; expire
+                5m )       ; minimum
+        IN  NS  ns1
+        IN  NS  ns.other.net.
+        IN  MX  10 mail
+ns1         A   192.0.2.1
+mail    300 A   192.0.2.2
+            AAAA 2001:db8::2
+www     IN  CNAME @
+txt         TXT "hello world" "with \"quotes\"" unquoted
+_sip._udp   SRV 0 5 5060 sip
+*.wild      A   192.0.2.3
+raw         TYPE999 \# 3 abcdef
+"#;
+
+    #[test]
+    fn test_parse_zone_uses_soa_owner_as_origin() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let zone = parse_zone(EXAMPLE_ZONE, None)?;
+        assert_eq!(zone.origin, "example.com");
+        assert_eq!(
+            zone.soa().map(|soa| &soa.data),
+            Some(&Data::Soa {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2023010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_zone_inherits_owner_ttl_and_class(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let zone = parse_zone(EXAMPLE_ZONE, None)?;
+        let ns = zone.rrset("example.com", RecordType::Ns);
+        assert_eq!(ns.len(), 2);
+        assert_eq!(ns[0].ttl, 3600);
+        assert_eq!(ns[1].data, Data::Ns("ns.other.net".to_string()));
+
+        // Records without a TTL use $TTL rather than the previous record's
+        assert_eq!(zone.rrset("mail.example.com", RecordType::A)[0].ttl, 300);
+        let aaaa = zone.rrset("mail.example.com", RecordType::Aaaa);
+        assert_eq!(aaaa.len(), 1);
+        assert_eq!(aaaa[0].ttl, 3600);
+        assert_eq!(aaaa[0].class, Class::In);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_zone_parses_record_data() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let zone = parse_zone(EXAMPLE_ZONE, None)?;
+        assert_eq!(
+            zone.rrset("WWW.example.com.", RecordType::Cname)[0].data,
+            Data::Cname("example.com".to_string())
+        );
+        assert_eq!(
+            zone.rrset("txt.example.com", RecordType::Txt)[0].data,
+            Data::Txt(vec![
+                b"hello world".to_vec(),
+                b"with \"quotes\"".to_vec(),
+                b"unquoted".to_vec()
+            ])
+        );
+        assert_eq!(
+            zone.rrset("_sip._udp.example.com", RecordType::Srv)[0].data,
+            Data::Srv {
+                priority: 0,
+                weight: 5,
+                port: 5060,
+                target: "sip.example.com".to_string()
+            }
+        );
+        assert_eq!(
+            zone.rrset("raw.example.com", RecordType::Unknown(999))[0].data,
+            Data::Unknown(vec![0xab, 0xcd, 0xef])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_contains_name_finds_empty_non_terminals(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let zone = parse_zone(EXAMPLE_ZONE, None)?;
+        assert!(zone.contains_name("wild.example.com"));
+        assert!(zone.contains_name("_udp.example.com"));
+        assert!(!zone.contains_name("missing.example.com"));
+        assert!(zone.lookup("wild.example.com").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_preceding_walks_backwards_and_wraps() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\nb 60 A 192.0.2.1\nd 60 A 192.0.2.2";
+        let zone = parse_zone(text, Some("example"))?;
+        let owners = |name| -> Vec<String> {
+            zone.preceding(name)
+                .map(|records| records[0].name.clone())
+                .collect()
+        };
+        assert_eq!(owners("c.example"), ["b.example", "example", "d.example"]);
+        assert_eq!(owners("a.example"), ["example", "d.example", "b.example"]);
+        assert_eq!(owners("d.example")[0], "b.example");
+        Ok(())
+    }
+
+    #[test]
+    fn test_increment_serial_wraps() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let text = "@ 60 SOA ns hostmaster 4294967295 2 3 4 5";
+        let mut zone = parse_zone(text, Some("example"))?;
+        assert_eq!(zone.increment_serial(), Some(0));
+        assert_eq!(zone.increment_serial(), Some(1));
+        assert_eq!(Zone::new("example").increment_serial(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_zone_uses_provided_origin() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\nwww 60 A 127.0.0.1\n";
+        let zone = parse_zone(text, Some("example.org."))?;
+        assert_eq!(zone.origin, "example.org");
+        assert_eq!(zone.rrset("www.example.org", RecordType::A).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_zone_rejects_invalid_zones() {
+        // No SOA record
+        assert!(parse_zone("example.com. 60 A 127.0.0.1", None).is_err());
+        // Records outside of the zone
+        let text = "example.com. 60 SOA ns hostmaster 1 2 3 4 5\nexample.net. 60 A 127.0.0.1";
+        assert!(parse_zone(text, None).is_err());
+        // Relative names without an origin
+        assert!(parse_zone("www 60 A 127.0.0.1", None).is_err());
+        // Missing TTL
+        assert!(parse_master_file("www.example.com. A 127.0.0.1", None).is_err());
+        // Unbalanced parentheses
+        assert!(parse_master_file("www.example.com. 60 SOA ( ns", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_ttl_accepts_units() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        assert_eq!(parse_ttl("300")?, 300);
+        assert_eq!(parse_ttl("1h30m")?, 5400);
+        assert_eq!(parse_ttl("1W")?, 604800);
+        assert_eq!(parse_ttl("2147483647")?, i32::MAX);
+        assert_eq!(parse_ttl("2147483647s")?, i32::MAX);
+        assert!(parse_ttl("1x").is_err());
+        assert!(parse_ttl("h").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ttl_rejects_overflow() {
+        for value in [
+            "999999999999999999999s",
+            "99999999999999999w",
+            "3000000000s",
+            "3000000000",
+            "2147483648",
+            "999999999999999999999",
+        ] {
+            let err = parse_ttl(value).unwrap_err();
+            assert_eq!(err.to_string(), format!("TTL {} is too large", value));
+        }
+    }
+
+    #[test]
+    fn test_format_record_round_trips_through_parser(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let zone = parse_zone(EXAMPLE_ZONE, None)?;
+        let text: String = zone
+            .records()
+            .map(|record| format_record(record) + "\n")
+            .collect();
+        assert_eq!(parse_zone(&text, None)?, zone);
+        assert_eq!(parse_zone(&format_zone(&zone), None)?, zone);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_data_escapes_strings_and_uses_generic_format() {
+        let txt = Data::Txt(vec![b"say \"hi\"\n".to_vec()]);
+        assert_eq!(format_data(&txt), r#""say \"hi\"\010""#);
+        let unknown = Data::Unknown(vec![0xab, 0x01]);
+        assert_eq!(format_data(&unknown), r"\# 2 ab01");
+    }
+
+    #[test]
+    fn test_dnssec_records_round_trip_through_presentation_format(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let text = r#"
+$ORIGIN example.com.
+@   3600 DNSKEY 257 3 13 (
+            mdsswUyr3DPW132mOi8V9xESWE8jTo0dxCjjnopKl+GqJxpVXckHAeF+
+            KkxLbxILfDLUT0rAK9iUzy1L53eKGQ== )
+@   3600 DS 2371 13 2 1F987CC6583E92DF0890718C42
+@   3600 RRSIG DNSKEY 13 2 3600 20240301000000 20240201000000 2371 example.com. (
+            Jj3f6jdGy6f2eMNpUzF3lUOcgvSw2TvkgPvoZ/Gr3QAyCkTE5Qm6dHZ3wDzr5dkhyGq3BO8IrRvm4r6Ly85k7g== )
+@   3600 NSEC www A NS SOA RRSIG NSEC DNSKEY TYPE1234
+@   3600 NSEC3PARAM 1 0 0 -
+2vptu5timamqttgl4luu9kg21e0aor3s 3600 NSEC3 1 1 0 AABBCCDD (
+            2VPTU5TIMAMQTTGL4LUU9KG21E0AOR3S A RRSIG )
+"#;
+        let records = parse_master_file(text, None)?;
+        assert_eq!(records.len(), 6);
+        match &records[2].data {
+            Data::Rrsig {
+                type_covered,
+                expiration,
+                signer_name,
+                ..
+            } => {
+                assert_eq!(*type_covered, RecordType::Dnskey);
+                assert_eq!(*expiration, 1709251200);
+                assert_eq!(signer_name, "example.com");
+            }
+            data => panic!("expected RRSIG data, got {:?}", data),
+        }
+        let formatted: Vec<String> = records.iter().map(format_record).collect();
+        assert_eq!(
+            formatted[3],
+            "example.com.\t3600\tIN\tNSEC\twww.example.com. A NS SOA RRSIG NSEC DNSKEY TYPE1234"
+        );
+        assert_eq!(
+            formatted[5],
+            "2vptu5timamqttgl4luu9kg21e0aor3s.example.com.\t3600\tIN\tNSEC3\t1 1 0 AABBCCDD 2VPTU5TIMAMQTTGL4LUU9KG21E0AOR3S A RRSIG"
+        );
+        assert_eq!(parse_master_file(&formatted.join("\n"), None)?, records);
+        Ok(())
+    }
+}
@@ -0,0 +1,941 @@
+use super::dump::{self, unix_time, DumpedEntry};
+use dns::dnssec;
+use dns::header::ResponseCode;
+use dns::name;
+use dns::record::{self, Class, Data, Record, RecordType};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use std::{error, fs};
+use tokio::time::Instant;
+
+/// How many CNAMEs a cache lookup will follow.
+const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// How many RRsets to keep. Once full, the least recently used RRset is
+    /// dropped to make room.
+    pub max_entries: usize,
+    /// RRsets are kept for at least this many seconds, whatever their TTL.
+    pub min_ttl: u32,
+    /// RRsets are kept for at most this many seconds, whatever their TTL.
+    pub max_ttl: u32,
+    /// Negative answers are kept for at most this many seconds, whatever
+    /// their SOA says (RFC 2308 §5).
+    pub max_negative_ttl: u32,
+    /// How many seconds past its expiry an entry may still be served when
+    /// fresh data can't be had (RFC 8767). Zero disables serving stale data.
+    pub stale_window: u32,
+    /// The TTL given to records served stale, so clients come back soon.
+    pub stale_ttl: u32,
+    /// How many times an entry has to be used before it's refreshed ahead of
+    /// expiry, once it's in the last tenth of its TTL. Zero disables
+    /// prefetching.
+    pub prefetch_min_hits: u32,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            max_entries: 10_000,
+            min_ttl: 0,
+            max_ttl: 60 * 60 * 24,
+            max_negative_ttl: 60 * 60 * 3,
+            stale_window: 60 * 60 * 24,
+            stale_ttl: 30,
+            prefetch_min_hits: 3,
+        }
+    }
+}
+
+/// Identifies a cached RRset. Name errors apply to every type of a name, so
+/// they are cached with a record type of ANY.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub name: String,
+    pub record_type: RecordType,
+    pub class: Class,
+}
+
+impl CacheKey {
+    pub fn new(name: &str, record_type: RecordType, class: Class) -> CacheKey {
+        CacheKey {
+            name: name::normalize(name),
+            record_type,
+            class,
+        }
+    }
+
+    /// Returns true if the key is for `name` or a name underneath it, and
+    /// has the given type. Either may be left out to match everything.
+    pub fn matches(&self, name: Option<&str>, record_type: Option<RecordType>) -> bool {
+        name.is_none_or(|name| name::is_subdomain(&self.name, name))
+            && record_type.is_none_or(|typ| self.record_type == typ)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Records,
+    /// The name exists but has no records of this type. The entry holds the
+    /// SOA record that came with the answer, and any NSEC records proving it.
+    NoData,
+    /// The name doesn't exist. The entry holds the SOA record that came with
+    /// the answer, and any NSEC records proving it.
+    NameError,
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: EntryKind,
+    records: Vec<Record>,
+    /// The TTL the entry was inserted with.
+    ttl: u32,
+    expires: Instant,
+    last_used: u64,
+    /// How many times the entry has been used since it was inserted.
+    hits: u32,
+    /// Set once the entry has been handed out for prefetching, so it's only
+    /// refreshed once.
+    prefetching: bool,
+}
+
+/// An entry as seen by a lookup.
+struct Found {
+    kind: EntryKind,
+    records: Vec<Record>,
+    stale: bool,
+    prefetch: bool,
+}
+
+/// What the cache knows about a name: any CNAMEs leading away from it, then
+/// the records at the end of the chain if those are cached too. Cached
+/// negative answers come with the SOA record in `authority`, and wildcard
+/// answers with the records proving them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedAnswer {
+    pub rcode: ResponseCode,
+    pub records: Vec<Record>,
+    pub authority: Vec<Record>,
+    /// Where the chain of cached CNAMEs ends, if the records for that name
+    /// aren't cached. The caller has to resolve the rest from here.
+    pub next: Option<String>,
+    /// Some of the records have expired, and are only fit to answer with
+    /// when fresh ones can't be had.
+    pub stale: bool,
+    /// Some of the records are popular and about to expire. The caller
+    /// should refresh them in the background.
+    pub prefetch: bool,
+}
+
+/// RRsets that have been seen recently, kept until their TTL runs out.
+#[derive(Debug)]
+pub struct Cache {
+    config: CacheConfig,
+    entries: HashMap<CacheKey, Entry>,
+    /// Keys by when they were last used, oldest first.
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Cache {
+        Cache {
+            config,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add records to the cache, grouped into RRsets. RRSIG records are kept
+    /// with the RRset they cover, and dropped if it isn't there. Each RRset
+    /// expires after the smallest TTL among its records, clamped to the
+    /// configured bounds.
+    pub fn insert(&mut self, records: &[Record]) {
+        self.insert_with_proofs(records, &[]);
+    }
+
+    /// Like `insert`, but RRsets synthesised from a wildcard also keep the
+    /// records that prove there was no closer match, so the answer can be
+    /// validated again when it comes from the cache.
+    fn insert_with_proofs(&mut self, records: &[Record], proofs: &[Record]) {
+        let mut rrsets: Vec<(CacheKey, Vec<Record>)> = vec![];
+        for record in records {
+            if record.record_type == RecordType::Opt {
+                continue;
+            }
+            let record_type = match &record.data {
+                Data::Rrsig { type_covered, .. } => *type_covered,
+                _ => record.record_type,
+            };
+            let key = CacheKey::new(&record.name, record_type, record.class);
+            match rrsets.iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, rrset)) => rrset.push(record.clone()),
+                None => rrsets.push((key, vec![record.clone()])),
+            }
+        }
+        rrsets.retain(|(_, rrset)| {
+            rrset
+                .iter()
+                .any(|record| record.record_type != RecordType::Rrsig)
+        });
+        for (key, mut rrset) in rrsets {
+            if !proofs.is_empty() && is_wildcard_expansion(&rrset) {
+                rrset.extend(proofs.iter().cloned());
+            }
+            let ttl = rrset.iter().map(|record| record.ttl.max(0)).min();
+            let ttl = self.clamp_ttl(ttl.unwrap_or(0) as u32);
+            if ttl > 0 {
+                self.remove(&CacheKey::new(&key.name, RecordType::Any, key.class));
+                self.insert_entry(key, EntryKind::Records, rrset, ttl);
+            }
+        }
+    }
+
+    /// Cache the outcome of a query: the records in its answer, and a
+    /// negative answer for the end of any CNAME chain if it has no records
+    /// of the type that was asked for. Negative answers are only cached if
+    /// they come with an SOA record, which says how long they last. Any
+    /// DNSSEC records that prove them are kept alongside the SOA.
+    pub fn insert_answer(
+        &mut self,
+        qname: &str,
+        qtype: RecordType,
+        rcode: ResponseCode,
+        answers: &[Record],
+        authority: &[Record],
+    ) {
+        let proofs: Vec<Record> = authority
+            .iter()
+            .filter(|record| is_proof(record))
+            .cloned()
+            .collect();
+        self.insert_with_proofs(answers, &proofs);
+        let mut current = name::normalize(qname);
+        for _ in 0..MAX_CNAME_CHAIN {
+            let owned = answers
+                .iter()
+                .filter(|record| name::eq(&record.name, &current));
+            let mut target = None;
+            for record in owned {
+                if record.record_type == qtype || qtype == RecordType::Any {
+                    return;
+                }
+                if let Data::Cname(cname) = &record.data {
+                    target = Some(name::normalize(cname));
+                }
+            }
+            match target {
+                Some(target) if qtype != RecordType::Cname => current = target,
+                _ => break,
+            }
+        }
+        let soa = authority
+            .iter()
+            .find(|record| record.record_type == RecordType::Soa);
+        let (soa, minimum) = match soa {
+            Some(
+                soa @ Record {
+                    data: Data::Soa { minimum, .. },
+                    ..
+                },
+            ) => (soa, *minimum),
+            _ => return,
+        };
+        let (key, kind) = match rcode {
+            ResponseCode::NameError => (
+                CacheKey::new(&current, RecordType::Any, Class::In),
+                EntryKind::NameError,
+            ),
+            ResponseCode::Success => (CacheKey::new(&current, qtype, Class::In), EntryKind::NoData),
+            _ => return,
+        };
+        // RFC 2308 §5: the smaller of the SOA's TTL and its minimum field
+        let ttl = (soa.ttl.max(0) as u32)
+            .min(minimum)
+            .min(self.config.max_negative_ttl);
+        if ttl > 0 {
+            let records = authority
+                .iter()
+                .filter(|record| {
+                    record.record_type == RecordType::Soa
+                        || record::is_dnssec_type(&record.record_type)
+                })
+                .cloned()
+                .collect();
+            self.insert_entry(key, kind, records, ttl);
+        }
+    }
+
+    /// Get a cached RRset, with TTLs counting down the time left until it
+    /// expires.
+    pub fn get(
+        &mut self,
+        name: &str,
+        record_type: RecordType,
+        class: Class,
+    ) -> Option<Vec<Record>> {
+        match self.get_entry(&CacheKey::new(name, record_type, class), false)? {
+            Found {
+                kind: EntryKind::Records,
+                records,
+                ..
+            } => Some(records),
+            _ => None,
+        }
+    }
+
+    /// Get an entry that hasn't expired yet or, if `allow_stale` is set,
+    /// one that expired within the stale window. Entries past the stale
+    /// window are dropped.
+    fn get_entry(&mut self, key: &CacheKey, allow_stale: bool) -> Option<Found> {
+        let now = Instant::now();
+        let expires = self.entries.get(key)?.expires;
+        let stale = expires <= now;
+        if stale {
+            let window = Duration::from_secs(self.config.stale_window as u64);
+            if expires + window <= now {
+                self.remove(key);
+                return None;
+            }
+            if !allow_stale {
+                return None;
+            }
+        }
+        self.touch(key);
+        let min_hits = self.config.prefetch_min_hits;
+        let entry = self.entries.get_mut(key)?;
+        let remaining = if stale {
+            self.config.stale_ttl
+        } else {
+            (expires - now).as_secs() as u32
+        };
+        let mut prefetch = false;
+        if !stale {
+            entry.hits = entry.hits.saturating_add(1);
+            // Refresh popular entries in the last tenth of their TTL
+            if min_hits > 0 && entry.hits >= min_hits && !entry.prefetching {
+                prefetch = remaining <= entry.ttl / 10;
+                entry.prefetching = prefetch;
+            }
+        }
+        let records = entry
+            .records
+            .iter()
+            .map(|record| Record {
+                ttl: remaining as i32,
+                ..record.clone()
+            })
+            .collect();
+        Some(Found {
+            kind: entry.kind,
+            records,
+            stale,
+            prefetch,
+        })
+    }
+
+    /// Look up the records of a name, following any cached CNAMEs. Returns
+    /// `None` if nothing is cached for the name itself.
+    pub fn lookup(&mut self, name: &str, record_type: RecordType) -> Option<CachedAnswer> {
+        self.lookup_with(name, record_type, false)
+    }
+
+    /// Like `lookup`, but also returns entries that expired within the
+    /// stale window, with their TTLs set to the stale TTL. Only use this
+    /// when fresh records can't be had (RFC 8767 §4).
+    pub fn lookup_stale(&mut self, name: &str, record_type: RecordType) -> Option<CachedAnswer> {
+        self.lookup_with(name, record_type, true)
+    }
+
+    fn lookup_with(
+        &mut self,
+        name: &str,
+        record_type: RecordType,
+        allow_stale: bool,
+    ) -> Option<CachedAnswer> {
+        let mut records = vec![];
+        let mut proofs = vec![];
+        let mut current = name::normalize(name);
+        let mut stale = false;
+        let mut prefetch = false;
+        for _ in 0..MAX_CNAME_CHAIN {
+            let class = Class::In;
+            let found = self
+                .get_entry(&CacheKey::new(&current, record_type, class), allow_stale)
+                .or_else(|| {
+                    self.get_entry(
+                        &CacheKey::new(&current, RecordType::Any, class),
+                        allow_stale,
+                    )
+                });
+            if let Some(found) = found {
+                let (rcode, authority) = match found.kind {
+                    EntryKind::Records => {
+                        let (rrset, rest) = split_proofs(found.records, record_type);
+                        records.extend(rrset);
+                        proofs.extend(rest);
+                        (ResponseCode::Success, proofs)
+                    }
+                    EntryKind::NoData => (ResponseCode::Success, found.records),
+                    EntryKind::NameError => (ResponseCode::NameError, found.records),
+                };
+                return Some(CachedAnswer {
+                    rcode,
+                    records,
+                    authority,
+                    next: None,
+                    stale: stale || found.stale,
+                    prefetch: prefetch || found.prefetch,
+                });
+            }
+            let cname = self.get_entry(
+                &CacheKey::new(&current, RecordType::Cname, class),
+                allow_stale,
+            );
+            let cname = match cname {
+                Some(cname) if record_type != RecordType::Cname => cname,
+                _ => break,
+            };
+            let target = cname.records.iter().find_map(|record| match &record.data {
+                Data::Cname(target) => Some(name::normalize(target)),
+                _ => None,
+            });
+            let target = match target {
+                Some(target) => target,
+                None => break,
+            };
+            stale |= cname.stale;
+            prefetch |= cname.prefetch;
+            let (rrset, rest) = split_proofs(cname.records, RecordType::Cname);
+            records.extend(rrset);
+            proofs.extend(rest);
+            current = target;
+        }
+        if records.is_empty() {
+            return None;
+        }
+        Some(CachedAnswer {
+            rcode: ResponseCode::Success,
+            records,
+            authority: proofs,
+            next: Some(current),
+            stale,
+            prefetch,
+        })
+    }
+
+    pub fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+    }
+
+    /// Remove the entries whose keys match a name and type, as in
+    /// `CacheKey::matches`. Returns how many were removed.
+    pub fn flush(&mut self, name: Option<&str>, record_type: Option<RecordType>) -> usize {
+        let keys: Vec<CacheKey> = self
+            .entries
+            .keys()
+            .filter(|key| key.matches(name, record_type))
+            .cloned()
+            .collect();
+        for key in &keys {
+            self.remove(key);
+        }
+        keys.len()
+    }
+
+    /// Every entry, including stale ones, least recently used first. Expiry
+    /// times are converted to wall clock time using `now`.
+    pub fn dump(&self, now: SystemTime) -> Vec<DumpedEntry> {
+        let instant = Instant::now();
+        let now = unix_time(now) as i64;
+        self.lru
+            .values()
+            .map(|key| {
+                let entry = &self.entries[key];
+                let remaining = if entry.expires >= instant {
+                    (entry.expires - instant).as_secs() as i64
+                } else {
+                    -((instant - entry.expires).as_secs() as i64)
+                };
+                DumpedEntry {
+                    key: key.clone(),
+                    kind: entry.kind,
+                    ttl: entry.ttl,
+                    expires: (now + remaining).max(0) as u64,
+                    records: entry.records.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Add entries from a dump, counting down their TTLs by however long it
+    /// has been since then. Entries that are past the stale window by `now`
+    /// are skipped. Returns how many entries were added.
+    pub fn restore(&mut self, entries: Vec<DumpedEntry>, now: SystemTime) -> usize {
+        let now = unix_time(now);
+        let window = self.config.stale_window as i64;
+        let mut restored = 0;
+        for entry in entries {
+            let remaining = entry.remaining(now);
+            if remaining + window <= 0 {
+                continue;
+            }
+            let offset = Duration::from_secs(remaining.unsigned_abs());
+            let expires = if remaining >= 0 {
+                Some(Instant::now() + offset)
+            } else {
+                Instant::now().checked_sub(offset)
+            };
+            if let Some(expires) = expires {
+                self.insert_with_expiry(entry.key, entry.kind, entry.records, entry.ttl, expires);
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    /// Write the cache to a file, replacing it in one go so a crash doesn't
+    /// leave half a dump behind.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let text = dump::format_dump(&self.dump(SystemTime::now()));
+        let partial = path.with_extension("partial");
+        fs::write(&partial, text)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Add the entries saved in a file. Returns how many were added.
+    pub fn load(&mut self, path: &Path) -> Result<usize, Box<dyn error::Error + Send + Sync>> {
+        let entries = dump::parse_dump(&fs::read_to_string(path)?)?;
+        Ok(self.restore(entries, SystemTime::now()))
+    }
+
+    fn clamp_ttl(&self, ttl: u32) -> u32 {
+        ttl.min(self.config.max_ttl).max(self.config.min_ttl)
+    }
+
+    fn insert_entry(&mut self, key: CacheKey, kind: EntryKind, records: Vec<Record>, ttl: u32) {
+        let expires = Instant::now() + Duration::from_secs(ttl as u64);
+        self.insert_with_expiry(key, kind, records, ttl, expires);
+    }
+
+    fn insert_with_expiry(
+        &mut self,
+        key: CacheKey,
+        kind: EntryKind,
+        records: Vec<Record>,
+        ttl: u32,
+        expires: Instant,
+    ) {
+        self.remove(&key);
+        while self.entries.len() >= self.config.max_entries {
+            match self.lru.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => return,
+            }
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        let entry = Entry {
+            kind,
+            records,
+            ttl,
+            expires,
+            last_used: self.tick,
+            hits: 0,
+            prefetching: false,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Mark an entry as the most recently used.
+    fn touch(&mut self, key: &CacheKey) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = self.tick;
+            self.lru.insert(self.tick, key.clone());
+        }
+    }
+}
+
+/// Returns true if an RRset's signatures show it was synthesised from a
+/// wildcard.
+fn is_wildcard_expansion(rrset: &[Record]) -> bool {
+    rrset.iter().any(|record| match &record.data {
+        Data::Rrsig { labels, .. } => *labels < dnssec::signature_labels(&record.name),
+        _ => false,
+    })
+}
+
+/// Returns true for NSEC and NSEC3 records, and the signatures over them.
+fn is_proof(record: &Record) -> bool {
+    match &record.data {
+        Data::Rrsig { type_covered, .. } => {
+            matches!(type_covered, RecordType::Nsec | RecordType::Nsec3)
+        }
+        _ => matches!(record.record_type, RecordType::Nsec | RecordType::Nsec3),
+    }
+}
+
+/// Separate the records of a cached RRset from the proofs kept with it.
+fn split_proofs(records: Vec<Record>, record_type: RecordType) -> (Vec<Record>, Vec<Record>) {
+    records.into_iter().partition(|record| {
+        let covered = match &record.data {
+            Data::Rrsig { type_covered, .. } => *type_covered,
+            _ => record.record_type,
+        };
+        covered == record_type || !is_proof(record)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    fn records(text: &str) -> Vec<Record> {
+        dns::zone::parse_master_file(text, None).unwrap()
+    }
+
+    fn cache() -> Cache {
+        Cache::new(CacheConfig::default())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_get_counts_ttl_down_until_expiry() {
+        let mut cache = cache();
+        cache.insert(&records(
+            "example.com. 300 A 192.0.2.1\nexample.com. 60 A 192.0.2.2",
+        ));
+        let cached = cache.get("EXAMPLE.com.", RecordType::A, Class::In).unwrap();
+        assert_eq!(cached.len(), 2);
+        assert!(cached.iter().all(|record| record.ttl == 60));
+
+        time::advance(Duration::from_secs(45)).await;
+        let cached = cache.get("example.com", RecordType::A, Class::In).unwrap();
+        assert!(cached.iter().all(|record| record.ttl == 15));
+
+        time::advance(Duration::from_secs(15)).await;
+        assert_eq!(cache.get("example.com", RecordType::A, Class::In), None);
+        // Expired entries are kept around in case they need to be served
+        // stale, until the stale window is over too
+        assert_eq!(cache.len(), 1);
+        time::advance(Duration::from_secs(60 * 60 * 24)).await;
+        assert_eq!(cache.get("example.com", RecordType::A, Class::In), None);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_clamps_ttls() {
+        let mut cache = Cache::new(CacheConfig {
+            min_ttl: 30,
+            max_ttl: 3600,
+            ..CacheConfig::default()
+        });
+        cache.insert(&records(
+            "short.com. 5 A 192.0.2.1\nlong.com. 86400 A 192.0.2.2",
+        ));
+        let short = cache.get("short.com", RecordType::A, Class::In).unwrap();
+        assert_eq!(short[0].ttl, 30);
+        let long = cache.get("long.com", RecordType::A, Class::In).unwrap();
+        assert_eq!(long[0].ttl, 3600);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_skips_zero_ttls() {
+        let mut cache = cache();
+        cache.insert(&records("example.com. 0 A 192.0.2.1"));
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_evicts_least_recently_used() {
+        let mut cache = Cache::new(CacheConfig {
+            max_entries: 2,
+            ..CacheConfig::default()
+        });
+        cache.insert(&records("a.com. 60 A 192.0.2.1\nb.com. 60 A 192.0.2.2"));
+        cache.get("a.com", RecordType::A, Class::In);
+        cache.insert(&records("c.com. 60 A 192.0.2.3"));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a.com", RecordType::A, Class::In).is_some());
+        assert!(cache.get("b.com", RecordType::A, Class::In).is_none());
+        assert!(cache.get("c.com", RecordType::A, Class::In).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_follows_cnames_and_returns_partial_chains() {
+        let mut cache = cache();
+        cache.insert(&records(
+            "www.example.com. 60 CNAME web.example.net.\nweb.example.net. 60 CNAME cdn.example.org.",
+        ));
+        let partial = cache.lookup("www.example.com", RecordType::A).unwrap();
+        assert_eq!(partial.records.len(), 2);
+        assert_eq!(partial.next, Some("cdn.example.org".to_string()));
+
+        cache.insert(&records("cdn.example.org. 60 A 192.0.2.1"));
+        let complete = cache.lookup("www.example.com", RecordType::A).unwrap();
+        assert_eq!(complete.records.len(), 3);
+        assert_eq!(complete.next, None);
+
+        let cname = cache.lookup("www.example.com", RecordType::Cname).unwrap();
+        assert_eq!(cname.records.len(), 1);
+        assert_eq!(cache.lookup("other.example.com", RecordType::A), None);
+    }
+
+    const SOA: &str = "example.com. 3600 SOA ns.example.com. hostmaster.example.com. 1 2 3 4 300";
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_answer_caches_name_errors_for_every_type() {
+        let mut cache = cache();
+        let soa = records(SOA);
+        cache.insert_answer(
+            "nope.example.com",
+            RecordType::A,
+            ResponseCode::NameError,
+            &[],
+            &soa,
+        );
+
+        time::advance(Duration::from_secs(100)).await;
+        let cached = cache.lookup("nope.example.com", RecordType::Mx).unwrap();
+        assert_eq!(cached.rcode, ResponseCode::NameError);
+        assert!(cached.records.is_empty());
+        // The SOA minimum is lower than its TTL, so that's what counts
+        assert_eq!(cached.authority[0].ttl, 200);
+        assert_eq!(cached.authority[0].data, soa[0].data);
+
+        time::advance(Duration::from_secs(200)).await;
+        assert_eq!(cache.lookup("nope.example.com", RecordType::A), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_answer_caches_no_data_per_type() {
+        let mut cache = cache();
+        cache.insert_answer(
+            "www.example.com",
+            RecordType::Aaaa,
+            ResponseCode::Success,
+            &[],
+            &records(SOA),
+        );
+        let cached = cache.lookup("www.example.com", RecordType::Aaaa).unwrap();
+        assert_eq!(cached.rcode, ResponseCode::Success);
+        assert!(cached.records.is_empty());
+        assert_eq!(cached.authority.len(), 1);
+        assert_eq!(cache.lookup("www.example.com", RecordType::A), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_answer_caches_name_error_at_end_of_cname_chain() {
+        let mut cache = cache();
+        let answers = records("alias.example.com. 60 CNAME gone.example.com.");
+        cache.insert_answer(
+            "alias.example.com",
+            RecordType::A,
+            ResponseCode::NameError,
+            &answers,
+            &records(SOA),
+        );
+        let cached = cache.lookup("alias.example.com", RecordType::A).unwrap();
+        assert_eq!(cached.rcode, ResponseCode::NameError);
+        assert_eq!(cached.records.len(), 1);
+        assert_eq!(cached.next, None);
+        assert_eq!(
+            cache
+                .lookup("gone.example.com", RecordType::Txt)
+                .unwrap()
+                .rcode,
+            ResponseCode::NameError
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_answer_skips_negative_answers_without_soa() {
+        let mut cache = cache();
+        cache.insert_answer(
+            "nope.example.com",
+            RecordType::A,
+            ResponseCode::NameError,
+            &[],
+            &[],
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_replaces_name_errors() {
+        let mut cache = cache();
+        cache.insert_answer(
+            "new.example.com",
+            RecordType::A,
+            ResponseCode::NameError,
+            &[],
+            &records(SOA),
+        );
+        cache.insert(&records("new.example.com. 60 TXT hello"));
+        assert_eq!(cache.lookup("new.example.com", RecordType::A), None);
+        let cached = cache.lookup("new.example.com", RecordType::Txt).unwrap();
+        assert_eq!(cached.records.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_stale_serves_expired_entries_within_window() {
+        let mut cache = Cache::new(CacheConfig {
+            stale_window: 600,
+            stale_ttl: 30,
+            ..CacheConfig::default()
+        });
+        cache.insert(&records(
+            "www.example.com. 60 CNAME example.com.\nexample.com. 300 A 192.0.2.1",
+        ));
+        let fresh = cache
+            .lookup_stale("www.example.com", RecordType::A)
+            .unwrap();
+        assert!(!fresh.stale);
+        assert_eq!(fresh.records[0].ttl, 60);
+
+        // Only the CNAME has expired, but that makes the whole answer stale
+        time::advance(Duration::from_secs(120)).await;
+        assert_eq!(cache.lookup("www.example.com", RecordType::A), None);
+        let stale = cache
+            .lookup_stale("www.example.com", RecordType::A)
+            .unwrap();
+        assert!(stale.stale);
+        assert_eq!(stale.records.len(), 2);
+        assert_eq!(stale.records[0].ttl, 30);
+        assert_eq!(stale.records[1].ttl, 180);
+
+        time::advance(Duration::from_secs(600)).await;
+        assert_eq!(cache.lookup_stale("www.example.com", RecordType::A), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_stale_is_disabled_by_zero_window() {
+        let mut cache = Cache::new(CacheConfig {
+            stale_window: 0,
+            ..CacheConfig::default()
+        });
+        cache.insert(&records("example.com. 60 A 192.0.2.1"));
+        time::advance(Duration::from_secs(60)).await;
+        assert_eq!(cache.lookup_stale("example.com", RecordType::A), None);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_asks_for_prefetch_of_popular_entries_once() {
+        let mut cache = Cache::new(CacheConfig {
+            prefetch_min_hits: 2,
+            ..CacheConfig::default()
+        });
+        cache.insert(&records(
+            "popular.com. 100 A 192.0.2.1\nrare.com. 100 A 192.0.2.2",
+        ));
+        let lookup = |cache: &mut Cache, name| cache.lookup(name, RecordType::A).unwrap();
+        assert!(!lookup(&mut cache, "popular.com").prefetch);
+        assert!(!lookup(&mut cache, "popular.com").prefetch);
+
+        // Popular, but not close enough to expiry yet
+        time::advance(Duration::from_secs(85)).await;
+        assert!(!lookup(&mut cache, "popular.com").prefetch);
+
+        time::advance(Duration::from_secs(10)).await;
+        assert!(lookup(&mut cache, "popular.com").prefetch);
+        assert!(!lookup(&mut cache, "popular.com").prefetch);
+        assert!(!lookup(&mut cache, "rare.com").prefetch);
+
+        // Refreshing the entry starts the count again
+        cache.insert(&records("popular.com. 100 A 192.0.2.1"));
+        assert!(!lookup(&mut cache, "popular.com").prefetch);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restore_counts_down_ttls_by_elapsed_wall_time() {
+        let mut cache = cache();
+        cache.insert(&records("a.com. 300 A 192.0.2.1\nb.com. 60 A 192.0.2.2"));
+        cache.insert_answer(
+            "nope.example.com",
+            RecordType::A,
+            ResponseCode::NameError,
+            &[],
+            &records(SOA),
+        );
+        let saved = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let text = dump::format_dump(&cache.dump(saved));
+
+        // Two minutes of downtime later
+        let mut restored = self::cache();
+        let entries = dump::parse_dump(&text).unwrap();
+        let count = restored.restore(entries, saved + Duration::from_secs(120));
+        assert_eq!(count, 3);
+        let a = restored.get("a.com", RecordType::A, Class::In).unwrap();
+        assert_eq!(a[0].ttl, 180);
+        let nope = restored.lookup("nope.example.com", RecordType::A).unwrap();
+        assert_eq!(nope.rcode, ResponseCode::NameError);
+        assert_eq!(nope.authority[0].ttl, 180);
+        // b.com expired while we were down, but can still be served stale
+        assert_eq!(restored.get("b.com", RecordType::A, Class::In), None);
+        let b = restored.lookup_stale("b.com", RecordType::A).unwrap();
+        assert!(b.stale);
+
+        // After a day, only the longest entries are still within the window
+        let mut restored = self::cache();
+        let entries = dump::parse_dump(&text).unwrap();
+        let count = restored.restore(entries, saved + Duration::from_secs(60 * 60 * 24 + 100));
+        assert_eq!(count, 2);
+        assert!(restored.lookup_stale("b.com", RecordType::A).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_removes_names_underneath() {
+        let mut cache = cache();
+        cache.insert(&records(
+            "example.com. 300 A 192.0.2.1
+www.example.com. 300 A 192.0.2.2
+www.example.com. 300 AAAA 2001:db8::2
+example.org. 300 A 192.0.2.3",
+        ));
+        assert_eq!(cache.flush(Some("www.example.com"), Some(RecordType::A)), 1);
+        assert!(cache.lookup("www.example.com", RecordType::Aaaa).is_some());
+        assert_eq!(cache.flush(Some("example.com."), None), 2);
+        assert!(cache.lookup("example.com", RecordType::A).is_none());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.flush(None, None), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("rust-dns-cache-{}", std::process::id()));
+        let mut cache = cache();
+        cache.insert(&records("example.com. 300 A 192.0.2.1"));
+        cache.save(&path).unwrap();
+
+        let mut loaded = self::cache();
+        assert_eq!(loaded.load(&path).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+        let cached = loaded.get("example.com", RecordType::A, Class::In).unwrap();
+        assert!(cached[0].ttl <= 300 && cached[0].ttl >= 298);
+    }
+}
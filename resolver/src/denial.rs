@@ -0,0 +1,537 @@
+//! Checks that NSEC and NSEC3 records prove a negative answer or a wildcard
+//! expansion (RFC 4035 §5.4, RFC 5155 §8, RFC 7129). The records passed in
+//! must already have had their signatures verified.
+
+use super::validate::ValidationStatus;
+use dns::dnssec;
+use dns::name;
+use dns::record::{self, Data, Record, RecordType};
+
+/// NSEC3 records with more iterations than this are treated as if the zone
+/// were unsigned, rather than spending the effort to check them (RFC 9276
+/// §3.2).
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/// The type code of DNAME records, which redirect everything below a name.
+const DNAME: u16 = 39;
+
+/// The verified NSEC and NSEC3 records of a response.
+pub(crate) struct Denials {
+    nsec: Vec<Record>,
+    nsec3: Vec<Record>,
+}
+
+/// What the NSEC3 records say about a name.
+struct Nsec3Match<'a> {
+    types: &'a [RecordType],
+}
+
+/// A closest encloser proof (RFC 5155 §8.3).
+struct EncloserProof {
+    closest_encloser: String,
+    /// The NSEC3 covering the next closer name has the opt-out flag, so
+    /// there may be an unsigned delegation there.
+    opt_out: bool,
+}
+
+impl Denials {
+    pub(crate) fn new(records: Vec<Record>) -> Denials {
+        let (nsec, nsec3) = records
+            .into_iter()
+            .filter(|record| matches!(record.record_type, RecordType::Nsec | RecordType::Nsec3))
+            .partition(|record| record.record_type == RecordType::Nsec);
+        Denials { nsec, nsec3 }
+    }
+
+    /// Prove that a name doesn't exist, and that no wildcard could have
+    /// matched it instead.
+    pub(crate) fn prove_name_error(&self, qname: &str) -> ValidationStatus {
+        if !self.nsec.is_empty() {
+            let closest_encloser = match self.nsec_closest_encloser(qname) {
+                Some(closest_encloser) => closest_encloser,
+                None => return ValidationStatus::Bogus,
+            };
+            let wildcard = name::child("*", &closest_encloser);
+            return status(self.nsec_covering(&wildcard).is_some());
+        }
+        if let Some(status) = self.nsec3_unusable() {
+            return status;
+        }
+        let proof = match self.encloser_proof(qname) {
+            Some(proof) => proof,
+            None => return ValidationStatus::Bogus,
+        };
+        let wildcard = name::child("*", &proof.closest_encloser);
+        if !self.nsec3_covers(&wildcard) {
+            return ValidationStatus::Bogus;
+        }
+        // The name might be an unsigned delegation in an opt-out span
+        if proof.opt_out {
+            return ValidationStatus::Insecure;
+        }
+        ValidationStatus::Secure
+    }
+
+    /// Prove that a name has no records of a type, either because the name
+    /// exists without them or because the wildcard that matches it does.
+    pub(crate) fn prove_no_data(&self, qname: &str, qtype: RecordType) -> ValidationStatus {
+        if !self.nsec.is_empty() {
+            if let Some(types) = self.nsec_matching(qname) {
+                return status(lacks(types, qtype) && !is_wrong_side(types, qtype));
+            }
+            // Empty non-terminals fall between two names, the second of which
+            // is underneath them
+            let is_empty_non_terminal = self.nsec.iter().any(|record| match &record.data {
+                Data::Nsec { next_name, .. } => {
+                    dnssec::nsec_covers(&record.name, next_name, qname)
+                        && name::is_subdomain(next_name, qname)
+                }
+                _ => false,
+            });
+            if is_empty_non_terminal {
+                return ValidationStatus::Secure;
+            }
+            let closest_encloser = match self.nsec_closest_encloser(qname) {
+                Some(closest_encloser) => closest_encloser,
+                None => return ValidationStatus::Bogus,
+            };
+            let wildcard = name::child("*", &closest_encloser);
+            return match self.nsec_matching(&wildcard) {
+                Some(types) => status(lacks(types, qtype)),
+                None => ValidationStatus::Bogus,
+            };
+        }
+        if let Some(status) = self.nsec3_unusable() {
+            return status;
+        }
+        if let Some(matched) = self.nsec3_matching(qname) {
+            return status(lacks(matched.types, qtype) && !is_wrong_side(matched.types, qtype));
+        }
+        let proof = match self.encloser_proof(qname) {
+            Some(proof) => proof,
+            None => return ValidationStatus::Bogus,
+        };
+        // DS records of unsigned delegations in opt-out spans (RFC 5155
+        // §8.6)
+        if qtype == RecordType::Ds {
+            return if proof.opt_out {
+                ValidationStatus::Insecure
+            } else {
+                ValidationStatus::Bogus
+            };
+        }
+        let wildcard = name::child("*", &proof.closest_encloser);
+        match self.nsec3_matching(&wildcard) {
+            Some(matched) => status(lacks(matched.types, qtype)),
+            None => ValidationStatus::Bogus,
+        }
+    }
+
+    /// Prove that an answer synthesised from the wildcard at a closest
+    /// encloser was the best match, because the name asked for doesn't
+    /// exist (RFC 4035 §5.3.4, RFC 5155 §8.8).
+    pub(crate) fn prove_wildcard_expansion(
+        &self,
+        qname: &str,
+        closest_encloser: &str,
+    ) -> ValidationStatus {
+        if !self.nsec.is_empty() {
+            return status(self.nsec_covering(qname).is_some());
+        }
+        if let Some(status) = self.nsec3_unusable() {
+            return status;
+        }
+        status(self.nsec3_covers(&next_closer(qname, closest_encloser)))
+    }
+
+    /// Returns true if the records prove that a name is a delegation to an
+    /// unsigned zone: it has NS records but no DS, or it falls in an
+    /// opt-out span where unsigned delegations aren't listed.
+    pub(crate) fn proves_insecure_delegation(&self, child: &str) -> bool {
+        if let Some(types) = self.nsec_matching(child) {
+            return is_unsigned_delegation(types);
+        }
+        if self.nsec3.is_empty() {
+            return false;
+        }
+        if let Some(status) = self.nsec3_unusable() {
+            return status == ValidationStatus::Insecure;
+        }
+        if let Some(matched) = self.nsec3_matching(child) {
+            return is_unsigned_delegation(matched.types);
+        }
+        self.encloser_proof(child)
+            .is_some_and(|proof| proof.opt_out)
+    }
+
+    fn nsec_matching(&self, value: &str) -> Option<&[RecordType]> {
+        self.nsec.iter().find_map(|record| match &record.data {
+            Data::Nsec { types, .. } if name::eq(&record.name, value) => Some(types.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// The NSEC record covering a name. Records at the parent side of a
+    /// zone cut don't count, as names below the cut belong to another zone.
+    fn nsec_covering(&self, value: &str) -> Option<&Record> {
+        self.nsec.iter().find(|record| match &record.data {
+            Data::Nsec { next_name, types } => {
+                let below_cut = is_delegation(types) && name::is_subdomain(value, &record.name);
+                dnssec::nsec_covers(&record.name, next_name, value) && !below_cut
+            }
+            _ => false,
+        })
+    }
+
+    /// The closest encloser of a name that doesn't exist: the deepest
+    /// ancestor it shares with either end of the NSEC record covering it.
+    fn nsec_closest_encloser(&self, qname: &str) -> Option<String> {
+        if self.nsec_matching(qname).is_some() {
+            return None;
+        }
+        let covering = self.nsec_covering(qname)?;
+        let next_name = match &covering.data {
+            Data::Nsec { next_name, .. } => next_name,
+            _ => return None,
+        };
+        let from_owner = common_ancestor(qname, &covering.name);
+        let from_next = common_ancestor(qname, next_name);
+        if name::labels(&from_owner).len() >= name::labels(&from_next).len() {
+            Some(from_owner)
+        } else {
+            Some(from_next)
+        }
+    }
+
+    /// NSEC3 records that can't be used give no proof at all. Unknown hash
+    /// algorithms and excessive iterations make the zone insecure rather
+    /// than bogus (RFC 5155 §8.1, RFC 9276 §3.2).
+    fn nsec3_unusable(&self) -> Option<ValidationStatus> {
+        if self.nsec3.is_empty() {
+            return Some(ValidationStatus::Bogus);
+        }
+        let usable = self.nsec3.iter().any(|record| match &record.data {
+            Data::Nsec3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                ..
+            } => {
+                *hash_algorithm == dnssec::NSEC3_SHA1
+                    && flags & !dnssec::OPT_OUT == 0
+                    && *iterations <= MAX_NSEC3_ITERATIONS
+            }
+            _ => false,
+        });
+        if usable {
+            None
+        } else {
+            Some(ValidationStatus::Insecure)
+        }
+    }
+
+    /// Hash a name with the parameters of an NSEC3 record.
+    fn hash(record: &Record, value: &str) -> Option<Vec<u8>> {
+        match &record.data {
+            Data::Nsec3 {
+                hash_algorithm: dnssec::NSEC3_SHA1,
+                iterations,
+                salt,
+                ..
+            } if *iterations <= MAX_NSEC3_ITERATIONS => {
+                dnssec::nsec3_hash(value, salt, *iterations).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// The NSEC3 records that could be about a name: those in the zone
+    /// directly above their hashed owner names.
+    fn nsec3_for<'a>(&'a self, value: &str) -> impl Iterator<Item = &'a Record> + 'a {
+        let value = name::normalize(value);
+        self.nsec3.iter().filter(move |record| {
+            name::parent(&record.name).is_some_and(|zone| name::is_subdomain(&value, &zone))
+        })
+    }
+
+    fn nsec3_matching(&self, value: &str) -> Option<Nsec3Match<'_>> {
+        self.nsec3_for(value).find_map(|record| {
+            let hash = Self::hash(record, value)?;
+            match &record.data {
+                Data::Nsec3 { types, .. } if dnssec::nsec3_owner_hash(&record.name)? == hash => {
+                    Some(Nsec3Match { types })
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns the opt-out flag of the NSEC3 record covering a name, if
+    /// there is one.
+    fn nsec3_covering(&self, value: &str) -> Option<bool> {
+        self.nsec3_for(value).find_map(|record| {
+            let hash = Self::hash(record, value)?;
+            let owner_hash = dnssec::nsec3_owner_hash(&record.name)?;
+            match &record.data {
+                Data::Nsec3 {
+                    flags, next_hashed, ..
+                } if dnssec::nsec3_covers(&owner_hash, next_hashed, &hash) => {
+                    Some(flags & dnssec::OPT_OUT != 0)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn nsec3_covers(&self, value: &str) -> bool {
+        self.nsec3_covering(value).is_some()
+    }
+
+    /// Find the closest ancestor of a name with a matching NSEC3 record,
+    /// and check that the name one label below it is covered.
+    fn encloser_proof(&self, qname: &str) -> Option<EncloserProof> {
+        let mut candidate = name::normalize(qname);
+        loop {
+            let parent = name::parent(&candidate)?;
+            if let Some(matched) = self.nsec3_matching(&parent) {
+                // A delegation or DNAME means the name belongs elsewhere
+                if is_delegation(matched.types)
+                    || matched.types.contains(&record::parse_record_type(DNAME))
+                {
+                    return None;
+                }
+                let opt_out = self.nsec3_covering(&candidate)?;
+                return Some(EncloserProof {
+                    closest_encloser: parent,
+                    opt_out,
+                });
+            }
+            candidate = parent;
+        }
+    }
+}
+
+fn status(proven: bool) -> ValidationStatus {
+    if proven {
+        ValidationStatus::Secure
+    } else {
+        ValidationStatus::Bogus
+    }
+}
+
+/// Returns true if a type bitmap shows neither the type nor a CNAME.
+fn lacks(types: &[RecordType], qtype: RecordType) -> bool {
+    !types.contains(&qtype) && !types.contains(&RecordType::Cname)
+}
+
+/// The NS records at a zone cut mark the parent's side of it, which can
+/// only prove things about the DS records there (RFC 6840 §4.1).
+fn is_wrong_side(types: &[RecordType], qtype: RecordType) -> bool {
+    match qtype {
+        RecordType::Ds => types.contains(&RecordType::Soa),
+        _ => is_delegation(types),
+    }
+}
+
+fn is_delegation(types: &[RecordType]) -> bool {
+    types.contains(&RecordType::Ns) && !types.contains(&RecordType::Soa)
+}
+
+fn is_unsigned_delegation(types: &[RecordType]) -> bool {
+    is_delegation(types) && !types.contains(&RecordType::Ds)
+}
+
+/// The deepest name that both names are underneath.
+fn common_ancestor(a: &str, b: &str) -> String {
+    let mut current = name::normalize(a);
+    while !name::is_subdomain(b, &current) {
+        current = match name::parent(&current) {
+            Some(parent) => parent,
+            None => return ".".to_string(),
+        };
+    }
+    current
+}
+
+/// The name one label below the closest encloser on the way to `qname`.
+fn next_closer(qname: &str, closest_encloser: &str) -> String {
+    let labels = name::labels(qname);
+    let depth = name::labels(closest_encloser).len() + 1;
+    labels[labels.len().saturating_sub(depth)..].join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::record::Class;
+    use RecordType::*;
+
+    const SALT: [u8; 4] = [0xaa, 0xbb, 0xcc, 0xdd];
+
+    fn record(owner: &str, data: Data) -> Record {
+        let record_type = match &data {
+            Data::Nsec { .. } => Nsec,
+            _ => Nsec3,
+        };
+        Record {
+            name: owner.to_string(),
+            record_type,
+            class: Class::In,
+            ttl: 3600,
+            data,
+        }
+    }
+
+    /// An NSEC chain over names in canonical order.
+    fn nsec_chain(names: &[(&str, &[RecordType])]) -> Denials {
+        let records = names
+            .iter()
+            .enumerate()
+            .map(|(i, (owner, types))| {
+                let next_name = names[(i + 1) % names.len()].0.to_string();
+                let types = types.to_vec();
+                record(owner, Data::Nsec { next_name, types })
+            })
+            .collect();
+        Denials::new(records)
+    }
+
+    /// An NSEC3 chain over names in the zone "example".
+    fn nsec3_chain(names: &[(&str, &[RecordType])], flags: u8, iterations: u16) -> Denials {
+        let mut hashed: Vec<(Vec<u8>, Vec<RecordType>)> = names
+            .iter()
+            .map(|(owner, types)| {
+                let hash = dnssec::nsec3_hash(owner, &SALT, iterations).unwrap();
+                (hash, types.to_vec())
+            })
+            .collect();
+        hashed.sort_by(|a, b| a.0.cmp(&b.0));
+        let records = (0..hashed.len())
+            .map(|i| {
+                let (hash, types) = hashed[i].clone();
+                let owner = name::child(&dnssec::encode_base32hex(&hash), "example");
+                let data = Data::Nsec3 {
+                    hash_algorithm: dnssec::NSEC3_SHA1,
+                    flags,
+                    iterations,
+                    salt: SALT.to_vec(),
+                    next_hashed: hashed[(i + 1) % hashed.len()].0.clone(),
+                    types,
+                };
+                record(&owner, data)
+            })
+            .collect();
+        Denials::new(records)
+    }
+
+    fn nsec_zone() -> Denials {
+        nsec_chain(&[
+            ("example", &[Soa, Ns, Rrsig, Nsec]),
+            ("a.example", &[A, Rrsig, Nsec]),
+            ("b.c.example", &[A, Rrsig, Nsec]),
+            ("*.w.example", &[Txt, Rrsig, Nsec]),
+            ("x.example", &[Ns, Nsec]),
+            ("z.example", &[A, Rrsig, Nsec]),
+        ])
+    }
+
+    fn nsec3_zone(flags: u8, iterations: u16) -> Denials {
+        let mut names: Vec<(&str, &[RecordType])> = vec![
+            ("example", &[Soa, Ns, Rrsig, Nsec3param]),
+            ("a.example", &[A, Rrsig]),
+            ("w.example", &[]),
+            ("*.w.example", &[Txt, Rrsig]),
+        ];
+        // Opt-out leaves unsigned delegations out of the chain
+        if flags & dnssec::OPT_OUT == 0 {
+            names.push(("x.example", &[Ns]));
+        }
+        nsec3_chain(&names, flags, iterations)
+    }
+
+    #[test]
+    fn test_nsec_proofs() {
+        let denials = nsec_zone();
+        let secure = ValidationStatus::Secure;
+        let bogus = ValidationStatus::Bogus;
+        assert_eq!(denials.prove_name_error("nope.example"), secure);
+        assert_eq!(denials.prove_name_error("a.example"), bogus);
+        assert_eq!(denials.prove_no_data("a.example", Mx), secure);
+        assert_eq!(denials.prove_no_data("a.example", A), bogus);
+        assert_eq!(denials.prove_no_data("c.example", Txt), secure);
+        assert_eq!(denials.prove_no_data("foo.w.example", Mx), secure);
+        assert_eq!(denials.prove_no_data("foo.w.example", Txt), bogus);
+        assert_eq!(denials.prove_no_data("x.example", Ds), secure);
+        assert_eq!(denials.prove_no_data("x.example", A), bogus);
+        assert_eq!(denials.prove_no_data("y.x.example", A), bogus);
+        assert_eq!(
+            denials.prove_wildcard_expansion("foo.w.example", "w.example"),
+            secure
+        );
+        assert_eq!(
+            denials.prove_wildcard_expansion("a.example", "example"),
+            bogus
+        );
+        assert!(denials.proves_insecure_delegation("x.example"));
+        assert!(!denials.proves_insecure_delegation("a.example"));
+
+        // The wildcard at the apex has to be denied too
+        let missing = Denials::new(vec![record(
+            "b.c.example",
+            Data::Nsec {
+                next_name: "*.w.example".to_string(),
+                types: vec![A, Rrsig, Nsec],
+            },
+        )]);
+        assert_eq!(missing.prove_name_error("nope.example"), bogus);
+    }
+
+    #[test]
+    fn test_nsec3_proofs() {
+        let denials = nsec3_zone(0, 12);
+        let secure = ValidationStatus::Secure;
+        let bogus = ValidationStatus::Bogus;
+        assert_eq!(denials.prove_name_error("nope.example"), secure);
+        assert_eq!(denials.prove_name_error("a.example"), bogus);
+        assert_eq!(denials.prove_no_data("a.example", Mx), secure);
+        assert_eq!(denials.prove_no_data("a.example", A), bogus);
+        assert_eq!(denials.prove_no_data("w.example", Txt), secure);
+        assert_eq!(denials.prove_no_data("foo.w.example", Mx), secure);
+        assert_eq!(denials.prove_no_data("foo.w.example", Txt), bogus);
+        assert_eq!(denials.prove_no_data("x.example", Ds), secure);
+        assert_eq!(denials.prove_no_data("y.x.example", A), bogus);
+        assert_eq!(
+            denials.prove_wildcard_expansion("foo.w.example", "w.example"),
+            secure
+        );
+        assert_eq!(
+            denials.prove_wildcard_expansion("a.example", "example"),
+            bogus
+        );
+        assert!(denials.proves_insecure_delegation("x.example"));
+        assert!(!denials.proves_insecure_delegation("a.example"));
+        assert_eq!(Denials::new(vec![]).prove_name_error("nope.example"), bogus);
+    }
+
+    #[test]
+    fn test_nsec3_opt_out_is_insecure() {
+        let denials = nsec3_zone(dnssec::OPT_OUT, 12);
+        let insecure = ValidationStatus::Insecure;
+        assert_eq!(denials.prove_no_data("x.example", Ds), insecure);
+        assert_eq!(denials.prove_name_error("x.example"), insecure);
+        assert!(denials.proves_insecure_delegation("x.example"));
+        assert_eq!(
+            denials.prove_no_data("a.example", Mx),
+            ValidationStatus::Secure
+        );
+    }
+
+    #[test]
+    fn test_nsec3_iteration_limit() {
+        let denials = nsec3_zone(0, MAX_NSEC3_ITERATIONS + 1);
+        let insecure = ValidationStatus::Insecure;
+        assert_eq!(denials.prove_name_error("nope.example"), insecure);
+        assert_eq!(denials.prove_no_data("a.example", Mx), insecure);
+        assert!(denials.proves_insecure_delegation("a.example"));
+    }
+}
@@ -0,0 +1,190 @@
+//! A text format for saving the cache to disk, so a restarted server doesn't
+//! have to fetch everything again. Each entry starts with a line like
+//!
+//! ```text
+//! $ENTRY example.com. A IN RRSET 300 1700000300
+//! ```
+//!
+//! giving the entry's key, its kind, the TTL it was cached with and the UNIX
+//! time it expires at, followed by its records in master file format.
+
+use super::cache::{CacheKey, EntryKind};
+use dns::record::{self, Record};
+use dns::zone;
+use std::error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "; rust-dns cache dump";
+
+/// A cache entry as saved in a dump. Expiry is kept as wall clock time, so a
+/// dump can be loaded again after any amount of downtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpedEntry {
+    pub key: CacheKey,
+    pub kind: EntryKind,
+    /// The TTL the entry was cached with.
+    pub ttl: u32,
+    /// When the entry expires, in seconds since the UNIX epoch.
+    pub expires: u64,
+    pub records: Vec<Record>,
+}
+
+impl DumpedEntry {
+    /// Seconds until the entry expires, or since it expired if negative.
+    pub fn remaining(&self, now: u64) -> i64 {
+        self.expires as i64 - now as i64
+    }
+}
+
+/// Seconds since the UNIX epoch.
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn format_dump(entries: &[DumpedEntry]) -> String {
+    let mut text = format!("{}\n", HEADER);
+    for entry in entries {
+        text.push_str(&format!(
+            "$ENTRY {} {} {} {} {} {}\n",
+            zone::format_name(&entry.key.name),
+            record::record_type_name(&entry.key.record_type),
+            record::class_name(&entry.key.class),
+            kind_name(entry.kind),
+            entry.ttl,
+            entry.expires,
+        ));
+        for record in &entry.records {
+            text.push_str(&zone::format_record(record));
+            text.push('\n');
+        }
+    }
+    text
+}
+
+pub fn parse_dump(text: &str) -> Result<Vec<DumpedEntry>, Box<dyn error::Error + Send + Sync>> {
+    let mut entries = vec![];
+    let mut current: Option<(DumpedEntry, String)> = None;
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let fields = match line.strip_prefix("$ENTRY") {
+            Some(fields) => fields,
+            None => match &mut current {
+                Some((_, records)) => {
+                    records.push_str(line);
+                    records.push('\n');
+                    continue;
+                }
+                None => {
+                    return Err(format!("line {}: record outside of an entry", index + 1).into())
+                }
+            },
+        };
+        if let Some(entry) = current.take() {
+            entries.push(finish_entry(entry)?);
+        }
+        let entry = parse_entry_line(fields)
+            .ok_or_else(|| format!("line {}: invalid entry: {}", index + 1, line))?;
+        current = Some((entry, String::new()));
+    }
+    if let Some(entry) = current {
+        entries.push(finish_entry(entry)?);
+    }
+    Ok(entries)
+}
+
+fn parse_entry_line(fields: &str) -> Option<DumpedEntry> {
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let [name, record_type, class, kind, ttl, expires] = fields[..] else {
+        return None;
+    };
+    Some(DumpedEntry {
+        key: CacheKey::new(
+            name,
+            record::parse_record_type_name(record_type)?,
+            record::parse_class_name(class)?,
+        ),
+        kind: parse_kind(kind)?,
+        ttl: ttl.parse().ok()?,
+        expires: expires.parse().ok()?,
+        records: vec![],
+    })
+}
+
+fn finish_entry(
+    (mut entry, records): (DumpedEntry, String),
+) -> Result<DumpedEntry, Box<dyn error::Error + Send + Sync>> {
+    entry.records = zone::parse_master_file(&records, None).map_err(|err| {
+        format!(
+            "invalid records for {}: {}",
+            zone::format_name(&entry.key.name),
+            err
+        )
+    })?;
+    Ok(entry)
+}
+
+pub fn kind_name(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Records => "RRSET",
+        EntryKind::NoData => "NODATA",
+        EntryKind::NameError => "NXDOMAIN",
+    }
+}
+
+fn parse_kind(name: &str) -> Option<EntryKind> {
+    match name {
+        "RRSET" => Some(EntryKind::Records),
+        "NODATA" => Some(EntryKind::NoData),
+        "NXDOMAIN" => Some(EntryKind::NameError),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::record::{Class, RecordType};
+
+    #[test]
+    fn test_dump_round_trip() {
+        let entries = vec![
+            DumpedEntry {
+                key: CacheKey::new("example.com", RecordType::Txt, Class::In),
+                kind: EntryKind::Records,
+                ttl: 300,
+                expires: 1_700_000_300,
+                records: zone::parse_master_file(
+                    "example.com. 300 TXT \"v=spf1 -all\"\nexample.com. 600 TXT \"a;b\"",
+                    None,
+                )
+                .unwrap(),
+            },
+            DumpedEntry {
+                key: CacheKey::new("nope.example.com", RecordType::Any, Class::In),
+                kind: EntryKind::NameError,
+                ttl: 60,
+                expires: 1_699_999_000,
+                records: zone::parse_master_file(
+                    "example.com. 3600 SOA ns.example.com. hostmaster.example.com. 1 2 3 4 60",
+                    None,
+                )
+                .unwrap(),
+            },
+        ];
+        let text = format_dump(&entries);
+        assert!(text.contains("$ENTRY nope.example.com. ANY IN NXDOMAIN 60 1699999000\n"));
+        assert_eq!(parse_dump(&text).unwrap(), entries);
+        assert_eq!(entries[1].remaining(1_700_000_000), -1000);
+    }
+
+    #[test]
+    fn test_parse_dump_rejects_invalid_entries() {
+        assert!(parse_dump("example.com. 60 A 192.0.2.1").is_err());
+        assert!(parse_dump("$ENTRY example.com. A IN MAYBE 60 0").is_err());
+        assert!(parse_dump("$ENTRY example.com. A IN RRSET 60").is_err());
+    }
+}
@@ -0,0 +1,534 @@
+use super::cache::{Cache, CacheConfig, CachedAnswer};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use client::{Client, ClientConfig};
+use dns::edns::{self, Edns};
+use dns::header::{Opcode, ResponseCode};
+use dns::packet::DnsPacket;
+use dns::record::{self, Class};
+use server::handler::{RequestContext, RequestHandler};
+use server::response;
+use std::error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct ForwarderConfig {
+    /// The servers to relay queries to, in order of preference.
+    pub upstreams: Vec<SocketAddr>,
+    /// How long to wait for each upstream to respond. This should be well
+    /// under the timeout of our own clients, so there's time to fail over.
+    pub timeout: Duration,
+    /// How many failures in a row before an upstream is considered down.
+    pub failure_threshold: u32,
+    /// How long an upstream stays down before we prefer it again.
+    pub down_time: Duration,
+    pub cache: CacheConfig,
+}
+
+impl Default for ForwarderConfig {
+    fn default() -> ForwarderConfig {
+        ForwarderConfig {
+            upstreams: vec![],
+            timeout: Duration::from_millis(800),
+            failure_threshold: 3,
+            down_time: Duration::from_secs(30),
+            cache: CacheConfig::default(),
+        }
+    }
+}
+
+/// How an upstream has been behaving recently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamHealth {
+    pub addr: SocketAddr,
+    /// Failures since the last good response.
+    pub consecutive_failures: u32,
+    /// While set, the upstream is only tried once the others have failed.
+    pub down_until: Option<Instant>,
+}
+
+impl UpstreamHealth {
+    fn is_down(&self, now: Instant) -> bool {
+        self.down_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Relays queries to upstream servers, which do the work of resolving them.
+/// Clones share their cache and upstream health.
+#[derive(Debug, Clone)]
+pub struct Forwarder {
+    config: ForwarderConfig,
+    client: Client,
+    health: Arc<Mutex<Vec<UpstreamHealth>>>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl Forwarder {
+    pub fn new(config: ForwarderConfig) -> Forwarder {
+        let client = Client::new(ClientConfig {
+            timeout: config.timeout,
+            attempts: 1,
+            ..ClientConfig::default()
+        });
+        let health = config
+            .upstreams
+            .iter()
+            .map(|addr| UpstreamHealth {
+                addr: *addr,
+                consecutive_failures: 0,
+                down_until: None,
+            })
+            .collect();
+        Forwarder {
+            cache: Arc::new(Mutex::new(Cache::new(config.cache.clone()))),
+            config,
+            client,
+            health: Arc::new(Mutex::new(health)),
+        }
+    }
+
+    pub fn config(&self) -> &ForwarderConfig {
+        &self.config
+    }
+
+    pub fn health(&self) -> Vec<UpstreamHealth> {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn cache(&self) -> &Arc<Mutex<Cache>> {
+        &self.cache
+    }
+
+    /// Answer a request from the cache if we can, otherwise forward it and
+    /// cache the answer. If no upstream can answer, expired records are
+    /// served from the cache instead (RFC 8767).
+    pub async fn answer(
+        &self,
+        request: &DnsPacket,
+    ) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        if let Some(cached) = self.lookup_cache(request, false) {
+            if cached.prefetch {
+                self.prefetch(request);
+            }
+            return Ok(cached_response(request, cached));
+        }
+        match self.forward(request).await {
+            Ok(response) if !is_server_failure(&response) => {
+                self.cache_response(request, &response);
+                Ok(response)
+            }
+            result => match self.lookup_cache(request, true) {
+                Some(cached) => Ok(cached_response(request, cached)),
+                None => result,
+            },
+        }
+    }
+
+    /// Find the whole answer to a request in the cache.
+    fn lookup_cache(&self, request: &DnsPacket, allow_stale: bool) -> Option<CachedAnswer> {
+        let question = match &request.questions[..] {
+            [question] if record::parse_class(question.class) == Class::In => question,
+            _ => return None,
+        };
+        if request.header.opcode != Opcode::Query {
+            return None;
+        }
+        let record_type = record::parse_record_type(question.typ);
+        let mut cache = self.cache.lock().unwrap();
+        let cached = if allow_stale {
+            cache.lookup_stale(&question.name, record_type)?
+        } else {
+            cache.lookup(&question.name, record_type)?
+        };
+        match cached.next {
+            Some(_) => None,
+            None => Some(cached),
+        }
+    }
+
+    fn cache_response(&self, request: &DnsPacket, response: &DnsPacket) {
+        if let [question] = &request.questions[..] {
+            if !response.header.truncation {
+                self.cache.lock().unwrap().insert_answer(
+                    &question.name,
+                    record::parse_record_type(question.typ),
+                    response.header.rcode,
+                    &response.answers,
+                    &response.authoritative_entries,
+                );
+            }
+        }
+    }
+
+    /// Refresh the cached answer to a request in the background, so it
+    /// doesn't expire while it's still popular.
+    fn prefetch(&self, request: &DnsPacket) {
+        let forwarder = self.clone();
+        let request = request.clone();
+        tokio::spawn(async move {
+            match forwarder.forward(&request).await {
+                Ok(response) if !is_server_failure(&response) => {
+                    forwarder.cache_response(&request, &response)
+                }
+                _ => eprintln!("Could not prefetch answer to request {}", request.header.id),
+            }
+        });
+    }
+
+    /// Send a request upstream under a fresh ID, trying each upstream in turn
+    /// until one answers. The response carries the ID of the original
+    /// request.
+    pub async fn forward(
+        &self,
+        request: &DnsPacket,
+    ) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        let mut upstream_request = request.clone();
+        let mut last_failure = None;
+        for addr in self.upstream_order() {
+            // A fresh ID stops clients from choosing the IDs we send upstream,
+            // which would make it easier to spoof responses to us
+            upstream_request.header.id = rand::random();
+            let failure = match self.client.send(addr, &upstream_request).await {
+                Ok(mut response) => {
+                    response.header.id = request.header.id;
+                    if !is_server_failure(&response) {
+                        self.record_success(addr);
+                        return Ok(response);
+                    }
+                    eprintln!("Upstream {} returned {:?}", addr, response.header.rcode);
+                    Some(response)
+                }
+                Err(err) => {
+                    eprintln!("Could not forward query to {}: {}", addr, err);
+                    None
+                }
+            };
+            self.record_failure(addr);
+            last_failure = failure.or(last_failure);
+        }
+        // Every upstream failed, but an error from one of them is still an
+        // answer
+        last_failure.ok_or_else(|| anyhow!("no upstream answered").into())
+    }
+
+    /// Upstreams in order of preference, with those that are down last.
+    fn upstream_order(&self) -> Vec<SocketAddr> {
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        let up = health.iter().filter(|upstream| !upstream.is_down(now));
+        let down = health.iter().filter(|upstream| upstream.is_down(now));
+        up.chain(down).map(|upstream| upstream.addr).collect()
+    }
+
+    fn record_success(&self, addr: SocketAddr) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(upstream) = health.iter_mut().find(|upstream| upstream.addr == addr) {
+            upstream.consecutive_failures = 0;
+            upstream.down_until = None;
+        }
+    }
+
+    fn record_failure(&self, addr: SocketAddr) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(upstream) = health.iter_mut().find(|upstream| upstream.addr == addr) {
+            upstream.consecutive_failures += 1;
+            if upstream.consecutive_failures >= self.config.failure_threshold {
+                upstream.down_until = Some(Instant::now() + self.config.down_time);
+            }
+        }
+    }
+}
+
+/// Build a response to a request from the cache. Stale answers are marked
+/// with an Extended DNS Error, if the client understands EDNS.
+fn cached_response(request: &DnsPacket, cached: CachedAnswer) -> DnsPacket {
+    let mut response = response::response_to(request);
+    response.header.recursion_available = true;
+    response.header.rcode = cached.rcode;
+    response.answers = cached.records;
+    response.authoritative_entries = cached.authority;
+    if cached.stale && edns::parse_edns(request).is_some() {
+        let edns = Edns {
+            options: vec![edns::extended_error(edns::STALE_ANSWER, "")],
+            ..Edns::default()
+        };
+        edns::set_edns(&mut response, &edns);
+    }
+    response
+}
+
+/// Returns true if a response says the upstream couldn't answer, as opposed
+/// to an answer that the name doesn't exist.
+fn is_server_failure(response: &DnsPacket) -> bool {
+    matches!(
+        response.header.rcode,
+        ResponseCode::ServerFailure | ResponseCode::Refused | ResponseCode::NotImplemented
+    )
+}
+
+#[async_trait]
+impl RequestHandler for Forwarder {
+    async fn handle(&self, request: &DnsPacket, _ctx: &RequestContext) -> DnsPacket {
+        match self.answer(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                eprintln!("Could not forward request {}: {}", request.header.id, err);
+                let mut response = response::error_response(request, ResponseCode::ServerFailure);
+                response.header.recursion_available = true;
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::record::RecordType;
+    use tokio::net::UdpSocket;
+    use tokio::time;
+
+    /// A fake upstream that answers every query with the given rcode, and
+    /// remembers the IDs it was sent. Successful answers hold an A record.
+    async fn start_upstream(
+        rcode: ResponseCode,
+    ) -> Result<(SocketAddr, Arc<Mutex<Vec<u16>>>), Box<dyn error::Error + Send + Sync>> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        let ids = Arc::new(Mutex::new(vec![]));
+        let received = ids.clone();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, peer) = sock.recv_from(&mut buf).await.unwrap();
+                let mut response = dns::parse_dns_packet(&buf[..len]).unwrap();
+                received.lock().unwrap().push(response.header.id);
+                response.header.query = false;
+                response.header.rcode = rcode;
+                response.header.recursion_available = true;
+                if rcode == ResponseCode::Success {
+                    let text = format!("{}. 300 A 192.0.2.53", response.questions[0].name);
+                    response.answers = dns::zone::parse_master_file(&text, None).unwrap();
+                }
+                if rcode == ResponseCode::NameError {
+                    response.authoritative_entries = dns::zone::parse_master_file(
+                        "com. 900 SOA a.gtld-servers.net. nstld.verisign-grs.com. 1 2 3 4 900",
+                        None,
+                    )
+                    .unwrap();
+                }
+                let bytes = dns::serialize_dns_packet(&response).unwrap();
+                sock.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        Ok((addr, ids))
+    }
+
+    /// An address that never answers.
+    async fn silent_upstream(
+    ) -> Result<(UdpSocket, SocketAddr), Box<dyn error::Error + Send + Sync>> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        Ok((sock, addr))
+    }
+
+    fn forwarder(upstreams: Vec<SocketAddr>) -> Forwarder {
+        Forwarder::new(ForwarderConfig {
+            upstreams,
+            timeout: Duration::from_millis(100),
+            failure_threshold: 2,
+            ..ForwarderConfig::default()
+        })
+    }
+
+    fn request() -> DnsPacket {
+        let mut request = dns::packet::build_query(1234, "example.com", &RecordType::A);
+        request.header.recursion_desired = true;
+        request
+    }
+
+    #[tokio::test]
+    async fn test_forward_uses_fresh_id_and_restores_original(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, ids) = start_upstream(ResponseCode::Success).await?;
+        let forwarder = forwarder(vec![addr]);
+        let mut sent = vec![];
+        for _ in 0..4 {
+            let response = forwarder.forward(&request()).await?;
+            assert_eq!(response.header.id, 1234);
+            assert!(response.header.recursion_available);
+            sent.push(ids.lock().unwrap().pop().unwrap());
+        }
+        assert!(sent.iter().any(|id| *id != 1234));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forward_fails_over_and_tracks_health(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (_sock, silent) = silent_upstream().await?;
+        let (failing, _) = start_upstream(ResponseCode::ServerFailure).await?;
+        let (working, ids) = start_upstream(ResponseCode::NameError).await?;
+        let forwarder = forwarder(vec![silent, failing, working]);
+
+        let response = forwarder.forward(&request()).await?;
+        assert_eq!(response.header.rcode, ResponseCode::NameError);
+        let health = forwarder.health();
+        assert_eq!(health[0].consecutive_failures, 1);
+        assert_eq!(health[1].consecutive_failures, 1);
+        assert_eq!(health[2].consecutive_failures, 0);
+        assert!(health.iter().all(|upstream| upstream.down_until.is_none()));
+
+        // The second failure in a row marks the first two upstreams as down,
+        // so the third query goes straight to the working one
+        forwarder.forward(&request()).await?;
+        let health = forwarder.health();
+        assert!(health[0].down_until.is_some() && health[1].down_until.is_some());
+        let start = Instant::now();
+        forwarder.forward(&request()).await?;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(ids.lock().unwrap().len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_answer_serves_repeated_queries_from_cache(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, ids) = start_upstream(ResponseCode::Success).await?;
+        let forwarder = forwarder(vec![addr]);
+        forwarder
+            .cache()
+            .lock()
+            .unwrap()
+            .insert(&dns::zone::parse_master_file(
+                "cached.com. 60 A 192.0.2.1",
+                None,
+            )?);
+
+        let mut request = request();
+        request.questions[0].name = "cached.com".to_string();
+        let response = forwarder.answer(&request).await?;
+        assert_eq!(response.header.id, 1234);
+        assert!(response.header.recursion_available);
+        assert_eq!(response.answers.len(), 1);
+        assert!(ids.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_answer_caches_name_errors() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, ids) = start_upstream(ResponseCode::NameError).await?;
+        let forwarder = forwarder(vec![addr]);
+        for _ in 0..2 {
+            let response = forwarder.answer(&request()).await?;
+            assert_eq!(response.header.rcode, ResponseCode::NameError);
+            assert_eq!(
+                response.authoritative_entries[0].record_type,
+                RecordType::Soa
+            );
+        }
+        assert_eq!(ids.lock().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_returns_servfail_when_every_upstream_fails(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (_sock, silent) = silent_upstream().await?;
+        let forwarder = forwarder(vec![silent]);
+        let ctx = RequestContext {
+            peer: "127.0.0.1:5353".parse()?,
+            protocol: server::handler::Protocol::Udp,
+            tsig_key: None,
+        };
+        let response = forwarder.handle(&request(), &ctx).await;
+        assert_eq!(response.header.id, 1234);
+        assert_eq!(response.header.rcode, ResponseCode::ServerFailure);
+        Ok(())
+    }
+
+    fn insert(
+        forwarder: &Forwarder,
+        text: &str,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let records = dns::zone::parse_master_file(text, None)?;
+        forwarder.cache().lock().unwrap().insert(&records);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_answer_serves_stale_records_when_upstreams_fail(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (_sock, silent) = silent_upstream().await?;
+        let (failing, _) = start_upstream(ResponseCode::ServerFailure).await?;
+        let forwarder = forwarder(vec![silent, failing]);
+        insert(&forwarder, "example.com. 60 A 192.0.2.1")?;
+        time::advance(Duration::from_secs(600)).await;
+        // Let the clock run again, so the network timeouts behave
+        time::resume();
+
+        let mut request = request();
+        edns::set_edns(&mut request, &Edns::default());
+        let response = forwarder.answer(&request).await?;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].ttl, 30);
+        let edns = edns::parse_edns(&response).unwrap();
+        assert_eq!(
+            edns.options,
+            vec![edns::extended_error(edns::STALE_ANSWER, "")]
+        );
+
+        // Without EDNS there's nowhere to put the extended error
+        let response = forwarder.answer(&self::request()).await?;
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(edns::parse_edns(&response), None);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_answer_prefers_fresh_records_to_stale_ones(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, ids) = start_upstream(ResponseCode::Success).await?;
+        let forwarder = forwarder(vec![addr]);
+        insert(&forwarder, "example.com. 60 A 192.0.2.1")?;
+        time::advance(Duration::from_secs(600)).await;
+        time::resume();
+
+        let response = forwarder.answer(&request()).await?;
+        assert_eq!(ids.lock().unwrap().len(), 1);
+        assert_eq!(response.answers[0].ttl, 300);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_answer_prefetches_popular_records_before_expiry(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, ids) = start_upstream(ResponseCode::Success).await?;
+        let forwarder = forwarder(vec![addr]);
+        insert(&forwarder, "example.com. 100 A 192.0.2.1")?;
+        time::advance(Duration::from_secs(95)).await;
+        time::resume();
+
+        for _ in 0..3 {
+            let response = forwarder.answer(&request()).await?;
+            assert!(response.answers[0].ttl <= 5);
+        }
+        // The third hit made the entry popular, so it's being refreshed in
+        // the background while the cached answer is still served
+        for _ in 0..50 {
+            if !ids.lock().unwrap().is_empty() {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        time::sleep(Duration::from_millis(10)).await;
+        let response = forwarder.answer(&request()).await?;
+        assert!(response.answers[0].ttl > 100);
+        assert_eq!(ids.lock().unwrap().len(), 1);
+        Ok(())
+    }
+}
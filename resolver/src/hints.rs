@@ -0,0 +1,48 @@
+//! Root hints, which tell a resolver where to find the root nameservers, and
+//! the trust anchors used to validate what they say.
+
+use dns::record::Record;
+use dns::zone;
+
+const ROOT_HINTS: &str = include_str!("root.hints");
+const ROOT_ANCHORS: &str = include_str!("root.anchors");
+
+/// The built-in hints for the root nameservers of the internet.
+pub fn root_hints() -> Vec<Record> {
+    zone::parse_master_file(ROOT_HINTS, None).expect("built-in root hints are valid")
+}
+
+/// The DS records of the root zone's key signing keys, which every chain of
+/// trust on the internet starts from.
+pub fn root_trust_anchors() -> Vec<Record> {
+    zone::parse_master_file(ROOT_ANCHORS, None).expect("built-in trust anchors are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::record::RecordType;
+
+    #[test]
+    fn test_root_hints_list_thirteen_servers() {
+        let hints = root_hints();
+        let count = |record_type| {
+            hints
+                .iter()
+                .filter(|record| record.record_type == record_type)
+                .count()
+        };
+        assert_eq!(count(RecordType::Ns), 13);
+        assert_eq!(count(RecordType::A), 13);
+        assert_eq!(count(RecordType::Aaaa), 13);
+    }
+
+    #[test]
+    fn test_root_trust_anchors_are_ds_records() {
+        let anchors = root_trust_anchors();
+        assert_eq!(anchors.len(), 2);
+        assert!(anchors
+            .iter()
+            .all(|record| record.record_type == RecordType::Ds && record.name == "."));
+    }
+}
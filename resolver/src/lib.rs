@@ -0,0 +1,15 @@
+//! This package provides resolvers, which answer queries for any name by
+//! asking other servers.
+
+mod cache;
+mod denial;
+pub mod dump;
+mod forward;
+pub mod hints;
+mod recursive;
+mod validate;
+
+pub use cache::{Cache, CacheConfig, CacheKey, CachedAnswer, EntryKind};
+pub use forward::{Forwarder, ForwarderConfig, UpstreamHealth};
+pub use recursive::{Resolution, Resolver, ResolverConfig};
+pub use validate::ValidationStatus;
@@ -0,0 +1,916 @@
+use super::cache::{Cache, CacheConfig};
+use super::hints;
+use super::validate::{ValidationStatus, Validator};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use client::{Client, ClientConfig};
+use dns::edns;
+use dns::header::{self, Opcode, ResponseCode};
+use dns::name;
+use dns::packet::DnsPacket;
+use dns::record::{self, Class, Data, Record, RecordType};
+use server::handler::{RequestContext, RequestHandler};
+use server::response;
+use std::error;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    /// NS and address records for the root zone, where every lookup starts.
+    pub root_hints: Vec<Record>,
+    /// The port nameservers listen on. This is only ever not 53 in tests.
+    pub port: u16,
+    /// How many referrals a single lookup may follow before giving up.
+    pub max_referrals: usize,
+    /// How many queries a single resolution may send in total, including
+    /// those needed to find nameserver addresses.
+    pub max_queries: usize,
+    /// How many CNAMEs a single resolution may follow.
+    pub max_cname_chain: usize,
+    /// How deeply lookups of nameserver addresses may nest, e.g. to find the
+    /// address of a nameserver whose own nameservers have no glue.
+    pub max_depth: usize,
+    /// How long to wait for each nameserver to respond.
+    pub timeout: Duration,
+    pub cache: CacheConfig,
+    /// DS or DNSKEY records that answers are validated against. With no
+    /// trust anchors, DNSSEC records aren't requested or checked.
+    pub trust_anchors: Vec<Record>,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> ResolverConfig {
+        ResolverConfig {
+            root_hints: hints::root_hints(),
+            port: 53,
+            max_referrals: 16,
+            max_queries: 64,
+            max_cname_chain: 8,
+            max_depth: 4,
+            timeout: Duration::from_secs(2),
+            cache: CacheConfig::default(),
+            trust_anchors: vec![],
+        }
+    }
+}
+
+/// The outcome of resolving a name. Negative answers carry the SOA record
+/// from the authority section, which says how long they may be cached, and
+/// signed answers any NSEC or NSEC3 records that prove them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    pub rcode: ResponseCode,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub status: ValidationStatus,
+}
+
+/// A recursive resolver, which finds answers by following referrals down
+/// from the root rather than relying on an upstream server.
+#[derive(Debug)]
+pub struct Resolver {
+    config: ResolverConfig,
+    client: Client,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// What a single iterative lookup found out about a name.
+struct Step {
+    rcode: ResponseCode,
+    answers: Vec<Record>,
+    authority: Vec<Record>,
+    /// The target of a CNAME that the server didn't follow for us.
+    next: Option<String>,
+}
+
+/// Limits shared by every query made on behalf of a single resolution.
+pub(crate) struct Budget {
+    queries: usize,
+}
+
+impl Budget {
+    pub(crate) fn new(queries: usize) -> Budget {
+        Budget { queries }
+    }
+
+    fn spend(&mut self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if self.queries == 0 {
+            return Err(anyhow!("too many queries").into());
+        }
+        self.queries -= 1;
+        Ok(())
+    }
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+impl Resolver {
+    pub fn new(config: ResolverConfig) -> Resolver {
+        let client = Client::new(ClientConfig {
+            timeout: config.timeout,
+            attempts: 1,
+            recursion_desired: false,
+            dnssec_ok: !config.trust_anchors.is_empty(),
+            ..ClientConfig::default()
+        });
+        Resolver {
+            cache: Arc::new(Mutex::new(Cache::new(config.cache.clone()))),
+            config,
+            client,
+        }
+    }
+
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    pub fn cache(&self) -> &Arc<Mutex<Cache>> {
+        &self.cache
+    }
+
+    /// Find the records of a given name and type, starting from the root.
+    /// If there are trust anchors, the answer is validated too.
+    pub async fn resolve(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Resolution, Box<dyn error::Error + Send + Sync>> {
+        let mut budget = Budget::new(self.config.max_queries);
+        let mut resolution = self
+            .resolve_with(name.to_string(), record_type, &mut budget, 0)
+            .await?;
+        if !self.config.trust_anchors.is_empty() {
+            // Validation gets its own budget, as it can need a few queries
+            // for every zone between the anchor and the answer
+            let budget = Budget::new(self.config.max_queries);
+            resolution.status = Validator::new(self, budget, SystemTime::now())
+                .validate(name, record_type, &resolution)
+                .await;
+        }
+        Ok(resolution)
+    }
+
+    /// Resolve a name, following any CNAMEs, without validating the answer.
+    /// This is boxed because finding nameserver addresses can need a
+    /// resolution of its own.
+    pub(crate) fn resolve_with<'a>(
+        &'a self,
+        name: String,
+        record_type: RecordType,
+        budget: &'a mut Budget,
+        depth: usize,
+    ) -> BoxFuture<'a, Result<Resolution, Box<dyn error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            let mut answers = vec![];
+            let mut authority = vec![];
+            let mut current = name::normalize(&name);
+            for _ in 0..=self.config.max_cname_chain {
+                let cached = self.cache.lock().unwrap().lookup(&current, record_type);
+                if let Some(cached) = cached {
+                    answers.extend(cached.records);
+                    authority.extend(cached.authority);
+                    match cached.next {
+                        Some(target) => {
+                            current = target;
+                            continue;
+                        }
+                        None => {
+                            return Ok(Resolution {
+                                rcode: cached.rcode,
+                                answers,
+                                authority,
+                                status: ValidationStatus::Indeterminate,
+                            })
+                        }
+                    }
+                }
+                let step = self.lookup(&current, &record_type, budget, depth).await?;
+                self.cache.lock().unwrap().insert_answer(
+                    &current,
+                    record_type,
+                    step.rcode,
+                    &step.answers,
+                    &step.authority,
+                );
+                answers.extend(step.answers);
+                authority.extend(step.authority);
+                match step.next {
+                    Some(target) => current = target,
+                    None => {
+                        return Ok(Resolution {
+                            rcode: step.rcode,
+                            answers,
+                            authority,
+                            status: ValidationStatus::Indeterminate,
+                        })
+                    }
+                }
+            }
+            Err(anyhow!("CNAME chain for {} is too long", name).into())
+        })
+    }
+
+    /// Follow referrals until a server answers for the name, starting from
+    /// the closest zone cut we know the nameservers of.
+    async fn lookup(
+        &self,
+        qname: &str,
+        qtype: &RecordType,
+        budget: &mut Budget,
+        depth: usize,
+    ) -> Result<Step, Box<dyn error::Error + Send + Sync>> {
+        let (mut zone, mut servers) = self.closest_servers(qname, qtype)?;
+        for _ in 0..self.config.max_referrals {
+            let response = self.query_any(&servers, qname, qtype, budget).await?;
+            if let Some(step) = answer_step(&response, qname, qtype, &zone) {
+                return Ok(step);
+            }
+            let (cut, nameservers) = match find_referral(&response, qname, &zone) {
+                Some(referral) => referral,
+                None => return Ok(negative_step(&response, &zone)),
+            };
+            let glue = glue(&response, &nameservers, &zone);
+            self.cache_referral(&response, &cut, &glue);
+            servers = glue
+                .iter()
+                .filter_map(|record| address(record, self.config.port))
+                .collect();
+            if servers.is_empty() {
+                servers = self
+                    .resolve_nameservers(&nameservers, budget, depth)
+                    .await?;
+            }
+            zone = cut;
+        }
+        Err(anyhow!("too many referrals while resolving {}", qname).into())
+    }
+
+    /// Send a query to each server in turn until one gives a usable answer.
+    async fn query_any(
+        &self,
+        servers: &[SocketAddr],
+        qname: &str,
+        qtype: &RecordType,
+        budget: &mut Budget,
+    ) -> Result<DnsPacket, Box<dyn error::Error + Send + Sync>> {
+        let request = self.client.build_query(qname, qtype);
+        for server in servers {
+            budget.spend()?;
+            match self.client.send(*server, &request).await {
+                Ok(response)
+                    if matches!(
+                        response.header.rcode,
+                        ResponseCode::Success | ResponseCode::NameError
+                    ) =>
+                {
+                    return Ok(response)
+                }
+                Ok(response) => eprintln!(
+                    "{} returned {:?} for {}",
+                    server, response.header.rcode, qname
+                ),
+                Err(err) => eprintln!("Could not query {} for {}: {}", server, qname, err),
+            }
+        }
+        Err(anyhow!("no nameserver answered for {}", qname).into())
+    }
+
+    /// Look up the addresses of nameservers that came without usable glue.
+    /// The first nameserver that resolves is used.
+    async fn resolve_nameservers(
+        &self,
+        nameservers: &[String],
+        budget: &mut Budget,
+        depth: usize,
+    ) -> Result<Vec<SocketAddr>, Box<dyn error::Error + Send + Sync>> {
+        if depth >= self.config.max_depth {
+            return Err(anyhow!("nameserver lookups are nested too deeply").into());
+        }
+        for nameserver in nameservers {
+            let resolution = self
+                .resolve_with(nameserver.clone(), RecordType::A, budget, depth + 1)
+                .await;
+            let answers = match resolution {
+                Ok(resolution) => resolution.answers,
+                Err(err) => {
+                    eprintln!("Could not resolve nameserver {}: {}", nameserver, err);
+                    continue;
+                }
+            };
+            let addrs: Vec<SocketAddr> = answers
+                .iter()
+                .filter_map(|record| address(record, self.config.port))
+                .collect();
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+        Err(anyhow!("could not find the address of any nameserver").into())
+    }
+
+    /// Cache the nameservers of a zone cut along with their glue, so later
+    /// lookups for names underneath it can skip the referrals above it.
+    fn cache_referral(&self, response: &DnsPacket, cut: &str, glue: &[Record]) {
+        let mut records: Vec<Record> = response
+            .authoritative_entries
+            .iter()
+            .filter(|record| record.record_type == RecordType::Ns && name::eq(&record.name, cut))
+            .cloned()
+            .collect();
+        records.extend_from_slice(glue);
+        self.cache.lock().unwrap().insert(&records);
+    }
+
+    /// The deepest zone above a name whose nameservers and their addresses
+    /// are cached, and those addresses, or the root servers if there is no
+    /// such zone. DS records are served from the parent side of a cut, so
+    /// lookups for them start above the name.
+    fn closest_servers(
+        &self,
+        qname: &str,
+        qtype: &RecordType,
+    ) -> Result<(String, Vec<SocketAddr>), Box<dyn error::Error + Send + Sync>> {
+        let mut current = match qtype {
+            RecordType::Ds => name::parent(qname),
+            _ => Some(name::normalize(qname)),
+        };
+        let mut cache = self.cache.lock().unwrap();
+        while let Some(zone) = current.filter(|zone| zone != ".") {
+            let nameservers = cache
+                .get(&zone, RecordType::Ns, Class::In)
+                .unwrap_or_default();
+            let mut servers = vec![];
+            for nameserver in nameservers
+                .iter()
+                .filter_map(|record| record::target_name(&record.data))
+            {
+                for record_type in [RecordType::A, RecordType::Aaaa] {
+                    let records = cache.get(nameserver, record_type, Class::In);
+                    servers.extend(
+                        records
+                            .iter()
+                            .flatten()
+                            .filter_map(|record| address(record, self.config.port)),
+                    );
+                }
+            }
+            if !servers.is_empty() {
+                return Ok((zone, servers));
+            }
+            current = name::parent(&zone);
+        }
+        drop(cache);
+        Ok((".".to_string(), self.root_servers()?))
+    }
+
+    fn root_servers(&self) -> Result<Vec<SocketAddr>, Box<dyn error::Error + Send + Sync>> {
+        let hints = &self.config.root_hints;
+        let nameservers: Vec<String> = hints
+            .iter()
+            .filter(|record| record.record_type == RecordType::Ns && name::eq(&record.name, "."))
+            .filter_map(|record| record::target_name(&record.data))
+            .map(name::normalize)
+            .collect();
+        let servers: Vec<SocketAddr> = hints
+            .iter()
+            .filter(|record| nameservers.contains(&name::normalize(&record.name)))
+            .filter_map(|record| address(record, self.config.port))
+            .collect();
+        if servers.is_empty() {
+            return Err(anyhow!("root hints have no nameserver addresses").into());
+        }
+        Ok(servers)
+    }
+}
+
+/// Collect the answer to a query, following the CNAME chain through the
+/// answer section. Only records inside the zone of the server that sent
+/// them are trusted. Returns `None` if the response holds no answer.
+fn answer_step(response: &DnsPacket, qname: &str, qtype: &RecordType, zone: &str) -> Option<Step> {
+    let trusted: Vec<&Record> = response
+        .answers
+        .iter()
+        .filter(|record| name::is_subdomain(&record.name, zone))
+        .collect();
+    let mut answers = vec![];
+    let mut current = qname.to_string();
+    loop {
+        let owned: Vec<&Record> = trusted
+            .iter()
+            .filter(|record| name::eq(&record.name, &current))
+            .copied()
+            .collect();
+        let matching: Vec<Record> = owned
+            .iter()
+            .filter(|record| *qtype == RecordType::Any || record.record_type == *qtype)
+            .map(|record| (*record).clone())
+            .collect();
+        if !matching.is_empty() {
+            answers.extend(matching);
+            if *qtype != RecordType::Any && *qtype != RecordType::Rrsig {
+                answers.extend(signatures(&owned, qtype));
+            }
+            return Some(Step {
+                rcode: ResponseCode::Success,
+                answers,
+                authority: wildcard_proofs(response, zone),
+                next: None,
+            });
+        }
+        let cname = owned.iter().find_map(|record| match &record.data {
+            Data::Cname(target) => Some(((*record).clone(), name::normalize(target))),
+            _ => None,
+        });
+        match cname {
+            Some((record, target)) if answers.len() < trusted.len() => {
+                answers.push(record);
+                answers.extend(signatures(&owned, &RecordType::Cname));
+                current = target;
+            }
+            _ => break,
+        }
+    }
+    if answers.is_empty() {
+        return None;
+    }
+    // The chain ends at a name the server didn't answer for. If it says the
+    // name doesn't exist we can stop, otherwise we have to ask elsewhere.
+    if response.header.rcode == ResponseCode::NameError {
+        return Some(Step {
+            rcode: ResponseCode::NameError,
+            answers,
+            authority: negative_authority(response, zone),
+            next: None,
+        });
+    }
+    Some(Step {
+        rcode: ResponseCode::Success,
+        answers,
+        authority: wildcard_proofs(response, zone),
+        next: Some(current),
+    })
+}
+
+/// Find a delegation to a zone closer to the name being resolved. Returns
+/// the new zone cut and the names of its nameservers.
+fn find_referral(response: &DnsPacket, qname: &str, zone: &str) -> Option<(String, Vec<String>)> {
+    if response.header.rcode != ResponseCode::Success || !response.answers.is_empty() {
+        return None;
+    }
+    let cut = response
+        .authoritative_entries
+        .iter()
+        .filter(|record| record.record_type == RecordType::Ns)
+        .map(|record| name::normalize(&record.name))
+        .find(|cut| {
+            // Referrals must move down the tree, towards the name
+            !name::eq(cut, zone) && name::is_subdomain(cut, zone) && name::is_subdomain(qname, cut)
+        })?;
+    let nameservers = response
+        .authoritative_entries
+        .iter()
+        .filter(|record| record.record_type == RecordType::Ns && name::eq(&record.name, &cut))
+        .filter_map(|record| record::target_name(&record.data))
+        .map(name::normalize)
+        .collect();
+    Some((cut, nameservers))
+}
+
+/// Turn a response with no answer and no referral into NXDOMAIN or NODATA.
+fn negative_step(response: &DnsPacket, zone: &str) -> Step {
+    Step {
+        rcode: response.header.rcode,
+        answers: vec![],
+        authority: negative_authority(response, zone),
+        next: None,
+    }
+}
+
+/// The records that come with a negative answer: the SOA, and the NSEC
+/// records and signatures that prove the answer if the zone is signed.
+fn negative_authority(response: &DnsPacket, zone: &str) -> Vec<Record> {
+    response
+        .authoritative_entries
+        .iter()
+        .filter(|record| {
+            record.record_type == RecordType::Soa || record::is_dnssec_type(&record.record_type)
+        })
+        .filter(|record| name::is_subdomain(&record.name, zone))
+        .cloned()
+        .collect()
+}
+
+/// The NSEC and NSEC3 records that come with a positive answer, which prove
+/// that an answer synthesised from a wildcard had no closer match.
+fn wildcard_proofs(response: &DnsPacket, zone: &str) -> Vec<Record> {
+    response
+        .authoritative_entries
+        .iter()
+        .filter(|record| record::is_dnssec_type(&record.record_type))
+        .filter(|record| name::is_subdomain(&record.name, zone))
+        .cloned()
+        .collect()
+}
+
+/// The RRSIG records among `records` that cover a type.
+fn signatures(records: &[&Record], covered: &RecordType) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|record| {
+            matches!(&record.data, Data::Rrsig { type_covered, .. } if type_covered == covered)
+        })
+        .map(|record| (*record).clone())
+        .collect()
+}
+
+/// The address records of the nameservers in a referral, taken from the
+/// additional section. Glue is only trusted if it is inside the zone of the
+/// server that sent it, as otherwise any server could redirect lookups for
+/// names it has no authority over.
+fn glue(response: &DnsPacket, nameservers: &[String], zone: &str) -> Vec<Record> {
+    response
+        .resource_entries
+        .iter()
+        .filter(|record| matches!(record.record_type, RecordType::A | RecordType::Aaaa))
+        .filter(|record| nameservers.contains(&name::normalize(&record.name)))
+        .filter(|record| name::is_subdomain(&record.name, zone))
+        .cloned()
+        .collect()
+}
+
+fn address(record: &Record, port: u16) -> Option<SocketAddr> {
+    let ip = match record.data {
+        Data::Addr(addr) => IpAddr::from(addr),
+        Data::Addr6(addr) => IpAddr::from(addr),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Remove signatures and proofs from a response for a client that didn't
+/// ask for them, unless it asked for that type directly.
+fn strip_dnssec_records(response: &mut DnsPacket, qtype: &RecordType) {
+    response.answers.retain(|record| {
+        record.record_type == *qtype || !record::is_dnssec_type(&record.record_type)
+    });
+    response
+        .authoritative_entries
+        .retain(|record| !record::is_dnssec_type(&record.record_type));
+}
+
+#[async_trait]
+impl RequestHandler for Resolver {
+    async fn handle(&self, request: &DnsPacket, _ctx: &RequestContext) -> DnsPacket {
+        let mut response = response::response_to(request);
+        response.header.recursion_available = true;
+        if request.header.opcode != Opcode::Query {
+            response.header.rcode = ResponseCode::NotImplemented;
+            return response;
+        }
+        if request.questions.len() != 1 {
+            response.header.rcode = ResponseCode::FormatError;
+            return response;
+        }
+        let question = &request.questions[0];
+        if record::parse_class(question.class) != Class::In {
+            response.header.rcode = ResponseCode::Refused;
+            return response;
+        }
+        let record_type = record::parse_record_type(question.typ);
+        let dnssec_ok = edns::parse_edns(request).is_some_and(|edns| edns.dnssec_ok);
+        let checking_disabled = request.header.reserved & header::CHECKING_DISABLED != 0;
+        match self.resolve(&question.name, record_type).await {
+            Ok(resolution)
+                if resolution.status == ValidationStatus::Bogus && !checking_disabled =>
+            {
+                eprintln!("Answer for {} failed validation", question.name);
+                response.header.rcode = ResponseCode::ServerFailure;
+            }
+            Ok(resolution) => {
+                // RFC 6840 §5.7: AD is only set for clients that show they
+                // understand it
+                let wants_ad = dnssec_ok || request.header.reserved & header::AUTHENTIC_DATA != 0;
+                if resolution.status == ValidationStatus::Secure && wants_ad {
+                    response.header.reserved |= header::AUTHENTIC_DATA;
+                }
+                response.header.rcode = resolution.rcode;
+                response.answers = resolution.answers;
+                response.authoritative_entries = resolution.authority;
+                if !dnssec_ok {
+                    strip_dnssec_records(&mut response, &record_type);
+                }
+            }
+            Err(err) => {
+                eprintln!("Could not resolve {}: {}", question.name, err);
+                response.header.rcode = ResponseCode::ServerFailure;
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::authority::Catalog;
+    use server::handler::Protocol;
+    use server::runtime::{Server, ServerConfig};
+    use tokio::net::UdpSocket;
+
+    const ROOT: &str = "
+. 3600 SOA a.root-servers.test. hostmaster.root-servers.test. 1 1800 900 604800 86400
+. 3600 NS a.root-servers.test.
+a.root-servers.test. 3600 A 127.0.0.2
+com. 3600 NS a.gtld-servers.net.
+net. 3600 NS a.gtld-servers.net.
+a.gtld-servers.net. 3600 A 127.0.0.3
+";
+
+    const COM: &str = "
+com. 3600 SOA a.gtld-servers.net. hostmaster.com. 1 1800 900 604800 86400
+com. 3600 NS a.gtld-servers.net.
+example.com. 3600 NS ns1.example.com.
+ns1.example.com. 3600 A 127.0.0.4
+other.com. 3600 NS ns.provider.net.
+evil.com. 3600 NS ns.evil.com.
+ns.evil.com. 3600 A 127.0.0.6
+loop.com. 3600 NS ns.loop.net.
+";
+
+    const NET: &str = "
+net. 3600 SOA a.gtld-servers.net. hostmaster.net. 1 1800 900 604800 86400
+net. 3600 NS a.gtld-servers.net.
+a.gtld-servers.net. 3600 A 127.0.0.3
+provider.net. 3600 NS ns.provider.net.
+ns.provider.net. 3600 A 127.0.0.5
+loop.net. 3600 NS ns.loop.com.
+";
+
+    const EXAMPLE: &str = "
+example.com. 3600 SOA ns1.example.com. hostmaster.example.com. 1 1800 900 604800 300
+example.com. 3600 NS ns1.example.com.
+ns1.example.com. 3600 A 127.0.0.4
+www.example.com. 3600 A 192.0.2.1
+chain.example.com. 3600 CNAME www.example.com.
+alias.example.com. 3600 CNAME www.other.com.
+";
+
+    const PROVIDER: &str = "
+provider.net. 3600 SOA ns.provider.net. hostmaster.provider.net. 1 1800 900 604800 300
+provider.net. 3600 NS ns.provider.net.
+ns.provider.net. 3600 A 127.0.0.5
+";
+
+    const OTHER: &str = "
+other.com. 3600 SOA ns.provider.net. hostmaster.other.com. 1 1800 900 604800 300
+other.com. 3600 NS ns.provider.net.
+www.other.com. 3600 A 192.0.2.10
+";
+
+    const WWW_EVIL: &str = "
+www.evil.com. 3600 SOA ns.provider.net. hostmaster.evil.com. 1 1800 900 604800 300
+www.evil.com. 3600 A 192.0.2.66
+";
+
+    /// evil.com hands out a delegation to ns.provider.net, along with glue
+    /// pointing at a server it controls.
+    const EVIL: &str = "
+evil.com. 3600 SOA ns.evil.com. hostmaster.evil.com. 1 1800 900 604800 300
+evil.com. 3600 NS ns.evil.com.
+ns.evil.com. 3600 A 127.0.0.6
+www.evil.com. 3600 NS ns.provider.net.
+";
+
+    const FAKE_PROVIDER: &str = "
+provider.net. 3600 SOA ns.provider.net. hostmaster.provider.net. 1 1800 900 604800 300
+provider.net. 3600 NS ns.provider.net.
+ns.provider.net. 3600 A 127.0.0.7
+";
+
+    const POISONED_WWW_EVIL: &str = "
+www.evil.com. 3600 SOA ns.provider.net. hostmaster.evil.com. 1 1800 900 604800 300
+www.evil.com. 3600 A 6.6.6.6
+";
+
+    /// Start a fake nameserver on each address, answering from the given
+    /// zones. Every server listens on the same port, which is returned.
+    async fn start_network() -> Result<u16, Box<dyn error::Error + Send + Sync>> {
+        let servers: &[(&str, &[&str])] = &[
+            ("127.0.0.2", &[ROOT]),
+            ("127.0.0.3", &[COM, NET]),
+            ("127.0.0.4", &[EXAMPLE]),
+            ("127.0.0.5", &[PROVIDER, OTHER, WWW_EVIL]),
+            ("127.0.0.6", &[EVIL, FAKE_PROVIDER]),
+            ("127.0.0.7", &[POISONED_WWW_EVIL]),
+        ];
+        let mut port = 0;
+        for (ip, zones) in servers {
+            let mut catalog = Catalog::new();
+            for zone in *zones {
+                catalog.insert(dns::zone::parse_zone(zone, None)?);
+            }
+            let sock = UdpSocket::bind((*ip, port)).await?;
+            port = sock.local_addr()?.port();
+            let server = Server::new(catalog, ServerConfig::default());
+            tokio::spawn(async move { server.serve_udp(sock).await });
+        }
+        Ok(port)
+    }
+
+    async fn resolver() -> Result<Resolver, Box<dyn error::Error + Send + Sync>> {
+        let port = start_network().await?;
+        let root_hints = dns::zone::parse_master_file(
+            ". 3600 NS a.root-servers.test.\na.root-servers.test. 3600 A 127.0.0.2",
+            None,
+        )?;
+        Ok(Resolver::new(ResolverConfig {
+            root_hints,
+            port,
+            timeout: Duration::from_millis(500),
+            ..ResolverConfig::default()
+        }))
+    }
+
+    fn addresses(resolution: &Resolution) -> Vec<Data> {
+        resolution
+            .answers
+            .iter()
+            .filter(|record| record.record_type == RecordType::A)
+            .map(|record| record.data.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_follows_referrals_from_root(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolution = resolver()
+            .await?
+            .resolve("www.example.com", RecordType::A)
+            .await?;
+        assert_eq!(resolution.rcode, ResponseCode::Success);
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 1])]);
+        // Without trust anchors, nothing is validated
+        assert_eq!(resolution.status, ValidationStatus::Indeterminate);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_looks_up_out_of_bailiwick_nameservers(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolution = resolver()
+            .await?
+            .resolve("www.other.com", RecordType::A)
+            .await?;
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 10])]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chases_cnames() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+
+        let resolution = resolver.resolve("chain.example.com", RecordType::A).await?;
+        assert_eq!(resolution.answers.len(), 2);
+        assert_eq!(resolution.answers[0].record_type, RecordType::Cname);
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 1])]);
+
+        // The target is in a zone on a different server
+        let resolution = resolver.resolve("alias.example.com", RecordType::A).await?;
+        assert_eq!(resolution.answers.len(), 2);
+        assert_eq!(resolution.answers[0].record_type, RecordType::Cname);
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 10])]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_continues_from_cached_cnames(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+        resolver
+            .cache()
+            .lock()
+            .unwrap()
+            .insert(&dns::zone::parse_master_file(
+                "cached.test. 60 CNAME www.example.com.",
+                None,
+            )?);
+        let resolution = resolver.resolve("cached.test", RecordType::A).await?;
+        assert_eq!(resolution.answers.len(), 2);
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 1])]);
+
+        // Now the whole chain is cached, so no queries are needed
+        let mut resolver = resolver;
+        resolver.config.max_queries = 0;
+        let resolution = resolver.resolve("cached.test", RecordType::A).await?;
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 1])]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_returns_negative_answers_with_soa(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+
+        let resolution = resolver.resolve("nope.example.com", RecordType::A).await?;
+        assert_eq!(resolution.rcode, ResponseCode::NameError);
+        assert!(resolution.answers.is_empty());
+        assert_eq!(resolution.authority[0].record_type, RecordType::Soa);
+
+        let resolution = resolver.resolve("www.example.com", RecordType::Mx).await?;
+        assert_eq!(resolution.rcode, ResponseCode::Success);
+        assert!(resolution.answers.is_empty());
+        assert_eq!(resolution.authority[0].name, "example.com");
+
+        // Both are cached, along with their SOA records
+        let mut resolver = resolver;
+        resolver.config.max_queries = 0;
+        let resolution = resolver
+            .resolve("nope.example.com", RecordType::Txt)
+            .await?;
+        assert_eq!(resolution.rcode, ResponseCode::NameError);
+        assert_eq!(resolution.authority[0].record_type, RecordType::Soa);
+        let resolution = resolver.resolve("www.example.com", RecordType::Mx).await?;
+        assert_eq!(resolution.authority[0].record_type, RecordType::Soa);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_starts_from_cached_delegations(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut resolver = resolver().await?;
+        resolver.resolve("www.example.com", RecordType::A).await?;
+
+        // The referrals to com and example.com were cached, so only the
+        // server for example.com needs to be asked
+        resolver.config.max_queries = 1;
+        let resolution = resolver.resolve("nope.example.com", RecordType::A).await?;
+        assert_eq!(resolution.rcode, ResponseCode::NameError);
+
+        // Other zones under com skip the root
+        resolver.config.max_queries = 2;
+        let resolution = resolver.resolve("ns.evil.com", RecordType::A).await?;
+        assert_eq!(addresses(&resolution), vec![Data::Addr([127, 0, 0, 6])]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ignores_out_of_bailiwick_glue(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolution = resolver()
+            .await?
+            .resolve("www.evil.com", RecordType::A)
+            .await?;
+        assert_eq!(addresses(&resolution), vec![Data::Addr([192, 0, 2, 66])]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_enforces_query_limit() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let mut resolver = resolver().await?;
+        resolver.config.max_queries = 3;
+        // Needs 3 queries to find ns.provider.net, then 2 more
+        assert!(resolver
+            .resolve("www.other.com", RecordType::A)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_gives_up_on_circular_delegations(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let result = resolver()
+            .await?
+            .resolve("www.loop.com", RecordType::A)
+            .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_sets_recursion_available_and_returns_servfail_on_error(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+        let ctx = RequestContext {
+            peer: "127.0.0.1:5353".parse()?,
+            protocol: Protocol::Udp,
+            tsig_key: None,
+        };
+
+        let request = dns::packet::build_query(1, "www.example.com", &RecordType::A);
+        let response = resolver.handle(&request, &ctx).await;
+        assert!(response.header.recursion_available);
+        assert!(!response.header.authoritative_answer);
+        assert_eq!(response.answers.len(), 1);
+
+        let request = dns::packet::build_query(2, "www.loop.com", &RecordType::A);
+        let response = resolver.handle(&request, &ctx).await;
+        assert_eq!(response.header.rcode, ResponseCode::ServerFailure);
+        Ok(())
+    }
+}
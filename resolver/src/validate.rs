@@ -0,0 +1,988 @@
+//! DNSSEC validation (RFC 4035 §5). Answers are checked against a chain of
+//! trust that starts at a configured trust anchor and runs down through the
+//! DS and DNSKEY records of each zone to the one that signed them.
+
+use super::denial::Denials;
+use super::dump::unix_time;
+use super::recursive::{Budget, Resolution, Resolver};
+use dns::dnssec;
+use dns::header::ResponseCode;
+use dns::name;
+use dns::record::{self, Data, Record, RecordType};
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// How far an answer could be trusted, as defined in RFC 4033 §5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationStatus {
+    /// Every record was signed by a key with a chain of trust to an anchor.
+    Secure,
+    /// A signed parent proved that the records come from an unsigned zone.
+    Insecure,
+    /// There is no trust anchor for the name, or validation wasn't asked
+    /// for.
+    Indeterminate,
+    /// Signatures were missing, invalid or expired where they should have
+    /// been present.
+    Bogus,
+}
+
+/// Checks the answers of a single resolution. Keys that have been validated
+/// are remembered for the rest of the check.
+pub(crate) struct Validator<'a> {
+    resolver: &'a Resolver,
+    budget: Budget,
+    now: u32,
+    /// The validated DNSKEYs of the secure zones found so far.
+    keys: HashMap<String, Vec<Data>>,
+}
+
+impl<'a> Validator<'a> {
+    pub(crate) fn new(resolver: &'a Resolver, budget: Budget, now: SystemTime) -> Validator<'a> {
+        Validator {
+            resolver,
+            budget,
+            now: unix_time(now) as u32,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Validate a resolution. Each RRset in the answer is checked on its
+    /// own, as a CNAME chain can cross into unsigned zones, and the result
+    /// is the least trustworthy status among them.
+    pub(crate) async fn validate(
+        &mut self,
+        qname: &str,
+        qtype: RecordType,
+        resolution: &Resolution,
+    ) -> ValidationStatus {
+        let mut status = ValidationStatus::Secure;
+        for (owner, record_type) in rrsets(&resolution.answers) {
+            let rrset = records_of(&resolution.answers, &owner, record_type);
+            let rrsigs = signatures(&resolution.answers, &owner, record_type);
+            let result = self
+                .validate_rrset(&owner, &rrset, &rrsigs, &resolution.authority)
+                .await;
+            status = status.max(result);
+        }
+        let negative = resolution.rcode == ResponseCode::NameError
+            || !resolution
+                .answers
+                .iter()
+                .any(|record| qtype == RecordType::Any || record.record_type == qtype);
+        if negative {
+            // The end of the chain, where the records we asked for are missing
+            let name = chain_end(qname, &resolution.answers);
+            let result = self.validate_negative(&name, qtype, resolution).await;
+            status = status.max(result);
+        }
+        status
+    }
+
+    /// Validate an RRset against its signatures. Records synthesised from a
+    /// wildcard also need proof that the name asked for doesn't exist.
+    async fn validate_rrset(
+        &mut self,
+        owner: &str,
+        rrset: &[Record],
+        rrsigs: &[Record],
+        authority: &[Record],
+    ) -> ValidationStatus {
+        if rrsigs.is_empty() {
+            // Unsigned records are only acceptable below an insecure
+            // delegation
+            return match self.secure_zone(owner).await {
+                Ok(_) => ValidationStatus::Bogus,
+                Err(status) => status,
+            };
+        }
+        let (signer, labels) = match &rrsigs[0].data {
+            Data::Rrsig {
+                signer_name,
+                labels,
+                ..
+            } => (name::normalize(signer_name), *labels),
+            _ => return ValidationStatus::Bogus,
+        };
+        if !name::is_subdomain(owner, &signer) {
+            return ValidationStatus::Bogus;
+        }
+        let keys = match self.secure_zone(&signer).await {
+            Ok((zone, keys)) if zone == signer => keys,
+            Ok(_) => return ValidationStatus::Bogus,
+            Err(status) => return status,
+        };
+        if !self.verify(rrset, rrsigs, &signer, &keys) {
+            return ValidationStatus::Bogus;
+        }
+        if labels < dnssec::signature_labels(owner) {
+            let owner_labels = name::labels(owner);
+            let closest_encloser = owner_labels[owner_labels.len() - labels as usize..].join(".");
+            let denials = self.verified_denials(authority, &signer, &keys);
+            return denials.prove_wildcard_expansion(owner, &closest_encloser);
+        }
+        ValidationStatus::Secure
+    }
+
+    /// Validate a negative answer. The SOA says which zone the answer came
+    /// from, and that zone's NSEC or NSEC3 records must prove it.
+    async fn validate_negative(
+        &mut self,
+        owner: &str,
+        qtype: RecordType,
+        resolution: &Resolution,
+    ) -> ValidationStatus {
+        let authority = &resolution.authority;
+        let soa = authority
+            .iter()
+            .find(|record| record.record_type == RecordType::Soa);
+        let zone = match soa {
+            Some(soa) => name::normalize(&soa.name),
+            None => {
+                return match self.secure_zone(owner).await {
+                    Ok(_) => ValidationStatus::Bogus,
+                    Err(status) => status,
+                }
+            }
+        };
+        if !name::is_subdomain(owner, &zone) {
+            return ValidationStatus::Bogus;
+        }
+        let keys = match self.secure_zone(&zone).await {
+            Ok((secure, keys)) if secure == zone => keys,
+            Ok(_) => return ValidationStatus::Bogus,
+            Err(status) => return status,
+        };
+        let soa_rrset = records_of(authority, &zone, RecordType::Soa);
+        let soa_rrsigs = signatures(authority, &zone, RecordType::Soa);
+        if !self.verify(&soa_rrset, &soa_rrsigs, &zone, &keys) {
+            return ValidationStatus::Bogus;
+        }
+        let denials = self.verified_denials(authority, &zone, &keys);
+        if resolution.rcode == ResponseCode::NameError {
+            denials.prove_name_error(owner)
+        } else {
+            denials.prove_no_data(owner, qtype)
+        }
+    }
+
+    /// Follow the chain of trust from the closest trust anchor down to a
+    /// name. Returns the deepest secure zone that the name belongs to and
+    /// its keys, or the status that stopped the walk: `Insecure` if a signed
+    /// zone proved a delegation on the way to be unsigned, `Bogus` if
+    /// anything failed to validate.
+    async fn secure_zone(&mut self, target: &str) -> Result<(String, Vec<Data>), ValidationStatus> {
+        let anchors = self.resolver.config().trust_anchors.clone();
+        let anchor = anchors
+            .iter()
+            .map(|record| name::normalize(&record.name))
+            .filter(|owner| name::is_subdomain(target, owner))
+            .max_by_key(|owner| name::labels(owner).len())
+            .ok_or(ValidationStatus::Indeterminate)?;
+        let mut zone = anchor.clone();
+        let mut keys = match self.keys.get(&anchor) {
+            Some(keys) => keys.clone(),
+            None => {
+                let anchored: Vec<&Record> = anchors
+                    .iter()
+                    .filter(|record| name::eq(&record.name, &anchor))
+                    .collect();
+                self.zone_keys(&anchor, &anchored).await?
+            }
+        };
+
+        for child in descend(&anchor, target) {
+            if let Some(child_keys) = self.keys.get(&child) {
+                zone = child;
+                keys = child_keys.clone();
+                continue;
+            }
+            let resolution = self.fetch(&child, RecordType::Ds).await?;
+            let ds = records_of(&resolution.answers, &child, RecordType::Ds);
+            if ds.is_empty() {
+                // No DS, so this is either not a zone cut or an unsigned one
+                let denials = self.verified_denials(&resolution.authority, &zone, &keys);
+                if denials.proves_insecure_delegation(&child) {
+                    return Err(ValidationStatus::Insecure);
+                }
+                continue;
+            }
+            let rrsigs = signatures(&resolution.answers, &child, RecordType::Ds);
+            if !self.verify(&ds, &rrsigs, &zone, &keys) {
+                return Err(ValidationStatus::Bogus);
+            }
+            let ds: Vec<&Record> = ds.iter().collect();
+            keys = self.zone_keys(&child, &ds).await?;
+            zone = child;
+        }
+        Ok((zone, keys))
+    }
+
+    /// Fetch and validate the DNSKEY RRset of a zone, given the DS records
+    /// or trusted DNSKEYs that vouch for it.
+    async fn zone_keys(
+        &mut self,
+        zone: &str,
+        anchors: &[&Record],
+    ) -> Result<Vec<Data>, ValidationStatus> {
+        let usable: Vec<&Record> = anchors
+            .iter()
+            .copied()
+            .filter(|anchor| match &anchor.data {
+                Data::Ds {
+                    algorithm,
+                    digest_type,
+                    ..
+                } => {
+                    dnssec::is_supported_algorithm(*algorithm)
+                        && matches!(*digest_type, dnssec::SHA256 | dnssec::SHA384)
+                }
+                Data::Dnskey { algorithm, .. } => dnssec::is_supported_algorithm(*algorithm),
+                _ => false,
+            })
+            .collect();
+        // Zones signed only with algorithms we don't know are treated as
+        // unsigned (RFC 4035 §5.2)
+        if usable.is_empty() {
+            return Err(ValidationStatus::Insecure);
+        }
+        let resolution = self.fetch(zone, RecordType::Dnskey).await?;
+        let dnskeys = records_of(&resolution.answers, zone, RecordType::Dnskey);
+        let rrsigs = signatures(&resolution.answers, zone, RecordType::Dnskey);
+        let trusted: Vec<Data> = dnskeys
+            .iter()
+            .map(|record| record.data.clone())
+            .filter(|dnskey| {
+                usable
+                    .iter()
+                    .any(|anchor| vouches_for(zone, &anchor.data, dnskey))
+            })
+            .collect();
+        if !self.verify(&dnskeys, &rrsigs, zone, &trusted) {
+            return Err(ValidationStatus::Bogus);
+        }
+        let keys: Vec<Data> = dnskeys
+            .into_iter()
+            .map(|record| record.data)
+            .filter(is_zone_key)
+            .collect();
+        self.keys.insert(name::normalize(zone), keys.clone());
+        Ok(keys)
+    }
+
+    /// The NSEC and NSEC3 records among some records that are properly
+    /// signed by a zone. Any others are ignored.
+    fn verified_denials(&self, records: &[Record], zone: &str, keys: &[Data]) -> Denials {
+        let verified = rrsets(records)
+            .into_iter()
+            .filter(|(_, record_type)| record::is_dnssec_type(record_type))
+            .flat_map(|(owner, record_type)| {
+                let rrset = records_of(records, &owner, record_type);
+                let rrsigs = signatures(records, &owner, record_type);
+                if self.verify(&rrset, &rrsigs, zone, keys) {
+                    rrset
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
+        Denials::new(verified)
+    }
+
+    /// Returns true if any of the signatures over an RRset was made by one
+    /// of the zone's keys and is currently valid.
+    fn verify(&self, rrset: &[Record], rrsigs: &[Record], zone: &str, keys: &[Data]) -> bool {
+        let owner = match rrset.first() {
+            Some(record) => &record.name,
+            None => return false,
+        };
+        rrsigs.iter().any(|rrsig| {
+            let (algorithm, labels, expiration, inception, key_tag, signer_name) = match &rrsig.data
+            {
+                Data::Rrsig {
+                    algorithm,
+                    labels,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    ..
+                } => (
+                    *algorithm,
+                    *labels,
+                    *expiration,
+                    *inception,
+                    *key_tag,
+                    signer_name,
+                ),
+                _ => return false,
+            };
+            let current = !dnssec::time_before(self.now, inception)
+                && !dnssec::time_before(expiration, self.now);
+            if !current || !name::eq(signer_name, zone) || labels > dnssec::signature_labels(owner)
+            {
+                return false;
+            }
+            keys.iter().any(|key| {
+                matches!(key, Data::Dnskey { algorithm: alg, .. } if *alg == algorithm)
+                    && dnssec::key_tag(key) == Some(key_tag)
+                    && dnssec::verify_signature(key, &rrsig.data, rrset).is_ok()
+            })
+        })
+    }
+
+    /// Resolve a record needed for validation, without validating it.
+    async fn fetch(
+        &mut self,
+        owner: &str,
+        record_type: RecordType,
+    ) -> Result<Resolution, ValidationStatus> {
+        self.resolver
+            .resolve_with(owner.to_string(), record_type, &mut self.budget, 0)
+            .await
+            .map_err(|err| {
+                eprintln!("Could not fetch {:?} for {}: {}", record_type, owner, err);
+                ValidationStatus::Bogus
+            })
+    }
+}
+
+/// Returns true if a trust anchor or DS record matches a DNSKEY.
+fn vouches_for(zone: &str, anchor: &Data, dnskey: &Data) -> bool {
+    match anchor {
+        Data::Ds {
+            key_tag,
+            digest_type,
+            digest,
+            ..
+        } => {
+            dnssec::key_tag(dnskey) == Some(*key_tag)
+                && dnssec::ds_digest(zone, dnskey, *digest_type)
+                    .is_ok_and(|expected| expected == *digest)
+        }
+        anchor => anchor == dnskey,
+    }
+}
+
+/// Only zone keys that haven't been revoked may sign records (RFC 4034
+/// §2.1.1, RFC 5011 §2.1).
+fn is_zone_key(dnskey: &Data) -> bool {
+    match dnskey {
+        Data::Dnskey { flags, .. } => flags & dnssec::ZONE_KEY != 0 && flags & dnssec::REVOKE == 0,
+        _ => false,
+    }
+}
+
+/// The names between an ancestor and a name, excluding the ancestor, from
+/// the top down.
+fn descend(ancestor: &str, target: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut current = name::normalize(target);
+    while !name::eq(&current, ancestor) {
+        names.push(current.clone());
+        current = match name::parent(&current) {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    names.reverse();
+    names
+}
+
+/// The owner and type of each RRset in a list of records, leaving out the
+/// signatures.
+fn rrsets(records: &[Record]) -> Vec<(String, RecordType)> {
+    let mut keys: Vec<(String, RecordType)> = vec![];
+    for record in records {
+        let key = (name::normalize(&record.name), record.record_type);
+        if record.record_type != RecordType::Rrsig && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+fn records_of(records: &[Record], owner: &str, record_type: RecordType) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|record| record.record_type == record_type && name::eq(&record.name, owner))
+        .cloned()
+        .collect()
+}
+
+/// The RRSIG records owned by a name that cover a type.
+fn signatures(records: &[Record], owner: &str, covered: RecordType) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|record| name::eq(&record.name, owner))
+        .filter(|record| {
+            matches!(&record.data, Data::Rrsig { type_covered, .. } if *type_covered == covered)
+        })
+        .cloned()
+        .collect()
+}
+
+/// The name at the end of the CNAME chain in an answer.
+fn chain_end(qname: &str, answers: &[Record]) -> String {
+    let mut current = name::normalize(qname);
+    for _ in 0..answers.len() {
+        let target = answers.iter().find_map(|record| match &record.data {
+            Data::Cname(target) if name::eq(&record.name, &current) => {
+                Some(name::normalize(target))
+            }
+            _ => None,
+        });
+        match target {
+            Some(target) => current = target,
+            None => break,
+        }
+    }
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recursive::ResolverConfig;
+    use dns::header;
+    use dns::record::Class;
+    use dns::zone::{self, Zone};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use server::authority::Catalog;
+    use server::handler::{Protocol, RequestContext, RequestHandler};
+    use server::runtime::{Server, ServerConfig};
+    use std::error;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    struct TestKey {
+        pair: Ed25519KeyPair,
+        dnskey: Data,
+    }
+
+    impl TestKey {
+        fn generate() -> TestKey {
+            let rng = ring::rand::SystemRandom::new();
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let dnskey = Data::Dnskey {
+                flags: dnssec::ZONE_KEY | dnssec::SECURE_ENTRY_POINT,
+                protocol: 3,
+                algorithm: dnssec::ED25519,
+                public_key: pair.public_key().as_ref().to_vec(),
+            };
+            TestKey { pair, dnskey }
+        }
+
+        fn ds(&self, owner: &str) -> String {
+            let ds = dnssec::ds_data(owner, &self.dnskey, dnssec::SHA256).unwrap();
+            format!(
+                "{} 3600 DS {}",
+                zone::format_name(owner),
+                zone::format_data(&ds)
+            )
+        }
+
+        /// Sign an RRset, valid for an hour either side of `at`.
+        fn sign(&self, origin: &str, rrset: &[Record], at: u32) -> Record {
+            let unsigned = |signature| Data::Rrsig {
+                type_covered: rrset[0].record_type,
+                algorithm: dnssec::ED25519,
+                labels: dnssec::signature_labels(&rrset[0].name),
+                original_ttl: rrset[0].ttl as u32,
+                expiration: at.wrapping_add(3600),
+                inception: at.wrapping_sub(3600),
+                key_tag: dnssec::key_tag(&self.dnskey).unwrap(),
+                signer_name: origin.to_string(),
+                signature,
+            };
+            let data = dnssec::signed_data(&unsigned(vec![]), rrset).unwrap();
+            let signature = self.pair.sign(&data).as_ref().to_vec();
+            Record {
+                record_type: RecordType::Rrsig,
+                data: unsigned(signature),
+                ..rrset[0].clone()
+            }
+        }
+    }
+
+    fn now() -> u32 {
+        unix_time(SystemTime::now()) as u32
+    }
+
+    /// Builds the denial records for a zone's owner names, which are in
+    /// canonical order.
+    type Chain = fn(&str, &[String], &[Record]) -> Vec<Record>;
+
+    /// Sign a zone with a single key: add its DNSKEY and a chain of denial
+    /// records, and sign every authoritative RRset. Glue and NS records at
+    /// zone cuts are left unsigned, as they belong to the child.
+    fn sign_zone(text: &str, key: &TestKey, chain: Chain) -> Vec<Record> {
+        let zone = zone::parse_zone(text, None).unwrap();
+        let origin = zone.origin.clone();
+        let mut records: Vec<Record> = zone.records().cloned().collect();
+        records.push(Record {
+            name: origin.clone(),
+            record_type: RecordType::Dnskey,
+            class: Class::In,
+            ttl: 3600,
+            data: key.dnskey.clone(),
+        });
+        let cuts: Vec<String> = records
+            .iter()
+            .filter(|record| record.record_type == RecordType::Ns && record.name != origin)
+            .map(|record| record.name.clone())
+            .collect();
+        let below_cut = |owner: &str| {
+            cuts.iter()
+                .any(|cut| name::is_subdomain(owner, cut) && !name::eq(owner, cut))
+        };
+        let mut owners: Vec<String> = vec![origin.clone()];
+        for record in zone.records() {
+            if !owners.contains(&record.name) && !below_cut(&record.name) {
+                owners.push(record.name.clone());
+            }
+        }
+        let denials = chain(&origin, &owners, &records);
+        records.extend(denials);
+        let mut signed = records.clone();
+        for (owner, record_type) in rrsets(&records) {
+            let delegation = record_type == RecordType::Ns && cuts.contains(&owner);
+            if delegation || below_cut(&owner) {
+                continue;
+            }
+            let rrset = records_of(&records, &owner, record_type);
+            signed.push(key.sign(&origin, &rrset, now()));
+        }
+        signed
+    }
+
+    fn types_at(records: &[Record], owner: &str) -> Vec<RecordType> {
+        let mut types: Vec<RecordType> = records
+            .iter()
+            .filter(|record| record.name == owner)
+            .map(|record| record.record_type)
+            .collect();
+        types.push(RecordType::Rrsig);
+        types.sort_by_key(dns::record::record_type_code);
+        types.dedup();
+        types
+    }
+
+    fn nsec_chain(_: &str, owners: &[String], records: &[Record]) -> Vec<Record> {
+        let mut chain = vec![];
+        for (index, owner) in owners.iter().enumerate() {
+            let mut types = types_at(records, owner);
+            types.push(RecordType::Nsec);
+            types.sort_by_key(dns::record::record_type_code);
+            chain.push(Record {
+                name: owner.clone(),
+                record_type: RecordType::Nsec,
+                class: Class::In,
+                ttl: 300,
+                data: Data::Nsec {
+                    next_name: owners[(index + 1) % owners.len()].clone(),
+                    types,
+                },
+            });
+        }
+        chain
+    }
+
+    /// An NSEC3 chain with a few iterations and a salt, covering empty
+    /// non-terminals too. Adds the NSEC3PARAM record to the apex.
+    fn nsec3_chain(origin: &str, owners: &[String], records: &[Record]) -> Vec<Record> {
+        let (salt, iterations) = (vec![0xaa, 0xbb], 2);
+        let mut chain = vec![Record {
+            name: origin.to_string(),
+            record_type: RecordType::Nsec3param,
+            class: Class::In,
+            ttl: 300,
+            data: Data::Nsec3param {
+                hash_algorithm: dnssec::NSEC3_SHA1,
+                flags: 0,
+                iterations,
+                salt: salt.clone(),
+            },
+        }];
+        let mut names: Vec<String> = vec![];
+        for owner in owners {
+            let mut current = owner.clone();
+            while !names.contains(&current) {
+                names.push(current.clone());
+                match name::parent(&current) {
+                    Some(parent) if name::is_subdomain(&parent, origin) => current = parent,
+                    _ => break,
+                }
+            }
+        }
+        let mut hashed: Vec<(Vec<u8>, Vec<RecordType>)> = names
+            .iter()
+            .map(|owner| {
+                let hash = dnssec::nsec3_hash(owner, &salt, iterations).unwrap();
+                let mut types = match owners.contains(owner) {
+                    true => types_at(records, owner),
+                    false => vec![],
+                };
+                if owner == origin {
+                    types.push(RecordType::Nsec3param);
+                    types.sort_by_key(dns::record::record_type_code);
+                }
+                (hash, types)
+            })
+            .collect();
+        hashed.sort_by(|a, b| a.0.cmp(&b.0));
+        chain.extend((0..hashed.len()).map(|index| Record {
+            name: name::child(&dnssec::encode_base32hex(&hashed[index].0), origin),
+            record_type: RecordType::Nsec3,
+            class: Class::In,
+            ttl: 300,
+            data: Data::Nsec3 {
+                hash_algorithm: dnssec::NSEC3_SHA1,
+                flags: 0,
+                iterations,
+                salt: salt.clone(),
+                next_hashed: hashed[(index + 1) % hashed.len()].0.clone(),
+                types: hashed[index].1.clone(),
+            },
+        }));
+        chain
+    }
+
+    fn into_zone(records: Vec<Record>) -> Zone {
+        let origin = records[0].name.clone();
+        let mut zone = Zone::new(&origin);
+        for record in records {
+            zone.insert(record).unwrap();
+        }
+        zone
+    }
+
+    /// Start a signed copy of the network in the recursive resolver's
+    /// tests: the root and com are signed, example.com is signed, but a few
+    /// of its records are broken, insecure.com is an unsigned delegation,
+    /// badkey.com is signed with a key its DS record doesn't match, and
+    /// hashed.com is signed with NSEC3. Returns the port every server listens on and the root trust anchor.
+    async fn start_signed_network() -> Result<(u16, Record), Box<dyn error::Error + Send + Sync>> {
+        let [root_key, com_key, example_key, badkey_key, hashed_key, other_key] =
+            [(); 6].map(|_| TestKey::generate());
+        let root = format!(
+            "
+. 3600 SOA a.root-servers.test. hostmaster.root-servers.test. 1 1800 900 604800 300
+. 3600 NS a.root-servers.test.
+a.root-servers.test. 3600 A 127.0.0.2
+com. 3600 NS a.gtld-servers.test.
+a.gtld-servers.test. 3600 A 127.0.0.3
+{}
+",
+            com_key.ds("com")
+        );
+        let com = format!(
+            "
+com. 3600 SOA a.gtld-servers.test. hostmaster.com. 1 1800 900 604800 300
+com. 3600 NS a.gtld-servers.test.
+example.com. 3600 NS ns.example.com.
+ns.example.com. 3600 A 127.0.0.4
+{}
+insecure.com. 3600 NS ns.insecure.com.
+ns.insecure.com. 3600 A 127.0.0.5
+badkey.com. 3600 NS ns.badkey.com.
+ns.badkey.com. 3600 A 127.0.0.6
+{}
+hashed.com. 3600 NS ns.hashed.com.
+ns.hashed.com. 3600 A 127.0.0.7
+{}
+",
+            example_key.ds("example.com"),
+            other_key.ds("badkey.com"),
+            hashed_key.ds("hashed.com")
+        );
+        let example = "
+example.com. 3600 SOA ns.example.com. hostmaster.example.com. 1 1800 900 604800 300
+example.com. 3600 NS ns.example.com.
+ns.example.com. 3600 A 127.0.0.4
+www.example.com. 3600 A 192.0.2.1
+alias.example.com. 3600 CNAME www.insecure.com.
+tampered.example.com. 3600 A 192.0.2.2
+expired.example.com. 3600 A 192.0.2.3
+*.wild.example.com. 3600 A 192.0.2.4
+";
+        let insecure = "
+insecure.com. 3600 SOA ns.insecure.com. hostmaster.insecure.com. 1 1800 900 604800 300
+insecure.com. 3600 NS ns.insecure.com.
+www.insecure.com. 3600 A 192.0.2.10
+";
+        let badkey = "
+badkey.com. 3600 SOA ns.badkey.com. hostmaster.badkey.com. 1 1800 900 604800 300
+badkey.com. 3600 NS ns.badkey.com.
+www.badkey.com. 3600 A 192.0.2.20
+";
+        let hashed = "
+hashed.com. 3600 SOA ns.hashed.com. hostmaster.hashed.com. 1 1800 900 604800 300
+hashed.com. 3600 NS ns.hashed.com.
+ns.hashed.com. 3600 A 127.0.0.7
+www.hashed.com. 3600 A 192.0.2.30
+www.deep.hashed.com. 3600 A 192.0.2.31
+*.wild.hashed.com. 3600 A 192.0.2.32
+";
+
+        let mut example = sign_zone(example, &example_key, nsec_chain);
+        for record in &mut example {
+            if record.name == "tampered.example.com" && record.record_type == RecordType::A {
+                record.data = Data::Addr([6, 6, 6, 6]);
+            }
+        }
+        let expired = records_of(&example, "expired.example.com", RecordType::A);
+        example.retain(|record| {
+            let covers_a = matches!(
+                record.data,
+                Data::Rrsig {
+                    type_covered: RecordType::A,
+                    ..
+                }
+            );
+            record.name != "expired.example.com" || !covers_a
+        });
+        example.push(example_key.sign("example.com", &expired, now() - 7200));
+
+        let zones = [
+            ("127.0.0.2", sign_zone(&root, &root_key, nsec_chain)),
+            ("127.0.0.3", sign_zone(&com, &com_key, nsec_chain)),
+            ("127.0.0.4", example),
+            (
+                "127.0.0.5",
+                zone::parse_zone(insecure, None)?
+                    .records()
+                    .cloned()
+                    .collect(),
+            ),
+            ("127.0.0.6", sign_zone(badkey, &badkey_key, nsec_chain)),
+            ("127.0.0.7", sign_zone(hashed, &hashed_key, nsec3_chain)),
+        ];
+        let mut port = 0;
+        for (ip, records) in zones {
+            let mut catalog = Catalog::new();
+            catalog.insert(into_zone(records));
+            let sock = UdpSocket::bind((ip, port)).await?;
+            port = sock.local_addr()?.port();
+            let server = Server::new(catalog, ServerConfig::default());
+            tokio::spawn(async move { server.serve_udp(sock).await });
+        }
+        let anchor = zone::parse_master_file(&root_key.ds("."), None)?.remove(0);
+        Ok((port, anchor))
+    }
+
+    async fn resolver() -> Result<Resolver, Box<dyn error::Error + Send + Sync>> {
+        let (port, anchor) = start_signed_network().await?;
+        let root_hints = zone::parse_master_file(
+            ". 3600 NS a.root-servers.test.\na.root-servers.test. 3600 A 127.0.0.2",
+            None,
+        )?;
+        Ok(Resolver::new(ResolverConfig {
+            root_hints,
+            port,
+            timeout: Duration::from_millis(500),
+            trust_anchors: vec![anchor],
+            ..ResolverConfig::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_validate_secure_answers() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+
+        let resolution = resolver.resolve("www.example.com", RecordType::A).await?;
+        assert_eq!(resolution.status, ValidationStatus::Secure);
+        assert!(resolution
+            .answers
+            .iter()
+            .any(|record| record.record_type == RecordType::Rrsig));
+
+        // NODATA is proven by the NSEC record at the name
+        let resolution = resolver.resolve("www.example.com", RecordType::Mx).await?;
+        assert!(resolution.answers.is_empty());
+        assert_eq!(resolution.status, ValidationStatus::Secure);
+
+        // Answered from the cache this time
+        let resolution = resolver.resolve("www.example.com", RecordType::A).await?;
+        assert_eq!(resolution.status, ValidationStatus::Secure);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_denial_of_existence() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let resolver = resolver().await?;
+        for zone in ["example.com", "hashed.com"] {
+            let resolution = resolver
+                .resolve(&format!("nope.{}", zone), RecordType::A)
+                .await?;
+            assert_eq!(resolution.rcode, ResponseCode::NameError);
+            assert_eq!(resolution.status, ValidationStatus::Secure, "{}", zone);
+
+            let wildcard = format!("anything.wild.{}", zone);
+            let resolution = resolver.resolve(&wildcard, RecordType::A).await?;
+            assert_eq!(resolution.answers[0].name, wildcard);
+            assert_eq!(resolution.status, ValidationStatus::Secure, "{}", zone);
+
+            // The wildcard exists, but has no MX records
+            let resolution = resolver.resolve(&wildcard, RecordType::Mx).await?;
+            assert!(resolution.answers.is_empty());
+            assert_eq!(resolution.status, ValidationStatus::Secure, "{}", zone);
+
+            // The wildcard answer again, from the cache with its proof
+            let resolution = resolver.resolve(&wildcard, RecordType::A).await?;
+            assert_eq!(resolution.status, ValidationStatus::Secure, "{}", zone);
+        }
+
+        // An empty non-terminal
+        let resolution = resolver.resolve("deep.hashed.com", RecordType::A).await?;
+        assert_eq!(resolution.rcode, ResponseCode::Success);
+        assert_eq!(resolution.status, ValidationStatus::Secure);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_missing_denials() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+        let validate = |name: &str, resolution: Resolution| {
+            let resolver = &resolver;
+            let name = name.to_string();
+            async move {
+                Validator::new(resolver, Budget::new(100), SystemTime::now())
+                    .validate(&name, RecordType::A, &resolution)
+                    .await
+            }
+        };
+        for name in ["nope.example.com", "nope.hashed.com"] {
+            let mut resolution = resolver.resolve(name, RecordType::A).await?;
+            resolution.authority.retain(|record| {
+                !matches!(
+                    &record.data,
+                    Data::Nsec { .. }
+                        | Data::Nsec3 { .. }
+                        | Data::Rrsig {
+                            type_covered: RecordType::Nsec | RecordType::Nsec3,
+                            ..
+                        }
+                )
+            });
+            assert_eq!(validate(name, resolution).await, ValidationStatus::Bogus);
+        }
+        for name in ["anything.wild.example.com", "anything.wild.hashed.com"] {
+            let mut resolution = resolver.resolve(name, RecordType::A).await?;
+            resolution.authority.clear();
+            assert_eq!(validate(name, resolution).await, ValidationStatus::Bogus);
+        }
+
+        // A proof with a broken signature counts for nothing
+        let mut resolution = resolver.resolve("nope.example.com", RecordType::A).await?;
+        for record in &mut resolution.authority {
+            if let Data::Nsec { next_name, .. } = &mut record.data {
+                *next_name = "zzz.example.com".to_string();
+            }
+        }
+        let status = validate("nope.example.com", resolution).await;
+        assert_eq!(status, ValidationStatus::Bogus);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_insecure_delegations() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let resolver = resolver().await?;
+
+        let resolution = resolver.resolve("www.insecure.com", RecordType::A).await?;
+        assert_eq!(resolution.answers.len(), 1);
+        assert_eq!(resolution.status, ValidationStatus::Insecure);
+
+        // A signed CNAME pointing into an unsigned zone
+        let resolution = resolver.resolve("alias.example.com", RecordType::A).await?;
+        assert_eq!(resolution.status, ValidationStatus::Insecure);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_bogus_answers() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+        for name in [
+            "tampered.example.com",
+            "expired.example.com",
+            "www.badkey.com",
+        ] {
+            let resolution = resolver.resolve(name, RecordType::A).await?;
+            assert_eq!(resolution.status, ValidationStatus::Bogus, "{}", name);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_sets_ad_bit_and_rejects_bogus_answers(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let resolver = resolver().await?;
+        let ctx = RequestContext {
+            peer: "127.0.0.1:5353".parse()?,
+            protocol: Protocol::Udp,
+            tsig_key: None,
+        };
+        let query = |name: &str, dnssec_ok: bool, reserved: u8| {
+            let mut request = dns::packet::build_query(1, name, &RecordType::A);
+            request.header.reserved = reserved;
+            let edns = dns::edns::Edns {
+                dnssec_ok,
+                ..dns::edns::Edns::default()
+            };
+            dns::edns::set_edns(&mut request, &edns);
+            request
+        };
+        let has_ad = |response: &dns::packet::DnsPacket| {
+            response.header.reserved & header::AUTHENTIC_DATA != 0
+        };
+
+        let response = resolver
+            .handle(&query("www.example.com", true, 0), &ctx)
+            .await;
+        assert!(has_ad(&response));
+        assert_eq!(response.answers.len(), 2);
+
+        // Clients that don't ask for DNSSEC get neither the AD bit nor the
+        // signatures
+        let response = resolver
+            .handle(&query("www.example.com", false, 0), &ctx)
+            .await;
+        assert!(!has_ad(&response));
+        assert_eq!(response.answers.len(), 1);
+        let response = resolver
+            .handle(
+                &query("www.example.com", false, header::AUTHENTIC_DATA),
+                &ctx,
+            )
+            .await;
+        assert!(has_ad(&response));
+        assert_eq!(response.answers.len(), 1);
+
+        let response = resolver
+            .handle(&query("www.insecure.com", true, 0), &ctx)
+            .await;
+        assert!(!has_ad(&response));
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+
+        let response = resolver
+            .handle(&query("tampered.example.com", true, 0), &ctx)
+            .await;
+        assert_eq!(response.header.rcode, ResponseCode::ServerFailure);
+        assert!(response.answers.is_empty());
+
+        // With CD set, the client gets the data and checks it itself
+        let response = resolver
+            .handle(
+                &query("tampered.example.com", true, header::CHECKING_DISABLED),
+                &ctx,
+            )
+            .await;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert!(!has_ad(&response));
+        assert_eq!(response.answers[0].data, Data::Addr([6, 6, 6, 6]));
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use super::handler::RequestContext;
+use anyhow::anyhow;
+use dns::name;
+use dns::record::{self, RecordType};
+use dns::update::Update;
+use std::error;
+use std::net::IpAddr;
+
+/// Decides who may make requests that aren't open to everyone, such as zone
+/// transfers. An empty list allows nobody.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    rules: Vec<Rule>,
+}
+
+/// A single entry in an access control list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Every client.
+    Any,
+    /// Clients with an address in this network.
+    Network { addr: IpAddr, prefix_len: u8 },
+    /// Requests signed with the TSIG key of this name.
+    Key(String),
+}
+
+impl Rule {
+    /// Parse a rule given as `any`, `key:<name>`, an address, or a network
+    /// like 10.0.0.0/8.
+    pub fn parse(value: &str) -> Result<Rule, Box<dyn error::Error + Send + Sync>> {
+        if value == "any" {
+            return Ok(Rule::Any);
+        }
+        if let Some(key) = value.strip_prefix("key:") {
+            return Ok(Rule::Key(dns::name::normalize(key)));
+        }
+        let (addr, prefix_len) = match value.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("invalid address {} in ACL", addr))?;
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| anyhow!("invalid prefix length in {}", value))?,
+            None => max_len,
+        };
+        Ok(Rule::Network { addr, prefix_len })
+    }
+
+    fn allows(&self, ctx: &RequestContext) -> bool {
+        match self {
+            Rule::Any => true,
+            Rule::Network { addr, prefix_len } => {
+                in_network(ctx.peer.ip().to_canonical(), *addr, *prefix_len)
+            }
+            Rule::Key(name) => ctx
+                .tsig_key
+                .as_ref()
+                .is_some_and(|key| dns::name::eq(key, name)),
+        }
+    }
+}
+
+impl Acl {
+    pub fn new(rules: Vec<Rule>) -> Acl {
+        Acl { rules }
+    }
+
+    /// Parse a list of rules in the form accepted by `Rule::parse`.
+    pub fn parse(values: &[String]) -> Result<Acl, Box<dyn error::Error + Send + Sync>> {
+        let rules = values
+            .iter()
+            .map(|value| Rule::parse(value))
+            .collect::<Result<_, _>>()?;
+        Ok(Acl::new(rules))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns true if any rule matches the request.
+    pub fn allows(&self, ctx: &RequestContext) -> bool {
+        self.rules.iter().any(|rule| rule.allows(ctx))
+    }
+}
+
+/// Decides which records clients may change with UPDATE. Each grant lets
+/// requests signed with one TSIG key change some names, so unsigned updates
+/// are never allowed. An empty policy allows nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdatePolicy {
+    grants: Vec<Grant>,
+}
+
+/// Lets requests signed with a key change records of some types at some
+/// names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub key: String,
+    pub scope: Scope,
+    /// The types of record that may change. Empty allows every type.
+    pub types: Vec<RecordType>,
+}
+
+/// The names a grant covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// Any name in the zone being updated.
+    Zone,
+    /// Exactly this name.
+    Name(String),
+    /// This name and the names underneath it.
+    Subdomain(String),
+    /// The name of the key itself, as hosts use to update their own records.
+    SelfName,
+}
+
+impl Grant {
+    /// Parse a grant given as `<key> <scope> [types]`, where the scope is
+    /// `zone`, `self`, `name:<name>` or `subdomain:<name>`, and the types are
+    /// separated by commas, e.g. `dhcp subdomain:dhcp.example.com A,AAAA`.
+    pub fn parse(value: &str) -> Result<Grant, Box<dyn error::Error + Send + Sync>> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        let (key, scope, types) = match fields[..] {
+            [key, scope] => (key, scope, None),
+            [key, scope, types] => (key, scope, Some(types)),
+            _ => return Err(anyhow!("expected <key> <scope> [types], got {:?}", value).into()),
+        };
+        let scope = match scope.split_once(':') {
+            None if scope == "zone" => Scope::Zone,
+            None if scope == "self" => Scope::SelfName,
+            Some(("name", name)) => Scope::Name(name::normalize(name)),
+            Some(("subdomain", name)) => Scope::Subdomain(name::normalize(name)),
+            _ => return Err(anyhow!("invalid update scope {}", scope).into()),
+        };
+        let types = match types {
+            Some(types) => types
+                .split(',')
+                .map(|name| {
+                    record::parse_record_type_name(name)
+                        .ok_or_else(|| anyhow!("unknown record type {}", name))
+                })
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        Ok(Grant {
+            key: name::normalize(key),
+            scope,
+            types,
+        })
+    }
+
+    fn allows(&self, key: &str, name: &str, record_type: RecordType) -> bool {
+        let in_scope = match &self.scope {
+            Scope::Zone => true,
+            Scope::Name(scope) => name::eq(name, scope),
+            Scope::Subdomain(scope) => name::is_subdomain(name, scope),
+            Scope::SelfName => name::eq(name, key),
+        };
+        name::eq(key, &self.key)
+            && in_scope
+            && (self.types.is_empty() || self.types.contains(&record_type))
+    }
+}
+
+impl UpdatePolicy {
+    pub fn new(grants: Vec<Grant>) -> UpdatePolicy {
+        UpdatePolicy { grants }
+    }
+
+    /// Parse a list of grants in the form accepted by `Grant::parse`.
+    pub fn parse(values: &[String]) -> Result<UpdatePolicy, Box<dyn error::Error + Send + Sync>> {
+        let grants = values
+            .iter()
+            .map(|value| Grant::parse(value))
+            .collect::<Result<_, _>>()?;
+        Ok(UpdatePolicy::new(grants))
+    }
+
+    pub fn grants(&self) -> &[Grant] {
+        &self.grants
+    }
+
+    /// Returns true if the request was signed, and some grant for its key
+    /// covers every change in the update.
+    pub fn allows(&self, ctx: &RequestContext, update: &Update) -> bool {
+        let key = match &ctx.tsig_key {
+            Some(key) => key,
+            None => return false,
+        };
+        update.changes.iter().all(|change| {
+            self.grants
+                .iter()
+                .any(|grant| grant.allows(key, change.name(), change.record_type()))
+        })
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u32::from(ip) as u128, u32::from(network) as u128, 32)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    let shift = bits - prefix_len as u32;
+    shift >= bits || ip >> shift == network >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::Protocol;
+
+    fn ctx(peer: &str, tsig_key: Option<&str>) -> RequestContext {
+        RequestContext {
+            peer: peer.parse().unwrap(),
+            protocol: Protocol::Tcp,
+            tsig_key: tsig_key.map(|key| key.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_acl_matches_networks_and_keys() {
+        let acl = Acl::parse(&[
+            "10.1.0.0/16".to_string(),
+            "192.0.2.1".to_string(),
+            "2001:db8::/32".to_string(),
+            "key:Transfer.".to_string(),
+        ])
+        .unwrap();
+        assert!(acl.allows(&ctx("10.1.200.3:53", None)));
+        assert!(!acl.allows(&ctx("10.2.0.1:53", None)));
+        assert!(acl.allows(&ctx("192.0.2.1:53", None)));
+        assert!(!acl.allows(&ctx("192.0.2.2:53", None)));
+        assert!(acl.allows(&ctx("[2001:db8::1]:53", None)));
+        assert!(acl.allows(&ctx("[::ffff:10.1.0.1]:53", None)));
+        assert!(acl.allows(&ctx("198.51.100.1:53", Some("transfer"))));
+        assert!(!acl.allows(&ctx("198.51.100.1:53", Some("update"))));
+
+        assert!(!Acl::default().allows(&ctx("127.0.0.1:53", None)));
+        assert!(Acl::new(vec![Rule::Any]).allows(&ctx("127.0.0.1:53", None)));
+        assert!(Acl::parse(&["0.0.0.0/0".to_string()])
+            .unwrap()
+            .allows(&ctx("203.0.113.9:53", None)));
+    }
+
+    #[test]
+    fn test_update_policy_limits_keys_to_their_grants() {
+        let policy = UpdatePolicy::parse(&[
+            "dhcp subdomain:dhcp.example.com A,AAAA".to_string(),
+            "host.example.com self".to_string(),
+            "admin zone".to_string(),
+        ])
+        .unwrap();
+        let update = |changes: &str| {
+            let mut request = dns::packet::build_query(1, "example.com", &RecordType::Soa);
+            request.authoritative_entries =
+                dns::zone::parse_master_file(changes, Some("example.com")).unwrap();
+            Update::parse(&request).unwrap()
+        };
+        let a = update("pc1.dhcp 60 A 192.0.2.1");
+        let txt = update("pc1.dhcp 60 TXT hello");
+        let own = update("host 60 TXT hello\nhost 60 A 192.0.2.2");
+        let mixed = update("pc1.dhcp 60 A 192.0.2.1\nwww 60 A 192.0.2.2");
+
+        assert!(policy.allows(&ctx("192.0.2.1:53", Some("dhcp")), &a));
+        assert!(!policy.allows(&ctx("192.0.2.1:53", Some("dhcp")), &txt));
+        assert!(!policy.allows(&ctx("192.0.2.1:53", Some("dhcp")), &mixed));
+        assert!(!policy.allows(&ctx("192.0.2.1:53", None), &a));
+        assert!(policy.allows(&ctx("192.0.2.1:53", Some("host.example.com")), &own));
+        assert!(!policy.allows(&ctx("192.0.2.1:53", Some("host.example.com")), &a));
+        assert!(policy.allows(&ctx("192.0.2.1:53", Some("admin")), &mixed));
+        assert!(!UpdatePolicy::default().allows(&ctx("192.0.2.1:53", Some("admin")), &a));
+
+        assert!(Grant::parse("dhcp").is_err());
+        assert!(Grant::parse("dhcp everywhere").is_err());
+        assert!(Grant::parse("dhcp zone A,BOGUS").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_rules() {
+        assert!(Rule::parse("10.0.0.0/33").is_err());
+        assert!(Rule::parse("example.com").is_err());
+        assert!(Rule::parse("10.0.0.0/x").is_err());
+    }
+}
@@ -0,0 +1,657 @@
+use super::denial::{self, signatures};
+use super::response;
+use super::transfer;
+use dns::dnssec;
+use dns::edns;
+use dns::header::{Opcode, ResponseCode};
+use dns::journal::{Diff, Journal};
+use dns::name;
+use dns::packet::DnsPacket;
+use dns::record::{self, Class, Data, Record, RecordType};
+use dns::zone::Zone;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// How many CNAMEs we are willing to follow when answering a single query.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// A set of zones that we answer authoritatively for.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    zones: Vec<Zone>,
+    /// Recent changes to each zone, keyed by the canonical form of its
+    /// origin, for answering IXFR requests.
+    journals: BTreeMap<Vec<u8>, Journal>,
+    /// Signalled whenever a zone is added, changed or removed.
+    changed: Arc<Notify>,
+}
+
+impl Catalog {
+    pub fn new() -> Catalog {
+        Catalog::default()
+    }
+
+    /// Add a zone to the catalog, replacing any zone with the same origin.
+    /// Replacing a zone with a newer version of it, with a higher serial,
+    /// records the changes in the zone's journal.
+    pub fn insert(&mut self, zone: Zone) {
+        if let Some(old) = self.zones.iter().find(|old| old.origin == zone.origin) {
+            if *old == zone {
+                return;
+            }
+            let journal = self
+                .journals
+                .entry(name::canonical_key(&zone.origin))
+                .or_default();
+            let newer = match (old.serial(), zone.serial()) {
+                (Some(old), Some(new)) => dnssec::time_before(old, new),
+                _ => false,
+            };
+            match Diff::between(old, &zone) {
+                Some(diff) if newer => journal.push(diff),
+                _ => journal.clear(),
+            }
+        }
+        self.zones.retain(|existing| existing.origin != zone.origin);
+        self.zones.push(zone);
+        self.changed.notify_one();
+    }
+
+    /// Like `insert`, but only if the zone being replaced still has the
+    /// given serial, so that changes made to it since the new version was
+    /// built from it aren't lost. Returns false if it has moved on.
+    pub fn replace(&mut self, zone: Zone, serial: Option<u32>) -> bool {
+        let current = self
+            .zones
+            .iter()
+            .find(|old| old.origin == zone.origin)
+            .map(Zone::serial);
+        if current != Some(serial) {
+            return false;
+        }
+        self.insert(zone);
+        true
+    }
+
+    /// Stop serving a zone, forgetting its journal.
+    pub fn remove(&mut self, origin: &str) -> Option<Zone> {
+        let index = self
+            .zones
+            .iter()
+            .position(|zone| name::eq(&zone.origin, origin))?;
+        self.journals.remove(&name::canonical_key(origin));
+        self.changed.notify_one();
+        Some(self.zones.remove(index))
+    }
+
+    /// Signalled whenever the zones in the catalog change. Only one task
+    /// should wait on it, and it may be woken for changes it has already
+    /// seen.
+    pub fn changed(&self) -> Arc<Notify> {
+        self.changed.clone()
+    }
+
+    /// The recent changes to a zone, if it has changed since it was loaded.
+    pub fn journal(&self, origin: &str) -> Option<&Journal> {
+        self.journals.get(&name::canonical_key(origin))
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// Find the most specific zone that a name belongs to.
+    pub fn find_zone(&self, name: &str) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| name::is_subdomain(name, &zone.origin))
+            .max_by_key(|zone| name::labels(&zone.origin).len())
+    }
+
+    /// Answer a query from the zones in the catalog.
+    pub fn answer(&self, request: &DnsPacket) -> DnsPacket {
+        let mut response = response::response_to(request);
+        if request.header.opcode != Opcode::Query {
+            response.header.rcode = ResponseCode::NotImplemented;
+            return response;
+        }
+        if request.questions.len() != 1 {
+            response.header.rcode = ResponseCode::FormatError;
+            return response;
+        }
+        let question = &request.questions[0];
+        let zone = match self.find_zone(&question.name) {
+            Some(zone) if record::parse_class(question.class) == Class::In => zone,
+            _ => {
+                response.header.rcode = ResponseCode::Refused;
+                return response;
+            }
+        };
+        let record_type = record::parse_record_type(question.typ);
+        // DS records live in the parent zone, on the other side of the cut
+        let zone = match name::parent(&question.name) {
+            Some(parent)
+                if record_type == RecordType::Ds && name::eq(&zone.origin, &question.name) =>
+            {
+                match self.find_zone(&parent) {
+                    Some(parent) => parent,
+                    None => zone,
+                }
+            }
+            _ => zone,
+        };
+        response.header.authoritative_answer = true;
+        let dnssec_ok = edns::parse_edns(request).is_some_and(|edns| edns.dnssec_ok);
+        self.resolve(zone, &question.name, record_type, dnssec_ok, &mut response);
+        self.add_additional_records(&mut response);
+        response
+    }
+
+    /// Answer a zone transfer request. Its question has to name one of the
+    /// zones in the catalog exactly.
+    pub fn transfer(&self, request: &DnsPacket) -> Vec<DnsPacket> {
+        let question = match &request.questions[..] {
+            [question] => question,
+            _ => return vec![response::error_response(request, ResponseCode::FormatError)],
+        };
+        let zone = self.zones.iter().find(|zone| {
+            name::eq(&zone.origin, &question.name)
+                && record::parse_class(question.class) == Class::In
+        });
+        let zone = match zone {
+            Some(zone) => zone,
+            None => return vec![response::error_response(request, ResponseCode::NotAuth)],
+        };
+        match record::parse_record_type(question.typ) {
+            RecordType::Ixfr => transfer::ixfr(zone, self.journal(&zone.origin), request),
+            _ => transfer::axfr(zone, request),
+        }
+    }
+
+    fn resolve(
+        &self,
+        zone: &Zone,
+        qname: &str,
+        qtype: RecordType,
+        dnssec_ok: bool,
+        response: &mut DnsPacket,
+    ) {
+        let mut zone = zone;
+        let mut qname = qname.to_string();
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(cut) = find_delegation(zone, &qname, &qtype) {
+                // Only the first name in a CNAME chain decides whether the
+                // answer is authoritative
+                if response.answers.is_empty() {
+                    response.header.authoritative_answer = false;
+                }
+                response
+                    .authoritative_entries
+                    .extend(zone.rrset(&cut, RecordType::Ns).into_iter().cloned());
+                if dnssec_ok {
+                    response
+                        .authoritative_entries
+                        .extend(denial::delegation(zone, &cut));
+                }
+                return;
+            }
+
+            // The closest encloser of the wildcard the records came from, if
+            // they were synthesised
+            let (records, wildcard) = if zone.contains_name(&qname) {
+                (zone.lookup(&qname).to_vec(), None)
+            } else {
+                match find_wildcard(zone, &qname) {
+                    Some((records, closest_encloser)) => (records, Some(closest_encloser)),
+                    None => {
+                        response.header.rcode = ResponseCode::NameError;
+                        response
+                            .authoritative_entries
+                            .extend(negative_soa(zone, dnssec_ok));
+                        if dnssec_ok {
+                            response
+                                .authoritative_entries
+                                .extend(denial::name_error(zone, &qname));
+                        }
+                        return;
+                    }
+                }
+            };
+            let wildcard_proof = match (&wildcard, dnssec_ok) {
+                (Some(closest_encloser), true) => {
+                    denial::wildcard_answer(zone, &qname, closest_encloser)
+                }
+                _ => vec![],
+            };
+
+            let matching: Vec<Record> = records
+                .iter()
+                .filter(|record| qtype == RecordType::Any || record.record_type == qtype)
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                response.answers.extend(matching);
+                if dnssec_ok && qtype != RecordType::Any && qtype != RecordType::Rrsig {
+                    response.answers.extend(signatures(&records, &qtype));
+                }
+                response.authoritative_entries.extend(wildcard_proof);
+                return;
+            }
+
+            let cname = records.iter().find_map(|record| match &record.data {
+                Data::Cname(target) => Some((record.clone(), target.clone())),
+                _ => None,
+            });
+            let (cname, target) = match cname {
+                Some(cname) => cname,
+                None => {
+                    response
+                        .authoritative_entries
+                        .extend(negative_soa(zone, dnssec_ok));
+                    if dnssec_ok {
+                        let proof = match &wildcard {
+                            Some(closest_encloser) => {
+                                denial::wildcard_no_data(zone, &qname, closest_encloser)
+                            }
+                            None => denial::no_data(zone, &qname),
+                        };
+                        response.authoritative_entries.extend(proof);
+                    }
+                    return;
+                }
+            };
+            response.answers.push(cname);
+            if dnssec_ok {
+                response
+                    .answers
+                    .extend(signatures(&records, &RecordType::Cname));
+            }
+            response.authoritative_entries.extend(wildcard_proof);
+
+            // Keep following the chain if the target is in one of our zones,
+            // otherwise the client has to resolve the rest itself
+            zone = match self.find_zone(&target) {
+                Some(zone) => zone,
+                None => return,
+            };
+            qname = target;
+        }
+    }
+
+    /// Add address records for the names that NS, MX and SRV records point
+    /// at, so the client doesn't need to look them up separately.
+    fn add_additional_records(&self, response: &mut DnsPacket) {
+        let targets: Vec<String> = response
+            .answers
+            .iter()
+            .chain(response.authoritative_entries.iter())
+            .filter(|record| {
+                matches!(
+                    record.record_type,
+                    RecordType::Ns | RecordType::Mx | RecordType::Srv
+                )
+            })
+            .filter_map(|record| record::target_name(&record.data))
+            .map(|target| target.to_string())
+            .collect();
+        for target in targets {
+            let zone = match self.find_zone(&target) {
+                Some(zone) => zone,
+                None => continue,
+            };
+            for record in zone.lookup(&target) {
+                let is_address = matches!(record.record_type, RecordType::A | RecordType::Aaaa);
+                let is_duplicate =
+                    response.answers.contains(record) || response.resource_entries.contains(record);
+                if is_address && !is_duplicate {
+                    response.resource_entries.push(record.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Find the zone cut between the zone apex and the name, if there is one.
+/// Queries for DS records at a cut are answered from this side of it.
+fn find_delegation(zone: &Zone, qname: &str, qtype: &RecordType) -> Option<String> {
+    let mut ancestors = vec![];
+    let mut current = name::normalize(qname);
+    if *qtype == RecordType::Ds && current != zone.origin {
+        current = name::parent(&current)?;
+    }
+    while current != zone.origin {
+        ancestors.push(current.clone());
+        current = name::parent(&current)?;
+    }
+    ancestors
+        .into_iter()
+        .rev()
+        .find(|ancestor| !zone.rrset(ancestor, RecordType::Ns).is_empty())
+}
+
+/// Synthesise records from a wildcard at the closest encloser of a name that
+/// doesn't exist (RFC 4592). Returns the records and the closest encloser.
+fn find_wildcard(zone: &Zone, qname: &str) -> Option<(Vec<Record>, String)> {
+    let mut closest_encloser = name::parent(qname)?;
+    while !zone.contains_name(&closest_encloser) {
+        closest_encloser = name::parent(&closest_encloser)?;
+    }
+    let wildcard = zone.lookup(&name::child("*", &closest_encloser));
+    if wildcard.is_empty() {
+        return None;
+    }
+    let records = wildcard
+        .iter()
+        .map(|record| Record {
+            name: qname.to_string(),
+            ..record.clone()
+        })
+        .collect();
+    Some((records, closest_encloser))
+}
+
+/// The SOA record to include in negative answers, along with its signatures
+/// if the client wants them. Its TTL is the smaller of the SOA's own TTL and
+/// its minimum field (RFC 2308 §3).
+fn negative_soa(zone: &Zone, dnssec_ok: bool) -> Vec<Record> {
+    let soa = match zone.soa() {
+        Some(soa) => soa,
+        None => return vec![],
+    };
+    let ttl = match soa.data {
+        Data::Soa { minimum, .. } => soa.ttl.min(minimum.min(i32::MAX as u32) as i32),
+        _ => soa.ttl,
+    };
+    let mut records = vec![Record { ttl, ..soa.clone() }];
+    if dnssec_ok {
+        records.extend(
+            signatures(zone.lookup(&zone.origin), &RecordType::Soa)
+                .into_iter()
+                .map(|rrsig| Record { ttl, ..rrsig }),
+        );
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::header::Header;
+    use dns::question::Question;
+    use dns::zone;
+
+    const EXAMPLE_ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 3600
+@           SOA     ns1 hostmaster 1 7200 900 1209600 300
+            NS      ns1
+            MX      10 mail
+ns1         A       192.0.2.1
+mail        A       192.0.2.2
+            AAAA    2001:db8::2
+www         CNAME   web
+web         A       192.0.2.3
+external    CNAME   www.example.net.
+*.wild      TXT     "wildcard"
+a.b.c       A       192.0.2.4
+sub         NS      ns1.sub
+            NS      ns.elsewhere.net.
+ns1.sub     A       192.0.2.5
+"#;
+
+    const OTHER_ZONE: &str = r#"
+$ORIGIN example.org.
+@           3600 SOA ns1.example.com. hostmaster.example.com. 1 7200 900 1209600 300
+alias       3600 CNAME www.example.com.
+"#;
+
+    fn catalog() -> Catalog {
+        let mut catalog = Catalog::new();
+        catalog.insert(zone::parse_zone(EXAMPLE_ZONE, None).unwrap());
+        catalog.insert(zone::parse_zone(OTHER_ZONE, None).unwrap());
+        catalog
+    }
+
+    fn query(name: &str, record_type: RecordType) -> DnsPacket {
+        DnsPacket {
+            header: Header {
+                id: 1234,
+                query: true,
+                opcode: Opcode::Query,
+                authoritative_answer: false,
+                truncation: false,
+                recursion_desired: true,
+                recursion_available: false,
+                reserved: 0,
+                rcode: ResponseCode::Success,
+                questions: 1,
+                answers: 0,
+                authoritative_entries: 0,
+                resource_entries: 0,
+            },
+            questions: vec![Question {
+                name: name.to_string(),
+                typ: record::record_type_code(&record_type),
+                class: 1,
+            }],
+            answers: vec![],
+            authoritative_entries: vec![],
+            resource_entries: vec![],
+        }
+    }
+
+    fn types(records: &[Record]) -> Vec<RecordType> {
+        records.iter().map(|record| record.record_type).collect()
+    }
+
+    #[test]
+    fn test_answer_returns_matching_records() {
+        let response = catalog().answer(&query("web.example.com", RecordType::A));
+        assert_eq!(response.header.id, 1234);
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data, Data::Addr([192, 0, 2, 3]));
+    }
+
+    #[test]
+    fn test_answer_returns_nxdomain_with_soa() {
+        let response = catalog().answer(&query("missing.example.com", RecordType::A));
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.header.rcode, ResponseCode::NameError);
+        assert!(response.answers.is_empty());
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![RecordType::Soa]
+        );
+        assert_eq!(response.authoritative_entries[0].ttl, 300);
+    }
+
+    #[test]
+    fn test_answer_returns_nodata_with_soa() {
+        let response = catalog().answer(&query("web.example.com", RecordType::Aaaa));
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert!(response.answers.is_empty());
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![RecordType::Soa]
+        );
+
+        // Empty non-terminals exist, so they get NODATA rather than NXDOMAIN
+        let response = catalog().answer(&query("b.c.example.com", RecordType::A));
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![RecordType::Soa]
+        );
+    }
+
+    #[test]
+    fn test_answer_follows_cnames_across_zones() {
+        let response = catalog().answer(&query("alias.example.org", RecordType::A));
+        assert_eq!(
+            types(&response.answers),
+            vec![RecordType::Cname, RecordType::Cname, RecordType::A]
+        );
+
+        // Chains that leave our zones are returned as far as we know them
+        let response = catalog().answer(&query("external.example.com", RecordType::A));
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(types(&response.answers), vec![RecordType::Cname]);
+    }
+
+    #[test]
+    fn test_answer_synthesises_wildcards() {
+        let response = catalog().answer(&query("anything.wild.example.com", RecordType::Txt));
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].name, "anything.wild.example.com");
+
+        // Wildcards don't apply to names that exist
+        let response = catalog().answer(&query("wild.example.com", RecordType::Txt));
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_answer_returns_referrals_for_delegations() {
+        let response = catalog().answer(&query("host.sub.example.com", RecordType::A));
+        assert!(!response.header.authoritative_answer);
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert!(response.answers.is_empty());
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![RecordType::Ns, RecordType::Ns]
+        );
+        // Glue is included for the in-zone nameserver only
+        assert_eq!(response.resource_entries.len(), 1);
+        assert_eq!(response.resource_entries[0].name, "ns1.sub.example.com");
+    }
+
+    #[test]
+    fn test_answer_adds_additional_records_for_targets() {
+        let response = catalog().answer(&query("example.com", RecordType::Mx));
+        assert_eq!(types(&response.answers), vec![RecordType::Mx]);
+        assert_eq!(
+            types(&response.resource_entries),
+            vec![RecordType::A, RecordType::Aaaa]
+        );
+    }
+
+    #[test]
+    fn test_answer_refuses_names_outside_of_zones() {
+        let response = catalog().answer(&query("example.net", RecordType::A));
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        assert!(!response.header.authoritative_answer);
+    }
+
+    #[test]
+    fn test_answer_prefers_most_specific_zone() {
+        let mut catalog = catalog();
+        let child = "@ 60 SOA ns1 hostmaster 1 2 3 4 5\nhost 60 A 192.0.2.9";
+        catalog.insert(zone::parse_zone(child, Some("sub.example.com")).unwrap());
+        let response = catalog.answer(&query("host.sub.example.com", RecordType::A));
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.answers[0].data, Data::Addr([192, 0, 2, 9]));
+    }
+
+    /// A zone with placeholder signatures, which is enough to check that the
+    /// right records are returned.
+    const SIGNED_ZONE: &str = r#"
+$ORIGIN example.net.
+$TTL 3600
+@           SOA     ns1 hostmaster 1 7200 900 1209600 300
+            RRSIG   SOA 13 2 3600 20300101000000 20200101000000 1 example.net. AAAA
+            NS      ns1
+            NSEC    ns1 SOA NS RRSIG NSEC
+ns1         A       192.0.2.1
+            RRSIG   A 13 3 3600 20300101000000 20200101000000 1 example.net. AAAA
+            NSEC    secure A RRSIG NSEC
+            RRSIG   NSEC 13 3 3600 20300101000000 20200101000000 1 example.net. AAAA
+secure      NS      ns1.secure
+            DS      1 13 2 AAAA
+            RRSIG   DS 13 3 3600 20300101000000 20200101000000 1 example.net. AAAA
+insecure    NS      ns1.insecure
+            NSEC    example.net. NS RRSIG NSEC
+            RRSIG   NSEC 13 3 3600 20300101000000 20200101000000 1 example.net. AAAA
+"#;
+
+    fn dnssec_query(name: &str, record_type: RecordType) -> DnsPacket {
+        let mut request = query(name, record_type);
+        let edns = dns::edns::Edns {
+            dnssec_ok: true,
+            ..dns::edns::Edns::default()
+        };
+        edns::set_edns(&mut request, &edns);
+        request
+    }
+
+    #[test]
+    fn test_answer_includes_dnssec_records_when_asked() {
+        let mut catalog = Catalog::new();
+        catalog.insert(zone::parse_zone(SIGNED_ZONE, None).unwrap());
+
+        let response = catalog.answer(&query("ns1.example.net", RecordType::A));
+        assert_eq!(types(&response.answers), vec![RecordType::A]);
+
+        let response = catalog.answer(&dnssec_query("ns1.example.net", RecordType::A));
+        assert_eq!(
+            types(&response.answers),
+            vec![RecordType::A, RecordType::Rrsig]
+        );
+
+        let response = catalog.answer(&dnssec_query("ns1.example.net", RecordType::Txt));
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![
+                RecordType::Soa,
+                RecordType::Rrsig,
+                RecordType::Nsec,
+                RecordType::Rrsig
+            ]
+        );
+
+        // The name and the wildcard at the apex are both covered
+        let response = catalog.answer(&dnssec_query("zzz.example.net", RecordType::A));
+        assert_eq!(response.header.rcode, ResponseCode::NameError);
+        let nsec_owners: Vec<&str> = response
+            .authoritative_entries
+            .iter()
+            .filter(|record| record.record_type == RecordType::Nsec)
+            .map(|record| record.name.as_str())
+            .collect();
+        assert_eq!(nsec_owners, vec!["insecure.example.net", "example.net"]);
+
+        let response = catalog.answer(&dnssec_query("www.secure.example.net", RecordType::A));
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![RecordType::Ns, RecordType::Ds, RecordType::Rrsig]
+        );
+        let response = catalog.answer(&dnssec_query("www.insecure.example.net", RecordType::A));
+        assert_eq!(
+            types(&response.authoritative_entries),
+            vec![RecordType::Ns, RecordType::Nsec, RecordType::Rrsig]
+        );
+
+        // DS queries are answered by the parent, not referred to the child
+        let response = catalog.answer(&dnssec_query("secure.example.net", RecordType::Ds));
+        assert!(response.header.authoritative_answer);
+        assert_eq!(
+            types(&response.answers),
+            vec![RecordType::Ds, RecordType::Rrsig]
+        );
+    }
+
+    #[test]
+    fn test_replace_keeps_zones_that_changed() {
+        let mut catalog = catalog();
+        let mut zone = catalog.zones()[0].clone();
+        zone.increment_serial();
+        let mut newer = zone.clone();
+        newer.increment_serial();
+        assert!(catalog.replace(zone, Some(1)));
+        assert!(!catalog.replace(newer.clone(), Some(1)));
+        assert_eq!(catalog.zones().last().unwrap().serial(), Some(2));
+        assert!(catalog.replace(newer, Some(2)));
+        assert_eq!(catalog.journal("example.com").unwrap().len(), 2);
+    }
+}
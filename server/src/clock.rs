@@ -0,0 +1,86 @@
+use async_trait::async_trait;
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+
+/// Where timers get the time from, so that tests can move it forward
+/// instead of waiting.
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Wait until the clock reaches `deadline`.
+    async fn sleep_until(&self, deadline: SystemTime);
+}
+
+/// The real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep_until(&self, deadline: SystemTime) {
+        let wait = deadline.duration_since(self.now()).unwrap_or_default();
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+pub struct VirtualClock {
+    now: watch::Sender<SystemTime>,
+}
+
+impl VirtualClock {
+    pub fn new(start: SystemTime) -> VirtualClock {
+        VirtualClock {
+            now: watch::channel(start).0,
+        }
+    }
+
+    /// Move the clock forward, waking anything whose deadline has passed.
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+#[async_trait]
+impl Clock for VirtualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.borrow()
+    }
+
+    async fn sleep_until(&self, deadline: SystemTime) {
+        let mut now = self.now.subscribe();
+        while *now.borrow_and_update() < deadline {
+            if now.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_virtual_clock_wakes_sleepers_when_advanced() {
+        let clock = Arc::new(VirtualClock::new(SystemTime::UNIX_EPOCH));
+        let deadline = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        let sleeper = tokio::spawn({
+            let clock = clock.clone();
+            async move { clock.sleep_until(deadline).await }
+        });
+        clock.advance(Duration::from_secs(59));
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+        clock.advance(Duration::from_secs(1));
+        sleeper.await.unwrap();
+        assert_eq!(clock.now(), deadline);
+    }
+}
@@ -0,0 +1,225 @@
+//! Proofs of non-existence for signed zones. Negative answers and wildcard
+//! expansions carry the NSEC or NSEC3 records that show the name or type
+//! asked for isn't in the zone (RFC 4035 §3.1.3, RFC 5155 §7.2).
+
+use dns::dnssec;
+use dns::name;
+use dns::record::{Data, Record, RecordType};
+use dns::zone::Zone;
+
+/// The parameters used to hash names in a zone signed with NSEC3.
+struct Nsec3Params {
+    salt: Vec<u8>,
+    iterations: u16,
+}
+
+/// Proof that a name doesn't exist: the closest encloser, and that neither
+/// the next closer name nor the wildcard at the closest encloser exist.
+pub(crate) fn name_error(zone: &Zone, qname: &str) -> Vec<Record> {
+    let closest_encloser = closest_encloser(zone, qname);
+    let wildcard = name::child("*", &closest_encloser);
+    match nsec3_params(zone) {
+        Some(params) => {
+            let mut proof = encloser_proof(zone, &params, qname, &closest_encloser);
+            extend_unique(&mut proof, nsec3_covering(zone, &params, &wildcard));
+            proof
+        }
+        None => {
+            let mut proof = nsec_covering(zone, qname);
+            extend_unique(&mut proof, nsec_covering(zone, &wildcard));
+            proof
+        }
+    }
+}
+
+/// Proof that a name exists but has no records of the type asked for.
+pub(crate) fn no_data(zone: &Zone, qname: &str) -> Vec<Record> {
+    match nsec3_params(zone) {
+        Some(params) => {
+            let proof = nsec3_matching(zone, &params, qname);
+            if !proof.is_empty() {
+                return proof;
+            }
+            // Unsigned delegations in an opt-out span have no NSEC3 record
+            // of their own
+            let closest_encloser = closest_encloser(zone, qname);
+            encloser_proof(zone, &params, qname, &closest_encloser)
+        }
+        None => {
+            let proof = signed_rrset(zone, qname, RecordType::Nsec);
+            if !proof.is_empty() {
+                return proof;
+            }
+            // Empty non-terminals don't have an NSEC record, but fall
+            // between two that do
+            nsec_covering(zone, qname)
+        }
+    }
+}
+
+/// Proof that there is no closer match than the wildcard an answer was
+/// synthesised from.
+pub(crate) fn wildcard_answer(zone: &Zone, qname: &str, closest_encloser: &str) -> Vec<Record> {
+    match nsec3_params(zone) {
+        Some(params) => nsec3_covering(zone, &params, &next_closer(qname, closest_encloser)),
+        None => nsec_covering(zone, qname),
+    }
+}
+
+/// Proof that a name doesn't exist, and that the wildcard that would have
+/// matched it has no records of the type asked for.
+pub(crate) fn wildcard_no_data(zone: &Zone, qname: &str, closest_encloser: &str) -> Vec<Record> {
+    let wildcard = name::child("*", closest_encloser);
+    match nsec3_params(zone) {
+        Some(params) => {
+            let mut proof = encloser_proof(zone, &params, qname, closest_encloser);
+            extend_unique(&mut proof, nsec3_matching(zone, &params, &wildcard));
+            proof
+        }
+        None => {
+            let mut proof = nsec_covering(zone, qname);
+            extend_unique(&mut proof, signed_rrset(zone, &wildcard, RecordType::Nsec));
+            proof
+        }
+    }
+}
+
+/// Records that tell a validator whether a delegated zone is signed: either
+/// the DS RRset at the cut, or proof that there isn't one (RFC 4035
+/// §3.1.4).
+pub(crate) fn delegation(zone: &Zone, cut: &str) -> Vec<Record> {
+    let ds = signed_rrset(zone, cut, RecordType::Ds);
+    if !ds.is_empty() {
+        return ds;
+    }
+    no_data(zone, cut)
+}
+
+/// The RRSIG records among `records` that cover a type.
+pub(crate) fn signatures(records: &[Record], covered: &RecordType) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|record| {
+            matches!(&record.data, Data::Rrsig { type_covered, .. } if type_covered == covered)
+        })
+        .cloned()
+        .collect()
+}
+
+/// An RRset owned by a name, followed by its signatures.
+fn signed_rrset(zone: &Zone, owner: &str, record_type: RecordType) -> Vec<Record> {
+    let records = zone.lookup(owner);
+    let mut rrset: Vec<Record> = records
+        .iter()
+        .filter(|record| record.record_type == record_type)
+        .cloned()
+        .collect();
+    if !rrset.is_empty() {
+        rrset.extend(signatures(records, &record_type));
+    }
+    rrset
+}
+
+fn nsec3_params(zone: &Zone) -> Option<Nsec3Params> {
+    zone.lookup(&zone.origin)
+        .iter()
+        .find_map(|record| match &record.data {
+            Data::Nsec3param {
+                hash_algorithm: dnssec::NSEC3_SHA1,
+                iterations,
+                salt,
+                ..
+            } => Some(Nsec3Params {
+                salt: salt.clone(),
+                iterations: *iterations,
+            }),
+            _ => None,
+        })
+}
+
+/// The closest ancestor of a name that exists in the zone.
+fn closest_encloser(zone: &Zone, qname: &str) -> String {
+    let mut current = name::normalize(qname);
+    while !zone.contains_name(&current) && current != zone.origin {
+        current = match name::parent(&current) {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    current
+}
+
+/// The name one label below the closest encloser on the way to `qname`.
+fn next_closer(qname: &str, closest_encloser: &str) -> String {
+    let labels = name::labels(qname);
+    let depth = name::labels(closest_encloser).len() + 1;
+    labels[labels.len().saturating_sub(depth)..].join(".")
+}
+
+/// The NSEC record whose span contains a name, with its signatures.
+fn nsec_covering(zone: &Zone, value: &str) -> Vec<Record> {
+    let owner = zone.preceding(value).find_map(|records| {
+        records.iter().find_map(|record| match &record.data {
+            Data::Nsec { next_name, .. } if dnssec::nsec_covers(&record.name, next_name, value) => {
+                Some(record.name.clone())
+            }
+            _ => None,
+        })
+    });
+    match owner {
+        Some(owner) => signed_rrset(zone, &owner, RecordType::Nsec),
+        None => vec![],
+    }
+}
+
+fn nsec3_matching(zone: &Zone, params: &Nsec3Params, value: &str) -> Vec<Record> {
+    match dnssec::nsec3_owner(value, &zone.origin, &params.salt, params.iterations) {
+        Ok(owner) => signed_rrset(zone, &owner, RecordType::Nsec3),
+        Err(_) => vec![],
+    }
+}
+
+/// The NSEC3 record whose span contains the hash of a name, with its
+/// signatures.
+fn nsec3_covering(zone: &Zone, params: &Nsec3Params, value: &str) -> Vec<Record> {
+    let hash = match dnssec::nsec3_hash(value, &params.salt, params.iterations) {
+        Ok(hash) => hash,
+        Err(_) => return vec![],
+    };
+    let hashed_owner = name::child(&dnssec::encode_base32hex(&hash), &zone.origin);
+    let owner = zone.preceding(&hashed_owner).find_map(|records| {
+        records.iter().find_map(|record| match &record.data {
+            Data::Nsec3 { next_hashed, .. } => {
+                let owner_hash = dnssec::nsec3_owner_hash(&record.name)?;
+                dnssec::nsec3_covers(&owner_hash, next_hashed, &hash).then(|| record.name.clone())
+            }
+            _ => None,
+        })
+    });
+    match owner {
+        Some(owner) => signed_rrset(zone, &owner, RecordType::Nsec3),
+        None => vec![],
+    }
+}
+
+/// The closest encloser proof (RFC 5155 §7.2.1): the NSEC3 record matching
+/// the closest encloser, and the one covering the next closer name.
+fn encloser_proof(
+    zone: &Zone,
+    params: &Nsec3Params,
+    qname: &str,
+    closest_encloser: &str,
+) -> Vec<Record> {
+    let mut proof = nsec3_matching(zone, params, closest_encloser);
+    let next_closer = next_closer(qname, closest_encloser);
+    extend_unique(&mut proof, nsec3_covering(zone, params, &next_closer));
+    proof
+}
+
+fn extend_unique(records: &mut Vec<Record>, more: Vec<Record>) {
+    for record in more {
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use super::authority::Catalog;
+use super::response;
+use async_trait::async_trait;
+use dns::header::ResponseCode;
+use dns::packet::DnsPacket;
+use dns::update::Update;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+/// The transport a request arrived over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+    Tls,
+    Https,
+}
+
+/// Everything we know about a request besides the packet itself.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub peer: SocketAddr,
+    pub protocol: Protocol,
+    /// The name of the TSIG key the request was signed with, once the
+    /// signature has been checked.
+    pub tsig_key: Option<String>,
+}
+
+/// Turns requests into responses. The server takes care of parsing,
+/// serializing and transport concerns, so implementations only need to decide
+/// what to answer.
+#[async_trait]
+pub trait RequestHandler: Send + Sync {
+    async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket;
+
+    /// Answer a zone transfer request, which may take several messages. The
+    /// server has already checked that the client may transfer zones.
+    async fn transfer(&self, request: &DnsPacket, _ctx: &RequestContext) -> Vec<DnsPacket> {
+        vec![response::error_response(request, ResponseCode::Refused)]
+    }
+
+    /// Make the changes in an UPDATE request. The server has already checked
+    /// that the client may make them.
+    async fn update(
+        &self,
+        request: &DnsPacket,
+        _update: &Update,
+        _ctx: &RequestContext,
+    ) -> DnsPacket {
+        response::error_response(request, ResponseCode::NotImplemented)
+    }
+}
+
+#[async_trait]
+impl RequestHandler for Catalog {
+    async fn handle(&self, request: &DnsPacket, _ctx: &RequestContext) -> DnsPacket {
+        self.answer(request)
+    }
+
+    async fn transfer(&self, request: &DnsPacket, _ctx: &RequestContext) -> Vec<DnsPacket> {
+        Catalog::transfer(self, request)
+    }
+}
+
+/// A catalog whose zones can change while it is being served, such as when
+/// they are signed again.
+#[async_trait]
+impl RequestHandler for RwLock<Catalog> {
+    async fn handle(&self, request: &DnsPacket, _ctx: &RequestContext) -> DnsPacket {
+        self.read().unwrap().answer(request)
+    }
+
+    async fn transfer(&self, request: &DnsPacket, _ctx: &RequestContext) -> Vec<DnsPacket> {
+        self.read().unwrap().transfer(request)
+    }
+}
+
+/// Answers queries for names inside local zones from a catalog, and passes
+/// everything else on to another handler, such as a resolver.
+pub struct LocalZones<H> {
+    catalog: Arc<RwLock<Catalog>>,
+    fallback: H,
+}
+
+impl<H> LocalZones<H> {
+    pub fn new(catalog: Arc<RwLock<Catalog>>, fallback: H) -> LocalZones<H> {
+        LocalZones { catalog, fallback }
+    }
+}
+
+#[async_trait]
+impl<H: RequestHandler> RequestHandler for LocalZones<H> {
+    async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+        let response = match &request.questions[..] {
+            [question] => {
+                let catalog = self.catalog.read().unwrap();
+                catalog
+                    .find_zone(&question.name)
+                    .is_some()
+                    .then(|| catalog.answer(request))
+            }
+            _ => None,
+        };
+        if let Some(response) = response {
+            response
+        } else {
+            self.fallback.handle(request, ctx).await
+        }
+    }
+
+    async fn transfer(&self, request: &DnsPacket, _ctx: &RequestContext) -> Vec<DnsPacket> {
+        self.catalog.read().unwrap().transfer(request)
+    }
+}
+
+#[async_trait]
+impl<H: RequestHandler + ?Sized> RequestHandler for Arc<H> {
+    async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+        (**self).handle(request, ctx).await
+    }
+
+    async fn transfer(&self, request: &DnsPacket, ctx: &RequestContext) -> Vec<DnsPacket> {
+        (**self).transfer(request, ctx).await
+    }
+
+    async fn update(
+        &self,
+        request: &DnsPacket,
+        update: &Update,
+        ctx: &RequestContext,
+    ) -> DnsPacket {
+        (**self).update(request, update, ctx).await
+    }
+}
+
+#[async_trait]
+impl<H: RequestHandler + ?Sized> RequestHandler for Box<H> {
+    async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+        (**self).handle(request, ctx).await
+    }
+
+    async fn transfer(&self, request: &DnsPacket, ctx: &RequestContext) -> Vec<DnsPacket> {
+        (**self).transfer(request, ctx).await
+    }
+
+    async fn update(
+        &self,
+        request: &DnsPacket,
+        update: &Update,
+        ctx: &RequestContext,
+    ) -> DnsPacket {
+        (**self).update(request, update, ctx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response;
+    use dns::header::ResponseCode;
+    use dns::record::RecordType;
+
+    /// Refuses everything, so we can tell when it was used.
+    struct Refuse;
+
+    #[async_trait]
+    impl RequestHandler for Refuse {
+        async fn handle(&self, request: &DnsPacket, _ctx: &RequestContext) -> DnsPacket {
+            response::error_response(request, ResponseCode::Refused)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_zones_passes_other_names_to_fallback() {
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\n@ 60 A 127.0.0.1";
+        let mut catalog = Catalog::new();
+        catalog.insert(dns::zone::parse_zone(text, Some("example.com")).unwrap());
+        let handler = LocalZones::new(Arc::new(RwLock::new(catalog)), Refuse);
+        let ctx = RequestContext {
+            peer: "127.0.0.1:5353".parse().unwrap(),
+            protocol: Protocol::Udp,
+            tsig_key: None,
+        };
+
+        let request = dns::packet::build_query(1, "example.com", &RecordType::A);
+        let response = handler.handle(&request, &ctx).await;
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.answers.len(), 1);
+
+        let request = dns::packet::build_query(2, "example.net", &RecordType::A);
+        let response = handler.handle(&request, &ctx).await;
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+    }
+}
@@ -0,0 +1,481 @@
+//! DNS over HTTPS (RFC 8484), and the JSON flavour of it that is easier to
+//! read when debugging.
+
+use super::handler::{Protocol, RequestContext, RequestHandler};
+use super::runtime::Server;
+use super::tcp;
+use client::https::{DEFAULT_PATH, MEDIA_TYPE};
+use data_encoding::BASE64URL_NOPAD;
+use dns::edns::{self, Edns};
+use dns::header::{AUTHENTIC_DATA, CHECKING_DISABLED};
+use dns::packet::DnsPacket;
+use dns::record::{self, Data, RecordType};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, ALLOW, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_rustls::TlsAcceptor;
+
+/// The media type of responses from the JSON API.
+const JSON_MEDIA_TYPE: &str = "application/dns-json";
+
+/// Answer requests arriving over HTTPS at `/dns-query`, over HTTP/1.1 or
+/// HTTP/2. Connections count against the same limit as TCP ones.
+pub(crate) async fn serve<H: RequestHandler + 'static>(
+    server: Server<H>,
+    listener: TcpListener,
+    tls: TlsAcceptor,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let connections = Arc::new(Semaphore::new(server.config().max_tcp_connections));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("Failed to accept HTTPS connection: {}", err);
+                continue;
+            }
+        };
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                println!(
+                    "Too many HTTPS connections, closing connection from {}",
+                    peer
+                );
+                continue;
+            }
+        };
+        let server = server.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let result = match tcp::handshake(&server, &tls, stream).await {
+                Ok(stream) => handle_connection(server, stream, peer).await,
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+                eprintln!("HTTPS connection with {} failed: {}", peer, err);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Serve HTTP on a connection until the client closes it, or it has been
+/// idle for the TCP idle timeout. Idle connections are shut down gracefully,
+/// which HTTP/1.1 keep-alive and HTTP/2 pings wouldn't do on their own.
+async fn handle_connection<H: RequestHandler + 'static>(
+    server: Server<H>,
+    stream: tokio_rustls::server::TlsStream<tokio::net::TcpStream>,
+    peer: SocketAddr,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let idle_timeout = server.config().tcp_idle_timeout;
+    let activity = Arc::new(Mutex::new(Activity {
+        in_flight: 0,
+        last_active: Instant::now(),
+    }));
+    let tracked = activity.clone();
+    let service = service_fn(move |request| {
+        let server = server.clone();
+        let in_flight = InFlight::start(&tracked);
+        async move {
+            let response = respond(&server, request, peer).await;
+            drop(in_flight);
+            Ok::<_, Infallible>(response)
+        }
+    });
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(idle_timeout);
+    let connection = builder.serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+    loop {
+        let idle = activity.lock().unwrap().idle();
+        tokio::select! {
+            result = connection.as_mut() => return result,
+            _ = tokio::time::sleep(idle_timeout.saturating_sub(idle)) => {}
+        }
+        if activity.lock().unwrap().idle() >= idle_timeout {
+            println!("Closing idle HTTPS connection from {}", peer);
+            connection.as_mut().graceful_shutdown();
+            return connection.await;
+        }
+    }
+}
+
+/// Whether a connection is answering any requests, and when it last did.
+#[derive(Debug)]
+struct Activity {
+    in_flight: usize,
+    last_active: Instant,
+}
+
+impl Activity {
+    /// How long the connection has had nothing to do.
+    fn idle(&self) -> Duration {
+        match self.in_flight {
+            0 => self.last_active.elapsed(),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Counts a request as in flight until it is dropped.
+struct InFlight(Arc<Mutex<Activity>>);
+
+impl InFlight {
+    fn start(activity: &Arc<Mutex<Activity>>) -> InFlight {
+        activity.lock().unwrap().in_flight += 1;
+        InFlight(activity.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut activity = self.0.lock().unwrap();
+        activity.in_flight -= 1;
+        activity.last_active = Instant::now();
+    }
+}
+
+/// Answer a single HTTP request.
+async fn respond<H: RequestHandler + 'static>(
+    server: &Server<H>,
+    request: Request<Incoming>,
+    peer: SocketAddr,
+) -> Response<Full<Bytes>> {
+    if request.uri().path() != DEFAULT_PATH {
+        return status(StatusCode::NOT_FOUND);
+    }
+    let params = parse_params(request.uri().query().unwrap_or(""));
+    let message = match (request.method(), params.get("dns"), params.get("name")) {
+        (&Method::GET, Some(dns), _) => match BASE64URL_NOPAD.decode(dns.as_bytes()) {
+            Ok(message) => message,
+            Err(_) => return status(StatusCode::BAD_REQUEST),
+        },
+        (&Method::GET, None, Some(name)) => return json(server, name, &params, peer).await,
+        (&Method::GET, None, None) => return status(StatusCode::BAD_REQUEST),
+        (&Method::POST, _, _) => {
+            let content_type = request.headers().get(CONTENT_TYPE);
+            if content_type.map(HeaderValue::as_bytes) != Some(MEDIA_TYPE.as_bytes()) {
+                return status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            match Limited::new(request.into_body(), u16::MAX as usize)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes().to_vec(),
+                Err(_) => return status(StatusCode::PAYLOAD_TOO_LARGE),
+            }
+        }
+        _ => {
+            let mut response = status(StatusCode::METHOD_NOT_ALLOWED);
+            let allow = HeaderValue::from_static("GET, POST");
+            response.headers_mut().insert(ALLOW, allow);
+            return response;
+        }
+    };
+
+    let ctx = RequestContext {
+        peer,
+        protocol: Protocol::Https,
+        tsig_key: None,
+    };
+    let bytes = match server.handle_message(&message, &ctx).await {
+        Some(bytes) => bytes,
+        None => return status(StatusCode::BAD_REQUEST),
+    };
+    let max_age = match dns::parse_dns_packet(&bytes) {
+        Ok(response) => max_age(&response),
+        Err(_) => 0,
+    };
+    ok(MEDIA_TYPE, max_age, bytes)
+}
+
+/// Answer a query given as `name`, `type`, `do` and `cd` parameters, in the
+/// JSON format that Google and Cloudflare use.
+async fn json<H: RequestHandler + 'static>(
+    server: &Server<H>,
+    name: &str,
+    params: &HashMap<&str, &str>,
+    peer: SocketAddr,
+) -> Response<Full<Bytes>> {
+    let record_type = match params.get("type") {
+        None => RecordType::A,
+        Some(typ) => match typ.parse() {
+            Ok(code) => record::parse_record_type(code),
+            Err(_) => match record::parse_record_type_name(typ) {
+                Some(record_type) => record_type,
+                None => return status(StatusCode::BAD_REQUEST),
+            },
+        },
+    };
+    let flag = |param| matches!(params.get(param), Some(&"1" | &"true"));
+    let mut request = dns::packet::build_query(0, name, &record_type);
+    request.header.recursion_desired = true;
+    if flag("cd") {
+        request.header.reserved |= CHECKING_DISABLED;
+    }
+    let edns = Edns {
+        dnssec_ok: flag("do"),
+        ..Edns::default()
+    };
+    edns::set_edns(&mut request, &edns);
+
+    let ctx = RequestContext {
+        peer,
+        protocol: Protocol::Https,
+        tsig_key: None,
+    };
+    let response = match dns::serialize_dns_packet(&request) {
+        Ok(bytes) => server.handle_message(&bytes, &ctx).await,
+        Err(_) => return status(StatusCode::BAD_REQUEST),
+    };
+    let response = match response.map(|bytes| dns::parse_dns_packet(&bytes)) {
+        Some(Ok(response)) => response,
+        _ => return status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let body = format_json(&response).to_string().into_bytes();
+    ok(JSON_MEDIA_TYPE, max_age(&response), body)
+}
+
+/// Turn a response into JSON. Sections without records are left out, as is
+/// the OPT record.
+fn format_json(response: &DnsPacket) -> Value {
+    let header = &response.header;
+    let mut value = json!({
+        "Status": dns::header::serialize_response_code(&header.rcode),
+        "TC": header.truncation,
+        "RD": header.recursion_desired,
+        "RA": header.recursion_available,
+        "AD": header.reserved & AUTHENTIC_DATA != 0,
+        "CD": header.reserved & CHECKING_DISABLED != 0,
+        "Question": response.questions.iter().map(|question| json!({
+            "name": dns::zone::format_name(&question.name),
+            "type": question.typ,
+        })).collect::<Vec<_>>(),
+    });
+    let sections = [
+        ("Answer", &response.answers),
+        ("Authority", &response.authoritative_entries),
+        ("Additional", &response.resource_entries),
+    ];
+    for (section, records) in sections {
+        let records: Vec<Value> = records
+            .iter()
+            .filter(|record| record.record_type != RecordType::Opt)
+            .map(|record| {
+                json!({
+                    "name": dns::zone::format_name(&record.name),
+                    "type": record::record_type_code(&record.record_type),
+                    "TTL": record.ttl,
+                    "data": dns::zone::format_data(&record.data),
+                })
+            })
+            .collect();
+        if !records.is_empty() {
+            value[section] = Value::Array(records);
+        }
+    }
+    value
+}
+
+/// How many seconds a response may be cached for (RFC 8484 §5.1): the
+/// smallest TTL in the answer. Negative answers are cached for as long as
+/// their SOA says (RFC 2308 §5), and other responses not at all.
+fn max_age(response: &DnsPacket) -> u32 {
+    let answers = response
+        .answers
+        .iter()
+        .map(|record| record.ttl.max(0) as u32)
+        .min();
+    if let Some(ttl) = answers {
+        return ttl;
+    }
+    let soa = response
+        .authoritative_entries
+        .iter()
+        .find_map(|record| match record.data {
+            Data::Soa { minimum, .. } => Some((record.ttl, minimum)),
+            _ => None,
+        });
+    match soa {
+        Some((ttl, minimum)) => (ttl.max(0) as u32).min(minimum),
+        None => 0,
+    }
+}
+
+/// Split a query string into its parameters.
+fn parse_params(query: &str) -> HashMap<&str, &str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .collect()
+}
+
+fn ok(content_type: &'static str, max_age: u32, body: Vec<u8>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    let cache_control = format!("max-age={}", max_age);
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_str(&cache_control).unwrap(),
+    );
+    response
+}
+
+fn status(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authority::Catalog;
+    use crate::runtime::ServerConfig;
+    use client::https::{HttpsConfig, Method as HttpsMethod};
+    use client::tls::{Authentication, TlsConfig};
+    use dns::header::ResponseCode;
+    use std::sync::RwLock;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName};
+    use tokio_rustls::{rustls, TlsConnector};
+
+    const ZONE: &str = "@ 3600 SOA ns hostmaster 1 2 3 4 300
+www 60 A 192.0.2.1
+www 300 A 192.0.2.2";
+
+    fn catalog() -> Catalog {
+        let mut catalog = Catalog::new();
+        catalog.insert(dns::zone::parse_zone(ZONE, Some("example.com")).unwrap());
+        catalog
+    }
+
+    /// Serve the test zone over HTTPS with a self-signed certificate for
+    /// localhost. Returns the server's address and certificate.
+    async fn start(
+        config: ServerConfig,
+    ) -> Result<(SocketAddr, CertificateDer<'static>), Box<dyn error::Error + Send + Sync>> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let cert = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let tls = crate::tls::server_config(vec![cert.clone()], key.into())?;
+
+        let server = Server::new(Arc::new(RwLock::new(catalog())), config);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { server.serve_https(listener, tls).await });
+        Ok((addr, cert))
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_over_https() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (addr, cert) = start(ServerConfig::default()).await?;
+        let authentication = Authentication::Certificate {
+            name: Some("localhost".to_string()),
+            roots: vec![cert],
+        };
+        let tls = TlsConfig::new(&authentication)?;
+        for method in [HttpsMethod::Get, HttpsMethod::Post] {
+            let config = HttpsConfig::new(tls.clone(), DEFAULT_PATH, method);
+            let request = dns::packet::build_query(1234, "www.example.com", &RecordType::A);
+            let timeout = Duration::from_secs(5);
+            let response =
+                client::https::query_signed(addr, &request, None, &config, timeout).await?;
+            assert_eq!(response.header.id, 1234);
+            assert_eq!(response.header.rcode, ResponseCode::Success);
+            assert_eq!(response.answers.len(), 2);
+        }
+
+        // Anywhere else is not found
+        let config = HttpsConfig::new(tls, "/resolve", HttpsMethod::Post);
+        let request = dns::packet::build_query(1, "www.example.com", &RecordType::A);
+        let timeout = Duration::from_secs(5);
+        let result = client::https::query_signed(addr, &request, None, &config, timeout).await;
+        assert!(result.unwrap_err().to_string().contains("404"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_closes_idle_connections() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let config = ServerConfig {
+            tcp_idle_timeout: Duration::from_millis(100),
+            ..ServerConfig::default()
+        };
+        let (addr, cert) = start(config).await?;
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert)?;
+        let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let name = ServerName::try_from("localhost")?;
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(name, stream)
+            .await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await?;
+        let connection = tokio::spawn(connection);
+
+        // HTTP/2 connections stay open after a response, but not forever
+        let request = Request::get("https://localhost/dns-query?name=www.example.com")
+            .body(Full::<Bytes>::default())?;
+        let response = sender.send_request(request).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        tokio::time::timeout(Duration::from_secs(2), connection).await???;
+        assert!(sender.is_closed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_age_is_smallest_answer_ttl() {
+        let catalog = catalog();
+        let request = dns::packet::build_query(1, "www.example.com", &RecordType::A);
+        assert_eq!(max_age(&catalog.answer(&request)), 60);
+
+        // Negative answers last for the SOA minimum, which is below its TTL
+        let request = dns::packet::build_query(1, "www.example.com", &RecordType::Mx);
+        assert_eq!(max_age(&catalog.answer(&request)), 300);
+        let request = dns::packet::build_query(1, "www.example.org", &RecordType::A);
+        assert_eq!(max_age(&catalog.answer(&request)), 0);
+    }
+
+    #[test]
+    fn test_format_json() {
+        let catalog = catalog();
+        let mut request = dns::packet::build_query(1, "www.example.com", &RecordType::A);
+        edns::set_edns(&mut request, &Edns::default());
+        let value = format_json(&catalog.answer(&request));
+        assert_eq!(value["Status"], 0);
+        assert_eq!(value["Question"][0]["name"], "www.example.com.");
+        assert_eq!(value["Answer"][0]["data"], "192.0.2.1");
+        assert_eq!(value["Answer"][1]["TTL"], 300);
+        assert!(value.get("Authority").is_none());
+        assert!(value.get("Additional").is_none());
+
+        let params = parse_params("name=www.example.com&type=AAAA&cd");
+        assert_eq!(params.get("type"), Some(&"AAAA"));
+        assert_eq!(params.get("cd"), None);
+    }
+}
@@ -0,0 +1,20 @@
+//! This package provides the building blocks of a DNS server. Implement
+//! [`handler::RequestHandler`] to decide how requests are answered, and run
+//! it with [`runtime::Server`].
+
+pub mod acl;
+pub mod authority;
+pub mod clock;
+mod denial;
+pub mod handler;
+mod https;
+pub mod notify;
+pub mod response;
+pub mod runtime;
+pub mod secondary;
+pub mod signer;
+mod tcp;
+pub mod tls;
+pub mod transfer;
+mod udp;
+pub mod update;
@@ -0,0 +1,148 @@
+use super::authority::Catalog;
+use anyhow::anyhow;
+use dns::header::{self, Opcode, ResponseCode};
+use dns::journal;
+use dns::name;
+use dns::packet::DnsPacket;
+use dns::record::{Record, RecordType};
+use dns::tsig::TsigKey;
+use std::collections::BTreeMap;
+use std::error;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How long to wait for a secondary to acknowledge a NOTIFY before sending
+/// it again.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many times to send a NOTIFY before giving up on a secondary.
+const NOTIFY_ATTEMPTS: usize = 5;
+
+/// Where to send NOTIFY messages, and how to sign them.
+#[derive(Debug, Clone, Default)]
+pub struct NotifyConfig {
+    pub secondaries: Vec<SocketAddr>,
+    pub key: Option<TsigKey>,
+}
+
+/// Build a NOTIFY message for a zone, carrying its new SOA record so that
+/// secondaries can tell whether they are already up to date.
+pub fn build_notify(id: u16, soa: &Record) -> DnsPacket {
+    let mut request = dns::packet::build_query(id, &soa.name, &RecordType::Soa);
+    request.header.opcode = Opcode::Notify;
+    request.header.authoritative_answer = true;
+    request.answers.push(soa.clone());
+    request
+}
+
+/// Tell a secondary that a zone has changed (RFC 1996 §3.6). The message is
+/// sent again until the secondary acknowledges it, or we run out of attempts.
+pub async fn notify(
+    addr: SocketAddr,
+    soa: &Record,
+    key: Option<&TsigKey>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let request = build_notify(rand::random(), soa);
+    let mut last_err = None;
+    for _ in 0..NOTIFY_ATTEMPTS {
+        match client::udp::query_signed(addr, &request, key, NOTIFY_TIMEOUT).await {
+            Ok(response) if response.header.rcode == ResponseCode::Success => return Ok(()),
+            Ok(response) => {
+                return Err(anyhow!(
+                    "{} rejected NOTIFY for {}: {}",
+                    addr,
+                    soa.name,
+                    header::response_code_name(&response.header.rcode)
+                )
+                .into())
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("no attempts made to notify {}", addr).into()))
+}
+
+/// Send NOTIFY to every secondary whenever a zone in the catalog gets a new
+/// serial, for as long as the server runs. Every zone is announced once at
+/// startup too, so secondaries hear about changes made while we were down.
+pub async fn keep_notifying(
+    catalog: Arc<RwLock<Catalog>>,
+    config: NotifyConfig,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let changed = catalog.read().unwrap().changed();
+    let mut announced: BTreeMap<Vec<u8>, u32> = BTreeMap::new();
+    loop {
+        let soas: Vec<Record> = catalog
+            .read()
+            .unwrap()
+            .zones()
+            .iter()
+            .filter_map(|zone| zone.soa().cloned())
+            .collect();
+        for soa in soas {
+            let serial = match journal::serial(&soa) {
+                Some(serial) => serial,
+                None => continue,
+            };
+            let previous = announced.insert(name::canonical_key(&soa.name), serial);
+            if previous == Some(serial) {
+                continue;
+            }
+            for addr in &config.secondaries {
+                let (addr, soa, key) = (*addr, soa.clone(), config.key.clone());
+                tokio::spawn(async move {
+                    match notify(addr, &soa, key.as_ref()).await {
+                        Ok(()) => println!("Notified {} of {} serial {}", addr, soa.name, serial),
+                        Err(err) => eprintln!("Could not notify {}: {}", addr, err),
+                    }
+                });
+            }
+        }
+        changed.notified().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UdpSocket;
+
+    fn zone(serial: u32) -> dns::zone::Zone {
+        let text = format!("@ 60 SOA ns hostmaster {} 2 3 4 5", serial);
+        dns::zone::parse_zone(&text, Some("example.com")).unwrap()
+    }
+
+    /// Receive a NOTIFY and acknowledge it, returning the serial it carried.
+    async fn acknowledge(sock: &UdpSocket) -> Result<u32, Box<dyn error::Error + Send + Sync>> {
+        let mut buf = vec![0; 512];
+        let (len, peer) = sock.recv_from(&mut buf).await?;
+        let request = dns::parse_dns_packet(&buf[..len])?;
+        assert_eq!(request.header.opcode, Opcode::Notify);
+        assert!(request.header.authoritative_answer);
+        assert_eq!(request.questions[0].name, "example.com");
+        let response = crate::response::response_to(&request);
+        sock.send_to(&dns::serialize_dns_packet(&response)?, peer)
+            .await?;
+        Ok(journal::serial(&request.answers[0]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_keep_notifying_announces_new_serials(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut catalog = Catalog::new();
+        catalog.insert(zone(1));
+        let catalog = Arc::new(RwLock::new(catalog));
+        let config = NotifyConfig {
+            secondaries: vec![sock.local_addr()?],
+            key: None,
+        };
+        tokio::spawn(keep_notifying(catalog.clone(), config));
+
+        assert_eq!(acknowledge(&sock).await?, 1);
+        catalog.write().unwrap().insert(zone(2));
+        assert_eq!(acknowledge(&sock).await?, 2);
+        Ok(())
+    }
+}
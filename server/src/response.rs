@@ -0,0 +1,247 @@
+use dns::header::{self, Header, ResponseCode};
+use dns::packet::DnsPacket;
+use dns::record::{Record, RecordType};
+use std::error;
+
+/// Create an empty response to a request, echoing its ID, opcode and
+/// questions.
+pub fn response_to(request: &DnsPacket) -> DnsPacket {
+    DnsPacket {
+        header: Header {
+            id: request.header.id,
+            query: false,
+            opcode: request.header.opcode,
+            authoritative_answer: false,
+            truncation: false,
+            recursion_desired: request.header.recursion_desired,
+            recursion_available: false,
+            reserved: 0,
+            rcode: ResponseCode::Success,
+            questions: request.questions.len() as u16,
+            answers: 0,
+            authoritative_entries: 0,
+            resource_entries: 0,
+        },
+        questions: request.questions.clone(),
+        answers: vec![],
+        authoritative_entries: vec![],
+        resource_entries: vec![],
+    }
+}
+
+/// Create a response to a request that carries nothing but an error code.
+pub fn error_response(request: &DnsPacket, rcode: ResponseCode) -> DnsPacket {
+    let mut response = response_to(request);
+    response.header.rcode = rcode;
+    response
+}
+
+/// Create a FORMERR response from the raw bytes of a request that could not
+/// be parsed. Returns `None` if there isn't enough of a header to reply to,
+/// or if the message is itself a response, as replying to those could start
+/// a loop between two servers.
+pub fn format_error(bytes: &[u8]) -> Option<DnsPacket> {
+    let id = u16::from_be_bytes(bytes.get(0..2)?.try_into().ok()?);
+    let flags = bytes.get(2).copied().unwrap_or(0);
+    if flags & 0b1000_0000 != 0 {
+        return None;
+    }
+    Some(DnsPacket {
+        header: Header {
+            id,
+            query: false,
+            opcode: header::parse_opcode((flags >> 3) & 0b1111),
+            authoritative_answer: false,
+            truncation: false,
+            recursion_desired: flags & 0b1 != 0,
+            recursion_available: false,
+            reserved: 0,
+            rcode: ResponseCode::FormatError,
+            questions: 0,
+            answers: 0,
+            authoritative_entries: 0,
+            resource_entries: 0,
+        },
+        questions: vec![],
+        answers: vec![],
+        authoritative_entries: vec![],
+        resource_entries: vec![],
+    })
+}
+
+/// Serialize a response so that it is no longer than `limit` bytes.
+///
+/// Whole RRsets are dropped from the end of the message until it fits.
+/// Additional records go first, as the client can look them up itself, and
+/// the OPT record is always kept. If any answer or authority records have to
+/// go, the TC bit is set so the client knows to retry over TCP.
+pub fn serialize_truncated(
+    response: &DnsPacket,
+    limit: usize,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let bytes = dns::serialize_dns_packet(response)?;
+    if bytes.len() <= limit {
+        return Ok(bytes);
+    }
+
+    let mut response = response.clone();
+    let opt: Vec<Record> = response
+        .resource_entries
+        .iter()
+        .filter(|record| record.record_type == RecordType::Opt)
+        .cloned()
+        .collect();
+    response
+        .resource_entries
+        .retain(|record| record.record_type != RecordType::Opt);
+
+    loop {
+        let section = if !response.resource_entries.is_empty() {
+            &mut response.resource_entries
+        } else if !response.authoritative_entries.is_empty() {
+            response.header.truncation = true;
+            &mut response.authoritative_entries
+        } else if !response.answers.is_empty() {
+            response.header.truncation = true;
+            &mut response.answers
+        } else {
+            response.header.truncation = true;
+            response.resource_entries.extend(opt);
+            return dns::serialize_dns_packet(&response);
+        };
+        pop_rrset(section);
+
+        let mut candidate = response.clone();
+        candidate.resource_entries.extend(opt.iter().cloned());
+        let bytes = dns::serialize_dns_packet(&candidate)?;
+        if bytes.len() <= limit {
+            return Ok(bytes);
+        }
+    }
+}
+
+/// Remove the last RRset from a section, i.e. the trailing run of records
+/// sharing a name, type and class.
+fn pop_rrset(records: &mut Vec<Record>) {
+    let last = match records.pop() {
+        Some(last) => last,
+        None => return,
+    };
+    while let Some(record) = records.last() {
+        if !dns::name::eq(&record.name, &last.name)
+            || record.record_type != last.record_type
+            || record.class != last.class
+        {
+            break;
+        }
+        records.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::edns::{self, Edns};
+    use dns::header::Opcode;
+    use dns::zone;
+
+    const QUERY: &[u8] = include_bytes!("../../examples/query_packet");
+
+    /// A response to the example query with the given number of A records in
+    /// the answer and additional sections.
+    fn large_response(answers: u8, additional: u8) -> DnsPacket {
+        let mut text = String::new();
+        for i in 0..answers {
+            text.push_str(&format!("google.com. 60 A 10.0.0.{}\n", i));
+        }
+        for i in 0..additional {
+            text.push_str(&format!("extra{}.google.com. 60 A 10.0.1.{}\n", i, i));
+        }
+        let records = zone::parse_master_file(&text, None).unwrap();
+        let mut response = response_to(&dns::parse_dns_packet(QUERY).unwrap());
+        response.answers = records[..answers as usize].to_vec();
+        response.resource_entries = records[answers as usize..].to_vec();
+        response
+    }
+
+    #[test]
+    fn test_format_error_echoes_id_and_flags() {
+        // ID 0x1234, QR=0, opcode STATUS, RD=1, then garbage
+        let response = format_error(&[0x12, 0x34, 0b0001_0001, 0xff]).unwrap();
+        assert_eq!(response.header.id, 0x1234);
+        assert!(!response.header.query);
+        assert_eq!(response.header.opcode, Opcode::Status);
+        assert!(response.header.recursion_desired);
+        assert_eq!(response.header.rcode, ResponseCode::FormatError);
+    }
+
+    #[test]
+    fn test_format_error_echoes_id_of_short_messages() {
+        let response = format_error(&[0x12, 0x34]).unwrap();
+        assert_eq!(response.header.id, 0x1234);
+        assert_eq!(response.header.opcode, Opcode::Query);
+    }
+
+    #[test]
+    fn test_format_error_ignores_messages_without_id() {
+        assert!(format_error(&[]).is_none());
+        assert!(format_error(&[0x12]).is_none());
+    }
+
+    #[test]
+    fn test_format_error_ignores_responses() {
+        assert!(format_error(&[0x12, 0x34, 0b1000_0000, 0x00]).is_none());
+    }
+
+    #[test]
+    fn test_serialize_truncated_leaves_small_responses_alone(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let response = large_response(2, 2);
+        let bytes = serialize_truncated(&response, 512)?;
+        assert_eq!(bytes, dns::serialize_dns_packet(&response)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_truncated_drops_additional_records_without_tc(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut response = large_response(2, 40);
+        edns::set_edns(&mut response, &Edns::default());
+        let bytes = serialize_truncated(&response, 512)?;
+        assert!(bytes.len() <= 512);
+
+        let truncated = dns::parse_dns_packet(&bytes)?;
+        assert!(!truncated.header.truncation);
+        assert_eq!(truncated.answers.len(), 2);
+        assert!(truncated.resource_entries.len() < 40);
+        assert!(edns::parse_edns(&truncated).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_truncated_drops_whole_rrsets_and_sets_tc(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        // One RRset that doesn't fit, so it has to go entirely.
+        let response = large_response(40, 0);
+        let bytes = serialize_truncated(&response, 512)?;
+        assert!(bytes.len() <= 512);
+
+        let truncated = dns::parse_dns_packet(&bytes)?;
+        assert!(truncated.header.truncation);
+        assert!(truncated.answers.is_empty());
+        assert_eq!(truncated.questions, response.questions);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_rrset_removes_trailing_rrset() {
+        let mut records = large_response(3, 2).resource_entries;
+        records.splice(0..0, large_response(3, 0).answers);
+        pop_rrset(&mut records);
+        assert_eq!(records.len(), 4);
+        pop_rrset(&mut records);
+        assert_eq!(records.len(), 3);
+        pop_rrset(&mut records);
+        assert!(records.is_empty());
+    }
+}
@@ -0,0 +1,639 @@
+use super::acl::{Acl, UpdatePolicy};
+use super::handler::{Protocol, RequestContext, RequestHandler};
+use super::https;
+use super::response;
+use super::tcp;
+use super::udp;
+use dns::edns::{self, Edns};
+use dns::header::{Opcode, ResponseCode};
+use dns::packet::DnsPacket;
+use dns::record::{self, RecordType};
+use dns::tsig::{self, TsigError, TsigKey, TsigSession};
+use dns::update::Update;
+use std::error;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::{rustls, TlsAcceptor};
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How many requests may be handled at the same time. Once this many are
+    /// in flight, the server stops reading new requests until one finishes.
+    pub max_concurrent_requests: usize,
+    /// How many TCP connections may be open at once. New connections beyond
+    /// this are closed straight away.
+    pub max_tcp_connections: usize,
+    /// How long a TCP connection may go without sending a request before we
+    /// close it.
+    pub tcp_idle_timeout: Duration,
+    /// The largest UDP response we will send, and the payload size we
+    /// advertise to EDNS clients. Clients that don't use EDNS get 512 bytes.
+    pub max_udp_payload_size: u16,
+    /// Keys that clients may sign requests with. Signed requests get signed
+    /// responses, and requests signed with any other key are refused.
+    pub tsig_keys: Vec<TsigKey>,
+    /// Who may transfer zones with AXFR or IXFR. Nobody can by default.
+    pub allow_transfer: Acl,
+    /// Which records clients may change with UPDATE, by the TSIG key they
+    /// sign with. Nobody can change anything by default.
+    pub update_policy: UpdatePolicy,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            max_concurrent_requests: 1024,
+            max_tcp_connections: 256,
+            tcp_idle_timeout: Duration::from_secs(10),
+            max_udp_payload_size: edns::DEFAULT_UDP_PAYLOAD_SIZE,
+            tsig_keys: Vec::new(),
+            allow_transfer: Acl::default(),
+            update_policy: UpdatePolicy::default(),
+        }
+    }
+}
+
+/// Runs a request handler behind one or more listeners, handling each
+/// request in its own task.
+pub struct Server<H> {
+    handler: Arc<H>,
+    config: Arc<ServerConfig>,
+    requests: Arc<Semaphore>,
+}
+
+impl<H> Clone for Server<H> {
+    fn clone(&self) -> Server<H> {
+        Server {
+            handler: self.handler.clone(),
+            config: self.config.clone(),
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+impl<H: RequestHandler + 'static> Server<H> {
+    pub fn new(handler: H, config: ServerConfig) -> Server<H> {
+        Server {
+            handler: Arc::new(handler),
+            requests: Arc::new(Semaphore::new(config.max_concurrent_requests)),
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Answer requests arriving on a UDP socket. This only returns if the
+    /// socket itself can no longer be used.
+    pub async fn serve_udp(
+        &self,
+        sock: UdpSocket,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        udp::serve(self.clone(), sock).await
+    }
+
+    /// Answer requests arriving on TCP connections accepted from a listener.
+    /// This only returns if the listener itself can no longer be used.
+    pub async fn serve_tcp(
+        &self,
+        listener: TcpListener,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        tcp::serve(self.clone(), listener, None).await
+    }
+
+    /// Answer requests arriving over TLS (RFC 7858), on connections accepted
+    /// from a listener. This only returns if the listener itself can no
+    /// longer be used.
+    pub async fn serve_tls(
+        &self,
+        listener: TcpListener,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        tcp::serve(self.clone(), listener, Some(TlsAcceptor::from(config))).await
+    }
+
+    /// Answer requests arriving over HTTPS (RFC 8484) at `/dns-query`, on
+    /// connections accepted from a listener. This only returns if the
+    /// listener itself can no longer be used.
+    pub async fn serve_https(
+        &self,
+        listener: TcpListener,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut config = (*config).clone();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        https::serve(self.clone(), listener, TlsAcceptor::from(Arc::new(config))).await
+    }
+
+    /// Wait until another request is allowed to be in flight. The request
+    /// counts against the limit until the permit is dropped.
+    pub(crate) async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.requests
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("request semaphore is never closed")
+    }
+
+    /// Turn the bytes of a single request into the bytes of its response.
+    /// Returns `None` when the request should be dropped without a reply.
+    pub async fn handle_message(&self, bytes: &[u8], ctx: &RequestContext) -> Option<Vec<u8>> {
+        self.handle_messages(bytes, ctx).await.into_iter().next()
+    }
+
+    /// Like `handle_message`, but for transports that can carry several
+    /// responses to one request, as zone transfers over TCP need.
+    pub async fn handle_messages(&self, bytes: &[u8], ctx: &RequestContext) -> Vec<Vec<u8>> {
+        let now = SystemTime::now();
+        let (request, ctx, session) = match self.prepare(bytes, ctx, now) {
+            Ok(prepared) => prepared,
+            Err(reply) => return reply.into_iter().collect(),
+        };
+        let is_transfer = matches!(
+            &request.questions[..],
+            [question] if matches!(
+                record::parse_record_type(question.typ),
+                RecordType::Axfr | RecordType::Ixfr
+            )
+        );
+        if is_transfer {
+            return self.transfer(&request, &ctx, session, now).await;
+        }
+        if request.header.opcode == Opcode::Update {
+            return self
+                .update(&request, &ctx, session, now)
+                .await
+                .into_iter()
+                .collect();
+        }
+        self.respond(&request, &ctx, session, now)
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// Parse a request and check its signature. On failure, returns the
+    /// reply to send instead, if any.
+    fn prepare(
+        &self,
+        bytes: &[u8],
+        ctx: &RequestContext,
+        now: SystemTime,
+    ) -> Result<(DnsPacket, RequestContext, Option<TsigSession>), Option<Vec<u8>>> {
+        let mut request = match dns::parse_dns_packet(bytes) {
+            Ok(request) => request,
+            Err(err) => {
+                println!("{} sent a malformed request: {}", ctx.peer, err);
+                let response = response::format_error(bytes).ok_or(None)?;
+                return Err(dns::serialize_dns_packet(&response).ok());
+            }
+        };
+        if !request.header.query {
+            println!("{} sent a response, dropping it", ctx.peer);
+            return Err(None);
+        }
+
+        let session = match tsig::verify_request(bytes, &self.config.tsig_keys, now) {
+            Ok(session) => session,
+            Err(failure) => {
+                println!("{} sent a badly signed request: {}", ctx.peer, failure);
+                let rcode = match failure.error {
+                    TsigError::Malformed(_) => ResponseCode::FormatError,
+                    _ => ResponseCode::NotAuth,
+                };
+                let response = response::error_response(&request, rcode);
+                let response = dns::serialize_dns_packet(&response).map_err(|_| None)?;
+                return Err(failure.sign_response(&response, now).ok());
+            }
+        };
+        let mut ctx = ctx.clone();
+        if let Some(session) = &session {
+            tsig::strip(&mut request.resource_entries);
+            ctx.tsig_key = Some(session.key().name.clone());
+        }
+        Ok((request, ctx, session))
+    }
+
+    /// Answer an ordinary request with a single response.
+    async fn respond(
+        &self,
+        request: &DnsPacket,
+        ctx: &RequestContext,
+        session: Option<TsigSession>,
+        now: SystemTime,
+    ) -> Option<Vec<u8>> {
+        let mut response = self.handler.handle(request, ctx).await;
+        if edns::parse_edns(request).is_some() && edns::parse_edns(&response).is_none() {
+            let edns = Edns {
+                udp_payload_size: self.config.max_udp_payload_size,
+                ..Edns::default()
+            };
+            edns::set_edns(&mut response, &edns);
+        }
+        for question in &request.questions {
+            println!(
+                "{} {:?} {} {} -> {:?} ({} answers)",
+                ctx.peer,
+                ctx.protocol,
+                question.name,
+                record::record_type_name(&record::parse_record_type(question.typ)),
+                response.header.rcode,
+                response.answers.len()
+            );
+        }
+        self.finish(request, &response, ctx, session, now)
+    }
+
+    /// Make the changes in an UPDATE request, if the update policy lets the
+    /// client make all of them.
+    async fn update(
+        &self,
+        request: &DnsPacket,
+        ctx: &RequestContext,
+        session: Option<TsigSession>,
+        now: SystemTime,
+    ) -> Option<Vec<u8>> {
+        let response = match Update::parse(request) {
+            Ok(update) => {
+                let response = if self.config.update_policy.allows(ctx, &update) {
+                    self.handler.update(request, &update, ctx).await
+                } else {
+                    println!("{} is not allowed to make this update", ctx.peer);
+                    response::error_response(request, ResponseCode::Refused)
+                };
+                println!(
+                    "{} {:?} {} UPDATE -> {:?} ({} prerequisites, {} changes)",
+                    ctx.peer,
+                    ctx.protocol,
+                    update.zone,
+                    response.header.rcode,
+                    update.prerequisites.len(),
+                    update.changes.len()
+                );
+                response
+            }
+            Err(rcode) => {
+                println!("{} sent a malformed update: {:?}", ctx.peer, rcode);
+                response::error_response(request, rcode)
+            }
+        };
+        self.finish(request, &response, ctx, session, now)
+    }
+
+    /// Serialize a response, truncating it to fit the transport and signing
+    /// it if the request was signed.
+    fn finish(
+        &self,
+        request: &DnsPacket,
+        response: &DnsPacket,
+        ctx: &RequestContext,
+        session: Option<TsigSession>,
+        now: SystemTime,
+    ) -> Option<Vec<u8>> {
+        let mut limit = self.max_response_size(request, ctx);
+        if let Some(session) = &session {
+            limit -= session.signature_len();
+        }
+        let bytes = match response::serialize_truncated(response, limit) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!("Could not serialize response to {}: {}", ctx.peer, err);
+                let response = response::error_response(request, ResponseCode::ServerFailure);
+                dns::serialize_dns_packet(&response).ok()?
+            }
+        };
+        match session {
+            Some(mut session) => session.sign(&bytes, now).ok(),
+            None => Some(bytes),
+        }
+    }
+
+    /// Answer a zone transfer request, if the client is allowed to make
+    /// one. AXFR only works over TCP and TLS, as it can span many messages.
+    /// Over UDP and HTTPS, IXFR requests only get the current SOA record,
+    /// which tells clients whether they need to try again over TCP (RFC 1995
+    /// §2).
+    async fn transfer(
+        &self,
+        request: &DnsPacket,
+        ctx: &RequestContext,
+        mut session: Option<TsigSession>,
+        now: SystemTime,
+    ) -> Vec<Vec<u8>> {
+        let question = &request.questions[0];
+        let record_type = record::parse_record_type(question.typ);
+        let single_message = matches!(ctx.protocol, Protocol::Udp | Protocol::Https);
+        let mut responses = if single_message && record_type == RecordType::Axfr {
+            vec![response::error_response(request, ResponseCode::FormatError)]
+        } else if !self.config.allow_transfer.allows(ctx) {
+            println!("{} is not allowed to transfer zones", ctx.peer);
+            vec![response::error_response(request, ResponseCode::Refused)]
+        } else {
+            self.handler.transfer(request, ctx).await
+        };
+        if single_message {
+            responses.truncate(1);
+            if let Some(response) = responses.first_mut() {
+                response.answers.truncate(1);
+            }
+        }
+        let rcode = match responses.first() {
+            Some(response) => response.header.rcode,
+            None => return vec![],
+        };
+        println!(
+            "{} {:?} {} {} -> {:?} ({} records in {} messages)",
+            ctx.peer,
+            ctx.protocol,
+            question.name,
+            record::record_type_name(&record_type),
+            rcode,
+            responses
+                .iter()
+                .map(|response| response.answers.len())
+                .sum::<usize>(),
+            responses.len()
+        );
+
+        let mut messages = vec![];
+        for response in &responses {
+            match dns::serialize_dns_packet(response) {
+                Ok(bytes) => messages.push(bytes),
+                Err(err) => {
+                    eprintln!("Could not serialize transfer to {}: {}", ctx.peer, err);
+                    let response = response::error_response(request, ResponseCode::ServerFailure);
+                    messages = dns::serialize_dns_packet(&response).into_iter().collect();
+                    break;
+                }
+            }
+        }
+        match &mut session {
+            Some(session) => messages
+                .iter()
+                .map(|message| session.sign(message, now))
+                .collect::<Result<_, _>>()
+                .unwrap_or_default(),
+            None => messages,
+        }
+    }
+
+    /// How large a response to this request may be. Over UDP this is the
+    /// smaller of the client's and our payload sizes.
+    fn max_response_size(&self, request: &DnsPacket, ctx: &RequestContext) -> usize {
+        match ctx.protocol {
+            Protocol::Udp => {
+                let client = edns::max_udp_payload_size(request);
+                let server = self
+                    .config
+                    .max_udp_payload_size
+                    .max(edns::MIN_UDP_PAYLOAD_SIZE);
+                client.min(server) as usize
+            }
+            Protocol::Tcp | Protocol::Tls | Protocol::Https => u16::MAX as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::Rule;
+    use crate::authority::Catalog;
+    use dns::zone;
+
+    const QUERY: &[u8] = include_bytes!("../../examples/query_packet");
+
+    fn server() -> Server<Catalog> {
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\n@ 60 A 127.0.0.1";
+        let mut catalog = Catalog::new();
+        catalog.insert(zone::parse_zone(text, Some("google.com")).unwrap());
+        Server::new(catalog, ServerConfig::default())
+    }
+
+    fn ctx() -> RequestContext {
+        RequestContext {
+            peer: "127.0.0.1:5353".parse().unwrap(),
+            protocol: Protocol::Udp,
+            tsig_key: None,
+        }
+    }
+
+    /// A server with enough A records at google.com to overflow 512 bytes.
+    fn large_server() -> Server<Catalog> {
+        let mut text = "@ 60 SOA ns hostmaster 1 2 3 4 5\n".to_string();
+        for i in 0..40 {
+            text.push_str(&format!("@ 60 A 10.0.0.{}\n", i));
+        }
+        let mut catalog = Catalog::new();
+        catalog.insert(zone::parse_zone(&text, Some("google.com")).unwrap());
+        Server::new(catalog, ServerConfig::default())
+    }
+
+    fn edns_query(udp_payload_size: u16) -> Vec<u8> {
+        let mut request = dns::parse_dns_packet(QUERY).unwrap();
+        let edns = Edns {
+            udp_payload_size,
+            ..Edns::default()
+        };
+        edns::set_edns(&mut request, &edns);
+        dns::serialize_dns_packet(&request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_answers_queries() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let response = server().handle_message(QUERY, &ctx()).await.unwrap();
+        let response = dns::parse_dns_packet(&response)?;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(response.answers.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_returns_format_error_for_truncated_queries(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let truncated = &QUERY[..QUERY.len() - 3];
+        let response = server().handle_message(truncated, &ctx()).await.unwrap();
+        let response = dns::parse_dns_packet(&response)?;
+        assert_eq!(response.header.id, 9398);
+        assert_eq!(response.header.rcode, ResponseCode::FormatError);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_drops_responses_and_garbage() {
+        let response_packet = include_bytes!("../../examples/response_packet");
+        assert_eq!(server().handle_message(response_packet, &ctx()).await, None);
+        assert_eq!(server().handle_message(&[0xff], &ctx()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_truncates_udp_responses_to_512_without_edns(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let response = large_server().handle_message(QUERY, &ctx()).await.unwrap();
+        assert!(response.len() <= 512);
+        let response = dns::parse_dns_packet(&response)?;
+        assert!(response.header.truncation);
+        assert!(response.answers.is_empty());
+        assert!(edns::parse_edns(&response).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_uses_negotiated_edns_payload_size(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let response = large_server()
+            .handle_message(&edns_query(4096), &ctx())
+            .await
+            .unwrap();
+        assert!(response.len() > 512 && response.len() <= 1232);
+        let response = dns::parse_dns_packet(&response)?;
+        assert!(!response.header.truncation);
+        assert_eq!(response.answers.len(), 40);
+        assert_eq!(edns::parse_edns(&response).unwrap().udp_payload_size, 1232);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_does_not_truncate_tcp_responses(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let ctx = RequestContext {
+            protocol: Protocol::Tcp,
+            ..ctx()
+        };
+        let response = large_server().handle_message(QUERY, &ctx).await.unwrap();
+        let response = dns::parse_dns_packet(&response)?;
+        assert!(!response.header.truncation);
+        assert_eq!(response.answers.len(), 40);
+        Ok(())
+    }
+
+    fn tsig_key() -> TsigKey {
+        TsigKey::new("transfer", tsig::Algorithm::HmacSha256, b"secret".to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_signs_responses_to_signed_requests(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut server = server();
+        Arc::get_mut(&mut server.config).unwrap().tsig_keys = vec![tsig_key()];
+        let mut session = tsig::TsigSession::new(tsig_key());
+        let request = session.sign(QUERY, SystemTime::now())?;
+
+        let response = server.handle_message(&request, &ctx()).await.unwrap();
+        assert_eq!(session.verify(&response, SystemTime::now()), Ok(true));
+        let response = dns::parse_dns_packet(&response)?;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(response.answers.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_refuses_requests_signed_with_unknown_keys(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut session = tsig::TsigSession::new(tsig_key());
+        let request = session.sign(QUERY, SystemTime::now())?;
+
+        let response = server().handle_message(&request, &ctx()).await.unwrap();
+        assert_eq!(
+            session.verify(&response, SystemTime::now()),
+            Err(TsigError::Rejected(tsig::BADKEY))
+        );
+        let response = dns::parse_dns_packet(&response)?;
+        assert_eq!(response.header.rcode, ResponseCode::NotAuth);
+        assert!(response.answers.is_empty());
+        Ok(())
+    }
+
+    fn axfr_query() -> Vec<u8> {
+        let request = dns::packet::build_query(5, "google.com", &RecordType::Axfr);
+        dns::serialize_dns_packet(&request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_messages_streams_zone_transfers(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let tcp = RequestContext {
+            protocol: Protocol::Tcp,
+            ..ctx()
+        };
+        let mut server = large_server();
+        let responses = server.handle_messages(&axfr_query(), &tcp).await;
+        assert_eq!(responses.len(), 1);
+        let response = dns::parse_dns_packet(&responses[0])?;
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+
+        Arc::get_mut(&mut server.config).unwrap().allow_transfer =
+            Acl::parse(&["127.0.0.0/8".to_string()])?;
+        let responses = server.handle_messages(&axfr_query(), &tcp).await;
+        let response = dns::parse_dns_packet(&responses[0])?;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(response.answers.len(), 42);
+        assert_eq!(response.answers[0].record_type, RecordType::Soa);
+        assert_eq!(response.answers[41].record_type, RecordType::Soa);
+
+        // Transfers can't be made over UDP
+        let response = server.handle_message(&axfr_query(), &ctx()).await.unwrap();
+        let response = dns::parse_dns_packet(&response)?;
+        assert_eq!(response.header.rcode, ResponseCode::FormatError);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_messages_signs_transfers_allowed_by_key(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let tcp = RequestContext {
+            protocol: Protocol::Tcp,
+            ..ctx()
+        };
+        let mut server = server();
+        let config = Arc::get_mut(&mut server.config).unwrap();
+        config.tsig_keys = vec![tsig_key()];
+        config.allow_transfer = Acl::parse(&["key:transfer".to_string()])?;
+
+        let responses = server.handle_messages(&axfr_query(), &tcp).await;
+        let response = dns::parse_dns_packet(&responses[0])?;
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+
+        let mut session = tsig::TsigSession::new(tsig_key());
+        let request = session.sign(&axfr_query(), SystemTime::now())?;
+        let responses = server.handle_messages(&request, &tcp).await;
+        assert_eq!(session.verify(&responses[0], SystemTime::now()), Ok(true));
+        let response = dns::parse_dns_packet(&responses[0])?;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert_eq!(response.answers.len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_answers_ixfr_over_udp_with_soa(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut server = large_server();
+        Arc::get_mut(&mut server.config).unwrap().allow_transfer = Acl::new(vec![Rule::Any]);
+        let mut request = dns::packet::build_query(5, "google.com", &RecordType::Ixfr);
+        let text = "@ 60 SOA ns hostmaster 0 2 3 4 5";
+        request.authoritative_entries = zone::parse_master_file(text, Some("google.com"))?;
+        let request = dns::serialize_dns_packet(&request)?;
+
+        let response = server.handle_message(&request, &ctx()).await.unwrap();
+        let response = dns::parse_dns_packet(&response)?;
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].record_type, RecordType::Soa);
+
+        let tcp = RequestContext {
+            protocol: Protocol::Tcp,
+            ..ctx()
+        };
+        let responses = server.handle_messages(&request, &tcp).await;
+        let response = dns::parse_dns_packet(&responses[0])?;
+        assert_eq!(response.answers.len(), 42);
+        Ok(())
+    }
+}
@@ -0,0 +1,603 @@
+use super::authority::Catalog;
+use super::clock::Clock;
+use super::handler::{RequestContext, RequestHandler};
+use super::response;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use dns::dnssec;
+use dns::header::{self, Opcode, ResponseCode};
+use dns::journal;
+use dns::name;
+use dns::packet::DnsPacket;
+use dns::record::{Data, Record, RecordType};
+use dns::tsig::TsigKey;
+use dns::update::Update;
+use dns::zone::Zone;
+use std::error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+
+/// How long to wait for a primary to answer each message.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before trying again to load a zone we don't have yet,
+/// as there's no SOA record to take the retry interval from.
+const INITIAL_RETRY: Duration = Duration::from_secs(60);
+
+/// A zone we keep a copy of, transferred from its primaries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecondaryZone {
+    pub origin: String,
+    /// Servers to transfer the zone from, tried in order. Only these may
+    /// send us NOTIFY messages for it.
+    pub primaries: Vec<SocketAddr>,
+}
+
+impl SecondaryZone {
+    /// Parse a zone given as `<origin>@<primary>`, where the primary's port
+    /// defaults to 53.
+    pub fn parse(value: &str) -> Result<SecondaryZone, Box<dyn error::Error + Send + Sync>> {
+        let (origin, primary) = value
+            .split_once('@')
+            .ok_or_else(|| anyhow!("expected <zone>@<primary>, got {}", value))?;
+        let primary = match primary.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => {
+                let ip: IpAddr = primary
+                    .parse()
+                    .map_err(|_| anyhow!("invalid primary address {}", primary))?;
+                SocketAddr::new(ip, 53)
+            }
+        };
+        Ok(SecondaryZone {
+            origin: name::normalize(origin),
+            primaries: vec![primary],
+        })
+    }
+}
+
+/// When a secondary zone should next be checked against its primaries, and
+/// when our copy of it stops being usable (RFC 1034 §4.3.5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timer {
+    pub next_check: SystemTime,
+    pub expires: Option<SystemTime>,
+    /// Whether a NOTIFY arrived since the last check started, which may
+    /// have been too late for it to see the change.
+    notify_pending: bool,
+}
+
+impl Timer {
+    /// A timer for a zone we haven't loaded yet, which is due straight away.
+    pub fn new(now: SystemTime) -> Timer {
+        Timer {
+            next_check: now,
+            expires: None,
+            notify_pending: false,
+        }
+    }
+
+    /// Note that a check is starting.
+    pub fn checking(&mut self) {
+        self.notify_pending = false;
+    }
+
+    /// Our copy matches the primary's, so wait the SOA's refresh interval
+    /// before checking again, and keep serving it until the expire interval.
+    pub fn refreshed(&mut self, soa: &Record, now: SystemTime) {
+        if let Data::Soa {
+            refresh, expire, ..
+        } = soa.data
+        {
+            self.next_check = now + Duration::from_secs(refresh.into());
+            self.expires = Some(now + Duration::from_secs(expire.into()));
+        }
+        self.check_again_if_notified(now);
+    }
+
+    /// None of the primaries could be reached, so try again after the SOA's
+    /// retry interval. Returns true if our copy has now expired.
+    pub fn failed(&mut self, soa: Option<&Record>, now: SystemTime) -> bool {
+        let retry = match soa.map(|soa| &soa.data) {
+            Some(Data::Soa { retry, .. }) => Duration::from_secs((*retry).into()),
+            _ => INITIAL_RETRY,
+        };
+        self.next_check = now + retry;
+        self.check_again_if_notified(now);
+        match self.expires {
+            Some(expires) if now >= expires => {
+                self.expires = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// A primary told us the zone changed, so check it straight away.
+    pub fn notified(&mut self, now: SystemTime) {
+        self.next_check = self.next_check.min(now);
+        self.notify_pending = true;
+    }
+
+    fn check_again_if_notified(&mut self, now: SystemTime) {
+        if self.notify_pending {
+            self.next_check = now;
+        }
+    }
+}
+
+/// Keeps copies of zones from their primaries up to date in a catalog. Each
+/// zone is checked when its SOA refresh timer runs out, or straight away when
+/// a primary sends a NOTIFY for it, and transferred with IXFR if the primary
+/// has a newer serial.
+pub struct Secondary {
+    catalog: Arc<RwLock<Catalog>>,
+    zones: Vec<SecondaryZone>,
+    key: Option<TsigKey>,
+    clock: Arc<dyn Clock>,
+    /// One timer for each zone, in the same order.
+    timers: Mutex<Vec<Timer>>,
+    wake: Notify,
+}
+
+impl Secondary {
+    /// Keep copies of `zones` in the catalog, signing requests to their
+    /// primaries with `key` if there is one.
+    pub fn new(
+        catalog: Arc<RwLock<Catalog>>,
+        zones: Vec<SecondaryZone>,
+        key: Option<TsigKey>,
+        clock: Arc<dyn Clock>,
+    ) -> Secondary {
+        let timers = vec![Timer::new(clock.now()); zones.len()];
+        Secondary {
+            catalog,
+            zones,
+            key,
+            clock,
+            timers: Mutex::new(timers),
+            wake: Notify::new(),
+        }
+    }
+
+    pub fn zones(&self) -> &[SecondaryZone] {
+        &self.zones
+    }
+
+    /// The timer of a zone, if it is one of ours.
+    pub fn timer(&self, origin: &str) -> Option<Timer> {
+        let index = self.position(origin)?;
+        Some(self.timers.lock().unwrap()[index])
+    }
+
+    /// Handle a NOTIFY for a zone, checking it straight away if it came from
+    /// one of the zone's primaries. Returns false if it didn't.
+    pub fn notified(&self, origin: &str, peer: IpAddr) -> bool {
+        let index = match self.position(origin) {
+            Some(index) => index,
+            None => return false,
+        };
+        let from_primary = self.zones[index]
+            .primaries
+            .iter()
+            .any(|primary| primary.ip().to_canonical() == peer.to_canonical());
+        if !from_primary {
+            return false;
+        }
+        self.timers.lock().unwrap()[index].notified(self.clock.now());
+        self.wake.notify_one();
+        true
+    }
+
+    /// Check zones as their timers run out, for as long as the server runs.
+    pub async fn run(&self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        loop {
+            let now = self.clock.now();
+            let due: Vec<usize> = self
+                .timers
+                .lock()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .filter(|(_, timer)| timer.next_check <= now)
+                .map(|(index, _)| index)
+                .collect();
+            for index in due {
+                self.check(index).await;
+            }
+            let next_check = self
+                .timers
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|timer| timer.next_check)
+                .min();
+            match next_check {
+                Some(next_check) => tokio::select! {
+                    _ = self.clock.sleep_until(next_check) => {}
+                    _ = self.wake.notified() => {}
+                },
+                None => self.wake.notified().await,
+            }
+        }
+    }
+
+    /// Bring one zone up to date, and set its timer for the next check.
+    async fn check(&self, index: usize) {
+        let zone = &self.zones[index];
+        self.timers.lock().unwrap()[index].checking();
+        let local = self
+            .catalog
+            .read()
+            .unwrap()
+            .zones()
+            .iter()
+            .find(|local| name::eq(&local.origin, &zone.origin))
+            .cloned();
+        let result = self.fetch(zone, local.as_ref()).await;
+        let now = self.clock.now();
+        let mut timers = self.timers.lock().unwrap();
+        let timer = &mut timers[index];
+        match result {
+            Ok(Some(updated)) => {
+                println!(
+                    "Transferred zone {} at serial {}",
+                    zone.origin,
+                    updated.serial().unwrap_or_default()
+                );
+                if let Some(soa) = updated.soa() {
+                    timer.refreshed(soa, now);
+                }
+                self.catalog.write().unwrap().insert(updated);
+            }
+            Ok(None) => {
+                if let Some(soa) = local.as_ref().and_then(|local| local.soa()) {
+                    timer.refreshed(soa, now);
+                }
+            }
+            Err(err) => {
+                eprintln!("Could not refresh zone {}: {}", zone.origin, err);
+                if timer.failed(local.as_ref().and_then(|local| local.soa()), now) {
+                    eprintln!("Zone {} has expired, no longer serving it", zone.origin);
+                    self.catalog.write().unwrap().remove(&zone.origin);
+                }
+            }
+        }
+    }
+
+    /// Ask each primary in turn for its serial, and transfer the zone from
+    /// the first that answers if it has a newer version. Returns `None` if
+    /// our copy is up to date.
+    async fn fetch(
+        &self,
+        zone: &SecondaryZone,
+        local: Option<&Zone>,
+    ) -> Result<Option<Zone>, Box<dyn error::Error + Send + Sync>> {
+        let mut last_err = anyhow!("zone {} has no primaries", zone.origin).into();
+        for primary in &zone.primaries {
+            match self.fetch_from(*primary, &zone.origin, local).await {
+                Ok(updated) => return Ok(updated),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    async fn fetch_from(
+        &self,
+        primary: SocketAddr,
+        origin: &str,
+        local: Option<&Zone>,
+    ) -> Result<Option<Zone>, Box<dyn error::Error + Send + Sync>> {
+        let key = self.key.as_ref();
+        let request = dns::packet::build_query(rand::random(), origin, &RecordType::Soa);
+        let response = client::udp::query_signed(primary, &request, key, REFRESH_TIMEOUT).await?;
+        if response.header.rcode != ResponseCode::Success {
+            return Err(anyhow!(
+                "{} answered SOA query for {} with {}",
+                primary,
+                origin,
+                header::response_code_name(&response.header.rcode)
+            )
+            .into());
+        }
+        let serial = response
+            .answers
+            .iter()
+            .find(|record| record.record_type == RecordType::Soa && name::eq(&record.name, origin))
+            .and_then(journal::serial)
+            .ok_or_else(|| anyhow!("{} sent no SOA record for {}", primary, origin))?;
+        match local {
+            Some(local)
+                if local
+                    .serial()
+                    .is_some_and(|local| !dnssec::time_before(local, serial)) =>
+            {
+                Ok(None)
+            }
+            Some(local) => Ok(Some(
+                client::transfer::ixfr(primary, local, key, REFRESH_TIMEOUT).await?,
+            )),
+            None => Ok(Some(
+                client::transfer::axfr(primary, origin, key, REFRESH_TIMEOUT).await?,
+            )),
+        }
+    }
+
+    fn position(&self, origin: &str) -> Option<usize> {
+        self.zones
+            .iter()
+            .position(|zone| name::eq(&zone.origin, origin))
+    }
+}
+
+/// Passes NOTIFY messages on to a `Secondary`, and every other request to
+/// another handler.
+pub struct AcceptNotify<H> {
+    secondary: Arc<Secondary>,
+    inner: H,
+}
+
+impl<H> AcceptNotify<H> {
+    pub fn new(secondary: Arc<Secondary>, inner: H) -> AcceptNotify<H> {
+        AcceptNotify { secondary, inner }
+    }
+}
+
+#[async_trait]
+impl<H: RequestHandler> RequestHandler for AcceptNotify<H> {
+    async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+        if request.header.opcode != Opcode::Notify {
+            return self.inner.handle(request, ctx).await;
+        }
+        let rcode = match &request.questions[..] {
+            [question] if self.secondary.notified(&question.name, ctx.peer.ip()) => {
+                ResponseCode::Success
+            }
+            [_] => ResponseCode::Refused,
+            _ => ResponseCode::FormatError,
+        };
+        let mut response = response::error_response(request, rcode);
+        response.header.authoritative_answer = rcode == ResponseCode::Success;
+        response
+    }
+
+    async fn transfer(&self, request: &DnsPacket, ctx: &RequestContext) -> Vec<DnsPacket> {
+        self.inner.transfer(request, ctx).await
+    }
+
+    /// Our copies of secondary zones are overwritten by the next transfer,
+    /// so they can only be updated on the primary. We don't forward updates
+    /// there as RFC 2136 §6 suggests, so they are refused, as BIND does.
+    async fn update(
+        &self,
+        request: &DnsPacket,
+        update: &Update,
+        ctx: &RequestContext,
+    ) -> DnsPacket {
+        if self.secondary.position(&update.zone).is_some() {
+            return response::error_response(request, ResponseCode::Refused);
+        }
+        self.inner.update(request, update, ctx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::{Acl, Rule};
+    use crate::clock::VirtualClock;
+    use crate::handler::Protocol;
+    use crate::notify;
+    use crate::runtime::{Server, ServerConfig};
+    use tokio::net::{TcpListener, UdpSocket};
+
+    fn version(serial: u32) -> Zone {
+        let text = format!(
+            "@ 60 SOA ns hostmaster {} 3600 600 86400 60\nwww 60 A 192.0.2.{}",
+            serial, serial
+        );
+        dns::zone::parse_zone(&text, Some("example.com")).unwrap()
+    }
+
+    /// Serve a catalog over UDP and TCP on the same port, letting anyone
+    /// transfer its zones.
+    async fn start_primary(
+        catalog: Arc<RwLock<Catalog>>,
+    ) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let sock = UdpSocket::bind(addr).await?;
+        let config = ServerConfig {
+            allow_transfer: Acl::new(vec![Rule::Any]),
+            ..ServerConfig::default()
+        };
+        let server = Server::new(catalog, config);
+        tokio::spawn(async move {
+            tokio::try_join!(server.serve_udp(sock), server.serve_tcp(listener))
+        });
+        Ok(addr)
+    }
+
+    /// Wait for the secondary's copy of the zone to reach a serial.
+    async fn wait_for_serial(catalog: &RwLock<Catalog>, serial: Option<u32>) -> bool {
+        for _ in 0..200 {
+            let current = catalog
+                .read()
+                .unwrap()
+                .find_zone("example.com")
+                .and_then(|zone| zone.serial());
+            if current == serial {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[test]
+    fn test_timer_follows_soa_intervals() {
+        let start = SystemTime::UNIX_EPOCH;
+        let soa = version(1).soa().unwrap().clone();
+        let mut timer = Timer::new(start);
+        assert_eq!(timer.next_check, start);
+
+        // Nothing loaded yet, so there's nothing to expire
+        assert!(!timer.failed(None, start));
+        assert_eq!(timer.next_check, start + INITIAL_RETRY);
+
+        timer.refreshed(&soa, start);
+        assert_eq!(timer.next_check, start + Duration::from_secs(3600));
+        timer.notified(start + Duration::from_secs(10));
+        assert_eq!(timer.next_check, start + Duration::from_secs(10));
+
+        // A NOTIFY during a check means checking again once it's done
+        timer.checking();
+        timer.notified(start + Duration::from_secs(11));
+        timer.refreshed(&soa, start + Duration::from_secs(12));
+        assert_eq!(timer.next_check, start + Duration::from_secs(12));
+        timer.checking();
+        timer.refreshed(&soa, start);
+
+        let mut now = start;
+        while !timer.failed(Some(&soa), now) {
+            assert_eq!(timer.next_check, now + Duration::from_secs(600));
+            now = timer.next_check;
+        }
+        assert_eq!(now, start + Duration::from_secs(86400));
+    }
+
+    #[tokio::test]
+    async fn test_secondary_follows_primary_on_timers_and_notify(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut primary_catalog = Catalog::new();
+        primary_catalog.insert(version(1));
+        let primary_catalog = Arc::new(RwLock::new(primary_catalog));
+        let primary = start_primary(primary_catalog.clone()).await?;
+
+        let catalog = Arc::new(RwLock::new(Catalog::new()));
+        let clock = Arc::new(VirtualClock::new(SystemTime::UNIX_EPOCH));
+        let zone = SecondaryZone {
+            origin: "example.com".to_string(),
+            primaries: vec![primary],
+        };
+        let secondary = Arc::new(Secondary::new(
+            catalog.clone(),
+            vec![zone],
+            None,
+            clock.clone(),
+        ));
+        tokio::spawn({
+            let secondary = secondary.clone();
+            async move { secondary.run().await }
+        });
+
+        // The zone is loaded straight away
+        assert!(wait_for_serial(&catalog, Some(1)).await);
+
+        // Changes are picked up once the refresh interval has passed
+        primary_catalog.write().unwrap().insert(version(2));
+        clock.advance(Duration::from_secs(3599));
+        assert!(!wait_for_serial(&catalog, Some(2)).await);
+        clock.advance(Duration::from_secs(1));
+        assert!(wait_for_serial(&catalog, Some(2)).await);
+
+        // Or straight away, when the primary sends a NOTIFY
+        primary_catalog.write().unwrap().insert(version(3));
+        let handler = AcceptNotify::new(secondary.clone(), catalog.clone());
+        let notify = notify::build_notify(1, version(3).soa().unwrap());
+        let stranger = RequestContext {
+            peer: "192.0.2.1:53".parse().unwrap(),
+            protocol: Protocol::Udp,
+            tsig_key: None,
+        };
+        let response = handler.handle(&notify, &stranger).await;
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        let ctx = RequestContext {
+            peer: SocketAddr::new(primary.ip(), 40000),
+            ..stranger
+        };
+        let response = handler.handle(&notify, &ctx).await;
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        assert!(wait_for_serial(&catalog, Some(3)).await);
+        assert_eq!(
+            catalog
+                .read()
+                .unwrap()
+                .journal("example.com")
+                .unwrap()
+                .len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secondary_drops_zone_once_expired(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut primary_catalog = Catalog::new();
+        primary_catalog.insert(version(1));
+        let primary = start_primary(Arc::new(RwLock::new(primary_catalog))).await?;
+        // A primary that has lost the zone, and so refuses to answer for it
+        let lost = start_primary(Arc::new(RwLock::new(Catalog::new()))).await?;
+
+        let catalog = Arc::new(RwLock::new(Catalog::new()));
+        let clock = Arc::new(VirtualClock::new(SystemTime::UNIX_EPOCH));
+        let zone = SecondaryZone {
+            origin: "example.com".to_string(),
+            primaries: vec![primary],
+        };
+        let secondary = Secondary::new(catalog.clone(), vec![zone], None, clock.clone());
+        secondary.check(0).await;
+        assert!(wait_for_serial(&catalog, Some(1)).await);
+
+        // Keep failing until the zone expires
+        let secondary = Secondary {
+            zones: vec![SecondaryZone {
+                origin: "example.com".to_string(),
+                primaries: vec![lost],
+            }],
+            ..secondary
+        };
+        let expires = secondary.timer("example.com").unwrap().expires.unwrap();
+        while clock.now() < expires {
+            clock.advance(Duration::from_secs(600));
+            secondary.check(0).await;
+        }
+        assert!(catalog.read().unwrap().find_zone("example.com").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secondary_zones_are_not_updated_locally() {
+        let mut catalog = Catalog::new();
+        catalog.insert(version(1));
+        let catalog = Arc::new(RwLock::new(catalog));
+        let zone = SecondaryZone {
+            origin: "example.com".to_string(),
+            primaries: vec!["192.0.2.53:53".parse().unwrap()],
+        };
+        let clock = Arc::new(VirtualClock::new(SystemTime::UNIX_EPOCH));
+        let secondary = Arc::new(Secondary::new(catalog.clone(), vec![zone], None, clock));
+        let updates =
+            crate::update::AcceptUpdates::new(catalog.clone(), Arc::new(vec![]), catalog.clone());
+        let handler = AcceptNotify::new(secondary, updates);
+
+        let update = Update::new("example.com").add_record(
+            dns::zone::parse_master_file("new 60 A 192.0.2.9", Some("example.com")).unwrap()[0]
+                .clone(),
+        );
+        let request = update.to_packet(1);
+        let ctx = RequestContext {
+            peer: "192.0.2.1:5353".parse().unwrap(),
+            protocol: Protocol::Udp,
+            tsig_key: Some("dhcp".to_string()),
+        };
+        let response = handler.update(&request, &update, &ctx).await;
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        let catalog = catalog.read().unwrap();
+        assert_eq!(catalog.find_zone("example.com").unwrap().serial(), Some(1));
+    }
+}
@@ -0,0 +1,836 @@
+//! Signing zones online (RFC 4035 §2). A signer adds the zone's DNSKEY
+//! records, an NSEC or NSEC3 chain and a signature over every authoritative
+//! RRset, and signs the zone again before those signatures expire.
+
+use super::authority::Catalog;
+use anyhow::anyhow;
+use dns::dnssec;
+use dns::key::{KeyState, SigningKey};
+use dns::name;
+use dns::record::{self, Class, Data, Record, RecordType};
+use dns::zone::Zone;
+use std::error;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// The keys that sign the DNSKEY RRset, and those that sign everything else.
+type KeySplit<'a> = (Vec<&'a SigningKey>, Vec<&'a SigningKey>);
+
+/// The longest we wait between checks on whether zones need signing again.
+const MAX_RESIGN_WAIT: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before trying again when a zone can't be signed.
+const RESIGN_RETRY_WAIT: Duration = Duration::from_secs(5 * 60);
+
+/// How a signed zone proves that names and types don't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    Nsec,
+    /// Hashed names (RFC 5155). With `opt_out`, unsigned delegations are left
+    /// out of the chain.
+    Nsec3 {
+        salt: Vec<u8>,
+        iterations: u16,
+        opt_out: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SignerConfig {
+    pub denial: Denial,
+    /// How long signatures are valid for.
+    pub validity: Duration,
+    /// Zones are signed again once their signatures have less than this
+    /// left.
+    pub refresh: Duration,
+    /// How far in the past signatures become valid, so validators with
+    /// clocks that are a little behind still accept them.
+    pub inception_offset: Duration,
+}
+
+impl Default for SignerConfig {
+    fn default() -> SignerConfig {
+        SignerConfig {
+            denial: Denial::Nsec,
+            validity: Duration::from_secs(30 * 24 * 60 * 60),
+            refresh: Duration::from_secs(7 * 24 * 60 * 60),
+            inception_offset: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Signs a single zone with a set of keys. Active keys with the SEP flag
+/// (KSKs) sign the DNSKEY RRset, and the others (ZSKs) sign everything else.
+/// If there are only active keys of one kind, they sign everything.
+#[derive(Debug)]
+pub struct Signer {
+    origin: String,
+    keys: Vec<SigningKey>,
+    config: SignerConfig,
+}
+
+impl Signer {
+    pub fn new(
+        keys: Vec<SigningKey>,
+        config: SignerConfig,
+    ) -> Result<Signer, Box<dyn error::Error + Send + Sync>> {
+        let origin = match keys.first() {
+            Some(key) => name::normalize(&key.owner),
+            None => return Err(anyhow!("a zone needs at least one key to be signed").into()),
+        };
+        if let Some(key) = keys.iter().find(|key| !name::eq(&key.owner, &origin)) {
+            return Err(
+                anyhow!("key {} is for {}, not {}", key.key_tag(), key.owner, origin).into(),
+            );
+        }
+        Ok(Signer {
+            origin,
+            keys,
+            config,
+        })
+    }
+
+    /// The zone the signer's keys belong to.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn keys(&self) -> &[SigningKey] {
+        &self.keys
+    }
+
+    pub fn config(&self) -> &SignerConfig {
+        &self.config
+    }
+
+    /// Sign a zone, replacing any DNSSEC records it already has. Names below
+    /// zone cuts are glue, and are neither signed nor part of the chain. Keys
+    /// are published and sign according to their state at `now`.
+    pub fn sign(
+        &self,
+        zone: &Zone,
+        now: SystemTime,
+    ) -> Result<Zone, Box<dyn error::Error + Send + Sync>> {
+        if !name::eq(&zone.origin, &self.origin) {
+            return Err(anyhow!("keys are for {}, not {}", self.origin, zone.origin).into());
+        }
+        let (soa_ttl, minimum) = match zone.soa() {
+            Some(Record {
+                ttl,
+                data: Data::Soa { minimum, .. },
+                ..
+            }) => (*ttl, *minimum),
+            _ => return Err(anyhow!("zone {} has no SOA record", zone.origin).into()),
+        };
+        let (ksks, zsks) = self.split_keys(now)?;
+        let dnskeys: Vec<Record> = self.keys.iter().map(SigningKey::dnskey_record).collect();
+        let mut unsigned = Zone::new(&self.origin);
+        for record in zone
+            .records()
+            .filter(|record| !is_generated(record, &dnskeys))
+        {
+            unsigned.insert(record.clone())?;
+        }
+        for key in &self.keys {
+            let state = key.state(now);
+            if matches!(
+                state,
+                KeyState::Published | KeyState::Active | KeyState::Retired
+            ) {
+                unsigned.insert(key.dnskey_record())?;
+            }
+        }
+        if let Denial::Nsec3 {
+            salt, iterations, ..
+        } = &self.config.denial
+        {
+            unsigned.insert(Record {
+                name: self.origin.clone(),
+                record_type: RecordType::Nsec3param,
+                class: Class::In,
+                ttl: 0,
+                data: Data::Nsec3param {
+                    hash_algorithm: dnssec::NSEC3_SHA1,
+                    flags: 0,
+                    iterations: *iterations,
+                    salt: salt.clone(),
+                },
+            })?;
+        }
+
+        let cuts: Vec<String> = unsigned
+            .records()
+            .filter(|record| {
+                record.record_type == RecordType::Ns && !name::eq(&record.name, &self.origin)
+            })
+            .map(|record| name::normalize(&record.name))
+            .collect();
+        let below_cut = |owner: &str| {
+            cuts.iter()
+                .any(|cut| name::is_subdomain(owner, cut) && !name::eq(owner, cut))
+        };
+        let authoritative: Vec<String> = owners(&unsigned)
+            .into_iter()
+            .filter(|owner| !below_cut(owner))
+            .collect();
+
+        // RFC 9077: negative answers last no longer than the SOA record
+        let denial_ttl = (minimum.min(i32::MAX as u32) as i32).min(soa_ttl);
+        let chain = match &self.config.denial {
+            Denial::Nsec => nsec_chain(&authoritative, &unsigned, denial_ttl),
+            Denial::Nsec3 {
+                salt,
+                iterations,
+                opt_out,
+            } => {
+                let chain = Nsec3Chain {
+                    origin: &self.origin,
+                    salt,
+                    iterations: *iterations,
+                    opt_out: *opt_out,
+                    ttl: denial_ttl,
+                };
+                chain.build(&authoritative, &unsigned, &cuts)?
+            }
+        };
+        for record in chain {
+            unsigned.insert(record)?;
+        }
+
+        let inception = dnssec::signature_time(now - self.config.inception_offset);
+        let expiration = dnssec::signature_time(now + self.config.validity);
+        let mut signed = unsigned.clone();
+        for owner in owners(&unsigned).iter().filter(|owner| !below_cut(owner)) {
+            let records = unsigned.lookup(owner);
+            for record_type in types_at(&unsigned, owner) {
+                // Only the DS and NSEC records at a zone cut belong to us
+                if cuts.contains(owner) && !matches!(record_type, RecordType::Ds | RecordType::Nsec)
+                {
+                    continue;
+                }
+                let rrset: Vec<Record> = records
+                    .iter()
+                    .filter(|record| record.record_type == record_type)
+                    .cloned()
+                    .collect();
+                let keys = if record_type == RecordType::Dnskey {
+                    &ksks
+                } else {
+                    &zsks
+                };
+                for key in keys {
+                    signed.insert(key.sign(&rrset, inception, expiration)?)?;
+                }
+            }
+        }
+        Ok(signed)
+    }
+
+    /// The DS records the parent zone should publish for the signer's key
+    /// signing keys, whatever state they are in.
+    pub fn ds_records(
+        &self,
+        digest_type: u8,
+    ) -> Result<Vec<Record>, Box<dyn error::Error + Send + Sync>> {
+        self.keys
+            .iter()
+            .filter(|key| key.is_key_signing_key())
+            .map(|key| key.ds_record(digest_type))
+            .collect()
+    }
+
+    /// How long until a zone signed by us needs signing again: when the first
+    /// of our signatures comes within the refresh window of expiring, or a
+    /// key changes state. Zones without any of our signatures need signing
+    /// straight away.
+    pub fn resign_after(&self, zone: &Zone, now: SystemTime) -> Duration {
+        let now = dnssec::signature_time(now);
+        let next_change = self
+            .keys
+            .iter()
+            .filter_map(|key| key.timing.next_change(now))
+            .map(|time| Duration::from_secs(time.wrapping_sub(now) as u64))
+            .min()
+            .unwrap_or(Duration::MAX);
+        let remaining = zone
+            .records()
+            .filter_map(|record| match &record.data {
+                Data::Rrsig {
+                    expiration,
+                    key_tag,
+                    signer_name,
+                    ..
+                } if name::eq(signer_name, &self.origin)
+                    && self.keys.iter().any(|key| key.key_tag() == *key_tag) =>
+                {
+                    // Serial number arithmetic, so expired signatures are
+                    // negative
+                    Some(expiration.wrapping_sub(now) as i32 as i64)
+                }
+                _ => None,
+            })
+            .min();
+        match remaining {
+            Some(remaining) => {
+                let wait = remaining - self.config.refresh.as_secs() as i64;
+                Duration::from_secs(wait.max(0) as u64).min(next_change)
+            }
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Split the keys that are active at `now` by what they sign.
+    fn split_keys(
+        &self,
+        now: SystemTime,
+    ) -> Result<KeySplit<'_>, Box<dyn error::Error + Send + Sync>> {
+        let (ksks, zsks): (Vec<&SigningKey>, Vec<&SigningKey>) = self
+            .keys
+            .iter()
+            .filter(|key| key.state(now) == KeyState::Active)
+            .partition(|key| key.is_key_signing_key());
+        match (ksks.is_empty(), zsks.is_empty()) {
+            (true, true) => Err(anyhow!("zone {} has no active keys", self.origin).into()),
+            (true, _) => Ok((zsks.clone(), zsks)),
+            (_, true) => Ok((ksks.clone(), ksks)),
+            _ => Ok((ksks, zsks)),
+        }
+    }
+}
+
+/// Sign a zone in the catalog again if it needs it, bumping its serial so
+/// secondaries pick up the new signatures. Returns how long until it needs
+/// signing again. The catalog isn't locked while signing, so queries are
+/// still answered, and if the zone changes in the meantime it is looked at
+/// again straight away.
+pub fn resign(
+    catalog: &RwLock<Catalog>,
+    signer: &Signer,
+    now: SystemTime,
+) -> Result<Duration, Box<dyn error::Error + Send + Sync>> {
+    let mut zone = catalog
+        .read()
+        .unwrap()
+        .zones()
+        .iter()
+        .find(|zone| name::eq(&zone.origin, signer.origin()))
+        .cloned()
+        .ok_or_else(|| anyhow!("zone {} is not in the catalog", signer.origin()))?;
+    let wait = signer.resign_after(&zone, now);
+    if !wait.is_zero() {
+        return Ok(wait);
+    }
+    let serial = zone.serial();
+    zone.increment_serial();
+    let signed = signer.sign(&zone, now)?;
+    let wait = signer.resign_after(&signed, now);
+    if !catalog.write().unwrap().replace(signed, serial) {
+        return Ok(Duration::ZERO);
+    }
+    Ok(wait)
+}
+
+/// Keep zones in a catalog signed for as long as the server runs. A zone
+/// that can't be signed, say because none of its keys are active any more,
+/// is served as it was last signed until signing it works again. This never
+/// returns.
+pub async fn keep_signed(
+    catalog: Arc<RwLock<Catalog>>,
+    signers: &[Signer],
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    loop {
+        let mut wait = MAX_RESIGN_WAIT;
+        for signer in signers {
+            let next = match resign(&catalog, signer, SystemTime::now()) {
+                Ok(next) => next,
+                Err(err) => {
+                    eprintln!(
+                        "Failed to sign zone {}, retrying in {:?}: {}",
+                        signer.origin(),
+                        RESIGN_RETRY_WAIT,
+                        err
+                    );
+                    RESIGN_RETRY_WAIT
+                }
+            };
+            wait = wait.min(next);
+        }
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Records the signer makes itself, which are dropped before signing again.
+/// DNSKEYs for keys we don't hold are kept, so new keys can be published
+/// ahead of time.
+fn is_generated(record: &Record, dnskeys: &[Record]) -> bool {
+    match record.record_type {
+        RecordType::Rrsig | RecordType::Nsec | RecordType::Nsec3 | RecordType::Nsec3param => true,
+        RecordType::Dnskey => dnskeys.iter().any(|dnskey| dnskey.data == record.data),
+        _ => false,
+    }
+}
+
+/// Every name in a zone, in canonical order.
+fn owners(zone: &Zone) -> Vec<String> {
+    let mut owners: Vec<String> = vec![];
+    for record in zone.records() {
+        let owner = name::normalize(&record.name);
+        if owners.last() != Some(&owner) {
+            owners.push(owner);
+        }
+    }
+    owners
+}
+
+/// The types present at a name, as listed in its NSEC or NSEC3 record.
+fn types_at(zone: &Zone, owner: &str) -> Vec<RecordType> {
+    let mut types: Vec<RecordType> = zone
+        .lookup(owner)
+        .iter()
+        .map(|record| record.record_type)
+        .collect();
+    types.sort_by_key(record::record_type_code);
+    types.dedup();
+    types
+}
+
+fn nsec_chain(owners: &[String], zone: &Zone, ttl: i32) -> Vec<Record> {
+    owners
+        .iter()
+        .enumerate()
+        .map(|(index, owner)| {
+            let mut types = types_at(zone, owner);
+            types.extend([RecordType::Rrsig, RecordType::Nsec]);
+            types.sort_by_key(record::record_type_code);
+            types.dedup();
+            Record {
+                name: owner.clone(),
+                record_type: RecordType::Nsec,
+                class: Class::In,
+                ttl,
+                data: Data::Nsec {
+                    next_name: owners[(index + 1) % owners.len()].clone(),
+                    types,
+                },
+            }
+        })
+        .collect()
+}
+
+/// The parameters of an NSEC3 chain.
+struct Nsec3Chain<'a> {
+    origin: &'a str,
+    salt: &'a [u8],
+    iterations: u16,
+    opt_out: bool,
+    ttl: i32,
+}
+
+impl Nsec3Chain<'_> {
+    /// Build the chain over the hashes of every name in the zone, including
+    /// empty non-terminals, which exist as far as NSEC3 is concerned.
+    fn build(
+        &self,
+        owners: &[String],
+        zone: &Zone,
+        cuts: &[String],
+    ) -> Result<Vec<Record>, Box<dyn error::Error + Send + Sync>> {
+        let mut names: Vec<String> = vec![];
+        for owner in owners {
+            let types = types_at(zone, owner);
+            let unsigned_delegation = cuts.contains(owner) && !types.contains(&RecordType::Ds);
+            if self.opt_out && unsigned_delegation {
+                continue;
+            }
+            let mut current = owner.clone();
+            while !names.contains(&current) {
+                names.push(current.clone());
+                match name::parent(&current) {
+                    Some(parent) if name::is_subdomain(&parent, self.origin) => current = parent,
+                    _ => break,
+                }
+            }
+        }
+        let mut hashed = vec![];
+        for owner in &names {
+            let hash = dnssec::nsec3_hash(owner, self.salt, self.iterations)?;
+            let mut types = types_at(zone, owner);
+            // Unsigned delegations are the only names with records but no
+            // signatures
+            let unsigned_delegation = cuts.contains(owner) && !types.contains(&RecordType::Ds);
+            if !types.is_empty() && !unsigned_delegation {
+                types.push(RecordType::Rrsig);
+                types.sort_by_key(record::record_type_code);
+            }
+            hashed.push((hash, types));
+        }
+        hashed.sort_by(|a, b| a.0.cmp(&b.0));
+        let flags = if self.opt_out { dnssec::OPT_OUT } else { 0 };
+        Ok((0..hashed.len())
+            .map(|index| Record {
+                name: name::child(&dnssec::encode_base32hex(&hashed[index].0), self.origin),
+                record_type: RecordType::Nsec3,
+                class: Class::In,
+                ttl: self.ttl,
+                data: Data::Nsec3 {
+                    hash_algorithm: dnssec::NSEC3_SHA1,
+                    flags,
+                    iterations: self.iterations,
+                    salt: self.salt.to_vec(),
+                    next_hashed: hashed[(index + 1) % hashed.len()].0.clone(),
+                    types: hashed[index].1.clone(),
+                },
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::key::{self, KeyTiming};
+    use dns::zone;
+    use std::time::UNIX_EPOCH;
+
+    /// The Ed25519 key from RFC 8080 §6.1, as a KSK.
+    const KSK: (&str, &str) = (
+        "example.com. 3600 IN DNSKEY 257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=",
+        "Algorithm: 15 (ED25519)\nPrivateKey: ODIyNjAzODQ2MjgwODAxMjI2NDUxOTAyMDQxNDIyNjI=",
+    );
+
+    /// The P-256 key from RFC 6605 §6.1, moved to example.com as a ZSK.
+    const ZSK: (&str, &str) = (
+        "example.com. 3600 IN DNSKEY 256 3 13 GojIhhXUN/u4v54ZQqGSnyhWJwaubCvTmeexv7bR6edbkrSqQpF64cYbcB7wNcP+e+MAnLr+Wi9xMWyQLc8NAA==",
+        "Algorithm: 13 (ECDSAP256SHA256)\nPrivateKey: GU6SnQ/Ou+xC5RumuIUIuJZteXT2z0O/ok1s38Et6mQ=",
+    );
+
+    const ZONE: &str = "
+$ORIGIN example.com.
+@           3600 SOA ns hostmaster 1 7200 900 1209600 300
+@           3600 NS ns
+ns          3600 A 192.0.2.1
+www.deep    3600 A 192.0.2.2
+*.wild      3600 TXT \"hello\"
+secure      3600 NS ns.secure
+secure      3600 DS 3613 15 2 3AA5AB37EFCE57F737FC1627013FEE07BDF241BD10F3B1964AB55C78E79A304B
+ns.secure   3600 A 192.0.2.3
+insecure    3600 NS ns.insecure
+ns.insecure 3600 A 192.0.2.4
+";
+
+    fn signer(denial: Denial) -> Signer {
+        let keys = [KSK, ZSK]
+            .iter()
+            .map(|(public, private)| SigningKey::parse(public, private).unwrap())
+            .collect();
+        let config = SignerConfig {
+            denial,
+            validity: Duration::from_secs(10 * 86400),
+            refresh: Duration::from_secs(2 * 86400),
+            ..SignerConfig::default()
+        };
+        Signer::new(keys, config).unwrap()
+    }
+
+    fn at(days: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + days * 86400)
+    }
+
+    /// The types of the RRsets at a name that are signed, and the key tags
+    /// that signed them.
+    fn signed_types(zone: &Zone, owner: &str) -> Vec<(RecordType, u16)> {
+        zone.lookup(owner)
+            .iter()
+            .filter_map(|record| match &record.data {
+                Data::Rrsig {
+                    type_covered,
+                    key_tag,
+                    ..
+                } => Some((*type_covered, *key_tag)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Check every signature in a zone against the signer's keys.
+    fn verify_all(zone: &Zone, signer: &Signer) {
+        for rrsig in zone
+            .records()
+            .filter(|record| record.record_type == RecordType::Rrsig)
+        {
+            let (type_covered, key_tag) = match &rrsig.data {
+                Data::Rrsig {
+                    type_covered,
+                    key_tag,
+                    ..
+                } => (*type_covered, *key_tag),
+                _ => unreachable!(),
+            };
+            let key = signer
+                .keys()
+                .iter()
+                .find(|key| key.key_tag() == key_tag)
+                .unwrap();
+            let rrset: Vec<Record> = zone
+                .rrset(&rrsig.name, type_covered)
+                .into_iter()
+                .cloned()
+                .collect();
+            dnssec::verify_signature(&key.dnskey, &rrsig.data, &rrset).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sign_zone_with_nsec() {
+        let signer = signer(Denial::Nsec);
+        let (ksk, zsk) = (signer.keys()[0].key_tag(), signer.keys()[1].key_tag());
+        let zone = zone::parse_zone(ZONE, None).unwrap();
+        let signed = signer.sign(&zone, at(0)).unwrap();
+        verify_all(&signed, &signer);
+
+        let apex = signed_types(&signed, "example.com");
+        assert!(apex.contains(&(RecordType::Dnskey, ksk)));
+        assert!(!apex.contains(&(RecordType::Dnskey, zsk)));
+        assert!(apex.contains(&(RecordType::Soa, zsk)));
+        assert!(!apex.contains(&(RecordType::Soa, ksk)));
+
+        // Only the DS and NSEC records at a cut are ours to sign, and glue
+        // isn't signed at all
+        let cut = signed_types(&signed, "secure.example.com");
+        assert_eq!(cut, vec![(RecordType::Ds, zsk), (RecordType::Nsec, zsk)]);
+        assert!(signed_types(&signed, "ns.secure.example.com").is_empty());
+        assert!(signed
+            .rrset("ns.insecure.example.com", RecordType::Nsec)
+            .is_empty());
+
+        let chain: Vec<(String, String)> = signed
+            .records()
+            .filter_map(|record| match &record.data {
+                Data::Nsec { next_name, .. } => Some((record.name.clone(), next_name.clone())),
+                _ => None,
+            })
+            .collect();
+        let owners = [
+            "example.com",
+            "www.deep.example.com",
+            "insecure.example.com",
+            "ns.example.com",
+            "secure.example.com",
+            "*.wild.example.com",
+        ];
+        for (index, (owner, next_name)) in chain.iter().enumerate() {
+            assert_eq!(owner, owners[index]);
+            assert_eq!(next_name, owners[(index + 1) % owners.len()]);
+        }
+        assert_eq!(chain.len(), owners.len());
+        let insecure = signed.rrset("insecure.example.com", RecordType::Nsec);
+        assert!(matches!(
+            &insecure[0].data,
+            Data::Nsec { types, .. } if *types == [RecordType::Ns, RecordType::Rrsig, RecordType::Nsec]
+        ));
+
+        // Signing again replaces the old records rather than adding to them
+        let again = signer.sign(&signed, at(1)).unwrap();
+        assert_eq!(again.records().count(), signed.records().count());
+        assert_eq!(again.rrset("example.com", RecordType::Dnskey).len(), 2);
+    }
+
+    #[test]
+    fn test_sign_zone_with_nsec3() {
+        for opt_out in [false, true] {
+            let signer = signer(Denial::Nsec3 {
+                salt: vec![0xaa, 0xbb],
+                iterations: 1,
+                opt_out,
+            });
+            let zone = zone::parse_zone(ZONE, None).unwrap();
+            let signed = signer.sign(&zone, at(0)).unwrap();
+            verify_all(&signed, &signer);
+            assert_eq!(signed.rrset("example.com", RecordType::Nsec3param).len(), 1);
+
+            let hashed = |value: &str| {
+                let owner = dnssec::nsec3_owner(value, "example.com", &[0xaa, 0xbb], 1).unwrap();
+                signed
+                    .rrset(&owner, RecordType::Nsec3)
+                    .into_iter()
+                    .next()
+                    .cloned()
+            };
+            let types = |value: &str| match hashed(value).map(|record| record.data) {
+                Some(Data::Nsec3 { types, .. }) => types,
+                _ => panic!("no NSEC3 record for {}", value),
+            };
+            assert!(types("example.com").contains(&RecordType::Nsec3param));
+            assert_eq!(types("deep.example.com"), vec![]);
+            assert_eq!(types("wild.example.com"), vec![]);
+            assert_eq!(
+                types("secure.example.com"),
+                vec![RecordType::Ns, RecordType::Ds, RecordType::Rrsig]
+            );
+            assert!(hashed("ns.secure.example.com").is_none());
+            // Opt-out leaves unsigned delegations out of the chain
+            assert_eq!(hashed("insecure.example.com").is_none(), opt_out);
+            let count = signed
+                .records()
+                .filter(|record| record.record_type == RecordType::Nsec3)
+                .count();
+            assert_eq!(count, if opt_out { 7 } else { 8 });
+        }
+    }
+
+    #[test]
+    fn test_ds_records_are_for_key_signing_keys() {
+        let ds = signer(Denial::Nsec).ds_records(dnssec::SHA256).unwrap();
+        assert_eq!(ds.len(), 1);
+        // RFC 8080 §6.1
+        assert_eq!(
+            zone::format_data(&ds[0].data),
+            "3613 15 2 3AA5AB37EFCE57F737FC1627013FEE07BDF241BD10F3B1964AB55C78E79A304B"
+        );
+    }
+
+    #[test]
+    fn test_resign_before_signatures_expire() {
+        let signer = signer(Denial::Nsec);
+        let zone = zone::parse_zone(ZONE, None).unwrap();
+        let serial = |catalog: &RwLock<Catalog>| match &catalog.read().unwrap().zones()[0]
+            .soa()
+            .unwrap()
+            .data
+        {
+            Data::Soa { serial, .. } => *serial,
+            _ => unreachable!(),
+        };
+        let day = Duration::from_secs(86400);
+
+        let mut catalog = Catalog::new();
+        catalog.insert(signer.sign(&zone, at(0)).unwrap());
+        let catalog = RwLock::new(catalog);
+        assert_eq!(resign(&catalog, &signer, at(1)).unwrap(), 7 * day);
+        assert_eq!(serial(&catalog), 1);
+
+        assert_eq!(resign(&catalog, &signer, at(9)).unwrap(), 8 * day);
+        assert_eq!(serial(&catalog), 2);
+        verify_all(&catalog.read().unwrap().zones()[0], &signer);
+
+        // Zones that were never signed are signed straight away
+        let mut catalog = Catalog::new();
+        catalog.insert(zone);
+        let catalog = RwLock::new(catalog);
+        assert_eq!(resign(&catalog, &signer, at(0)).unwrap(), 8 * day);
+        assert_eq!(serial(&catalog), 2);
+    }
+
+    #[test]
+    fn test_updated_zones_are_signed_again() {
+        let signer = signer(Denial::Nsec);
+        let zone = zone::parse_zone(ZONE, None).unwrap();
+        let mut catalog = Catalog::new();
+        catalog.insert(signer.sign(&zone, at(0)).unwrap());
+        let catalog = RwLock::new(catalog);
+
+        let mut request = dns::packet::build_query(1, "example.com", &RecordType::Soa);
+        request.authoritative_entries =
+            zone::parse_master_file("new 3600 A 192.0.2.9", Some("example.com")).unwrap();
+        let update = dns::update::Update::parse(&request).unwrap();
+        let signers = [signer];
+        let rcode = crate::update::apply(&catalog, &update, &signers, at(1));
+        assert_eq!(rcode, dns::header::ResponseCode::Success);
+
+        let catalog = catalog.read().unwrap();
+        let zone = &catalog.zones()[0];
+        assert_eq!(zone.serial(), Some(2));
+        assert_eq!(
+            signed_types(zone, "new.example.com")
+                .iter()
+                .filter(|(record_type, _)| *record_type == RecordType::A)
+                .count(),
+            1
+        );
+        verify_all(zone, &signers[0]);
+    }
+
+    #[test]
+    fn test_zone_signing_key_rollover() {
+        let time = |days| Some(dnssec::signature_time(at(days)));
+        let mut keys: Vec<SigningKey> = [KSK, ZSK]
+            .iter()
+            .map(|(public, private)| SigningKey::parse(public, private).unwrap())
+            .collect();
+        keys[1].timing = KeyTiming {
+            inactive: time(5),
+            delete: time(8),
+            ..KeyTiming::default()
+        };
+        let timing = KeyTiming {
+            publish: time(0),
+            activate: time(5),
+            ..KeyTiming::default()
+        };
+        let files = key::generate_key("example.com", dnssec::ECDSAP256SHA256, 256, &timing);
+        keys.push(files.unwrap().load().unwrap());
+        let tags: Vec<u16> = keys.iter().map(SigningKey::key_tag).collect();
+        let signer = Signer::new(
+            keys,
+            SignerConfig {
+                validity: Duration::from_secs(10 * 86400),
+                refresh: Duration::from_secs(2 * 86400),
+                ..SignerConfig::default()
+            },
+        )
+        .unwrap();
+        let zone = zone::parse_zone(ZONE, None).unwrap();
+        let soa_signers = |zone: &Zone| -> Vec<u16> {
+            let signatures = signed_types(zone, "example.com").into_iter();
+            signatures
+                .filter(|(record_type, _)| *record_type == RecordType::Soa)
+                .map(|(_, key_tag)| key_tag)
+                .collect()
+        };
+
+        // The new key is published ahead of time, and the zone is signed
+        // again when it takes over
+        let signed = signer.sign(&zone, at(0)).unwrap();
+        assert_eq!(signed.rrset("example.com", RecordType::Dnskey).len(), 3);
+        assert_eq!(soa_signers(&signed), vec![tags[1]]);
+        assert_eq!(
+            signer.resign_after(&signed, at(0)),
+            Duration::from_secs(5 * 86400)
+        );
+
+        // The old key stays published until its signatures have expired
+        let signed = signer.sign(&signed, at(5)).unwrap();
+        assert_eq!(signed.rrset("example.com", RecordType::Dnskey).len(), 3);
+        assert_eq!(soa_signers(&signed), vec![tags[2]]);
+        verify_all(&signed, &signer);
+        let signed = signer.sign(&signed, at(8)).unwrap();
+        assert_eq!(signed.rrset("example.com", RecordType::Dnskey).len(), 2);
+        verify_all(&signed, &signer);
+    }
+
+    #[tokio::test]
+    async fn test_keep_signed_survives_inactive_keys() {
+        // The only key went inactive yesterday, without a successor
+        let (public, private) = ZSK;
+        let mut key = SigningKey::parse(public, private).unwrap();
+        let yesterday = SystemTime::now() - Duration::from_secs(86400);
+        key.timing = KeyTiming {
+            inactive: Some(dnssec::signature_time(yesterday)),
+            ..KeyTiming::default()
+        };
+        let signers = [Signer::new(vec![key], SignerConfig::default()).unwrap()];
+        let mut catalog = Catalog::new();
+        catalog.insert(zone::parse_zone(ZONE, None).unwrap());
+        let catalog = Arc::new(RwLock::new(catalog));
+        assert!(resign(&catalog, &signers[0], SystemTime::now()).is_err());
+
+        // The zone keeps being served as it was, rather than stopping the
+        // server
+        let signing = keep_signed(catalog.clone(), &signers);
+        let result = tokio::time::timeout(Duration::from_millis(50), signing).await;
+        assert!(result.is_err());
+        let catalog = catalog.read().unwrap();
+        assert_eq!(catalog.zones()[0].serial(), Some(1));
+        assert!(signed_types(&catalog.zones()[0], "example.com").is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use super::handler::{Protocol, RequestContext, RequestHandler};
+use super::runtime::Server;
+use std::error;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// How many responses may be waiting to be written to a single connection.
+const MAX_QUEUED_RESPONSES: usize = 32;
+
+/// Answer requests arriving over TCP, following RFC 7766. Each connection may
+/// pipeline several requests, and their responses are written as soon as
+/// they are ready, which may be out of order. With an acceptor, each
+/// connection starts with a TLS handshake, and the messages are carried
+/// inside the session in the same way (RFC 7858).
+pub(crate) async fn serve<H: RequestHandler + 'static>(
+    server: Server<H>,
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let connections = Arc::new(Semaphore::new(server.config().max_tcp_connections));
+    let protocol = match tls {
+        Some(_) => Protocol::Tls,
+        None => Protocol::Tcp,
+    };
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("Failed to accept TCP connection: {}", err);
+                continue;
+            }
+        };
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                println!("Too many TCP connections, closing connection from {}", peer);
+                continue;
+            }
+        };
+        let server = server.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(acceptor) => match handshake(&server, &acceptor, stream).await {
+                    Ok(stream) => handle_connection(server, stream, peer, protocol).await,
+                    Err(err) => Err(err),
+                },
+                None => handle_connection(server, stream, peer, protocol).await,
+            };
+            if let Err(err) = result {
+                eprintln!("{:?} connection with {} failed: {}", protocol, peer, err);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Run the server's side of a TLS handshake, giving up if the client takes
+/// longer than it would be allowed to sit idle.
+pub(crate) async fn handshake<H: RequestHandler + 'static>(
+    server: &Server<H>,
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+) -> Result<TlsStream<TcpStream>, io::Error> {
+    let idle_timeout = server.config().tcp_idle_timeout;
+    match tokio::time::timeout(idle_timeout, acceptor.accept(stream)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out waiting for TLS handshake",
+        )),
+    }
+}
+
+async fn handle_connection<H, S>(
+    server: Server<H>,
+    stream: S,
+    peer: SocketAddr,
+    protocol: Protocol,
+) -> Result<(), io::Error>
+where
+    H: RequestHandler + 'static,
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let idle_timeout = server.config().tcp_idle_timeout;
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Responses are funnelled through a single writer so that requests can be
+    // handled concurrently without interleaving their bytes
+    let (responses, mut queue) = mpsc::channel::<Vec<u8>>(MAX_QUEUED_RESPONSES);
+    let writer = tokio::spawn(async move {
+        while let Some(response) = queue.recv().await {
+            writer.write_all(&response).await?;
+        }
+        writer.shutdown().await
+    });
+
+    loop {
+        let request = match tokio::time::timeout(idle_timeout, read_message(&mut reader)).await {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) => break,
+            Ok(Err(err)) => {
+                eprintln!("Failed to read from {}: {}", peer, err);
+                break;
+            }
+            Err(_) => {
+                println!("Closing idle {:?} connection from {}", protocol, peer);
+                break;
+            }
+        };
+        let permit = server.acquire().await;
+        let server = server.clone();
+        let responses = responses.clone();
+        tokio::spawn(async move {
+            let ctx = RequestContext {
+                peer,
+                protocol,
+                tsig_key: None,
+            };
+            // Zone transfers span several messages, which are sent together
+            // so responses to other requests can't end up between them
+            let mut framed = vec![];
+            for response in server.handle_messages(&request, &ctx).await {
+                match dns::tcp::frame_message(&response) {
+                    Ok(response) => framed.extend(response),
+                    Err(err) => eprintln!("Could not send response to {}: {}", peer, err),
+                }
+            }
+            if !framed.is_empty() {
+                // This only fails if the connection has already died
+                let _ = responses.send(framed).await;
+            }
+            drop(permit);
+        });
+    }
+
+    // The writer finishes once every in-flight request has responded
+    drop(responses);
+    writer.await?
+}
+
+/// Read one length-prefixed message. Returns `None` if the stream ends
+/// between messages.
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut len = [0; 2];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response;
+    use crate::runtime::ServerConfig;
+    use async_trait::async_trait;
+    use dns::packet::DnsPacket;
+    use std::time::Duration;
+
+    /// Answers requests with even IDs more slowly than those with odd IDs.
+    struct EvenIsSlowHandler;
+
+    #[async_trait]
+    impl RequestHandler for EvenIsSlowHandler {
+        async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+            assert_eq!(ctx.protocol, Protocol::Tcp);
+            if request.header.id.is_multiple_of(2) {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            response::response_to(request)
+        }
+    }
+
+    fn query(id: u16) -> Vec<u8> {
+        let mut query = include_bytes!("../../examples/query_packet").to_vec();
+        query[0..2].copy_from_slice(&id.to_be_bytes());
+        dns::tcp::frame_message(&query).unwrap()
+    }
+
+    async fn start(
+        config: ServerConfig,
+    ) -> Result<SocketAddr, Box<dyn error::Error + Send + Sync>> {
+        let server = Server::new(EvenIsSlowHandler, config);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { server.serve_tcp(listener).await });
+        Ok(addr)
+    }
+
+    async fn read_id(stream: &mut TcpStream) -> Result<u16, Box<dyn error::Error + Send + Sync>> {
+        let message = read_message(stream).await?.ok_or("connection closed")?;
+        Ok(dns::parse_dns_packet(&message)?.header.id)
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_pipelined_requests_out_of_order(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let addr = start(ServerConfig::default()).await?;
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&[query(2), query(3)].concat()).await?;
+        assert_eq!(read_id(&mut stream).await?, 3);
+        assert_eq!(read_id(&mut stream).await?, 2);
+
+        // Requests split across several writes are reassembled
+        let request = query(5);
+        stream.write_all(&request[..5]).await?;
+        stream.flush().await?;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stream.write_all(&request[5..]).await?;
+        assert_eq!(read_id(&mut stream).await?, 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_finishes_responses_after_client_stops_writing(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let addr = start(ServerConfig::default()).await?;
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&query(2)).await?;
+        stream.shutdown().await?;
+        assert_eq!(read_id(&mut stream).await?, 2);
+        assert_eq!(read_message(&mut stream).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_closes_idle_connections() -> Result<(), Box<dyn error::Error + Send + Sync>>
+    {
+        let config = ServerConfig {
+            tcp_idle_timeout: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let addr = start(config).await?;
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&query(1)).await?;
+        assert_eq!(read_id(&mut stream).await?, 1);
+        let closed =
+            tokio::time::timeout(Duration::from_secs(1), read_message(&mut stream)).await?;
+        assert_eq!(closed?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_limits_connections() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let config = ServerConfig {
+            max_tcp_connections: 1,
+            ..ServerConfig::default()
+        };
+        let addr = start(config).await?;
+        let mut first = TcpStream::connect(addr).await?;
+        first.write_all(&query(1)).await?;
+        assert_eq!(read_id(&mut first).await?, 1);
+
+        let mut second = TcpStream::connect(addr).await?;
+        let _ = second.write_all(&query(3)).await;
+        assert!(!matches!(read_message(&mut second).await, Ok(Some(_))));
+
+        // Once the first connection is closed there's room for another
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut third = TcpStream::connect(addr).await?;
+        third.write_all(&query(5)).await?;
+        assert_eq!(read_id(&mut third).await?, 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::anyhow;
+use std::path::Path;
+use std::sync::Arc;
+use std::{error, fs, io};
+use tokio_rustls::rustls::crypto;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+
+/// The settings for answering over TLS with a certificate chain, which
+/// starts with our own certificate, and its private key.
+pub fn server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<Arc<ServerConfig>, Box<dyn error::Error + Send + Sync>> {
+    let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Load a private key from a PEM file.
+pub fn load_private_key(
+    path: &Path,
+) -> Result<PrivateKeyDer<'static>, Box<dyn error::Error + Send + Sync>> {
+    let mut reader = io::BufReader::new(fs::File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow!("no private key found in {}", path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authority::Catalog;
+    use crate::runtime::{Server, ServerConfig as RuntimeConfig};
+    use client::tls::{Authentication, TlsConfig};
+    use dns::header::ResponseCode;
+    use dns::record::RecordType;
+    use std::sync::RwLock;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::pki_types::PrivatePkcs8KeyDer;
+
+    #[tokio::test]
+    async fn test_serve_answers_over_tls() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let cert = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let config = server_config(vec![cert.clone()], key.into())?;
+
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\nwww 60 A 192.0.2.1";
+        let mut catalog = Catalog::new();
+        catalog.insert(dns::zone::parse_zone(text, Some("example.com"))?);
+        let server = Server::new(Arc::new(RwLock::new(catalog)), RuntimeConfig::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { server.serve_tls(listener, config).await });
+
+        let authentication = Authentication::Certificate {
+            name: Some("localhost".to_string()),
+            roots: vec![cert],
+        };
+        let mut connection = TlsConfig::new(&authentication)?.connect(addr).await?;
+        // Several queries can share a session
+        for id in 1..=2 {
+            let request = dns::packet::build_query(id, "www.example.com", &RecordType::A);
+            connection.send(&request).await?;
+            let response =
+                tokio::time::timeout(Duration::from_secs(5), connection.receive()).await??;
+            assert_eq!(response.header.id, id);
+            assert_eq!(response.header.rcode, ResponseCode::Success);
+            assert_eq!(response.answers.len(), 1);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use super::response;
+use dns::dnssec;
+use dns::header::ResponseCode;
+use dns::journal::{self, Journal};
+use dns::packet::DnsPacket;
+use dns::record::{self, Record, RecordType};
+use dns::zone::Zone;
+
+/// How large each message of a transfer may get, leaving room below the
+/// 65535 bytes TCP allows for a TSIG record.
+const MAX_MESSAGE_SIZE: usize = 60 * 1024;
+
+/// Answer an AXFR request with the whole zone, split over as many messages
+/// as it takes (RFC 5936 §2.2). The zone's SOA record comes first and last,
+/// so the client knows when the transfer is complete. Only the first message
+/// repeats the question.
+pub fn axfr(zone: &Zone, request: &DnsPacket) -> Vec<DnsPacket> {
+    let soa = match zone.soa() {
+        Some(soa) => soa.clone(),
+        None => {
+            return vec![response::error_response(
+                request,
+                ResponseCode::ServerFailure,
+            )]
+        }
+    };
+    let mut records = vec![soa.clone()];
+    records.extend(
+        zone.records()
+            .filter(|record| record.record_type != RecordType::Soa)
+            .cloned(),
+    );
+    records.push(soa);
+    split_messages(request, records)
+}
+
+/// Answer an IXFR request with the changes since the version of the zone in
+/// the request's authority section (RFC 1995 §4). Clients that are up to
+/// date just get the SOA record, and the whole zone is sent if the journal
+/// doesn't go back far enough.
+pub fn ixfr(zone: &Zone, journal: Option<&Journal>, request: &DnsPacket) -> Vec<DnsPacket> {
+    let client_serial = request
+        .authoritative_entries
+        .iter()
+        .find(|record| record.record_type == RecordType::Soa)
+        .and_then(journal::serial);
+    let (client_serial, soa) = match (client_serial, zone.soa()) {
+        (Some(client_serial), Some(soa)) => (client_serial, soa),
+        (None, _) => return vec![response::error_response(request, ResponseCode::FormatError)],
+        (_, None) => {
+            return vec![response::error_response(
+                request,
+                ResponseCode::ServerFailure,
+            )]
+        }
+    };
+    let up_to_date = match journal::serial(soa) {
+        Some(serial) => !dnssec::time_before(client_serial, serial),
+        None => false,
+    };
+    if up_to_date {
+        return split_messages(request, vec![soa.clone()]);
+    }
+    match journal.and_then(|journal| journal.since(client_serial)) {
+        Some(diffs) if diffs.last().is_some_and(|diff| diff.to == *soa) => {
+            let mut records = vec![soa.clone()];
+            records.extend(diffs.iter().flat_map(|diff| diff.records()).cloned());
+            records.push(soa.clone());
+            split_messages(request, records)
+        }
+        _ => axfr(zone, request),
+    }
+}
+
+/// Pack records into as few messages as possible, in order.
+fn split_messages(request: &DnsPacket, records: Vec<Record>) -> Vec<DnsPacket> {
+    let mut first = response::response_to(request);
+    first.header.authoritative_answer = true;
+    let header_size = dns::serialize_dns_packet(&first).map_or(0, |bytes| bytes.len());
+
+    let mut messages = vec![];
+    let mut message = first;
+    let mut size = header_size;
+    for record in records {
+        let record_size = record::serialize_single_record(&record).map_or(0, |bytes| bytes.len());
+        if size + record_size > MAX_MESSAGE_SIZE && !message.answers.is_empty() {
+            let mut next = message.clone();
+            next.questions.clear();
+            next.answers.clear();
+            next.header.questions = 0;
+            messages.push(message);
+            message = next;
+            size = dns::serialize_dns_packet(&message).map_or(0, |bytes| bytes.len());
+        }
+        size += record_size;
+        message.answers.push(record);
+    }
+    messages.push(message);
+    for message in &mut messages {
+        message.header.answers = message.answers.len() as u16;
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authority::Catalog;
+
+    fn request() -> DnsPacket {
+        dns::packet::build_query(7, "example.com", &RecordType::Axfr)
+    }
+
+    #[test]
+    fn test_axfr_starts_and_ends_with_soa() {
+        let text =
+            "@ 60 SOA ns hostmaster 1 2 3 4 5\n@ 60 NS ns\nns 60 A 192.0.2.1\nwww 60 A 192.0.2.2";
+        let zone = dns::zone::parse_zone(text, Some("example.com")).unwrap();
+        let messages = axfr(&zone, &request());
+        assert_eq!(messages.len(), 1);
+        let answers = &messages[0].answers;
+        assert_eq!(answers.len(), 5);
+        assert_eq!(answers[0].record_type, RecordType::Soa);
+        assert_eq!(answers[4], answers[0]);
+        assert!(answers[1..4]
+            .iter()
+            .all(|record| record.record_type != RecordType::Soa));
+        assert_eq!(messages[0].header.id, 7);
+        assert!(messages[0].header.authoritative_answer);
+        assert_eq!(messages[0].questions.len(), 1);
+    }
+
+    #[test]
+    fn test_axfr_splits_large_zones() {
+        let mut text = "@ 60 SOA ns hostmaster 1 2 3 4 5\n".to_string();
+        for i in 0..2000 {
+            text.push_str(&format!("host{} 60 TXT \"{}\"\n", i, "x".repeat(40)));
+        }
+        let zone = dns::zone::parse_zone(&text, Some("example.com")).unwrap();
+        let messages = axfr(&zone, &request());
+        assert!(messages.len() > 1);
+        assert_eq!(messages[0].questions.len(), 1);
+        let mut count = 0;
+        for message in &messages {
+            let bytes = dns::serialize_dns_packet(message).unwrap();
+            assert!(bytes.len() <= MAX_MESSAGE_SIZE);
+            assert_eq!(message.header.id, 7);
+            count += message.answers.len();
+        }
+        assert_eq!(count, 2002);
+        assert!(messages[1..]
+            .iter()
+            .all(|message| message.questions.is_empty()));
+    }
+
+    #[test]
+    fn test_axfr_fails_without_soa() {
+        let zone = Zone::new("example.com");
+        let messages = axfr(&zone, &request());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].header.rcode, ResponseCode::ServerFailure);
+    }
+
+    fn version(serial: u32) -> Zone {
+        let text = format!(
+            "@ 60 SOA ns hostmaster {} 2 3 4 5\n@ 60 NS ns\nns 60 A 192.0.2.{}",
+            serial, serial
+        );
+        dns::zone::parse_zone(&text, Some("example.com")).unwrap()
+    }
+
+    fn ixfr_request(serial: u32) -> DnsPacket {
+        let mut request = dns::packet::build_query(7, "example.com", &RecordType::Ixfr);
+        request
+            .authoritative_entries
+            .push(version(serial).soa().unwrap().clone());
+        request
+    }
+
+    fn catalog() -> Catalog {
+        let mut catalog = Catalog::new();
+        for serial in 1..=3 {
+            catalog.insert(version(serial));
+        }
+        catalog
+    }
+
+    #[test]
+    fn test_catalog_journals_newer_versions() {
+        let mut catalog = catalog();
+        assert_eq!(catalog.journal("example.com").unwrap().len(), 2);
+
+        // Going back to an older version breaks the history
+        catalog.insert(version(1));
+        assert!(catalog.journal("example.com").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ixfr_sends_changes_since_client_serial() {
+        let catalog = catalog();
+        let zone = catalog.find_zone("example.com").unwrap();
+        let journal = catalog.journal("example.com");
+
+        let messages = ixfr(zone, journal, &ixfr_request(1));
+        assert_eq!(messages.len(), 1);
+        let records = &messages[0].answers;
+        // SOA 3, SOA 1, -A, SOA 2, +A, SOA 2, -A, SOA 3, +A, SOA 3
+        assert_eq!(records.len(), 10);
+        let serials: Vec<u32> = records.iter().filter_map(journal::serial).collect();
+        assert_eq!(serials, [3, 1, 2, 2, 3, 3]);
+        let transfer = journal::parse_transfer("example.com", records, Some(1)).unwrap();
+        let mut client = version(1);
+        match transfer {
+            Some(journal::Transfer::Incremental(diffs)) => {
+                for diff in diffs {
+                    diff.apply(&mut client).unwrap();
+                }
+            }
+            other => panic!("expected an incremental transfer, got {:?}", other),
+        }
+        assert_eq!(client, version(3));
+    }
+
+    #[test]
+    fn test_ixfr_falls_back_to_whole_zone() {
+        let catalog = catalog();
+        let zone = catalog.find_zone("example.com").unwrap();
+
+        // Up to date clients only get the SOA record
+        let messages = ixfr(zone, catalog.journal("example.com"), &ixfr_request(3));
+        assert_eq!(messages[0].answers.len(), 1);
+
+        // The journal doesn't go back to serial 0, so the whole zone is sent
+        let messages = ixfr(zone, catalog.journal("example.com"), &ixfr_request(0));
+        assert_eq!(messages[0].answers, axfr(zone, &ixfr_request(0))[0].answers);
+        let messages = ixfr(zone, None, &ixfr_request(1));
+        assert_eq!(messages[0].answers.len(), 4);
+
+        let mut request = ixfr_request(1);
+        request.authoritative_entries.clear();
+        let messages = ixfr(zone, None, &request);
+        assert_eq!(messages[0].header.rcode, ResponseCode::FormatError);
+    }
+}
@@ -0,0 +1,142 @@
+use super::handler::{Protocol, RequestContext, RequestHandler};
+use super::runtime::Server;
+use std::error;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// The largest datagram we will read. Anything beyond this is discarded by
+/// the socket.
+const MAX_DATAGRAM_SIZE: usize = 4096;
+
+/// Answer requests arriving on a UDP socket, each in its own task. Failures
+/// to handle individual datagrams are logged and skipped.
+pub(crate) async fn serve<H: RequestHandler + 'static>(
+    server: Server<H>,
+    sock: UdpSocket,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let sock = Arc::new(sock);
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, peer) = match sock.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => {
+                // e.g. an ICMP port unreachable from an earlier response
+                eprintln!("Failed to receive datagram: {}", err);
+                continue;
+            }
+        };
+        let request = buf[..len].to_vec();
+        let permit = server.acquire().await;
+        let server = server.clone();
+        let sock = sock.clone();
+        tokio::spawn(async move {
+            let ctx = RequestContext {
+                peer,
+                protocol: Protocol::Udp,
+                tsig_key: None,
+            };
+            if let Some(response) = server.handle_message(&request, &ctx).await {
+                if let Err(err) = sock.send_to(&response, peer).await {
+                    eprintln!("Failed to send response to {}: {}", peer, err);
+                }
+            }
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response;
+    use crate::runtime::ServerConfig;
+    use async_trait::async_trait;
+    use dns::header::ResponseCode;
+    use dns::packet::DnsPacket;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Answers every request after a short delay, keeping track of how many
+    /// requests it was handling at once.
+    #[derive(Default)]
+    struct SlowHandler {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl RequestHandler for SlowHandler {
+        async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+            assert_eq!(ctx.protocol, Protocol::Udp);
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            response::response_to(request)
+        }
+    }
+
+    fn query(id: u16) -> Vec<u8> {
+        let mut query = include_bytes!("../../examples/query_packet").to_vec();
+        query[0..2].copy_from_slice(&id.to_be_bytes());
+        query
+    }
+
+    async fn start(
+        config: ServerConfig,
+    ) -> Result<(Server<SlowHandler>, UdpSocket), Box<dyn error::Error + Send + Sync>> {
+        let server = Server::new(SlowHandler::default(), config);
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        tokio::spawn({
+            let server = server.clone();
+            async move { server.serve_udp(sock).await }
+        });
+        let client = UdpSocket::bind("127.0.0.1:0").await?;
+        client.connect(addr).await?;
+        Ok((server, client))
+    }
+
+    #[tokio::test]
+    async fn test_serve_handles_requests_concurrently_up_to_limit(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let config = ServerConfig {
+            max_concurrent_requests: 3,
+            ..ServerConfig::default()
+        };
+        let (server, client) = start(config).await?;
+        for id in 0..8 {
+            client.send(&query(id)).await?;
+        }
+        let mut ids = vec![];
+        let mut buf = [0; 512];
+        for _ in 0..8 {
+            let len = client.recv(&mut buf).await?;
+            ids.push(dns::parse_dns_packet(&buf[..len])?.header.id);
+        }
+        ids.sort();
+        assert_eq!(ids, (0..8).collect::<Vec<u16>>());
+        assert_eq!(server.handler().max_in_flight.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_keeps_running_after_bad_datagrams(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let (_, client) = start(ServerConfig::default()).await?;
+        let mut buf = [0; 512];
+        client.send(&[0xff]).await?;
+        client.send(&[0x00, 0x01, 0x00]).await?;
+        let len = client.recv(&mut buf).await?;
+        let response = dns::parse_dns_packet(&buf[..len])?;
+        assert_eq!(response.header.id, 1);
+        assert_eq!(response.header.rcode, ResponseCode::FormatError);
+
+        client.send(&query(2)).await?;
+        let len = client.recv(&mut buf).await?;
+        let response = dns::parse_dns_packet(&buf[..len])?;
+        assert_eq!(response.header.id, 2);
+        assert_eq!(response.header.rcode, ResponseCode::Success);
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use super::authority::Catalog;
+use super::handler::{RequestContext, RequestHandler};
+use super::response;
+use super::signer::Signer;
+use async_trait::async_trait;
+use dns::header::ResponseCode;
+use dns::name;
+use dns::packet::DnsPacket;
+use dns::update::Update;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// How many times an update is tried against a zone that keeps changing
+/// while it's being signed.
+const MAX_UPDATE_ATTEMPTS: usize = 3;
+
+/// Make the changes in an update to a zone in the catalog, all at once.
+/// Zones we hold keys for are signed again before the new version is
+/// served. The catalog isn't locked while signing, so if the zone changes in
+/// the meantime the update is made again on top of the new version. Returns
+/// the response code for the update.
+pub fn apply(
+    catalog: &RwLock<Catalog>,
+    update: &Update,
+    signers: &[Signer],
+    now: SystemTime,
+) -> ResponseCode {
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        let zone = catalog
+            .read()
+            .unwrap()
+            .zones()
+            .iter()
+            .find(|zone| name::eq(&zone.origin, &update.zone))
+            .cloned();
+        let zone = match zone {
+            Some(zone) => zone,
+            None => return ResponseCode::NotAuth,
+        };
+        let updated = match update.apply(&zone) {
+            Ok(Some(updated)) => updated,
+            Ok(None) => return ResponseCode::Success,
+            Err(rcode) => return rcode,
+        };
+        let signer = signers
+            .iter()
+            .find(|signer| name::eq(signer.origin(), &update.zone));
+        let updated = match signer {
+            Some(signer) => match signer.sign(&updated, now) {
+                Ok(signed) => signed,
+                Err(err) => {
+                    eprintln!("Could not sign zone {} after update: {}", update.zone, err);
+                    return ResponseCode::ServerFailure;
+                }
+            },
+            None => updated,
+        };
+        if catalog.write().unwrap().replace(updated, zone.serial()) {
+            return ResponseCode::Success;
+        }
+    }
+    eprintln!(
+        "Zone {} kept changing while an update was applied to it",
+        update.zone
+    );
+    ResponseCode::ServerFailure
+}
+
+/// Makes the changes in UPDATE requests to the zones in a catalog, and
+/// passes every other request on to another handler.
+pub struct AcceptUpdates<H> {
+    catalog: Arc<RwLock<Catalog>>,
+    signers: Arc<Vec<Signer>>,
+    inner: H,
+}
+
+impl<H> AcceptUpdates<H> {
+    pub fn new(
+        catalog: Arc<RwLock<Catalog>>,
+        signers: Arc<Vec<Signer>>,
+        inner: H,
+    ) -> AcceptUpdates<H> {
+        AcceptUpdates {
+            catalog,
+            signers,
+            inner,
+        }
+    }
+}
+
+#[async_trait]
+impl<H: RequestHandler> RequestHandler for AcceptUpdates<H> {
+    async fn handle(&self, request: &DnsPacket, ctx: &RequestContext) -> DnsPacket {
+        self.inner.handle(request, ctx).await
+    }
+
+    async fn transfer(&self, request: &DnsPacket, ctx: &RequestContext) -> Vec<DnsPacket> {
+        self.inner.transfer(request, ctx).await
+    }
+
+    async fn update(
+        &self,
+        request: &DnsPacket,
+        update: &Update,
+        _ctx: &RequestContext,
+    ) -> DnsPacket {
+        let rcode = apply(&self.catalog, update, &self.signers, SystemTime::now());
+        response::error_response(request, rcode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::UpdatePolicy;
+    use crate::handler::Protocol;
+    use crate::runtime::{Server, ServerConfig};
+    use dns::header::Opcode;
+    use dns::record::RecordType;
+    use dns::tsig::{self, TsigKey, TsigSession};
+    use std::error;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    fn key() -> TsigKey {
+        TsigKey::new("dhcp", tsig::Algorithm::HmacSha256, b"secret".to_vec())
+    }
+
+    fn server(catalog: Arc<RwLock<Catalog>>) -> Server<AcceptUpdates<Arc<RwLock<Catalog>>>> {
+        let config = ServerConfig {
+            tsig_keys: vec![key()],
+            update_policy: UpdatePolicy::parse(&["dhcp subdomain:dhcp.example.com A".to_string()])
+                .unwrap(),
+            ..ServerConfig::default()
+        };
+        let handler = AcceptUpdates::new(catalog.clone(), Arc::new(vec![]), catalog);
+        Server::new(handler, config)
+    }
+
+    fn update_request(changes: &str) -> Vec<u8> {
+        let mut request = dns::packet::build_query(3, "example.com", &RecordType::Soa);
+        request.header.opcode = Opcode::Update;
+        request.authoritative_entries =
+            dns::zone::parse_master_file(changes, Some("example.com")).unwrap();
+        dns::serialize_dns_packet(&request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_server_applies_updates_allowed_by_policy(
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\n@ 60 NS ns";
+        let mut catalog = Catalog::new();
+        catalog.insert(dns::zone::parse_zone(text, Some("example.com"))?);
+        let catalog = Arc::new(RwLock::new(catalog));
+        let server = server(catalog.clone());
+        let ctx = RequestContext {
+            peer: "192.0.2.1:5353".parse().unwrap(),
+            protocol: Protocol::Udp,
+            tsig_key: None,
+        };
+        let rcode = |response: &[u8]| dns::parse_dns_packet(response).unwrap().header.rcode;
+
+        // Unsigned updates, and changes outside the grant, are refused
+        let request = update_request("pc1.dhcp 60 A 192.0.2.10");
+        let response = server.handle_message(&request, &ctx).await.unwrap();
+        assert_eq!(rcode(&response), ResponseCode::Refused);
+        let mut session = TsigSession::new(key());
+        let signed = session.sign(&update_request("www 60 A 192.0.2.10"), SystemTime::now())?;
+        let response = server.handle_message(&signed, &ctx).await.unwrap();
+        assert_eq!(rcode(&response), ResponseCode::Refused);
+        assert_eq!(catalog.read().unwrap().zones()[0].serial(), Some(1));
+
+        let mut session = TsigSession::new(key());
+        let signed = session.sign(&request, SystemTime::now())?;
+        let response = server.handle_message(&signed, &ctx).await.unwrap();
+        assert_eq!(session.verify(&response, SystemTime::now()), Ok(true));
+        assert_eq!(rcode(&response), ResponseCode::Success);
+        let catalog = catalog.read().unwrap();
+        let zone = &catalog.zones()[0];
+        assert_eq!(zone.serial(), Some(2));
+        assert_eq!(zone.rrset("pc1.dhcp.example.com", RecordType::A).len(), 1);
+        assert_eq!(catalog.journal("example.com").unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_sends_built_updates() -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let text = "@ 60 SOA ns hostmaster 1 2 3 4 5\n@ 60 NS ns\npc1.dhcp 60 A 192.0.2.10";
+        let mut catalog = Catalog::new();
+        catalog.insert(dns::zone::parse_zone(text, Some("example.com"))?);
+        let catalog = Arc::new(RwLock::new(catalog));
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        let server = server(catalog.clone());
+        tokio::spawn(async move { server.serve_udp(sock).await });
+
+        let record = |line: &str| dns::zone::parse_master_file(line, Some("example.com"));
+        let timeout = Duration::from_secs(1);
+        let update = Update::new("example.com")
+            .name_not_in_use("pc1.dhcp.example.com")
+            .add_record(record("pc1.dhcp 60 A 192.0.2.11")?.remove(0));
+        let rcode = client::update::send(addr, &update, Some(&key()), timeout).await?;
+        assert_eq!(rcode, ResponseCode::YxDomain);
+
+        let update = Update::new("example.com")
+            .rrset_equals(record("pc1.dhcp 60 A 192.0.2.10")?.remove(0))
+            .delete_rrset("pc1.dhcp.example.com", RecordType::A)
+            .add_record(record("pc1.dhcp 60 A 192.0.2.11")?.remove(0));
+        let rcode = client::update::send(addr, &update, Some(&key()), timeout).await?;
+        assert_eq!(rcode, ResponseCode::Success);
+        let catalog = catalog.read().unwrap();
+        let addrs = catalog.zones()[0].rrset("pc1.dhcp.example.com", RecordType::A);
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].data, dns::record::Data::Addr([192, 0, 2, 11]));
+        Ok(())
+    }
+}